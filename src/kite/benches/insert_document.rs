@@ -11,8 +11,8 @@ use kite::term::Term;
 use kite::token::Token;
 use kite::schema::{FieldType, FIELD_INDEXED};
 use kite::document::Document;
-use kite::store::{IndexStore, IndexReader};
-use kite::store::memory::{MemoryIndexStore, MemoryIndexStoreReader};
+use kite::store::IndexStore;
+use kite::store::memory::MemoryIndexStore;
 
 
 #[bench]
@@ -23,7 +23,7 @@ fn bench_insert_document(b: &mut Bencher) {
     let mut tokens = Vec::new();
     for t in 0..5000 {
         tokens.push(Token {
-            term: Term::from_string(t),
+            term: Term::from_string(&t.to_string()),
             position: t
         });
     }
@@ -32,12 +32,12 @@ fn bench_insert_document(b: &mut Bencher) {
     b.iter(|| {
         i += 1;
 
-        store.insert_or_update_document(Document {
+        store.insert_or_update_document(&Document {
             key: i.to_string(),
             indexed_fields: hashmap! {
                 body_field => tokens.clone()
             },
             stored_fields: hashmap! {},
-        });
+        }).unwrap();
     });
 }
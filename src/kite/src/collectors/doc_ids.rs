@@ -0,0 +1,90 @@
+use collectors::{Collector, MergeableCollector, DocumentMatch};
+
+
+/// Collects the ids of all matching documents, in the order they were matched
+pub struct DocIdCollector {
+    doc_ids: Vec<u64>,
+}
+
+
+impl DocIdCollector {
+    pub fn new() -> DocIdCollector {
+        DocIdCollector {
+            doc_ids: Vec::new(),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<u64> {
+        self.doc_ids
+    }
+}
+
+
+impl Collector for DocIdCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        self.doc_ids.push(doc.doc_id());
+    }
+}
+
+
+impl MergeableCollector for DocIdCollector {
+    fn new_empty(&self) -> DocIdCollector {
+        DocIdCollector::new()
+    }
+
+    /// Appends the other collector's documents after this collector's documents
+    fn merge(&mut self, other: DocIdCollector) {
+        self.doc_ids.extend(other.doc_ids);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use collectors::{Collector, MergeableCollector, DocumentMatch};
+    use super::DocIdCollector;
+
+
+    #[test]
+    fn test_doc_id_collector_inital_state() {
+        let collector = DocIdCollector::new();
+
+        assert_eq!(collector.into_vec(), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_doc_id_collector_needs_score() {
+        let collector = DocIdCollector::new();
+
+        assert_eq!(collector.needs_score(), false);
+    }
+
+    #[test]
+    fn test_doc_id_collector_collect() {
+        let mut collector = DocIdCollector::new();
+
+        collector.collect(DocumentMatch::new_unscored(3));
+        collector.collect(DocumentMatch::new_unscored(1));
+        collector.collect(DocumentMatch::new_unscored(2));
+
+        assert_eq!(collector.into_vec(), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_doc_id_collector_merge() {
+        let mut collector = DocIdCollector::new();
+        collector.collect(DocumentMatch::new_unscored(3));
+
+        let mut other_collector = collector.new_empty();
+        other_collector.collect(DocumentMatch::new_unscored(1));
+        other_collector.collect(DocumentMatch::new_unscored(2));
+
+        collector.merge(other_collector);
+
+        assert_eq!(collector.into_vec(), vec![3, 1, 2]);
+    }
+}
@@ -1,5 +1,6 @@
 pub mod total_count;
 pub mod top_score;
+pub mod doc_ids;
 
 
 #[derive(Debug)]
@@ -39,4 +40,21 @@ impl DocumentMatch {
 pub trait Collector {
     fn needs_score(&self) -> bool;
     fn collect(&mut self, doc: DocumentMatch);
+
+    /// The score that a document must reach to have any effect on the collector
+    ///
+    /// Searches may skip documents that cannot score at least this much.
+    fn min_competitive_score(&self) -> Option<f64> {
+        None
+    }
+}
+
+
+/// A collector that can be split up so documents can be collected on many threads
+pub trait MergeableCollector: Collector + Sized + Send + Sync {
+    /// Creates a collector with the same settings as this one, but no documents
+    fn new_empty(&self) -> Self;
+
+    /// Adds the documents from another collector into this one
+    fn merge(&mut self, other: Self);
 }
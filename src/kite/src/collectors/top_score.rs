@@ -1,7 +1,7 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
-use collectors::{Collector, DocumentMatch};
+use collectors::{Collector, MergeableCollector, DocumentMatch};
 
 
 /// An f64 that cannot be NaN.
@@ -112,12 +112,39 @@ impl Collector for TopScoreCollector {
             self.heap.pop();
         }
     }
+
+    fn min_competitive_score(&self) -> Option<f64> {
+        // Until the heap is full, any document can get in
+        if self.heap.len() < self.max_docs {
+            return None;
+        }
+
+        // The top of the heap is the lowest scoring document (scores are negated)
+        self.heap.peek().map(|scored_document| -scored_document.score.0)
+    }
+}
+
+
+impl MergeableCollector for TopScoreCollector {
+    fn new_empty(&self) -> TopScoreCollector {
+        TopScoreCollector::new(self.max_docs)
+    }
+
+    fn merge(&mut self, other: TopScoreCollector) {
+        for scored_document in other.heap.into_vec() {
+            self.heap.push(scored_document);
+
+            if self.heap.len() > self.max_docs {
+                self.heap.pop();
+            }
+        }
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use collectors::{Collector, DocumentMatch};
+    use collectors::{Collector, MergeableCollector, DocumentMatch};
     use super::TopScoreCollector;
 
 
@@ -166,4 +193,36 @@ mod tests {
         assert_eq!(docs[0].id, 2);
         assert_eq!(docs[1].id, 0);
     }
+
+    #[test]
+    fn test_top_score_collector_merge() {
+        let mut collector = TopScoreCollector::new(2);
+        collector.collect(DocumentMatch::new_scored(0, 1.0f64));
+        collector.collect(DocumentMatch::new_scored(1, 0.5f64));
+
+        let mut other_collector = collector.new_empty();
+        other_collector.collect(DocumentMatch::new_scored(2, 2.0f64));
+        other_collector.collect(DocumentMatch::new_scored(3, 0.1f64));
+
+        collector.merge(other_collector);
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].id, 2);
+        assert_eq!(docs[1].id, 0);
+    }
+
+    #[test]
+    fn test_top_score_collector_min_competitive_score() {
+        let mut collector = TopScoreCollector::new(2);
+
+        collector.collect(DocumentMatch::new_scored(0, 1.0f64));
+        assert_eq!(collector.min_competitive_score(), None);
+
+        collector.collect(DocumentMatch::new_scored(1, 0.5f64));
+        assert_eq!(collector.min_competitive_score(), Some(0.5f64));
+
+        collector.collect(DocumentMatch::new_scored(2, 2.0f64));
+        assert_eq!(collector.min_competitive_score(), Some(1.0f64));
+    }
 }
@@ -1,4 +1,4 @@
-use collectors::{Collector, DocumentMatch};
+use collectors::{Collector, MergeableCollector, DocumentMatch};
 
 
 pub struct TotalCountCollector {
@@ -30,9 +30,20 @@ impl Collector for TotalCountCollector {
 }
 
 
+impl MergeableCollector for TotalCountCollector {
+    fn new_empty(&self) -> TotalCountCollector {
+        TotalCountCollector::new()
+    }
+
+    fn merge(&mut self, other: TotalCountCollector) {
+        self.total_count += other.total_count;
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
-    use collectors::{Collector, DocumentMatch};
+    use collectors::{Collector, MergeableCollector, DocumentMatch};
     use super::TotalCountCollector;
 
 
@@ -60,4 +71,18 @@ mod tests {
 
         assert_eq!(collector.get_total_count(), 3);
     }
+
+    #[test]
+    fn test_total_count_collector_merge() {
+        let mut collector = TotalCountCollector::new();
+        collector.collect(DocumentMatch::new_unscored(0));
+
+        let mut other_collector = collector.new_empty();
+        other_collector.collect(DocumentMatch::new_unscored(1));
+        other_collector.collect(DocumentMatch::new_unscored(2));
+
+        collector.merge(other_collector);
+
+        assert_eq!(collector.get_total_count(), 3);
+    }
 }
@@ -1,29 +1,67 @@
 use std::fmt;
-use std::io::{Cursor, Read};
+use std::iter::FromIterator;
 
 use roaring::{RoaringBitmap, Iter as RoaringBitmapIter};
-use byteorder::{ByteOrder, BigEndian};
+use byteorder::{ByteOrder, BigEndian, ReadBytesExt, WriteBytesExt};
+
+
+/// Containers with more documents than this are stored as bitmaps rather than arrays
+const MAX_ARRAY_CONTAINER_LEN: usize = 4096;
+
+/// The number of documents in a container that has every document set
+const FULL_CONTAINER_LEN: usize = 65536;
+
+
+/// Writes one container of the serialised form
+///
+/// A container holds the low 16 bits of every document id that shares the same high
+/// 16 bits. Depending on how many documents it contains, it's stored as either:
+///  - An array of sorted two byte values (up to MAX_ARRAY_CONTAINER_LEN documents)
+///  - A bitmap of 65536 bits
+///  - Nothing at all, if every document is in the container
+fn write_container(bytes: &mut Vec<u8>, high: u16, lows: &[u16]) {
+    bytes.write_u16::<BigEndian>(high).unwrap();
+    bytes.write_u32::<BigEndian>(lows.len() as u32).unwrap();
+
+    if lows.len() == FULL_CONTAINER_LEN {
+        // Full container, nothing else to write
+    } else if lows.len() <= MAX_ARRAY_CONTAINER_LEN {
+        for low in lows.iter() {
+            bytes.write_u16::<BigEndian>(*low).unwrap();
+        }
+    } else {
+        let mut bitmap = [0u8; FULL_CONTAINER_LEN / 8];
+        for low in lows.iter() {
+            bitmap[*low as usize / 8] |= 1 << (*low % 8);
+        }
+        bytes.extend_from_slice(&bitmap);
+    }
+}
+
+
+/// An error that occurred while reading a serialised DocIdSet
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocIdSetReadError {
+    /// The data ended part way through a container
+    UnexpectedEnd,
+
+    /// A container claimed to hold more documents than a container can hold
+    InvalidContainerLength(u32),
+}
 
 
 #[derive(Clone)]
 pub struct DocIdSet {
-    data: RoaringBitmap<u16>,
+    data: RoaringBitmap<u32>,
 }
 
 
 impl DocIdSet {
-    pub fn new_filled(mut num_docs: u32) -> DocIdSet {
-        let mut data: RoaringBitmap<u16> = RoaringBitmap::new();
-
-        // Cap num_docs to 65536
-        // Note: we cannot simply make num_docs a u16 as 65536 is a valid length
-        if num_docs > 65536 {
-            num_docs = 65536;
-        }
+    pub fn new_filled(num_docs: u32) -> DocIdSet {
+        let mut data: RoaringBitmap<u32> = RoaringBitmap::new();
 
         for doc_id in 0..num_docs {
-            // Note: As num_docs is limited to 65536, doc_id cannot be greater than 65535
-            data.insert(doc_id as u16);
+            data.insert(doc_id);
         }
 
         DocIdSet {
@@ -31,24 +69,98 @@ impl DocIdSet {
         }
     }
 
-    pub fn from_bytes(data: Vec<u8>) -> DocIdSet {
-        let mut roaring_data: RoaringBitmap<u16> = RoaringBitmap::new();
-        let mut cursor = Cursor::new(data);
+    /// Reads a DocIdSet that was serialised with `to_bytes`
+    ///
+    /// An empty value is read as an empty set.
+    pub fn from_bytes(data: Vec<u8>) -> Result<DocIdSet, DocIdSetReadError> {
+        let mut roaring_data: RoaringBitmap<u32> = RoaringBitmap::new();
+        let mut bytes = &data[..];
 
-        loop {
-            let mut buf = [0, 2];
-            match cursor.read_exact(&mut buf) {
-                Ok(()) => {
-                    let doc_id = BigEndian::read_u16(&buf);
-                    roaring_data.insert(doc_id);
+        if bytes.is_empty() {
+            return Ok(DocIdSet {
+                data: roaring_data
+            });
+        }
+
+        let num_containers = try!(bytes.read_u32::<BigEndian>().map_err(|_| DocIdSetReadError::UnexpectedEnd));
+
+        for _ in 0..num_containers {
+            let high = (try!(bytes.read_u16::<BigEndian>().map_err(|_| DocIdSetReadError::UnexpectedEnd)) as u32) << 16;
+            let len = try!(bytes.read_u32::<BigEndian>().map_err(|_| DocIdSetReadError::UnexpectedEnd));
+
+            // RoaringBitmap doesn't let containers be built directly, but values are
+            // inserted in ascending order so each insert goes into the last container
+            if len as usize == FULL_CONTAINER_LEN {
+                for low in 0..FULL_CONTAINER_LEN as u32 {
+                    roaring_data.insert(high | low);
                 }
-                Err(_) => break,
+            } else if len as usize <= MAX_ARRAY_CONTAINER_LEN {
+                if bytes.len() < len as usize * 2 {
+                    return Err(DocIdSetReadError::UnexpectedEnd);
+                }
+
+                let (array, rest) = bytes.split_at(len as usize * 2);
+                for low in array.chunks(2) {
+                    roaring_data.insert(high | BigEndian::read_u16(low) as u32);
+                }
+                bytes = rest;
+            } else if len as usize <= FULL_CONTAINER_LEN {
+                if bytes.len() < FULL_CONTAINER_LEN / 8 {
+                    return Err(DocIdSetReadError::UnexpectedEnd);
+                }
+
+                // Only the set bits are visited, by repeatedly clearing the lowest one
+                let (bitmap, rest) = bytes.split_at(FULL_CONTAINER_LEN / 8);
+                for (byte_index, byte) in bitmap.iter().enumerate() {
+                    let mut byte = *byte;
+                    while byte != 0 {
+                        roaring_data.insert(high | (byte_index as u32 * 8 + byte.trailing_zeros()));
+                        byte &= byte - 1;
+                    }
+                }
+                bytes = rest;
+            } else {
+                return Err(DocIdSetReadError::InvalidContainerLength(len));
             }
         }
 
-        DocIdSet {
+        Ok(DocIdSet {
             data: roaring_data
+        })
+    }
+
+    /// Serialises the DocIdSet into a compressed form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        let mut num_containers = 0;
+
+        // Document ids are iterated in order, so we write out each container as soon
+        // as we see a document id from the next one
+        let mut current_high = None;
+        let mut lows = Vec::new();
+        for doc_id in self.iter() {
+            let high = (doc_id >> 16) as u16;
+
+            if current_high != Some(high) {
+                if let Some(current_high) = current_high {
+                    write_container(&mut bytes, current_high, &lows);
+                    num_containers += 1;
+                    lows.clear();
+                }
+
+                current_high = Some(high);
+            }
+
+            lows.push(doc_id as u16);
         }
+
+        if let Some(current_high) = current_high {
+            write_container(&mut bytes, current_high, &lows);
+            num_containers += 1;
+        }
+
+        BigEndian::write_u32(&mut bytes[0..4], num_containers);
+        bytes
     }
 
     pub fn iter<'a>(&'a self) -> DocIdSetIterator<'a> {
@@ -57,31 +169,52 @@ impl DocIdSet {
         }
     }
 
-    pub fn contains_doc(&self, doc_id: u16) -> bool {
+    pub fn contains_doc(&self, doc_id: u32) -> bool {
         self.data.contains(doc_id)
     }
 
     pub fn union(&self, other: &DocIdSet) -> DocIdSet {
-        let mut data: RoaringBitmap<u16> = self.data.clone();
-        data.union_with(&other.data);
-
-        DocIdSet {
-            data: data
-        }
+        let mut doc_id_set = self.clone();
+        doc_id_set.union_with(other);
+        doc_id_set
     }
 
     pub fn intersection(&self, other: &DocIdSet) -> DocIdSet {
-        let mut data: RoaringBitmap<u16> = self.data.clone();
-        data.intersect_with(&other.data);
-
-        DocIdSet {
-            data: data
-        }
+        let mut doc_id_set = self.clone();
+        doc_id_set.intersect_with(other);
+        doc_id_set
     }
 
     pub fn exclusion(&self, other: &DocIdSet) -> DocIdSet {
-        let mut data: RoaringBitmap<u16> = self.data.clone();
-        data.difference_with(&other.data);
+        let mut doc_id_set = self.clone();
+        doc_id_set.difference_with(other);
+        doc_id_set
+    }
+
+    /// Adds the documents in another set to this one, without allocating a new set
+    pub fn union_with(&mut self, other: &DocIdSet) {
+        self.data.union_with(&other.data);
+    }
+
+    /// Removes documents that aren't in another set from this one, without allocating a new set
+    pub fn intersect_with(&mut self, other: &DocIdSet) {
+        self.data.intersect_with(&other.data);
+    }
+
+    /// Removes documents that are in another set from this one, without allocating a new set
+    pub fn difference_with(&mut self, other: &DocIdSet) {
+        self.data.difference_with(&other.data);
+    }
+}
+
+
+impl FromIterator<u32> for DocIdSet {
+    fn from_iter<I: IntoIterator<Item=u32>>(iter: I) -> DocIdSet {
+        let mut data: RoaringBitmap<u32> = RoaringBitmap::new();
+
+        for doc_id in iter {
+            data.insert(doc_id);
+        }
 
         DocIdSet {
             data: data
@@ -111,14 +244,91 @@ impl fmt::Debug for DocIdSet {
 
 
 pub struct DocIdSetIterator<'a> {
-    inner: RoaringBitmapIter<'a, u16>,
+    inner: RoaringBitmapIter<'a, u32>,
 }
 
 
 impl<'a> Iterator for DocIdSetIterator<'a> {
-    type Item = u16;
+    type Item = u32;
 
-    fn next(&mut self) -> Option<u16> {
+    fn next(&mut self) -> Option<u32> {
         self.inner.next()
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::{DocIdSet, DocIdSetReadError};
+
+    fn round_trip(doc_id_set: &DocIdSet) -> Vec<u32> {
+        DocIdSet::from_bytes(doc_id_set.to_bytes()).unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_to_bytes_empty() {
+        let doc_id_set = DocIdSet::new_filled(0);
+
+        assert_eq!(round_trip(&doc_id_set), Vec::<u32>::new());
+        assert_eq!(doc_id_set.to_bytes().len(), 4);
+    }
+
+    #[test]
+    fn test_to_bytes_sparse() {
+        let doc_ids = vec![1, 5, 70000, 70001, 4000000];
+        let doc_id_set = doc_ids.iter().cloned().collect::<DocIdSet>();
+
+        assert_eq!(round_trip(&doc_id_set), doc_ids);
+    }
+
+    #[test]
+    fn test_to_bytes_dense() {
+        // Every other document, so the first container is stored as a bitmap
+        let doc_ids = (0..20000).map(|doc_id| doc_id * 2).collect::<Vec<u32>>();
+        let doc_id_set = doc_ids.iter().cloned().collect::<DocIdSet>();
+
+        assert_eq!(round_trip(&doc_id_set), doc_ids);
+        assert!(doc_id_set.to_bytes().len() < doc_ids.len() * 4);
+    }
+
+    #[test]
+    fn test_to_bytes_full() {
+        // The first container is full so shouldn't take up any space
+        let doc_id_set = DocIdSet::new_filled(65537);
+
+        assert_eq!(round_trip(&doc_id_set), (0..65537).collect::<Vec<u32>>());
+        assert_eq!(doc_id_set.to_bytes().len(), 4 + 6 + 6 + 2);
+    }
+
+    #[test]
+    fn test_from_bytes_invalid() {
+        assert_eq!(DocIdSet::from_bytes(vec![]).unwrap().iter().count(), 0);
+
+        // The data ends part way through a container
+        let mut bytes = vec![1, 2, 3].into_iter().collect::<DocIdSet>().to_bytes();
+        bytes.pop();
+        assert_eq!(DocIdSet::from_bytes(bytes).err(), Some(DocIdSetReadError::UnexpectedEnd));
+
+        let mut bytes = (0..20000).map(|doc_id| doc_id * 2).collect::<DocIdSet>().to_bytes();
+        bytes.pop();
+        assert_eq!(DocIdSet::from_bytes(bytes).err(), Some(DocIdSetReadError::UnexpectedEnd));
+
+        // A container that is larger than 65536 documents
+        let bytes = vec![0, 0, 0, 1, 0, 0, 0, 1, 0, 1];
+        assert_eq!(DocIdSet::from_bytes(bytes).err(), Some(DocIdSetReadError::InvalidContainerLength(65537)));
+    }
+
+    #[test]
+    fn test_in_place_operations() {
+        let mut doc_id_set = vec![1, 2, 3].into_iter().collect::<DocIdSet>();
+
+        doc_id_set.union_with(&vec![3, 4].into_iter().collect());
+        assert_eq!(doc_id_set.iter().collect::<Vec<u32>>(), vec![1, 2, 3, 4]);
+
+        doc_id_set.intersect_with(&vec![2, 3, 4, 5].into_iter().collect());
+        assert_eq!(doc_id_set.iter().collect::<Vec<u32>>(), vec![2, 3, 4]);
+
+        doc_id_set.difference_with(&vec![3].into_iter().collect());
+        assert_eq!(doc_id_set.iter().collect::<Vec<u32>>(), vec![2, 4]);
+    }
+}
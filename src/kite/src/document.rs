@@ -10,7 +10,7 @@ use schema::FieldRef;
 
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct DocRef(u32, u16);
+pub struct DocRef(u32, u32);
 
 
 impl DocRef {
@@ -18,22 +18,22 @@ impl DocRef {
         self.0
     }
 
-    pub fn ord(&self) -> u16 {
+    pub fn ord(&self) -> u32 {
         self.1
     }
 
     pub fn as_u64(&self) -> u64 {
-        (self.0 as u64) << 16 | (self.1 as u64)
+        (self.0 as u64) << 32 | (self.1 as u64)
     }
 
-    pub fn from_segment_ord(segment: u32, ord: u16) -> DocRef {
+    pub fn from_segment_ord(segment: u32, ord: u32) -> DocRef {
         DocRef(segment, ord)
     }
 
     pub fn from_u64(val: u64) -> DocRef {
-        let segment = (val >> 16) & 0xFFFFFFFF;
-        let ord = val & 0xFFFF;
-        DocRef(segment as u32, ord as u16)
+        let segment = (val >> 32) & 0xFFFFFFFF;
+        let ord = val & 0xFFFFFFFF;
+        DocRef(segment as u32, ord as u32)
     }
 }
 
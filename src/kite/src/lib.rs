@@ -14,11 +14,13 @@ pub mod term;
 pub mod token;
 pub mod doc_id_set;
 pub mod schema;
+pub mod norms;
 pub mod document;
 pub mod segment;
 pub mod similarity;
 pub mod query;
 pub mod collectors;
+pub mod store;
 
 pub use term::{Term, TermRef};
 pub use token::Token;
@@ -0,0 +1,251 @@
+use std::u16;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use schema::Norms;
+
+
+/// Converts the number of tokens in a field into a single byte
+///
+/// This is how lengths are stored with `Norms::Byte`. Short lengths are kept quite
+/// precisely but the longer the field, the more it's rounded.
+pub fn encode_length_byte(token_count: u32) -> u8 {
+    let length = ((token_count as f64).sqrt() - 1.0) * 3.0;
+    if length > 255.0 { 255.0 } else { length } as u8
+}
+
+
+/// Converts a byte that was encoded by `encode_length_byte` back into a length
+pub fn decode_length_byte(length: u8) -> f64 {
+    let length_sqrt = (length as f64) / 3.0 + 1.0;
+    length_sqrt * length_sqrt
+}
+
+
+/// An error that occurred while reading serialised FieldNorms
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldNormsReadError {
+    /// The data didn't have a header
+    UnexpectedEnd,
+
+    /// The header gave a number of bytes per document that no precision uses
+    InvalidWidth(u8),
+}
+
+
+/// The lengths of a field in every document of a segment
+///
+/// Lengths are stored in a dense array indexed by document ordinal, so a whole
+/// segment's worth of lengths can be loaded with a single read. Documents past the
+/// end of the array don't have the field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldNorms {
+    norms: Norms,
+    data: Vec<u8>,
+}
+
+
+impl FieldNorms {
+    /// Stores the token counts of a field (indexed by document ordinal) with the given
+    /// precision, returns None if norms are disabled
+    pub fn new(norms: Norms, token_counts: &[u32]) -> Option<FieldNorms> {
+        let mut data = vec![0; token_counts.len() * FieldNorms::width(norms)];
+
+        match norms {
+            Norms::Disabled => return None,
+            Norms::Byte => {
+                for (doc_id, token_count) in token_counts.iter().enumerate() {
+                    data[doc_id] = encode_length_byte(*token_count);
+                }
+            }
+            Norms::Short => {
+                for (doc_id, token_count) in token_counts.iter().enumerate() {
+                    let token_count = if *token_count > u16::MAX as u32 { u16::MAX } else { *token_count as u16 };
+                    BigEndian::write_u16(&mut data[doc_id * 2..doc_id * 2 + 2], token_count);
+                }
+            }
+            Norms::Int => {
+                for (doc_id, token_count) in token_counts.iter().enumerate() {
+                    BigEndian::write_u32(&mut data[doc_id * 4..doc_id * 4 + 4], *token_count);
+                }
+            }
+        }
+
+        Some(FieldNorms {
+            norms: norms,
+            data: data,
+        })
+    }
+
+    /// The number of bytes that are stored for each document
+    fn width(norms: Norms) -> usize {
+        match norms {
+            Norms::Disabled => 0,
+            Norms::Byte => 1,
+            Norms::Short => 2,
+            Norms::Int => 4,
+        }
+    }
+
+    pub fn norms(&self) -> Norms {
+        self.norms
+    }
+
+    /// The number of documents that lengths are stored for
+    pub fn num_docs(&self) -> u32 {
+        (self.data.len() / FieldNorms::width(self.norms)) as u32
+    }
+
+    /// The value that was stored for a document, 0 if the document doesn't have the field
+    fn raw_value(&self, doc_id: u32) -> u32 {
+        let width = FieldNorms::width(self.norms);
+        let start = doc_id as usize * width;
+
+        if start + width > self.data.len() {
+            return 0;
+        }
+
+        match self.norms {
+            Norms::Disabled => 0,
+            Norms::Byte => self.data[start] as u32,
+            Norms::Short => BigEndian::read_u16(&self.data[start..start + 2]) as u32,
+            Norms::Int => BigEndian::read_u32(&self.data[start..start + 4]),
+        }
+    }
+
+    /// The length of the field in a document
+    ///
+    /// Every document that matches a term in the field has at least one token, so
+    /// documents without a length are given a length of 1.
+    pub fn length(&self, doc_id: u32) -> f64 {
+        let value = self.raw_value(doc_id);
+
+        match self.norms {
+            Norms::Byte => decode_length_byte(value as u8),
+            _ if value == 0 => 1.0,
+            _ => value as f64,
+        }
+    }
+
+    /// The number of tokens in the field of a document
+    ///
+    /// If the lengths were stored with `Norms::Byte`, this is the smallest token count
+    /// that is stored as the same byte.
+    pub fn token_count(&self, doc_id: u32) -> u32 {
+        match self.norms {
+            Norms::Byte if self.raw_value(doc_id) != 0 => self.length(doc_id).ceil() as u32,
+            _ => self.raw_value(doc_id),
+        }
+    }
+
+    /// The token count that is read back for a document with the given number of tokens
+    ///
+    /// This is lower than the given count if the lengths are stored with a precision
+    /// that can't hold it exactly.
+    pub fn stored_token_count(&self, token_count: u32) -> u32 {
+        match FieldNorms::new(self.norms, &[token_count]) {
+            Some(norms) => norms.token_count(0),
+            None => token_count,
+        }
+    }
+
+    /// The length that a document with the given number of tokens would be given
+    pub fn length_of(&self, token_count: u32) -> f64 {
+        match FieldNorms::new(self.norms, &[token_count]) {
+            Some(norms) => norms.length(0),
+            None => 1.0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + self.data.len());
+        bytes.push(FieldNorms::width(self.norms) as u8);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Reads norms that were serialised with `to_bytes`
+    ///
+    /// The first byte is the number of bytes stored for each document, which tells us
+    /// the precision they were stored with.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FieldNorms, FieldNormsReadError> {
+        if bytes.is_empty() {
+            return Err(FieldNormsReadError::UnexpectedEnd);
+        }
+
+        let norms = match bytes[0] {
+            1 => Norms::Byte,
+            2 => Norms::Short,
+            4 => Norms::Int,
+            width => return Err(FieldNormsReadError::InvalidWidth(width)),
+        };
+
+        Ok(FieldNorms {
+            norms: norms,
+            data: bytes[1..].to_vec(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use schema::Norms;
+
+    use super::{FieldNorms, FieldNormsReadError};
+
+    #[test]
+    fn test_lengths() {
+        let norms = FieldNorms::new(Norms::Short, &[3, 0, 100000]).unwrap();
+
+        assert_eq!(norms.length(0), 3.0);
+        assert_eq!(norms.length(1), 1.0);
+        assert_eq!(norms.length(2), 65535.0);
+
+        // Documents past the end don't have the field
+        assert_eq!(norms.length(3), 1.0);
+        assert_eq!(norms.token_count(3), 0);
+    }
+
+    #[test]
+    fn test_byte_lengths_are_rounded() {
+        let norms = FieldNorms::new(Norms::Byte, &[1, 10, 1000]).unwrap();
+
+        assert_eq!(norms.length(0), 1.0);
+        assert!(norms.length(1) != 10.0);
+        assert_eq!(norms.length(1), norms.length_of(10));
+        assert_eq!(norms.length_of(norms.token_count(2)), norms.length(2));
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert_eq!(FieldNorms::new(Norms::Disabled, &[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn test_to_from_bytes() {
+        for norms in vec![Norms::Byte, Norms::Short, Norms::Int] {
+            let field_norms = FieldNorms::new(norms, &[5, 0, 12, 70000]).unwrap();
+
+            assert_eq!(FieldNorms::from_bytes(&field_norms.to_bytes()), Ok(field_norms));
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_invalid() {
+        assert_eq!(FieldNorms::from_bytes(&[]), Err(FieldNormsReadError::UnexpectedEnd));
+        assert_eq!(FieldNorms::from_bytes(&[0]), Err(FieldNormsReadError::InvalidWidth(0)));
+        assert_eq!(FieldNorms::from_bytes(&[3, 0, 0, 0]), Err(FieldNormsReadError::InvalidWidth(3)));
+    }
+
+    #[test]
+    fn test_stored_token_count() {
+        let norms = FieldNorms::new(Norms::Byte, &[]).unwrap();
+        assert!(norms.stored_token_count(10) <= 10);
+        assert_eq!(norms.stored_token_count(10), FieldNorms::new(Norms::Byte, &[10]).unwrap().token_count(0));
+
+        let norms = FieldNorms::new(Norms::Short, &[]).unwrap();
+        assert_eq!(norms.stored_token_count(10), 10);
+        assert_eq!(norms.stored_token_count(100000), 65535);
+    }
+}
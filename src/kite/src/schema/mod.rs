@@ -63,11 +63,39 @@ pub enum FieldType {
 }
 
 
+/// How the length of an indexed field is stored in each document for length
+/// normalisation (known as "norms")
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Norms {
+    /// Lengths aren't stored, every document is scored as if the field had the average length
+    Disabled,
+
+    /// One byte per document. Lengths are rounded, more so the longer the field is
+    Byte,
+
+    /// Two bytes per document. Lengths are exact up to 65535 tokens
+    Short,
+
+    /// Four bytes per document. Lengths are always exact
+    Int,
+}
+
+
+impl Default for Norms {
+    fn default() -> Norms {
+        Norms::Short
+    }
+}
+
+
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct FieldInfo {
     name: String,
     pub field_type: FieldType,
     pub field_flags: FieldFlags,
+
+    /// Fields that were added before norms were configurable don't have this set
+    norms: Option<Norms>,
 }
 
 
@@ -77,8 +105,17 @@ impl FieldInfo {
             name: name,
             field_type: field_type,
             field_flags: field_flags,
+            norms: Some(Norms::default()),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn norms(&self) -> Norms {
+        self.norms.unwrap_or(Norms::default())
+    }
 }
 
 
@@ -154,6 +191,20 @@ impl Schema {
         Ok(field_ref)
     }
 
+    /// Changes how the lengths of a field are stored
+    ///
+    /// This only applies to documents that are indexed from now on. Segments are
+    /// converted when they are merged.
+    pub fn set_field_norms(&mut self, field_ref: &FieldRef, norms: Norms) -> bool {
+        match self.fields.get_mut(field_ref) {
+            Some(field_info) => {
+                field_info.norms = Some(norms);
+                true
+            }
+            None => false
+        }
+    }
+
     pub fn remove_field(&mut self, field_ref: &FieldRef) -> bool {
         match self.fields.remove(field_ref) {
             Some(removed_field) => {
@@ -1,17 +1,32 @@
 use schema::FieldRef;
 use term::TermRef;
 use doc_id_set::DocIdSet;
+use norms::FieldNorms;
 use document::DocRef;
 
 
 pub trait Segment {
     fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String>;
-    fn load_stored_field_value_raw(&self, doc_ord: u16, field_ref: FieldRef, value_type: &[u8]) -> Result<Option<Vec<u8>>, String>;
+    fn load_stored_field_value_raw(&self, doc_ord: u32, field_ref: FieldRef, value_type: &[u8]) -> Result<Option<Vec<u8>>, String>;
     fn load_term_directory(&self, field_ref: FieldRef, term_ref: TermRef) -> Result<Option<DocIdSet>, String>;
+    /// Loads the frequencies of a term in the documents of its term directory
+    ///
+    /// Returns (doc id, frequency) pairs sorted by doc id. Documents in the term
+    /// directory that aren't listed have a frequency of 1.
+    fn load_term_frequencies(&self, field_ref: FieldRef, term_ref: TermRef) -> Result<Option<Vec<(u32, u32)>>, String>;
+    /// Loads the maximum frequency of a term and the minimum length of the field in
+    /// the documents of its term directory
+    ///
+    /// These are used to find the highest score a document could get for the term.
+    fn load_term_score_bound(&self, field_ref: FieldRef, term_ref: TermRef) -> Result<Option<(u32, u32)>, String>;
+    /// Loads the lengths of a field in every document of the segment
+    ///
+    /// Returns None if the field isn't in the segment or norms were disabled for it.
+    fn load_field_norms(&self, field_ref: FieldRef) -> Result<Option<FieldNorms>, String>;
     fn load_deletion_list(&self) -> Result<Option<DocIdSet>, String>;
     fn id(&self) -> u32;
 
-    fn doc_ref(&self, ord: u16) -> DocRef {
+    fn doc_ref(&self, ord: u32) -> DocRef {
         DocRef::from_segment_ord(self.id(), ord)
     }
 }
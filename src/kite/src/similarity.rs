@@ -19,6 +19,15 @@ fn idf(term_docs: u64, total_docs: u64) -> f64 {
 }
 
 
+/// The average length of a field, as used for length normalisation
+///
+/// Fields that don't store their lengths are scored as if they had this length.
+#[inline]
+pub fn average_length(total_tokens: u64, total_docs: u64) -> f64 {
+    (total_tokens as f64 + 1.0f64) / (total_docs as f64 + 1.0f64)
+}
+
+
 impl SimilarityModel {
     pub fn score(&self, term_frequency: u32, length: f64, total_tokens: u64, total_docs: u64, total_docs_with_term: u64) -> f64 {
         match *self {
@@ -31,7 +40,7 @@ impl SimilarityModel {
             SimilarityModel::Bm25{k1, b} => {
                 let tf = tf(term_frequency);
                 let idf = idf(total_docs_with_term, total_docs);
-                let average_length = (total_tokens as f64 + 1.0f64) / (total_docs as f64 + 1.0f64);
+                let average_length = average_length(total_tokens, total_docs);
 
                 idf * (k1 + 1.0) * (tf / (tf + (k1 * ((1.0 - b) + b * length.sqrt() / average_length.sqrt())) + 1.0f64))
             }
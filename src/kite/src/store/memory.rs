@@ -0,0 +1,421 @@
+//! A store that keeps the whole index in memory
+//!
+//! This is intended for tests and benchmarks. Searches are run by checking every
+//! document against the query so it doesn't scale to large indices.
+
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+use term::Term;
+use schema::{Schema, FieldType, FieldFlags, FieldRef, AddFieldError, Norms};
+use similarity::average_length;
+use document::{Document, DocRef, FieldValue};
+use query::Query;
+use query::term_scorer::TermScorer;
+use collectors::{Collector, DocumentMatch};
+use store::{IndexStore, IndexReader};
+
+
+#[derive(Debug, Clone)]
+struct MemoryIndex {
+    schema: Schema,
+    next_doc_id: u64,
+    docs: BTreeMap<u64, Document>,
+    doc_keys: HashMap<String, u64>,
+}
+
+
+#[derive(Debug)]
+pub struct MemoryIndexStore {
+    // Readers hold a reference to the index as it was when they were created. Writers
+    // copy the index if a reader is still using it
+    index: RwLock<Arc<MemoryIndex>>,
+}
+
+
+impl MemoryIndexStore {
+    pub fn new() -> MemoryIndexStore {
+        MemoryIndexStore {
+            index: RwLock::new(Arc::new(MemoryIndex {
+                schema: Schema::new(),
+                next_doc_id: 0,
+                docs: BTreeMap::new(),
+                doc_keys: HashMap::new(),
+            })),
+        }
+    }
+}
+
+
+impl<'a> IndexStore<'a> for MemoryIndexStore {
+    type Reader = MemoryIndexStoreReader<'a>;
+
+    fn reader(&'a self) -> MemoryIndexStoreReader<'a> {
+        MemoryIndexStoreReader {
+            index: self.index.read().unwrap().clone(),
+            store: PhantomData,
+        }
+    }
+
+    fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldRef, AddFieldError> {
+        let index = Arc::make_mut(self.index.get_mut().unwrap());
+        index.schema.add_field(name, field_type, field_flags)
+    }
+
+    fn remove_field(&mut self, field_ref: &FieldRef) -> bool {
+        let index = Arc::make_mut(self.index.get_mut().unwrap());
+        index.schema.remove_field(field_ref)
+    }
+
+    fn insert_or_update_document(&self, doc: &Document) -> Result<(), String> {
+        let mut index = self.index.write().unwrap();
+        let index = Arc::make_mut(&mut *index);
+
+        if let Some(old_doc_id) = index.doc_keys.remove(&doc.key) {
+            index.docs.remove(&old_doc_id);
+        }
+
+        let doc_id = index.next_doc_id;
+        index.next_doc_id += 1;
+        index.docs.insert(doc_id, doc.clone());
+        index.doc_keys.insert(doc.key.clone(), doc_id);
+
+        Ok(())
+    }
+
+    fn remove_document_by_key(&self, doc_key: &str) -> Result<bool, String> {
+        let mut index = self.index.write().unwrap();
+        let index = Arc::make_mut(&mut *index);
+
+        match index.doc_keys.remove(doc_key) {
+            Some(doc_id) => {
+                index.docs.remove(&doc_id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+
+pub struct MemoryIndexStoreReader<'a> {
+    index: Arc<MemoryIndex>,
+    store: PhantomData<&'a MemoryIndexStore>,
+}
+
+
+/// Index-wide statistics used for scoring, these are calculated as they are needed
+#[derive(Default)]
+struct MemoryStatistics {
+    total_docs: HashMap<FieldRef, u64>,
+    total_tokens: HashMap<FieldRef, u64>,
+    term_document_frequencies: HashMap<(FieldRef, Term), u64>,
+}
+
+
+impl<'a> MemoryIndexStoreReader<'a> {
+    fn total_docs(&self, stats: &mut MemoryStatistics, field_ref: FieldRef) -> u64 {
+        let index = &self.index;
+        *stats.total_docs.entry(field_ref).or_insert_with(|| {
+            index.docs.values().filter(|doc| doc.indexed_fields.contains_key(&field_ref)).count() as u64
+        })
+    }
+
+    fn total_tokens(&self, stats: &mut MemoryStatistics, field_ref: FieldRef) -> u64 {
+        let index = &self.index;
+        *stats.total_tokens.entry(field_ref).or_insert_with(|| {
+            index.docs.values().filter_map(|doc| doc.indexed_fields.get(&field_ref)).map(|tokens| tokens.len() as u64).sum()
+        })
+    }
+
+    fn term_document_frequency(&self, stats: &mut MemoryStatistics, field_ref: FieldRef, term: &Term) -> u64 {
+        let index = &self.index;
+        *stats.term_document_frequencies.entry((field_ref, term.clone())).or_insert_with(|| {
+            index.docs.values().filter(|doc| term_frequency(doc, field_ref, term) > 0).count() as u64
+        })
+    }
+
+    fn score_term(&self, stats: &mut MemoryStatistics, doc: &Document, field_ref: FieldRef, term: &Term, scorer: &TermScorer) -> f64 {
+        let term_frequency = term_frequency(doc, field_ref, term);
+        if term_frequency == 0 {
+            return 0.0f64;
+        }
+
+        let total_tokens = self.total_tokens(stats, field_ref);
+        let total_docs = self.total_docs(stats, field_ref);
+        let term_docs = self.term_document_frequency(stats, field_ref, term);
+
+        // Lengths are always exact, unless norms are disabled for the field
+        let length = match self.index.schema.get(&field_ref).map(|field_info| field_info.norms()) {
+            Some(Norms::Disabled) => average_length(total_tokens, total_docs),
+            _ => doc.indexed_fields.get(&field_ref).map(|tokens| tokens.len()).unwrap_or(1) as f64,
+        };
+
+        scorer.similarity_model.score(term_frequency, length, total_tokens, total_docs, term_docs) * scorer.boost
+    }
+
+    /// Scores a document that is known to match the query
+    ///
+    /// This combines scores in the same way as the RocksDB store
+    fn score_doc(&self, stats: &mut MemoryStatistics, doc: &Document, query: &Query) -> f64 {
+        match *query {
+            Query::All{score} => score,
+            Query::None => 0.0f64,
+            Query::Term{field, ref term, ref scorer} => {
+                self.score_term(stats, doc, field, term, scorer)
+            }
+            Query::MultiTerm{field, ref term_selector, ref scorer} => {
+                // Find all terms in the index that match the selector
+                let mut terms = Vec::new();
+                for other_doc in self.index.docs.values() {
+                    if let Some(tokens) = other_doc.indexed_fields.get(&field) {
+                        for token in tokens.iter() {
+                            if term_selector.matches(&token.term) && !terms.contains(&token.term) {
+                                terms.push(token.term.clone());
+                            }
+                        }
+                    }
+                }
+
+                if terms.is_empty() {
+                    return 0.0f64;
+                }
+
+                let mut total_score = 0.0f64;
+                for term in terms.iter() {
+                    total_score += self.score_term(stats, doc, field, term, scorer);
+                }
+
+                total_score / terms.len() as f64
+            }
+            Query::Conjunction{ref queries} | Query::Disjunction{ref queries} => {
+                if queries.is_empty() {
+                    return 0.0f64;
+                }
+
+                let mut total_score = 0.0f64;
+                for query in queries.iter() {
+                    total_score += self.score_doc(stats, doc, query);
+                }
+
+                total_score / queries.len() as f64
+            }
+            Query::DisjunctionMax{ref queries} => {
+                let mut max_score = 0.0f64;
+                for query in queries.iter() {
+                    let score = self.score_doc(stats, doc, query);
+                    if score > max_score {
+                        max_score = score;
+                    }
+                }
+
+                max_score
+            }
+            Query::Filter{ref query, ..} => self.score_doc(stats, doc, query),
+            Query::Exclude{ref query, ..} => self.score_doc(stats, doc, query),
+        }
+    }
+}
+
+
+fn term_frequency(doc: &Document, field_ref: FieldRef, term: &Term) -> u32 {
+    match doc.indexed_fields.get(&field_ref) {
+        Some(tokens) => tokens.iter().filter(|token| token.term == *term).count() as u32,
+        None => 0,
+    }
+}
+
+
+fn doc_matches(doc: &Document, query: &Query) -> bool {
+    match *query {
+        Query::All{..} => true,
+        Query::None => false,
+        Query::Term{field, ref term, ..} => term_frequency(doc, field, term) > 0,
+        Query::MultiTerm{field, ref term_selector, ..} => {
+            match doc.indexed_fields.get(&field) {
+                Some(tokens) => tokens.iter().any(|token| term_selector.matches(&token.term)),
+                None => false,
+            }
+        }
+        Query::Conjunction{ref queries} => queries.iter().all(|query| doc_matches(doc, query)),
+        Query::Disjunction{ref queries} => queries.iter().any(|query| doc_matches(doc, query)),
+        Query::DisjunctionMax{ref queries} => queries.iter().any(|query| doc_matches(doc, query)),
+        Query::Filter{ref query, ref filter} => doc_matches(doc, query) && doc_matches(doc, filter),
+        Query::Exclude{ref query, ref exclude} => doc_matches(doc, query) && !doc_matches(doc, exclude),
+    }
+}
+
+
+impl<'a> IndexReader<'a> for MemoryIndexStoreReader<'a> {
+    fn schema(&self) -> &Schema {
+        &self.index.schema
+    }
+
+    fn contains_document_key(&self, doc_key: &str) -> bool {
+        self.index.doc_keys.contains_key(doc_key)
+    }
+
+    fn read_stored_field(&self, field_ref: FieldRef, doc_ref: DocRef) -> Result<Option<FieldValue>, String> {
+        if !self.index.schema.contains_key(&field_ref) {
+            return Err(format!("invalid field ref: {:?}", field_ref));
+        }
+
+        match self.index.docs.get(&doc_ref.as_u64()) {
+            Some(doc) => Ok(doc.stored_fields.get(&field_ref).cloned()),
+            None => Ok(None),
+        }
+    }
+
+    fn search<C: Collector>(&self, collector: &mut C, query: &Query) -> Result<(), String> {
+        let mut stats = MemoryStatistics::default();
+
+        for (doc_id, doc) in self.index.docs.iter() {
+            if !doc_matches(doc, query) {
+                continue;
+            }
+
+            if collector.needs_score() {
+                let score = self.score_doc(&mut stats, doc, query);
+                collector.collect(DocumentMatch::new_scored(*doc_id, score));
+            } else {
+                collector.collect(DocumentMatch::new_unscored(*doc_id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use term::Term;
+    use token::Token;
+    use schema::{FieldType, FieldRef, FIELD_INDEXED, FIELD_STORED};
+    use document::{Document, DocRef, FieldValue};
+    use query::Query;
+    use query::term_scorer::TermScorer;
+    use collectors::top_score::TopScoreCollector;
+    use collectors::total_count::TotalCountCollector;
+    use store::{IndexStore, IndexReader};
+    use super::MemoryIndexStore;
+
+    fn make_test_store() -> (MemoryIndexStore, FieldRef, FieldRef) {
+        let mut store = MemoryIndexStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let docs = vec![("test_doc", "hello world", 1), ("another_test_doc", "howdy partner", 2)];
+        for (key, title, pk) in docs {
+            let tokens = title.split(' ').enumerate().map(|(position, word)| {
+                Token {
+                    term: Term::from_string(word),
+                    position: position as u32 + 1,
+                }
+            }).collect();
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => tokens,
+                },
+                stored_fields: hashmap! {
+                    pk_field => FieldValue::Integer(pk),
+                },
+            }).unwrap();
+        }
+
+        (store, title_field, pk_field)
+    }
+
+    fn search_pks(store: &MemoryIndexStore, pk_field: FieldRef, query: &Query) -> Vec<i64> {
+        let reader = store.reader();
+        let mut collector = TopScoreCollector::new(10);
+        reader.search(&mut collector, query).unwrap();
+
+        collector.into_sorted_vec().iter().map(|doc| {
+            match reader.read_stored_field(pk_field, DocRef::from_u64(doc.doc_id())).unwrap() {
+                Some(FieldValue::Integer(pk)) => pk,
+                _ => panic!("document has no pk"),
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_search_term() {
+        let (store, title_field, pk_field) = make_test_store();
+
+        let pks = search_pks(&store, pk_field, &Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        });
+
+        assert_eq!(pks, vec![1]);
+    }
+
+    #[test]
+    fn test_search_disjunction() {
+        let (store, title_field, pk_field) = make_test_store();
+
+        let mut pks = search_pks(&store, pk_field, &Query::new_disjunction(vec![
+            Query::Term {
+                field: title_field,
+                term: Term::from_string("hello"),
+                scorer: TermScorer::default(),
+            },
+            Query::Term {
+                field: title_field,
+                term: Term::from_string("howdy"),
+                scorer: TermScorer::default(),
+            },
+        ]));
+        pks.sort();
+
+        assert_eq!(pks, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_update_document() {
+        let (store, title_field, _pk_field) = make_test_store();
+        let reader_before = store.reader();
+
+        store.insert_or_update_document(&Document {
+            key: "test_doc".to_string(),
+            indexed_fields: hashmap! {
+                title_field => vec![Token { term: Term::from_string("goodbye"), position: 1 }],
+            },
+            stored_fields: hashmap! {},
+        }).unwrap();
+
+        let query = Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        };
+
+        // Readers created before the update shouldn't see it
+        let mut collector = TotalCountCollector::new();
+        reader_before.search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 0);
+        assert!(store.reader().contains_document_key("test_doc"));
+    }
+
+    #[test]
+    fn test_remove_document() {
+        let (store, _title_field, _pk_field) = make_test_store();
+
+        assert_eq!(store.remove_document_by_key("test_doc"), Ok(true));
+        assert_eq!(store.remove_document_by_key("test_doc"), Ok(false));
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::new_all()).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+        assert!(!store.reader().contains_document_key("test_doc"));
+    }
+}
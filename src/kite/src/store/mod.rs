@@ -0,0 +1,45 @@
+//! Backend-agnostic traits for storing and searching an index
+//!
+//! These cover what the search engine itself needs: managing fields, inserting and
+//! deleting documents, reading stored fields and searching. `memory::MemoryIndexStore`
+//! implements them without touching the disk, so the engine can be tested quickly.
+//!
+//! The server only runs on the RocksDB store (`kite_rocksdb`). It relies on features
+//! that are outside of these traits, such as document versions, snapshots, segment
+//! merging and the write buffer, so in-memory indices aren't available there.
+
+pub mod memory;
+
+use schema::{Schema, FieldType, FieldFlags, FieldRef, AddFieldError};
+use document::{Document, DocRef, FieldValue};
+use query::Query;
+use collectors::Collector;
+
+
+/// A storage backend for an index
+///
+/// Errors from the backend are returned as strings as each backend has its own
+/// error types.
+pub trait IndexStore<'a> {
+    type Reader: IndexReader<'a>;
+
+    /// Returns a reader that sees a consistent view of the index
+    fn reader(&'a self) -> Self::Reader;
+
+    fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldRef, AddFieldError>;
+    fn remove_field(&mut self, field_ref: &FieldRef) -> bool;
+
+    /// Inserts a document, replacing any document that has the same key
+    fn insert_or_update_document(&self, doc: &Document) -> Result<(), String>;
+
+    /// Deletes the document with the given key. Returns false if it didn't exist
+    fn remove_document_by_key(&self, doc_key: &str) -> Result<bool, String>;
+}
+
+
+pub trait IndexReader<'a> {
+    fn schema(&self) -> &Schema;
+    fn contains_document_key(&self, doc_key: &str) -> bool;
+    fn read_stored_field(&self, field_ref: FieldRef, doc_ref: DocRef) -> Result<Option<FieldValue>, String>;
+    fn search<C: Collector>(&self, collector: &mut C, query: &Query) -> Result<(), String>;
+}
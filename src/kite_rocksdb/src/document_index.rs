@@ -1,31 +1,148 @@
-use std::sync::RwLock;
-use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use rocksdb::{self, DB, WriteBatch};
 use kite::doc_id_set::DocIdSet;
 use kite::document::DocRef;
-use byteorder::{ByteOrder, BigEndian, WriteBytesExt};
+use byteorder::{ByteOrder, BigEndian};
 
 use key_builder::KeyBuilder;
 use segment_ops::SegmentMergeError;
 
 
+/// Identifies a particular write of a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DocumentVersion {
+    /// Incremented every time the document with this key is replaced
+    pub version: u64,
+
+    /// Position of the write in the sequence of all writes to the index
+    pub seq_no: u64,
+}
+
+
+impl DocumentVersion {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        BigEndian::write_u64(&mut bytes[0..8], self.version);
+        BigEndian::write_u64(&mut bytes[8..16], self.seq_no);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> DocumentVersion {
+        DocumentVersion {
+            version: BigEndian::read_u64(&bytes[0..8]),
+            seq_no: BigEndian::read_u64(&bytes[8..16]),
+        }
+    }
+}
+
+
+/// An entry in the primary key index
+#[derive(Debug, Clone, Copy)]
+struct PrimaryKeyEntry {
+    doc_ref: DocRef,
+    version: DocumentVersion,
+}
+
+
+impl PrimaryKeyEntry {
+    fn to_bytes(&self) -> [u8; 24] {
+        let mut bytes = [0; 24];
+        BigEndian::write_u32(&mut bytes[0..4], self.doc_ref.segment());
+        BigEndian::write_u32(&mut bytes[4..8], self.doc_ref.ord());
+        BigEndian::write_u64(&mut bytes[8..16], self.version.version);
+        BigEndian::write_u64(&mut bytes[16..24], self.version.seq_no);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> PrimaryKeyEntry {
+        let segment = BigEndian::read_u32(&bytes[0..4]);
+
+        // Older entries are recognised by their length:
+        //  - 6 bytes: two byte document ordinal, written before versioning was added
+        //  - 22 bytes: two byte document ordinal with a version
+        //  - 24 bytes: four byte document ordinal with a version
+        let (ord, version) = match bytes.len() {
+            6 => {
+                (BigEndian::read_u16(&bytes[4..6]) as u32, DocumentVersion {
+                    version: 1,
+                    seq_no: 0,
+                })
+            }
+            22 => {
+                (BigEndian::read_u16(&bytes[4..6]) as u32, DocumentVersion {
+                    version: BigEndian::read_u64(&bytes[6..14]),
+                    seq_no: BigEndian::read_u64(&bytes[14..22]),
+                })
+            }
+            _ => {
+                (BigEndian::read_u32(&bytes[4..8]), DocumentVersion {
+                    version: BigEndian::read_u64(&bytes[8..16]),
+                    seq_no: BigEndian::read_u64(&bytes[16..24]),
+                })
+            }
+        };
+
+        PrimaryKeyEntry {
+            doc_ref: DocRef::from_segment_ord(segment, ord),
+            version: version,
+        }
+    }
+}
+
+
+/// Parses a value from the primary key index into the document it points to and its version
+pub fn parse_primary_key_entry(bytes: &[u8]) -> (DocRef, DocumentVersion) {
+    let entry = PrimaryKeyEntry::from_bytes(bytes);
+    (entry.doc_ref, entry.version)
+}
+
+
+/// The number of sequence numbers that are reserved on disk at a time
+///
+/// Only the end of the reserved block is persisted, so sequence numbers are never
+/// reused after a restart, though up to this many may be skipped.
+const SEQ_NO_BLOCK_SIZE: u64 = 1000;
+
+
+/// Hands out sequence numbers from a block that has been reserved on disk
+#[derive(Debug)]
+struct SeqNoAllocator {
+    next_seq_no: u64,
+    reserved_until: u64,
+}
+
+
 /// Manages the index's "document index"
+///
+/// The version of a deleted document is kept in `deleted_versions` so that, if the
+/// document is created again, it carries on from that version rather than starting
+/// from 1. When both locks are needed, `primary_key_index` must be locked first.
 pub struct DocumentIndexManager {
-    primary_key_index: RwLock<BTreeMap<Vec<u8>, DocRef>>,
+    primary_key_index: RwLock<BTreeMap<Vec<u8>, PrimaryKeyEntry>>,
+    deleted_versions: RwLock<BTreeMap<Vec<u8>, DocumentVersion>>,
+    seq_no_allocator: Mutex<SeqNoAllocator>,
 }
 
 
 impl DocumentIndexManager {
     /// Generates a new document index
-    pub fn new(_db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
+    pub fn new(db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
+        try!(db.put(b".next_seq_no", b"0"));
+
         Ok(DocumentIndexManager {
             primary_key_index: RwLock::new(BTreeMap::new()),
+            deleted_versions: RwLock::new(BTreeMap::new()),
+            seq_no_allocator: Mutex::new(SeqNoAllocator {
+                next_seq_no: 0,
+                reserved_until: 0,
+            }),
         })
     }
 
     /// Loads the document index from an index
-    pub fn open(db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
+    pub fn open(db: &DB) -> Result<DocumentIndexManager, String> {
         // Read primary key index
         let mut primary_key_index = BTreeMap::new();
         let mut iter = db.iterator();
@@ -38,23 +155,85 @@ impl DocumentIndexManager {
             }
 
             let v = iter.value().unwrap();
-            let segment = BigEndian::read_u32(&v[0..4]);
-            let ord = BigEndian::read_u16(&v[4..6]);
-            let doc_ref = DocRef::from_segment_ord(segment, ord);
+            primary_key_index.insert(k[1..].to_vec(), PrimaryKeyEntry::from_bytes(&v));
+        }
+
+        // Read versions of deleted documents
+        let mut deleted_versions = BTreeMap::new();
+        let mut iter = db.iterator();
+        iter.seek(b"r");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'r' {
+                break;
+            }
 
-            primary_key_index.insert(k[1..].to_vec(), doc_ref);
+            let v = iter.value().unwrap();
+            deleted_versions.insert(k[1..].to_vec(), DocumentVersion::from_bytes(&v));
         }
 
+        // Indices created before versioning was added don't have a sequence number counter
+        // Everything below the stored value may have been handed out before the index was
+        // closed, so allocation carries on from there
+        let next_seq_no = match try!(db.get(b".next_seq_no")) {
+            Some(next_seq_no) => {
+                match next_seq_no.to_utf8().and_then(|next_seq_no| next_seq_no.parse::<u64>().ok()) {
+                    Some(next_seq_no) => next_seq_no,
+                    None => return Err("unable to parse next sequence number".to_string()),
+                }
+            }
+            None => 0,
+        };
+
         Ok(DocumentIndexManager {
             primary_key_index: RwLock::new(primary_key_index),
+            deleted_versions: RwLock::new(deleted_versions),
+            seq_no_allocator: Mutex::new(SeqNoAllocator {
+                next_seq_no: next_seq_no,
+                reserved_until: next_seq_no,
+            }),
+        })
+    }
+
+    /// Allocates a sequence number for a write
+    ///
+    /// Sequence numbers are reserved on disk in blocks, so most allocations don't need
+    /// to write anything. The lock makes sure the persisted value only ever goes up.
+    pub fn allocate_seq_no(&self, db: &DB) -> Result<u64, rocksdb::Error> {
+        let mut allocator = self.seq_no_allocator.lock().unwrap();
+
+        if allocator.next_seq_no >= allocator.reserved_until {
+            let reserved_until = allocator.next_seq_no + SEQ_NO_BLOCK_SIZE;
+            try!(db.put(b".next_seq_no", reserved_until.to_string().as_bytes()));
+            allocator.reserved_until = reserved_until;
+        }
+
+        let seq_no = allocator.next_seq_no;
+        allocator.next_seq_no += 1;
+        Ok(seq_no)
+    }
+
+    /// Finds the latest version of a key, including versions of deleted documents
+    fn last_version(&self, primary_key_index: &BTreeMap<Vec<u8>, PrimaryKeyEntry>, deleted_versions: &BTreeMap<Vec<u8>, DocumentVersion>, key: &Vec<u8>) -> Option<DocumentVersion> {
+        match primary_key_index.get(key) {
+            Some(entry) => Some(entry.version),
+            None => deleted_versions.get(key).cloned(),
+        }
+    }
+
+    /// Allocates the version that follows the latest version of a key
+    fn next_version(&self, db: &DB, last_version: Option<DocumentVersion>) -> Result<DocumentVersion, rocksdb::Error> {
+        Ok(DocumentVersion {
+            version: last_version.map(|version| version.version + 1).unwrap_or(1),
+            seq_no: try!(self.allocate_seq_no(db)),
         })
     }
 
     fn delete_document_by_ref_unchecked(&self, write_batch: &mut WriteBatch, doc_ref: DocRef) -> Result<(), rocksdb::Error> {
         let kb = KeyBuilder::segment_del_list(doc_ref.segment());
-        let mut previous_doc_id_bytes = [0; 2];
-        BigEndian::write_u16(&mut previous_doc_id_bytes, doc_ref.ord());
-        try!(write_batch.merge(&kb.key(), &previous_doc_id_bytes));
+        let doc_id_set = vec![doc_ref.ord()].into_iter().collect::<DocIdSet>();
+        try!(write_batch.merge(&kb.key(), &doc_id_set.to_bytes()));
 
         // Increment deleted docs
         let kb = KeyBuilder::segment_stat(doc_ref.segment(), b"deleted_docs");
@@ -65,70 +244,177 @@ impl DocumentIndexManager {
         Ok(())
     }
 
-    pub fn insert_or_replace_key(&self, db: &DB, key: &Vec<u8>, doc_ref: DocRef) -> Result<Option<DocRef>, rocksdb::Error> {
+    /// Points a key at a new document, deleting the document it previously pointed to
+    ///
+    /// If `version` is `None`, the version is incremented from the previous document's
+    /// version (even if that document was deleted) and a new sequence number is allocated.
+    pub fn insert_or_replace_key(&self, db: &DB, key: &Vec<u8>, doc_ref: DocRef, version: Option<DocumentVersion>) -> Result<DocumentVersion, rocksdb::Error> {
+        let mut primary_key_index = self.primary_key_index.write().unwrap();
+        let mut deleted_versions = self.deleted_versions.write().unwrap();
+        let previous_entry = primary_key_index.get(key).cloned();
+
+        let version = match version {
+            Some(version) => version,
+            None => {
+                let last_version = self.last_version(&primary_key_index, &deleted_versions, key);
+                try!(self.next_version(db, last_version))
+            }
+        };
+
+        let entry = PrimaryKeyEntry {
+            doc_ref: doc_ref,
+            version: version,
+        };
+
         // Update primary_key_index
         let mut write_batch = WriteBatch::default();
-        let previous_doc_ref = self.primary_key_index.write().unwrap().insert(key.clone(), doc_ref);
-
         let kb = KeyBuilder::primary_key_index(key);
-        let mut doc_ref_bytes = [0; 6];
-        BigEndian::write_u32(&mut doc_ref_bytes, doc_ref.segment());
-        BigEndian::write_u16(&mut doc_ref_bytes[4..], doc_ref.ord());
-        try!(write_batch.put(&kb.key(), &doc_ref_bytes));
+        try!(write_batch.put(&kb.key(), &entry.to_bytes()));
 
         // If there was a document there previously, delete it
-        if let Some(previous_doc_ref) = previous_doc_ref {
-            try!(self.delete_document_by_ref_unchecked(&mut write_batch, previous_doc_ref));
+        if let Some(previous_entry) = previous_entry {
+            try!(self.delete_document_by_ref_unchecked(&mut write_batch, previous_entry.doc_ref));
+        }
+
+        // The document exists again so the deleted version is no longer needed
+        let has_deleted_version = deleted_versions.contains_key(key);
+        if has_deleted_version {
+            let kb = KeyBuilder::deleted_document_version(key);
+            try!(write_batch.delete(&kb.key()));
         }
 
         // Write document data
         try!(db.write(write_batch));
+        primary_key_index.insert(key.clone(), entry);
+
+        if has_deleted_version {
+            deleted_versions.remove(key);
+        }
 
-        Ok(previous_doc_ref)
+        Ok(version)
     }
 
+    /// Deletes the document with the given key, returns its reference if it existed
+    ///
+    /// The version of the deletion is remembered so the key carries on from it if it is
+    /// used again.
     pub fn delete_document_by_key(&self, db: &DB, key: &Vec<u8>) -> Result<Option<DocRef>, rocksdb::Error> {
-        // Remove document from index
-        let doc_ref = self.primary_key_index.write().unwrap().remove(key);
+        let mut primary_key_index = self.primary_key_index.write().unwrap();
+        let mut deleted_versions = self.deleted_versions.write().unwrap();
+
+        let entry = match primary_key_index.get(key).cloned() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let version = try!(self.next_version(db, Some(entry.version)));
+
+        let mut write_batch = WriteBatch::default();
+
+        let kb = KeyBuilder::primary_key_index(key);
+        try!(write_batch.delete(&kb.key()));
+
+        let kb = KeyBuilder::deleted_document_version(key);
+        try!(write_batch.put(&kb.key(), &version.to_bytes()));
 
-        if let Some(doc_ref) = doc_ref {
-            let mut write_batch = WriteBatch::default();
+        try!(self.delete_document_by_ref_unchecked(&mut write_batch, entry.doc_ref));
 
-            try!(self.delete_document_by_ref_unchecked(&mut write_batch, doc_ref));
+        try!(db.write(write_batch));
+        primary_key_index.remove(key);
+        deleted_versions.insert(key.clone(), version);
+
+        Ok(Some(entry.doc_ref))
+    }
+
+    /// Deletes a batch of documents in a single write batch, with versions that were
+    /// allocated by the caller
+    ///
+    /// The versions are remembered even for keys that don't exist, so a key that was
+    /// created and deleted again before reaching the store still has its version.
+    /// Returns the number of documents that were deleted.
+    pub fn delete_documents_by_key(&self, db: &DB, keys: &[(Vec<u8>, DocumentVersion)]) -> Result<usize, rocksdb::Error> {
+        let mut primary_key_index = self.primary_key_index.write().unwrap();
+        let mut deleted_versions = self.deleted_versions.write().unwrap();
+
+        let mut write_batch = WriteBatch::default();
+        let mut deleted_keys = HashSet::new();
+        let mut num_deleted = 0;
+        for &(ref key, version) in keys.iter() {
+            let kb = KeyBuilder::deleted_document_version(key);
+            try!(write_batch.put(&kb.key(), &version.to_bytes()));
+
+            // Each document must only be counted as deleted once
+            if !deleted_keys.insert(key) {
+                continue;
+            }
+
+            if let Some(entry) = primary_key_index.get(key) {
+                let kb = KeyBuilder::primary_key_index(key);
+                try!(write_batch.delete(&kb.key()));
+
+                try!(self.delete_document_by_ref_unchecked(&mut write_batch, entry.doc_ref));
+                num_deleted += 1;
+            }
+        }
 
-            try!(db.write(write_batch));
+        if keys.is_empty() {
+            return Ok(0);
         }
 
-        Ok(doc_ref)
+        try!(db.write(write_batch));
+
+        for &(ref key, version) in keys.iter() {
+            primary_key_index.remove(key);
+            deleted_versions.insert(key.clone(), version);
+        }
+
+        Ok(num_deleted)
     }
 
     pub fn contains_document_key(&self, key: &Vec<u8>) -> bool {
         self.primary_key_index.read().unwrap().contains_key(key)
     }
 
-    pub fn commit_segment_merge(&self, db: &DB, mut write_batch: WriteBatch, source_segments: &Vec<u32>, dest_segment: u32, doc_ref_mapping: &HashMap<DocRef, u16>) -> Result<(), SegmentMergeError> {
+    pub fn get_document_ref(&self, key: &Vec<u8>) -> Option<DocRef> {
+        self.primary_key_index.read().unwrap().get(key).map(|entry| entry.doc_ref)
+    }
+
+    pub fn get_document_version(&self, key: &Vec<u8>) -> Option<DocumentVersion> {
+        self.primary_key_index.read().unwrap().get(key).map(|entry| entry.version)
+    }
+
+    /// Retrieves the latest version of a key, including the version it was deleted at
+    pub fn get_last_version(&self, key: &Vec<u8>) -> Option<DocumentVersion> {
+        let primary_key_index = self.primary_key_index.read().unwrap();
+        let deleted_versions = self.deleted_versions.read().unwrap();
+        self.last_version(&primary_key_index, &deleted_versions, key)
+    }
+
+    pub fn commit_segment_merge(&self, db: &DB, mut write_batch: WriteBatch, source_segments: &Vec<u32>, dest_segment: u32, doc_ref_mapping: &HashMap<DocRef, u32>) -> Result<(), SegmentMergeError> {
         // Lock the primary key index
         let mut primary_key_index = self.primary_key_index.write().unwrap();
 
         // Update primary keys to point to their new locations
-        let mut keys_to_update: HashMap<Vec<u8>, DocRef> = HashMap::with_capacity(doc_ref_mapping.len());
-        for (key, doc_ref) in primary_key_index.iter() {
-            if doc_ref_mapping.contains_key(&doc_ref) {
-                keys_to_update.insert(key.clone(), *doc_ref);
+        let mut keys_to_update: HashMap<Vec<u8>, PrimaryKeyEntry> = HashMap::with_capacity(doc_ref_mapping.len());
+        for (key, entry) in primary_key_index.iter() {
+            if doc_ref_mapping.contains_key(&entry.doc_ref) {
+                keys_to_update.insert(key.clone(), *entry);
             }
         }
 
-        for (key, doc_ref) in keys_to_update {
-            let new_doc_ord = doc_ref_mapping.get(&doc_ref).unwrap();
-            let new_doc_ref = DocRef::from_segment_ord(dest_segment, *new_doc_ord);
+        for (key, entry) in keys_to_update {
+            let new_doc_ord = doc_ref_mapping.get(&entry.doc_ref).unwrap();
+
+            // The document has only moved so it keeps its version
+            let new_entry = PrimaryKeyEntry {
+                doc_ref: DocRef::from_segment_ord(dest_segment, *new_doc_ord),
+                version: entry.version,
+            };
 
             let kb = KeyBuilder::primary_key_index(&key);
-            let mut doc_ref_bytes = [0; 6];
-            BigEndian::write_u32(&mut doc_ref_bytes, new_doc_ref.segment());
-            BigEndian::write_u16(&mut doc_ref_bytes[4..], new_doc_ref.ord());
-            try!(write_batch.put(&kb.key(), &doc_ref_bytes));
+            try!(write_batch.put(&kb.key(), &new_entry.to_bytes()));
 
-            primary_key_index.insert(key, new_doc_ref);
+            primary_key_index.insert(key, new_entry);
         }
 
         // Merge deletion lists
@@ -138,19 +424,27 @@ impl DocumentIndexManager {
             let kb = KeyBuilder::segment_del_list(*source_segment);
             match try!(db.get(&kb.key())) {
                 Some(docid_set) => {
-                    let doc_id_set = DocIdSet::from_bytes(docid_set.to_vec());
+                    let doc_id_set = try!(DocIdSet::from_bytes(docid_set.to_vec()));
                     for doc_id in doc_id_set.iter() {
+                        // Documents that were deleted before the merge started were dropped
                         let doc_ref = DocRef::from_segment_ord(*source_segment, doc_id);
-                        let new_doc_id = doc_ref_mapping.get(&doc_ref).unwrap();
-                        deletion_list.write_u16::<BigEndian>(*new_doc_id).unwrap();
+                        if let Some(new_doc_id) = doc_ref_mapping.get(&doc_ref) {
+                            deletion_list.push(*new_doc_id);
+                        }
                     }
                 }
                 None => {},
             }
         }
 
+        let kb = KeyBuilder::segment_stat(dest_segment, b"deleted_docs");
+        let mut deleted_docs_bytes = [0; 8];
+        BigEndian::write_i64(&mut deleted_docs_bytes, deletion_list.len() as i64);
+        try!(write_batch.put(&kb.key(), &deleted_docs_bytes));
+
         let kb = KeyBuilder::segment_del_list(dest_segment);
-        try!(db.put(&kb.key(), &deletion_list));
+        let deletion_list = deletion_list.into_iter().collect::<DocIdSet>();
+        try!(db.put(&kb.key(), &deletion_list.to_bytes()));
 
         // Commit!
         try!(db.write_without_wal(write_batch));
@@ -0,0 +1,548 @@
+use std::str;
+use std::mem;
+use std::collections::{HashMap, BTreeMap};
+
+use rocksdb::{self, DB, WriteBatch};
+use kite::doc_id_set::DocIdSet;
+use kite::schema::Norms;
+use kite::norms::{FieldNorms, decode_length_byte};
+use byteorder::{ByteOrder, BigEndian};
+
+use key_builder::KeyBuilder;
+use segment_stats::parse_statistic_key;
+use term_frequency_list;
+
+
+/// The version of the on-disk format that this version of the store writes
+///
+/// Version history:
+///  1. Document ordinals are two bytes
+///  2. Document ordinals are four bytes
+///  3. Term directories and deletion lists are compressed (see DocIdSet::to_bytes)
+///  4. Statistics are aggregated across all active segments (see KeyBuilder::index_stat)
+///  5. Field lengths are stored in a single array per field for each segment (see FieldNorms)
+///  6. Term frequencies are only stored in term frequency lists (see term_frequency_list)
+pub const FORMAT_VERSION: u32 = 6;
+
+
+/// The format version that term directories and deletion lists were compressed in
+///
+/// Indices written in earlier versions must be opened with the merge operator that they
+/// were written with until `upgrade_doc_id_lists` has run (see `merge_keys_v2`).
+pub const COMPRESSED_DOC_ID_LISTS_FORMAT_VERSION: u32 = 3;
+
+
+/// The number of keys that each write batch of an upgrade changes
+///
+/// Upgrades are written in several batches so that large indices don't have to be
+/// rewritten in memory all at once.
+const UPGRADE_BATCH_SIZE: usize = 10000;
+
+
+/// Reads the on-disk format version of an index
+///
+/// Indices created before the format version was recorded are version 1.
+pub fn read_format_version(db: &DB) -> Result<u32, rocksdb::Error> {
+    match try!(db.get(b".format_version")) {
+        Some(format_version) => Ok(format_version.to_utf8().unwrap().parse::<u32>().unwrap()),
+        None => Ok(1),
+    }
+}
+
+
+pub fn write_format_version(db: &DB) -> Result<(), rocksdb::Error> {
+    db.put(b".format_version", FORMAT_VERSION.to_string().as_bytes())
+}
+
+
+/// Writes the changes of an upgrade step in batches of `UPGRADE_BATCH_SIZE` keys
+///
+/// The last batch of each step also bumps the format version to the version that the
+/// step upgrades to, so a step is either recorded as finished along with its last change
+/// or is run again the next time the index is opened. Steps must therefore either be safe
+/// to run again from the start or record how far they got with a resume key (see
+/// `for_each_key_after`).
+struct UpgradeWriter<'a> {
+    db: &'a DB,
+    write_batch: WriteBatch,
+    batch_len: usize,
+}
+
+
+impl<'a> UpgradeWriter<'a> {
+    fn new(db: &'a DB) -> UpgradeWriter<'a> {
+        UpgradeWriter {
+            db: db,
+            write_batch: WriteBatch::default(),
+            batch_len: 0,
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), rocksdb::Error> {
+        self.batch_len += 1;
+        self.write_batch.put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), rocksdb::Error> {
+        self.batch_len += 1;
+        self.write_batch.delete(key)
+    }
+
+    /// Writes out the current batch if it is full
+    ///
+    /// If a resume key is given, it is written in the same batch so an interrupted step
+    /// can carry on after it.
+    fn write_if_full(&mut self, resume_key: Option<&[u8]>) -> Result<(), rocksdb::Error> {
+        if self.batch_len < UPGRADE_BATCH_SIZE {
+            return Ok(());
+        }
+
+        if let Some(resume_key) = resume_key {
+            try!(self.write_batch.put(b".format_upgrade_resume_key", resume_key));
+        }
+
+        let write_batch = mem::replace(&mut self.write_batch, WriteBatch::default());
+        self.batch_len = 0;
+        self.db.write(write_batch)
+    }
+
+    /// Writes out the remaining changes, for steps that share a format version with a
+    /// later step
+    fn flush(self) -> Result<(), rocksdb::Error> {
+        self.db.write(self.write_batch)
+    }
+
+    /// Writes out the remaining changes along with the format version that the step
+    /// upgraded the index to
+    fn finish(mut self, format_version: u32) -> Result<(), rocksdb::Error> {
+        try!(self.write_batch.put(b".format_version", format_version.to_string().as_bytes()));
+        try!(self.write_batch.delete(b".format_upgrade_resume_key"));
+        self.db.write(self.write_batch)
+    }
+}
+
+
+/// Calls `f` with every key and value that starts with one of `prefixes` (which must be
+/// in ascending order)
+///
+/// Steps that can't tell which keys they have already converted pass their resume key to
+/// `UpgradeWriter::write_if_full`. If the step was interrupted, keys up to and including
+/// the resume key are skipped.
+fn for_each_key_after<F>(db: &DB, prefixes: &[u8], mut f: F) -> Result<(), rocksdb::Error>
+    where F: FnMut(&[u8], &[u8]) -> Result<(), rocksdb::Error>
+{
+    let resume_key = try!(db.get(b".format_upgrade_resume_key")).map(|resume_key| resume_key.to_vec());
+
+    for prefix in prefixes.iter() {
+        let mut iter = db.iterator();
+        iter.seek(&[*prefix]);
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != *prefix {
+                break;
+            }
+
+            if let Some(ref resume_key) = resume_key {
+                if &k[..] <= &resume_key[..] {
+                    continue;
+                }
+            }
+
+            try!(f(&k, &iter.value().unwrap()));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Upgrades the term directories and deletion lists of an index that was written before
+/// they were compressed
+///
+/// The index must be opened with the merge operator of the format that it was written in,
+/// this runs before `upgrade`.
+pub fn upgrade_doc_id_lists(db: &DB) -> Result<(), String> {
+    let format_version = try!(read_format_version(db));
+
+    if format_version < 2 {
+        try!(widen_doc_id_lists(db));
+    }
+
+    if format_version < 3 {
+        try!(compress_doc_id_lists(db));
+    }
+
+    Ok(())
+}
+
+
+/// Upgrades an index to the current format version
+///
+/// Each step records the version it upgrades to when it finishes, so an upgrade that is
+/// interrupted carries on from the step that was running.
+pub fn upgrade(db: &DB) -> Result<(), String> {
+    let format_version = try!(read_format_version(db));
+
+    if format_version > FORMAT_VERSION {
+        return Err(format!("unsupported index format version: {}", format_version));
+    }
+
+    if format_version < COMPRESSED_DOC_ID_LISTS_FORMAT_VERSION {
+        return Err(format!("term directories and deletion lists must be upgraded first, index format version: {}", format_version));
+    }
+
+    if format_version < 4 {
+        try!(rebuild_term_document_frequencies(db));
+        try!(aggregate_statistics(db));
+    }
+
+    if format_version < 5 {
+        try!(convert_field_lengths(db));
+    }
+
+    if format_version < 6 {
+        try!(convert_term_frequencies(db));
+    }
+
+    Ok(())
+}
+
+
+/// Converts term directories and deletion lists from two byte document ordinals to
+/// four byte document ordinals
+///
+/// Primary key entries don't need converting as their length tells us which format
+/// they were written in.
+fn widen_doc_id_lists(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut writer = UpgradeWriter::new(db);
+
+    try!(for_each_key_after(db, b"dx", |k, value| {
+        let mut new_value = Vec::with_capacity(value.len() * 2);
+        for doc_id in value.chunks(2) {
+            let mut doc_id_bytes = [0; 4];
+            BigEndian::write_u32(&mut doc_id_bytes, BigEndian::read_u16(doc_id) as u32);
+            new_value.extend_from_slice(&doc_id_bytes);
+        }
+
+        try!(writer.put(k, &new_value));
+        writer.write_if_full(Some(k))
+    }));
+
+    writer.finish(2)
+}
+
+
+/// Converts term directories and deletion lists from arrays of four byte document
+/// ordinals into compressed DocIdSets
+fn compress_doc_id_lists(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut writer = UpgradeWriter::new(db);
+
+    try!(for_each_key_after(db, b"dx", |k, value| {
+        let doc_id_set = value.chunks(4).map(|doc_id| BigEndian::read_u32(doc_id)).collect::<DocIdSet>();
+        try!(writer.put(k, &doc_id_set.to_bytes()));
+        writer.write_if_full(Some(k))
+    }));
+
+    writer.finish(3)
+}
+
+
+/// Rewrites the term document frequency statistics of every segment
+///
+/// These used to be keyed by the TermRefs of the segment builder, which are different
+/// to the TermRefs of the index. They are recounted from the term directories.
+///
+/// This is safe to run again if it is interrupted as the recounted statistics are deleted
+/// and then written again.
+fn rebuild_term_document_frequencies(db: &DB) -> Result<(), String> {
+    let mut writer = UpgradeWriter::new(db);
+
+    let mut iter = db.iterator();
+    iter.seek(b"s");
+    while iter.next() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b's' {
+            break;
+        }
+
+        let (_, statistic_name) = parse_statistic_key(&k);
+        if statistic_name.starts_with(b"tdf-") {
+            try!(writer.delete(&k));
+            try!(writer.write_if_full(None));
+        }
+    }
+
+    let mut iter = db.iterator();
+    iter.seek(b"d");
+    while iter.next() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'd' {
+            break;
+        }
+
+        let mut nums_iter = k[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
+        let (field, term, segment) = (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap());
+
+        let doc_id_set = try!(DocIdSet::from_bytes(iter.value().unwrap().to_vec()).map_err(|e| format!("failed to read term directory: {:?}", e)));
+        let num_docs = doc_id_set.iter().count();
+        let kb = KeyBuilder::segment_stat(segment, &KeyBuilder::segment_stat_term_doc_frequency_stat_name(field, term));
+        let mut value_bytes = [0; 8];
+        BigEndian::write_i64(&mut value_bytes, num_docs as i64);
+        try!(writer.put(&kb.key(), &value_bytes));
+        try!(writer.write_if_full(None));
+    }
+
+    Ok(try!(writer.flush()))
+}
+
+
+/// Writes the index statistics by summing the statistics of the active segments
+///
+/// This is the last step of the upgrade to version 4. It is safe to run again as the
+/// index statistics are overwritten.
+fn aggregate_statistics(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut active_segments = Vec::new();
+    let mut iter = db.iterator();
+    iter.seek(b"a");
+    while iter.next() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'a' {
+            break;
+        }
+
+        active_segments.push(str::from_utf8(&k[1..]).unwrap().parse::<u32>().unwrap());
+    }
+
+    let mut statistics: HashMap<Vec<u8>, i64> = HashMap::new();
+    for segment in active_segments {
+        let kb = KeyBuilder::segment_stat_prefix(segment);
+        let mut iter = db.iterator();
+        iter.seek(&kb.key());
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if !k.starts_with(kb.key()) {
+                break;
+            }
+
+            let (_, statistic_name) = parse_statistic_key(&k);
+            if statistic_name == b"deleted_docs" {
+                continue;
+            }
+
+            *statistics.entry(statistic_name).or_insert(0) += BigEndian::read_i64(&iter.value().unwrap());
+        }
+    }
+
+    let mut writer = UpgradeWriter::new(db);
+    for (statistic_name, value) in statistics {
+        let kb = KeyBuilder::index_stat(&statistic_name);
+        let mut value_bytes = [0; 8];
+        BigEndian::write_i64(&mut value_bytes, value);
+        try!(writer.put(&kb.key(), &value_bytes));
+        try!(writer.write_if_full(None));
+    }
+
+    writer.finish(4)
+}
+
+
+/// Converts stored value keys "v1/2/3/val" into tuples of the segment, document ordinal,
+/// field ordinal and value type (1, 2, 3, ['v', 'a', 'l'])
+fn parse_stored_value_key(key: &[u8]) -> (u32, u32, u32, &[u8]) {
+    let mut parts_iter = key[1..].split(|b| *b == b'/');
+    let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let doc_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let field = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let value_type = parts_iter.next().unwrap();
+
+    (segment, doc_id, field, value_type)
+}
+
+
+/// Moves the field lengths of each document into a FieldNorms array for each field of
+/// each segment
+///
+/// These used to be stored as a single byte stored value ("len") in each document.
+/// The bytes are kept as they are, so they are converted with `Norms::Byte`.
+///
+/// Like `convert_term_frequencies`, the norms are all written before any of the old
+/// values are deleted and are only written again if deleting hasn't recorded a resume key.
+fn convert_field_lengths(db: &DB) -> Result<(), rocksdb::Error> {
+    if try!(db.get(b".format_upgrade_resume_key")).is_none() {
+        try!(write_field_norms(db));
+    }
+
+    let mut writer = UpgradeWriter::new(db);
+
+    try!(for_each_key_after(db, b"v", |k, _| {
+        let (_, _, _, value_type) = parse_stored_value_key(k);
+        if value_type == b"len" {
+            try!(writer.delete(k));
+            try!(writer.write_if_full(Some(k)));
+        }
+
+        Ok(())
+    }));
+
+    writer.finish(5)
+}
+
+
+/// Writes the field norms of each field that has lengths stored in the documents of a
+/// segment
+///
+/// Stored values are ordered by segment, so only one segment's lengths are held in memory
+/// at a time. This is safe to run again as the norms are overwritten.
+fn write_field_norms(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut writer = UpgradeWriter::new(db);
+    let mut current_segment = None;
+    let mut field_lengths: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+
+    let mut iter = db.iterator();
+    iter.seek(b"v");
+    while iter.next() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'v' {
+            break;
+        }
+
+        let (segment, doc_id, field, value_type) = parse_stored_value_key(&k);
+
+        if value_type != b"len" {
+            continue;
+        }
+
+        if current_segment != Some(segment) {
+            if let Some(current_segment) = current_segment {
+                try!(put_field_norms(&mut writer, current_segment, &field_lengths));
+                field_lengths.clear();
+            }
+
+            current_segment = Some(segment);
+        }
+
+        // Convert the byte back into a token count that encodes to the same byte
+        let value = iter.value().unwrap();
+        let token_count = decode_length_byte(value[0]).ceil() as u32;
+
+        let lengths = field_lengths.entry(field).or_insert_with(Vec::new);
+        if lengths.len() <= doc_id as usize {
+            lengths.resize(doc_id as usize + 1, 0);
+        }
+        lengths[doc_id as usize] = token_count;
+    }
+
+    if let Some(current_segment) = current_segment {
+        try!(put_field_norms(&mut writer, current_segment, &field_lengths));
+    }
+
+    writer.flush()
+}
+
+
+fn put_field_norms(writer: &mut UpgradeWriter, segment: u32, field_lengths: &BTreeMap<u32, Vec<u32>>) -> Result<(), rocksdb::Error> {
+    for (&field, lengths) in field_lengths.iter() {
+        let field_norms = FieldNorms::new(Norms::Byte, lengths).unwrap();
+        let kb = KeyBuilder::segment_field_norms(segment, field);
+        try!(writer.put(&kb.key(), &field_norms.to_bytes()));
+        try!(writer.write_if_full(None));
+    }
+
+    Ok(())
+}
+
+
+/// Moves the term frequencies that used to be stored in each document into term
+/// frequency lists
+///
+/// These were stored values ("tf" followed by a term ordinal) for each term that occurred
+/// more than once in a field. Search read them with the ordinal of the term in the index's
+/// term dictionary, so they are moved into the list of that term. This scores every
+/// document the same as it was scored before the upgrade.
+///
+/// The lists are all written before any of the old values are deleted. Deleting records
+/// a resume key, and the lists are only written again if there isn't one, so a list is
+/// never rebuilt from values that were partly deleted.
+fn convert_term_frequencies(db: &DB) -> Result<(), rocksdb::Error> {
+    if try!(db.get(b".format_upgrade_resume_key")).is_none() {
+        try!(write_term_frequency_lists(db));
+    }
+
+    let mut writer = UpgradeWriter::new(db);
+
+    try!(for_each_key_after(db, b"v", |k, _| {
+        let (_, _, _, value_type) = parse_stored_value_key(k);
+        if value_type.starts_with(b"tf") {
+            try!(writer.delete(k));
+            try!(writer.write_if_full(Some(k)));
+        }
+
+        Ok(())
+    }));
+
+    writer.finish(6)
+}
+
+
+/// Writes a term frequency list for each term that has term frequencies stored in the
+/// documents of a segment
+///
+/// Stored values are ordered by segment, so only one segment's lists are held in memory
+/// at a time. This is safe to run again as the lists are overwritten.
+fn write_term_frequency_lists(db: &DB) -> Result<(), rocksdb::Error> {
+    let mut writer = UpgradeWriter::new(db);
+    let mut current_segment = None;
+    let mut term_frequency_lists: BTreeMap<(u32, u32), Vec<(u32, u32)>> = BTreeMap::new();
+
+    let mut iter = db.iterator();
+    iter.seek(b"v");
+    while iter.next() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'v' {
+            break;
+        }
+
+        let (segment, doc_id, field, value_type) = parse_stored_value_key(&k);
+
+        if !value_type.starts_with(b"tf") {
+            continue;
+        }
+
+        if current_segment != Some(segment) {
+            if let Some(current_segment) = current_segment {
+                try!(put_term_frequency_lists(&mut writer, current_segment, &term_frequency_lists));
+                term_frequency_lists.clear();
+            }
+
+            current_segment = Some(segment);
+        }
+
+        let term = str::from_utf8(&value_type[2..]).unwrap().parse::<u32>().unwrap();
+        let frequency = BigEndian::read_i64(&iter.value().unwrap()) as u32;
+        term_frequency_lists.entry((field, term)).or_insert_with(Vec::new).push((doc_id, frequency));
+    }
+
+    if let Some(current_segment) = current_segment {
+        try!(put_term_frequency_lists(&mut writer, current_segment, &term_frequency_lists));
+    }
+
+    writer.flush()
+}
+
+
+fn put_term_frequency_lists(writer: &mut UpgradeWriter, segment: u32, term_frequency_lists: &BTreeMap<(u32, u32), Vec<(u32, u32)>>) -> Result<(), rocksdb::Error> {
+    for (&(field, term), term_frequencies) in term_frequency_lists.iter() {
+        let kb = KeyBuilder::segment_term_frequency_list(segment, field, term);
+        try!(writer.put(&kb.key(), &term_frequency_list::encode(term_frequencies)));
+        try!(writer.write_if_full(None));
+    }
+
+    Ok(())
+}
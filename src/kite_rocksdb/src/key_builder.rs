@@ -16,7 +16,7 @@ impl KeyBuilder {
         }
     }
 
-    pub fn stored_field_value(segment: u32, doc_ord: u16, field_ord: u32, value_type: &[u8]) -> KeyBuilder {
+    pub fn stored_field_value(segment: u32, doc_ord: u32, field_ord: u32, value_type: &[u8]) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b'v');
         kb.push_string(segment.to_string().as_bytes());
@@ -44,13 +44,56 @@ impl KeyBuilder {
         kb
     }
 
-    pub fn term_dict_mapping(term: &[u8]) -> KeyBuilder {
+    /// The version that a document was deleted at
+    pub fn deleted_document_version(key: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::with_capacity(1 + key.len());
+        kb.push_char(b'r');
+        kb.push_string(key);
+        kb
+    }
+
+    pub fn term_dict_mapping(field_ord: u32, term: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::with_capacity(12 + term.len());
+        kb.push_char(b'f');
+        kb.push_string(field_ord.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(term);
+        kb
+    }
+
+    /// Term dictionary mappings from before dictionaries were scoped by field
+    pub fn legacy_term_dict_mapping(term: &[u8]) -> KeyBuilder {
         let mut kb = KeyBuilder::with_capacity(1 + term.len());
         kb.push_char(b't');
         kb.push_string(term);
         kb
     }
 
+    pub fn segment_term_dict_prefix(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'e');
+        kb.push_string(segment.to_string().as_bytes());
+        kb.separator();
+        kb
+    }
+
+    pub fn segment_term_dict_field_prefix(segment: u32, field_ord: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::segment_term_dict_prefix(segment);
+        kb.push_string(field_ord.to_string().as_bytes());
+        kb.separator();
+        kb
+    }
+
+    /// Key of a block in a segment's term dictionary
+    ///
+    /// Blocks are keyed by their last term. The term isn't escaped so the blocks
+    /// are kept in the same order as the terms.
+    pub fn segment_term_dict_block(segment: u32, field_ord: u32, last_term: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::segment_term_dict_field_prefix(segment, field_ord);
+        kb.push_raw(last_term);
+        kb
+    }
+
     pub fn segment_active(segment: u32) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b'a');
@@ -69,6 +112,49 @@ impl KeyBuilder {
         kb
     }
 
+    /// Key of the list of term frequencies for a term directory
+    ///
+    /// These are ordered the same as term directories (field/term/segment).
+    pub fn segment_term_frequency_list(segment: u32, field_ord: u32, term_ord: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'p');
+        kb.push_string(field_ord.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(term_ord.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(segment.to_string().as_bytes());
+        kb
+    }
+
+    /// Key of the score bound of a term in a segment (see `TermScoreBound`)
+    ///
+    /// These are ordered the same as term directories (field/term/segment).
+    pub fn segment_term_score_bound(segment: u32, field_ord: u32, term_ord: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'b');
+        kb.push_string(field_ord.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(term_ord.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(segment.to_string().as_bytes());
+        kb
+    }
+
+    pub fn segment_norms_prefix(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'n');
+        kb.push_string(segment.to_string().as_bytes());
+        kb.separator();
+        kb
+    }
+
+    /// Key of the lengths of a field in every document of a segment (see `FieldNorms`)
+    pub fn segment_field_norms(segment: u32, field_ord: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::segment_norms_prefix(segment);
+        kb.push_string(field_ord.to_string().as_bytes());
+        kb
+    }
+
     pub fn segment_stat_prefix(segment: u32) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b's');
@@ -134,6 +220,18 @@ impl KeyBuilder {
         stat_name
     }
 
+    /// Key of a statistic that is aggregated across all active segments
+    ///
+    /// These use the same names as segment statistics and are kept up to date as
+    /// segments are written and merged so searches don't need to sum the statistics
+    /// of every segment.
+    pub fn index_stat(name: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::with_capacity(1 + name.len());
+        kb.push_char(b'i');
+        kb.push_string(name);
+        kb
+    }
+
     pub fn segment_del_list(segment: u32) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b'x');
@@ -160,6 +258,12 @@ impl KeyBuilder {
         }
     }
 
+    /// Appends bytes without escaping them, this must only be used for the
+    /// last part of a key
+    pub fn push_raw(&mut self, s: &[u8]) {
+        self.key.extend_from_slice(s);
+    }
+
     pub fn separator(&mut self) {
         self.key.push(b'/');
     }
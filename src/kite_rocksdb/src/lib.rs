@@ -5,70 +5,86 @@ extern crate rustc_serialize;
 extern crate maplit;
 extern crate byteorder;
 extern crate chrono;
+extern crate rayon;
+#[macro_use]
+extern crate log;
 
 mod key_builder;
+mod format;
 mod segment;
 mod segment_manager;
 mod segment_ops;
 mod segment_stats;
+mod merge_policy;
 mod segment_builder;
 mod term_dictionary;
+mod segment_term_dictionary;
+mod term_frequency_list;
+mod term_score_bound;
 mod document_index;
 mod search;
 
 use std::str;
 use std::fmt;
 use std::path::Path;
+use std::ops::Deref;
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, BTreeMap};
 
 use rocksdb::{DB, WriteBatch, Options, MergeOperands, Snapshot};
-use kite::{Document, DocRef, TermRef};
+use kite::{Document, DocRef, Term, TermRef};
 use kite::document::FieldValue;
-use kite::schema::{Schema, FieldType, FieldFlags, FieldRef, AddFieldError};
+use kite::doc_id_set::DocIdSet;
+use kite::schema::{Schema, FieldType, FieldFlags, FieldRef, AddFieldError, Norms};
+use kite::norms::FieldNorms;
+use kite::query::Query;
+use kite::query::term_selector::TermSelector;
+use kite::collectors::Collector;
+use kite::store::{IndexStore, IndexReader};
 use rustc_serialize::json;
 use byteorder::{ByteOrder, BigEndian};
 use chrono::{NaiveDateTime, DateTime, UTC};
 
 use key_builder::KeyBuilder;
 use segment_manager::SegmentManager;
-use term_dictionary::TermDictionaryManager;
-use document_index::DocumentIndexManager;
+use term_dictionary::{TermDictionaryManager, parse_term_ref};
+use document_index::{DocumentIndexManager, parse_primary_key_entry};
+
+pub use search::statistics::{StatisticsReader, RocksDBStatisticsReader};
+pub use search::executor::SearchExecutor;
+pub use document_index::DocumentVersion;
+pub use segment_stats::SegmentStatistics;
+pub use merge_policy::{MergePolicy, TieredMergePolicy, NoMergePolicy};
 
 
 fn merge_keys(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Vec<u8> {
     match key[0] {
         b'd' | b'x' => {
-            // Sequence of two byte document ids
+            // Compressed set of document ids (see DocIdSet::to_bytes)
             // d = directory
             // x = deletion list
-
-            // Allocate vec for new Value
-            let new_size = match existing_val {
-                Some(existing_val) => existing_val.len(),
-                None => 0,
-            } + operands.size_hint().0 * 2;
-
-            let mut new_val = Vec::with_capacity(new_size);
-
-            // Push existing value
-            existing_val.map(|v| {
-                for b in v {
-                    new_val.push(*b);
-                }
-            });
-
-            // Append new entries
-            for op in operands {
-                for b in op {
-                    new_val.push(*b);
+            let values = existing_val.into_iter().chain(operands).collect::<Vec<_>>();
+            let mut doc_id_set = DocIdSet::new_filled(0);
+
+            for value in values.iter() {
+                match DocIdSet::from_bytes(value.to_vec()) {
+                    Ok(value_doc_id_set) => doc_id_set.union_with(&value_doc_id_set),
+                    Err(e) => {
+                        // Merge operators can't report errors. Rather than losing any
+                        // document ids, the values are kept as they are (appended together)
+                        // so the error is reported when the key is read
+                        error!("unable to merge document ids of key {:?}: {:?}", key, e);
+                        return values.concat();
+                    }
                 }
             }
 
-            new_val
+            doc_id_set.to_bytes()
         }
-        b's' => {
+        b's' | b'i' => {
             // Statistic
+            // s = segment statistic
+            // i = index statistic
             // An i64 number that can be incremented or decremented
             let mut value = match existing_val {
                 Some(existing_val) => BigEndian::read_i64(existing_val),
@@ -91,6 +107,29 @@ fn merge_keys(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOpera
 }
 
 
+/// The merge operator of indices written before format version 3
+///
+/// Term directories and deletion lists used to be arrays of document ordinals that were
+/// appended to with merge operands. Operands that haven't been compacted yet must be
+/// merged in the same way until the lists have been upgraded (see
+/// `format::upgrade_doc_id_lists`).
+fn merge_keys_v2(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Vec<u8> {
+    match key[0] {
+        b'd' | b'x' => {
+            // Sequence of document ordinals
+            let mut new_val = existing_val.map(|existing_val| existing_val.to_vec()).unwrap_or_else(Vec::new);
+
+            for op in operands {
+                new_val.extend_from_slice(op);
+            }
+
+            new_val
+        }
+        _ => merge_keys(key, existing_val, operands),
+    }
+}
+
+
 #[derive(Debug)]
 pub enum DocumentInsertError {
     /// A RocksDB error occurred
@@ -141,6 +180,9 @@ impl RocksDBIndexStore {
         };
         try!(db.put(b".schema", schema_encoded.as_bytes()));
 
+        // Format version
+        try!(format::write_format_version(&db));
+
         // Segment manager
         let segments = try!(SegmentManager::new(&db));
 
@@ -160,9 +202,29 @@ impl RocksDBIndexStore {
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<RocksDBIndexStore, String> {
+        // Term directories and deletion lists of indices written before format version 3
+        // may have merge operands that `merge_keys` can't read. These are upgraded with the
+        // merge operator they were written with before the index is opened normally. The
+        // format version is checked with compactions disabled so no merges are run with
+        // the wrong merge operator
+        let format_version = {
+            let mut opts = Options::default();
+            opts.set_merge_operator("merge operator", merge_keys);
+            opts.set_disable_auto_compactions(true);
+            let db = try!(DB::open(&opts, path.as_ref()));
+            try!(format::read_format_version(&db))
+        };
+
+        if format_version < format::COMPRESSED_DOC_ID_LISTS_FORMAT_VERSION {
+            let mut opts = Options::default();
+            opts.set_merge_operator("merge operator", merge_keys_v2);
+            let db = try!(DB::open(&opts, path.as_ref()));
+            try!(format::upgrade_doc_id_lists(&db));
+        }
+
         let mut opts = Options::default();
         opts.set_merge_operator("merge operator", merge_keys);
-        let db = try!(DB::open(&opts, path));
+        let db = try!(DB::open(&opts, path.as_ref()));
 
         let schema = match try!(db.get(b".schema")) {
             Some(schema) => {
@@ -175,6 +237,9 @@ impl RocksDBIndexStore {
             None => return Err("unable to find schema in store".into()),
         };
 
+        // Upgrade indices that were written in an older format
+        try!(format::upgrade(&db));
+
         // Segment manager
         let segments = try!(SegmentManager::open(&db));
 
@@ -208,6 +273,21 @@ impl RocksDBIndexStore {
         Ok(field_ref)
     }
 
+    /// Changes how the lengths of a field are stored (see `Norms`)
+    pub fn set_field_norms(&mut self, field_ref: &FieldRef, norms: Norms) -> bool {
+        let mut schema_copy = (*self.schema).clone();
+        let field_found = schema_copy.set_field_norms(field_ref, norms);
+
+        if field_found {
+            self.schema = Arc::new(schema_copy);
+
+            // FIXME: How do we throw this error?
+            self.db.put(b".schema", json::encode(&self.schema).unwrap().as_bytes()).unwrap();
+        }
+
+        field_found
+    }
+
     pub fn remove_field(&mut self, field_ref: &FieldRef) -> bool {
         let mut schema_copy = (*self.schema).clone();
         let field_removed = schema_copy.remove_field(field_ref);
@@ -233,7 +313,59 @@ impl RocksDBIndexStore {
 
         // Update document index
         let doc_ref = DocRef::from_segment_ord(segment, 0);
-        try!(self.document_index.insert_or_replace_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), doc_ref));
+        try!(self.document_index.insert_or_replace_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), doc_ref, None));
+
+        Ok(())
+    }
+
+    /// Inserts a batch of documents, these are written into as few segments as possible
+    pub fn insert_or_update_documents(&self, docs: &[Document]) -> Result<(), DocumentInsertError> {
+        self.insert_documents(docs.iter().map(|doc| (doc, None)))
+    }
+
+    /// Inserts a batch of documents with versions that were allocated by the caller
+    ///
+    /// Use `allocate_seq_no` to allocate the sequence numbers.
+    pub fn insert_or_update_versioned_documents(&self, docs: &[(&Document, DocumentVersion)]) -> Result<(), DocumentInsertError> {
+        self.insert_documents(docs.iter().map(|&(doc, version)| (doc, Some(version))))
+    }
+
+    fn insert_documents<'a, I: Iterator<Item=(&'a Document, Option<DocumentVersion>)>>(&self, docs: I) -> Result<(), DocumentInsertError> {
+        let mut builder = segment_builder::SegmentBuilder::new();
+        let mut doc_keys = Vec::new();
+
+        for (doc, version) in docs {
+            let doc_ord = match builder.add_document(doc) {
+                Ok(doc_ord) => doc_ord,
+                Err(segment_builder::DocumentInsertError::SegmentFull) => {
+                    // Write out the full segment and start a new one
+                    try!(self.commit_segment_builder(&builder, &doc_keys));
+                    builder = segment_builder::SegmentBuilder::new();
+                    doc_keys.clear();
+
+                    try!(builder.add_document(doc))
+                }
+            };
+
+            doc_keys.push((doc.key.clone(), doc_ord, version));
+        }
+
+        if !doc_keys.is_empty() {
+            try!(self.commit_segment_builder(&builder, &doc_keys));
+        }
+
+        Ok(())
+    }
+
+    fn commit_segment_builder(&self, builder: &segment_builder::SegmentBuilder, doc_keys: &[(String, u32, Option<DocumentVersion>)]) -> Result<(), DocumentInsertError> {
+        // Write the segment
+        let segment = try!(self.write_segment(builder));
+
+        // Update document index
+        for &(ref doc_key, doc_ord, version) in doc_keys.iter() {
+            let doc_ref = DocRef::from_segment_ord(segment, doc_ord);
+            try!(self.document_index.insert_or_replace_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), doc_ref, version));
+        }
 
         Ok(())
     }
@@ -250,29 +382,64 @@ impl RocksDBIndexStore {
         let kb = KeyBuilder::segment_active(segment);
         try!(write_batch.put(&kb.key(), b""));
 
-        // Merge the term dictionary
-        // Writes new terms to disk and generates mapping between the builder's term dictionary and the real one
-        let mut term_dictionary_map: HashMap<TermRef, TermRef> = HashMap::new();
-        for (term, current_term_ref) in builder.term_dictionary.iter() {
-            let new_term_ref = try!(self.term_dictionary.get_or_create(&self.db, term));
-            term_dictionary_map.insert(*current_term_ref, new_term_ref);
+        // The builder's term dictionary is shared by all fields, look up terms by the
+        // builder's TermRefs so they can be added to each field's dictionary
+        let mut builder_terms: HashMap<TermRef, &Term> = HashMap::new();
+        for (term, term_ref) in builder.term_dictionary.iter() {
+            builder_terms.insert(*term_ref, term);
         }
 
+        // Statistics of the segment, these are also added to the index statistics
+        let mut statistics = builder.statistics.clone();
+
         // Write term directories
+        let mut segment_term_dictionaries: HashMap<u32, BTreeMap<Term, TermRef>> = HashMap::new();
         for (&(field_ref, term_ref), doc_ids) in builder.term_directories.iter() {
-            let new_term_ref = term_dictionary_map.get(&term_ref).expect("TermRef not in term_dictionary_map");
+            // Merge the term into the field's term dictionary
+            let term = builder_terms.get(&term_ref).expect("TermRef not in builder term dictionary");
+            let new_term_ref = try!(self.term_dictionary.get_or_create(&self.db, field_ref, term));
+            segment_term_dictionaries.entry(field_ref.ord()).or_insert_with(BTreeMap::new).insert((*term).clone(), new_term_ref);
 
             // Convert doc_id list to bytes
-            let mut doc_ids_bytes = Vec::with_capacity(doc_ids.len() * 2);
-            for doc_id in doc_ids.iter() {
-                let mut doc_id_bytes = [0; 2];
-                BigEndian::write_u16(&mut doc_id_bytes, *doc_id);
-                doc_ids_bytes.push(doc_id_bytes[0]);
-                doc_ids_bytes.push(doc_id_bytes[1]);
-            }
+            let doc_ids_bytes = doc_ids.iter().cloned().collect::<DocIdSet>().to_bytes();
 
             let kb = KeyBuilder::segment_dir_list(segment, field_ref.ord(), new_term_ref.ord());
             try!(write_batch.put(&kb.key(), &doc_ids_bytes));
+
+            // Term document frequency
+            // This is keyed by the index's TermRef so it's counted here rather than by the builder
+            let stat_name = KeyBuilder::segment_stat_term_doc_frequency_stat_name(field_ref.ord(), new_term_ref.ord());
+            statistics.insert(stat_name, doc_ids.len() as i64);
+
+            // Write term frequencies
+            if let Some(term_frequencies) = builder.term_frequency_lists.get(&(field_ref, term_ref)) {
+                let kb = KeyBuilder::segment_term_frequency_list(segment, field_ref.ord(), new_term_ref.ord());
+                try!(write_batch.put(&kb.key(), &term_frequency_list::encode(term_frequencies)));
+            }
+
+            // Write score bound
+            if let Some(term_score_bound) = builder.term_score_bounds.get(&(field_ref, term_ref)) {
+                let kb = KeyBuilder::segment_term_score_bound(segment, field_ref.ord(), new_term_ref.ord());
+                try!(write_batch.put(&kb.key(), &term_score_bound.to_bytes()));
+            }
+        }
+
+        // Write the segment's term dictionaries
+        for (field_ord, terms) in segment_term_dictionaries.iter() {
+            try!(segment_term_dictionary::write_term_dictionary(&mut write_batch, segment, *field_ord, terms));
+        }
+
+        // Write field lengths
+        for (field_ref, field_lengths) in builder.field_lengths.iter() {
+            let norms = match self.schema.get(field_ref) {
+                Some(field_info) => field_info.norms(),
+                None => continue,
+            };
+
+            if let Some(field_norms) = FieldNorms::new(norms, field_lengths) {
+                let kb = KeyBuilder::segment_field_norms(segment, field_ref.ord());
+                try!(write_batch.put(&kb.key(), &field_norms.to_bytes()));
+            }
         }
 
         // Write stored fields
@@ -282,12 +449,17 @@ impl RocksDBIndexStore {
         }
 
         // Write statistics
-        for (name, value) in builder.statistics.iter() {
-            let kb = KeyBuilder::segment_stat(segment, name);
-
+        // The segment is activated by this write batch so its statistics are added to
+        // the index statistics at the same time
+        for (name, value) in statistics.iter() {
             let mut value_bytes = [0; 8];
             BigEndian::write_i64(&mut value_bytes, *value);
+
+            let kb = KeyBuilder::segment_stat(segment, name);
             try!(write_batch.put(&kb.key(), &value_bytes));
+
+            let kb = KeyBuilder::index_stat(name);
+            try!(write_batch.merge(&kb.key(), &value_bytes));
         }
 
         // Write data
@@ -303,10 +475,24 @@ impl RocksDBIndexStore {
         }
     }
 
+    /// Allocates a sequence number for a write to the index
+    pub fn allocate_seq_no(&self) -> Result<u64, rocksdb::Error> {
+        self.document_index.allocate_seq_no(&self.db)
+    }
+
+    /// Deletes a batch of documents in a single write with versions that were allocated
+    /// by the caller, returns the number that were deleted
+    ///
+    /// Use `allocate_seq_no` to allocate the sequence numbers.
+    pub fn remove_versioned_documents_by_key(&self, doc_keys: &[(&str, DocumentVersion)]) -> Result<usize, rocksdb::Error> {
+        let keys = doc_keys.iter().map(|&(doc_key, version)| (doc_key.as_bytes().to_vec(), version)).collect::<Vec<_>>();
+        self.document_index.delete_documents_by_key(&self.db, &keys)
+    }
+
     pub fn reader<'a>(&'a self) -> RocksDBIndexReader<'a> {
         RocksDBIndexReader {
             store: &self,
-            snapshot: self.db.snapshot(),
+            snapshot: ReaderSnapshot(self.db.snapshot()),
         }
     }
 }
@@ -319,6 +505,7 @@ impl fmt::Debug for RocksDBIndexStore {
 }
 
 
+#[derive(Debug)]
 pub enum StoredFieldReadError {
     /// The provided FieldRef wasn't valid for this index
     InvalidFieldRef(FieldRef),
@@ -344,9 +531,33 @@ impl From<rocksdb::Error> for StoredFieldReadError {
 }
 
 
+/// The snapshot that a reader reads from
+///
+/// `Snapshot` holds a raw pointer so it isn't `Sync`, but RocksDB snapshots are immutable
+/// and RocksDB allows them to be read from many threads at the same time. Every method of
+/// `Snapshot` only reads through the pointer (each read creates its own read options
+/// and iterators), so it's safe to share between threads.
+///
+/// This is what lets `SearchExecutor` search the segments of a reader in parallel. The
+/// rest of the reader is `Sync` on its own.
+struct ReaderSnapshot<'a>(Snapshot<'a>);
+
+
+unsafe impl<'a> Sync for ReaderSnapshot<'a> {}
+
+
+impl<'a> Deref for ReaderSnapshot<'a> {
+    type Target = Snapshot<'a>;
+
+    fn deref(&self) -> &Snapshot<'a> {
+        &self.0
+    }
+}
+
+
 pub struct RocksDBIndexReader<'a> {
     store: &'a RocksDBIndexStore,
-    snapshot: Snapshot<'a>
+    snapshot: ReaderSnapshot<'a>,
 }
 
 
@@ -355,11 +566,65 @@ impl<'a> RocksDBIndexReader<'a> {
         &self.store.schema
     }
 
+    /// Retrieves the TermRef for the given term in a field
+    pub fn term_ref(&self, field_ref: FieldRef, term: &Term) -> Result<Option<TermRef>, String> {
+        let kb = KeyBuilder::term_dict_mapping(field_ref.ord(), term.as_bytes());
+        Ok(try!(self.snapshot.get(kb.key())).map(|term_ref| parse_term_ref(&term_ref)))
+    }
+
+    /// Finds the terms in a field that match the selector
+    ///
+    /// Only terms that are in an active segment are returned.
+    pub fn select_terms(&self, field_ref: FieldRef, term_selector: &TermSelector) -> Vec<TermRef> {
+        let mut seen_term_refs = HashSet::new();
+        let mut term_refs = Vec::new();
+
+        for segment in self.store.segments.iter_active(&self) {
+            for term_ref in segment.select_terms(field_ref, term_selector) {
+                if seen_term_refs.insert(term_ref) {
+                    term_refs.push(term_ref);
+                }
+            }
+        }
+
+        term_refs
+    }
+
     pub fn contains_document_key(&self, doc_key: &str) -> bool {
         // TODO: use snapshot
         self.store.document_index.contains_document_key(&doc_key.as_bytes().iter().cloned().collect())
     }
 
+    /// Retrieves the version of the document with the given key
+    pub fn get_document_version(&self, doc_key: &str) -> Option<DocumentVersion> {
+        // TODO: use snapshot
+        self.store.document_index.get_document_version(&doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    /// Retrieves the latest version of the given key, this is the version it was deleted at
+    /// if the document has since been deleted
+    pub fn get_last_document_version(&self, doc_key: &str) -> Option<DocumentVersion> {
+        // TODO: use snapshot
+        self.store.document_index.get_last_version(&doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    /// Finds the current version of the document with the given key
+    pub fn find_document_by_key(&self, doc_key: &str) -> Option<DocRef> {
+        // TODO: use snapshot
+        self.store.document_index.get_document_ref(&doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    /// Finds the document with the given key and its version as they were when the
+    /// reader was opened
+    ///
+    /// Unlike `find_document_by_key`, this reads the primary key index from the snapshot,
+    /// so the document can't be replaced or moved by a merge between looking it up and
+    /// reading its stored fields.
+    pub fn find_document_in_snapshot(&self, doc_key: &str) -> Result<Option<(DocRef, DocumentVersion)>, rocksdb::Error> {
+        let kb = KeyBuilder::primary_key_index(doc_key.as_bytes());
+        Ok(try!(self.snapshot.get(&kb.key())).map(|entry| parse_primary_key_entry(&entry)))
+    }
+
     pub fn read_stored_field(&self, field_ref: FieldRef, doc_ref: DocRef) -> Result<Option<FieldValue>, StoredFieldReadError> {
         let field_info = match self.schema().get(&field_ref) {
             Some(field_info) => field_info,
@@ -417,19 +682,73 @@ impl<'a> RocksDBIndexReader<'a> {
 }
 
 
+
+impl<'a> IndexStore<'a> for RocksDBIndexStore {
+    type Reader = RocksDBIndexReader<'a>;
+
+    fn reader(&'a self) -> RocksDBIndexReader<'a> {
+        RocksDBIndexStore::reader(self)
+    }
+
+    fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldRef, AddFieldError> {
+        RocksDBIndexStore::add_field(self, name, field_type, field_flags)
+    }
+
+    fn remove_field(&mut self, field_ref: &FieldRef) -> bool {
+        RocksDBIndexStore::remove_field(self, field_ref)
+    }
+
+    fn insert_or_update_document(&self, doc: &Document) -> Result<(), String> {
+        RocksDBIndexStore::insert_or_update_document(self, doc).map_err(|e| format!("{:?}", e))
+    }
+
+    fn remove_document_by_key(&self, doc_key: &str) -> Result<bool, String> {
+        RocksDBIndexStore::remove_document_by_key(self, doc_key).map_err(|e| format!("{:?}", e))
+    }
+}
+
+
+impl<'a> IndexReader<'a> for RocksDBIndexReader<'a> {
+    fn schema(&self) -> &Schema {
+        RocksDBIndexReader::schema(self)
+    }
+
+    fn contains_document_key(&self, doc_key: &str) -> bool {
+        RocksDBIndexReader::contains_document_key(self, doc_key)
+    }
+
+    fn read_stored_field(&self, field_ref: FieldRef, doc_ref: DocRef) -> Result<Option<FieldValue>, String> {
+        RocksDBIndexReader::read_stored_field(self, field_ref, doc_ref).map_err(|e| format!("{:?}", e))
+    }
+
+    fn search<C: Collector>(&self, collector: &mut C, query: &Query) -> Result<(), String> {
+        RocksDBIndexReader::search(self, collector, query)
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use std::fs::remove_dir_all;
 
-    use rocksdb::{DB, Options};
-    use kite::{Term, Token, Document};
+    use rocksdb::{DB, Options, WriteBatch};
+    use kite::{Term, Token, Document, DocRef};
     use kite::document::FieldValue;
-    use kite::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use kite::schema::{FieldType, Norms, FIELD_INDEXED, FIELD_STORED};
+    use kite::segment::Segment;
     use kite::query::Query;
     use kite::query::term_scorer::TermScorer;
+    use kite::query::term_selector::TermSelector;
     use kite::collectors::top_score::TopScoreCollector;
+    use kite::collectors::total_count::TotalCountCollector;
+    use kite::collectors::doc_ids::DocIdCollector;
+    use kite::doc_id_set::DocIdSet;
+    use kite::norms::encode_length_byte;
 
-    use super::RocksDBIndexStore;
+    use key_builder::KeyBuilder;
+    use segment::RocksDBSegment;
+    use format;
+    use super::{RocksDBIndexStore, DocumentVersion, SearchExecutor, StatisticsReader, RocksDBStatisticsReader, MergePolicy, TieredMergePolicy};
 
     #[test]
     fn test_create() {
@@ -567,4 +886,947 @@ mod tests {
         let docs = collector.into_sorted_vec();
         println!("{:?}", docs);
     }
+
+    #[test]
+    fn test_insert_or_update_documents() {
+        remove_dir_all("test_indices/test_insert_or_update_documents");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_insert_or_update_documents").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let make_doc = |key: &str, word: &str| {
+            Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => vec![
+                        Token { term: Term::from_string(word), position: 1 },
+                    ],
+                },
+                stored_fields: hashmap! {},
+            }
+        };
+
+        store.insert_or_update_documents(&[
+            make_doc("first", "hello"),
+            make_doc("second", "hello"),
+            make_doc("first", "goodbye"),
+        ]).unwrap();
+
+        // All documents should be written into a single segment
+        assert_eq!(store.get_segment_statistics().unwrap().len(), 1);
+
+        // The later version of "first" should replace the earlier one
+        let query = Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+        assert!(store.reader().contains_document_key("first"));
+    }
+
+    #[test]
+    fn test_remove_documents() {
+        remove_dir_all("test_indices/test_remove_documents");
+
+        make_test_store("test_indices/test_remove_documents");
+
+        let store = RocksDBIndexStore::open("test_indices/test_remove_documents").unwrap();
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+
+        let query = Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = DocIdCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        let doc_refs = collector.into_vec().into_iter().map(DocRef::from_u64).collect::<Vec<_>>();
+        assert_eq!(doc_refs.len(), 2);
+        assert!(doc_refs.contains(&store.reader().find_document_by_key("test_doc").unwrap()));
+
+        let version = DocumentVersion { version: 2, seq_no: store.allocate_seq_no().unwrap() };
+        assert_eq!(store.remove_versioned_documents_by_key(&[("test_doc", version), ("another_test_doc", version), ("missing_doc", version)]).unwrap(), 2);
+        assert_eq!(store.reader().get_last_document_version("missing_doc"), Some(version));
+
+        // Removing documents a second time shouldn't do anything
+        assert_eq!(store.remove_versioned_documents_by_key(&[("test_doc", version), ("another_test_doc", version)]).unwrap(), 0);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 0);
+        assert!(!store.reader().contains_document_key("test_doc"));
+        assert!(!store.reader().contains_document_key("another_test_doc"));
+        assert_eq!(store.reader().find_document_by_key("test_doc"), None);
+    }
+
+    #[test]
+    fn test_document_versions() {
+        remove_dir_all("test_indices/test_document_versions");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_document_versions").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let make_doc = |key: &str| {
+            Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => vec![
+                        Token { term: Term::from_string("hello"), position: 1 },
+                    ],
+                },
+                stored_fields: hashmap! {},
+            }
+        };
+
+        assert_eq!(store.reader().get_document_version("test_doc"), None);
+
+        // Versions are incremented every time the document is replaced
+        store.insert_or_update_document(&make_doc("test_doc")).unwrap();
+        assert_eq!(store.reader().get_document_version("test_doc"), Some(DocumentVersion { version: 1, seq_no: 0 }));
+
+        store.insert_or_update_document(&make_doc("test_doc")).unwrap();
+        assert_eq!(store.reader().get_document_version("test_doc"), Some(DocumentVersion { version: 2, seq_no: 1 }));
+
+        // Versions allocated by the caller are used as they are
+        let seq_no = store.allocate_seq_no().unwrap();
+        assert_eq!(seq_no, 2);
+        store.insert_or_update_versioned_documents(&[(&make_doc("test_doc"), DocumentVersion { version: 5, seq_no: seq_no })]).unwrap();
+        assert_eq!(store.reader().get_document_version("test_doc"), Some(DocumentVersion { version: 5, seq_no: 2 }));
+
+        // Versions are kept when the document is moved by a merge and when the index is reopened
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+        drop(store);
+
+        let store = RocksDBIndexStore::open("test_indices/test_document_versions").unwrap();
+        assert_eq!(store.reader().get_document_version("test_doc"), Some(DocumentVersion { version: 5, seq_no: 2 }));
+
+        // Sequence numbers are never reused after reopening
+        let seq_no = store.allocate_seq_no().unwrap();
+        assert!(seq_no > 2);
+
+        // Deleting the document removes it but its version is remembered
+        store.remove_document_by_key("test_doc").unwrap();
+        assert_eq!(store.reader().get_document_version("test_doc"), None);
+        let deleted_version = store.reader().get_last_document_version("test_doc").unwrap();
+        assert_eq!(deleted_version.version, 6);
+        assert!(deleted_version.seq_no > seq_no);
+
+        // Recreating the document carries on from the deleted version, even after reopening
+        drop(store);
+        let store = RocksDBIndexStore::open("test_indices/test_document_versions").unwrap();
+        assert_eq!(store.reader().get_last_document_version("test_doc"), Some(deleted_version));
+
+        store.insert_or_update_document(&make_doc("test_doc")).unwrap();
+        let version = store.reader().get_document_version("test_doc").unwrap();
+        assert_eq!(version.version, 7);
+        assert!(version.seq_no > deleted_version.seq_no);
+    }
+
+    #[test]
+    fn test_find_document_in_snapshot() {
+        remove_dir_all("test_indices/test_find_document_in_snapshot");
+
+        make_test_store("test_indices/test_find_document_in_snapshot");
+
+        let store = RocksDBIndexStore::open("test_indices/test_find_document_in_snapshot").unwrap();
+        let reader = store.reader();
+
+        let (doc_ref, version) = reader.find_document_in_snapshot("test_doc").unwrap().unwrap();
+        assert_eq!(reader.find_document_by_key("test_doc"), Some(doc_ref));
+        assert_eq!(reader.get_document_version("test_doc"), Some(version));
+        assert_eq!(reader.find_document_in_snapshot("missing_doc").unwrap(), None);
+
+        // Changes made after the reader was opened aren't visible through its snapshot
+        store.remove_document_by_key("test_doc").unwrap();
+        assert_eq!(reader.find_document_in_snapshot("test_doc").unwrap(), Some((doc_ref, version)));
+        assert_eq!(store.reader().find_document_in_snapshot("test_doc").unwrap(), None);
+    }
+
+    #[test]
+    fn test_term_frequencies() {
+        remove_dir_all("test_indices/test_term_frequencies");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_term_frequencies").unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        store.insert_or_update_document(&Document {
+            key: "once".to_string(),
+            indexed_fields: hashmap! {
+                body_field => vec![
+                    Token { term: Term::from_string("lorem"), position: 1 },
+                    Token { term: Term::from_string("ipsum"), position: 2 },
+                ],
+            },
+            stored_fields: hashmap! {},
+        }).unwrap();
+
+        store.insert_or_update_document(&Document {
+            key: "twice".to_string(),
+            indexed_fields: hashmap! {
+                body_field => vec![
+                    Token { term: Term::from_string("lorem"), position: 1 },
+                    Token { term: Term::from_string("lorem"), position: 2 },
+                ],
+            },
+            stored_fields: hashmap! {},
+        }).unwrap();
+
+        // Term frequencies must survive merging
+        store.merge_segments(&vec![1, 2]).unwrap();
+        store.purge_segments(&vec![1, 2]).unwrap();
+
+        let query = Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = TopScoreCollector::new(10);
+        store.reader().search(&mut collector, &query).unwrap();
+        let docs = collector.into_sorted_vec();
+
+        assert_eq!(docs.len(), 2);
+        assert_eq!(DocRef::from_u64(docs[0].doc_id()), store.reader().find_document_by_key("twice").unwrap());
+        assert!(docs[0].score().unwrap() > docs[1].score().unwrap());
+    }
+
+    #[test]
+    fn test_top_k_pruning() {
+        remove_dir_all("test_indices/test_top_k_pruning");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_top_k_pruning").unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for i in 1..21 {
+            let mut tokens = Vec::new();
+            for position in 0..i {
+                tokens.push(Token { term: Term::from_string("lorem"), position: position });
+            }
+            if i % 2 == 0 {
+                tokens.push(Token { term: Term::from_string("ipsum"), position: i });
+            }
+
+            store.insert_or_update_document(&Document {
+                key: format!("doc{}", i),
+                indexed_fields: hashmap! {
+                    body_field => tokens,
+                },
+                stored_fields: hashmap! {},
+            }).unwrap();
+        }
+
+        let segments = (1..21).collect::<Vec<u32>>();
+        store.merge_segments(&segments[..10].to_vec()).unwrap();
+        store.purge_segments(&segments[..10].to_vec()).unwrap();
+
+        let query = Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: body_field,
+                    term: Term::from_string("lorem"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: body_field,
+                    term: Term::from_string("ipsum"),
+                    scorer: TermScorer::default(),
+                },
+            ]
+        };
+
+        // Find all scores, the collector never fills up so nothing is skipped
+        let mut collector = TopScoreCollector::new(100);
+        store.reader().search(&mut collector, &query).unwrap();
+        let all_scores = collector.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<_>>();
+        assert_eq!(all_scores.len(), 20);
+
+        // Skipping documents mustn't change the top documents
+        let mut collector = TopScoreCollector::new(3);
+        store.reader().search(&mut collector, &query).unwrap();
+        let top_scores = collector.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<_>>();
+        assert_eq!(top_scores, all_scores[..3].to_vec());
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        remove_dir_all("test_indices/test_parallel_search");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_parallel_search").unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Each document is written into its own segment
+        for i in 1..11 {
+            let mut tokens = Vec::new();
+            for position in 0..i {
+                tokens.push(Token { term: Term::from_string("lorem"), position: position });
+            }
+
+            store.insert_or_update_document(&Document {
+                key: format!("doc{}", i),
+                indexed_fields: hashmap! {
+                    body_field => tokens,
+                },
+                stored_fields: hashmap! {},
+            }).unwrap();
+        }
+        store.remove_document_by_key("doc10").unwrap();
+
+        let query = Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        };
+
+        let executor = SearchExecutor::with_threads(4).unwrap();
+        let reader = store.reader();
+
+        let mut collector = TopScoreCollector::new(3);
+        reader.search(&mut collector, &query).unwrap();
+        let expected = collector.into_sorted_vec().iter().map(|doc| (doc.doc_id(), doc.score())).collect::<Vec<_>>();
+
+        let mut collector = TopScoreCollector::new(3);
+        executor.search(&reader, &mut collector, &query).unwrap();
+        let docs = collector.into_sorted_vec().iter().map(|doc| (doc.doc_id(), doc.score())).collect::<Vec<_>>();
+        assert_eq!(docs, expected);
+
+        let mut collector = TotalCountCollector::new();
+        executor.search(&reader, &mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 9);
+    }
+
+    #[test]
+    fn test_index_statistics() {
+        remove_dir_all("test_indices/test_index_statistics");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_index_statistics").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let make_doc = |key: &str, words: &[&str]| {
+            Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => words.iter().enumerate().map(|(position, word)| {
+                        Token { term: Term::from_string(word), position: position as u32 + 1 }
+                    }).collect(),
+                },
+                stored_fields: hashmap! {},
+            }
+        };
+
+        store.insert_or_update_document(&make_doc("first", &["hello", "world"])).unwrap();
+        store.insert_or_update_document(&make_doc("second", &["hello", "hello"])).unwrap();
+        store.insert_or_update_document(&make_doc("third", &["goodbye"])).unwrap();
+
+        let hello = store.reader().term_ref(title_field, &Term::from_string("hello")).unwrap().unwrap();
+        let world = store.reader().term_ref(title_field, &Term::from_string("world")).unwrap().unwrap();
+
+        {
+            let reader = store.reader();
+            let mut stats = RocksDBStatisticsReader::new(&reader);
+            assert_eq!(stats.total_docs(title_field).unwrap(), 3);
+            assert_eq!(stats.total_tokens(title_field).unwrap(), 5);
+            assert_eq!(stats.term_document_frequency(title_field, hello).unwrap(), 2);
+            assert_eq!(stats.term_document_frequency(title_field, world).unwrap(), 1);
+        }
+
+        // Merging doesn't change the statistics
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        {
+            let reader = store.reader();
+            let mut stats = RocksDBStatisticsReader::new(&reader);
+            assert_eq!(stats.total_docs(title_field).unwrap(), 3);
+            assert_eq!(stats.total_tokens(title_field).unwrap(), 5);
+            assert_eq!(stats.term_document_frequency(title_field, hello).unwrap(), 2);
+        }
+
+        // Deleted documents are subtracted from the index statistics when they are dropped by a merge
+        store.remove_document_by_key("first").unwrap();
+
+        // Before the merge, they are left out of the document and token totals
+        // but are still counted in the term document frequencies
+        {
+            let reader = store.reader();
+            let mut stats = RocksDBStatisticsReader::new(&reader);
+            assert_eq!(stats.total_docs(title_field).unwrap(), 2);
+            assert_eq!(stats.total_tokens(title_field).unwrap(), 3);
+            assert_eq!(stats.term_document_frequency(title_field, hello).unwrap(), 2);
+        }
+
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        let segment_stats = store.get_segment_statistics().unwrap();
+        assert_eq!(segment_stats.len(), 1);
+        assert_eq!(segment_stats[0].1.total_docs(), 2);
+        assert_eq!(segment_stats[0].1.deleted_docs(), 0);
+
+        {
+            let reader = store.reader();
+            let mut stats = RocksDBStatisticsReader::new(&reader);
+            assert_eq!(stats.total_docs(title_field).unwrap(), 2);
+            assert_eq!(stats.total_tokens(title_field).unwrap(), 3);
+            assert_eq!(stats.term_document_frequency(title_field, hello).unwrap(), 1);
+            assert_eq!(stats.term_document_frequency(title_field, world).unwrap(), 0);
+        }
+
+        // The remaining documents are still searchable
+        let query = Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = DocIdCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        let doc_refs = collector.into_vec().into_iter().map(DocRef::from_u64).collect::<Vec<_>>();
+        assert_eq!(doc_refs, vec![store.reader().find_document_by_key("second").unwrap()]);
+
+        // Token totals are carried forward from segments written before term frequency lists
+        let mut write_batch = WriteBatch::default();
+        let mut iter = store.db.iterator();
+        iter.seek(b"p");
+        while iter.next() {
+            let k = iter.key().unwrap();
+            if k[0] != b'p' {
+                break;
+            }
+
+            write_batch.delete(&k).unwrap();
+        }
+        store.db.write(write_batch).unwrap();
+
+        store.insert_or_update_document(&make_doc("fourth", &["hello", "world", "world"])).unwrap();
+
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        {
+            let reader = store.reader();
+            let mut stats = RocksDBStatisticsReader::new(&reader);
+            assert_eq!(stats.total_docs(title_field).unwrap(), 3);
+            assert_eq!(stats.total_tokens(title_field).unwrap(), 6);
+        }
+    }
+
+    #[test]
+    fn test_field_norms() {
+        remove_dir_all("test_indices/test_field_norms");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_field_norms").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        assert!(store.set_field_norms(&body_field, Norms::Disabled));
+
+        let make_doc = |key: &str, num_words: u32| {
+            Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => (0..num_words).map(|position| {
+                        Token { term: Term::from_string("hello"), position: position + 1 }
+                    }).collect(),
+                    body_field => vec![
+                        Token { term: Term::from_string("lorem"), position: 1 },
+                    ],
+                },
+                stored_fields: hashmap! {},
+            }
+        };
+
+        store.insert_or_update_document(&make_doc("first", 10)).unwrap();
+        store.insert_or_update_document(&make_doc("second", 11)).unwrap();
+
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        let merged_segment = store.merge_segments(&segments).unwrap().unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        let reader = store.reader();
+        let segment = RocksDBSegment::new(&reader, merged_segment);
+
+        // Lengths are stored exactly
+        let title_norms = segment.load_field_norms(title_field).unwrap().unwrap();
+        assert_eq!(title_norms.norms(), Norms::Short);
+        assert_eq!(title_norms.num_docs(), 2);
+        let mut lengths = vec![title_norms.length(0), title_norms.length(1)];
+        lengths.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(lengths, vec![10.0, 11.0]);
+
+        // Nothing is stored for fields with norms disabled
+        assert_eq!(segment.load_field_norms(body_field).unwrap(), None);
+    }
+
+    #[test]
+    fn test_merged_score_bounds_with_rounded_norms() {
+        remove_dir_all("test_indices/test_merged_score_bounds_with_rounded_norms");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_merged_score_bounds_with_rounded_norms").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let make_doc = |key: &str, num_words: u32| {
+            Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => (0..num_words).map(|position| {
+                        Token { term: Term::from_string("hello"), position: position + 1 }
+                    }).collect(),
+                },
+                stored_fields: hashmap! {},
+            }
+        };
+
+        // The length of this document is rounded down when it's read back
+        assert!(store.set_field_norms(&title_field, Norms::Byte));
+        store.insert_or_update_document(&make_doc("first", 10)).unwrap();
+        assert!(store.set_field_norms(&title_field, Norms::Short));
+        store.insert_or_update_document(&make_doc("second", 12)).unwrap();
+
+        let segments = store.get_segment_statistics().unwrap().into_iter().map(|(segment, _)| segment).collect::<Vec<_>>();
+        let merged_segment = store.merge_segments(&segments).unwrap().unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        let reader = store.reader();
+        let segment = RocksDBSegment::new(&reader, merged_segment);
+        let hello = reader.term_ref(title_field, &Term::from_string("hello")).unwrap().unwrap();
+
+        // The bound mustn't be longer than the stored length of any document
+        let title_norms = segment.load_field_norms(title_field).unwrap().unwrap();
+        let (_, min_field_length) = segment.load_term_score_bound(title_field, hello).unwrap().unwrap();
+        assert!(min_field_length < 10);
+        assert!(min_field_length <= title_norms.token_count(0));
+        assert!(min_field_length <= title_norms.token_count(1));
+    }
+
+    #[test]
+    fn test_merge_deleted_segments() {
+        remove_dir_all("test_indices/test_merge_deleted_segments");
+
+        let store = make_test_store("test_indices/test_merge_deleted_segments");
+        store.remove_document_by_key("test_doc").unwrap();
+        store.remove_document_by_key("another_test_doc").unwrap();
+
+        // Segments with no live documents are merged away without creating a new segment
+        let segment_stats = store.get_segment_statistics().unwrap();
+        let merge = TieredMergePolicy::default().find_merge(&segment_stats).unwrap();
+        assert_eq!(merge.len(), segment_stats.len());
+
+        assert_eq!(store.merge_segments(&merge).unwrap(), None);
+        store.purge_segments(&merge).unwrap();
+
+        assert!(store.get_segment_statistics().unwrap().is_empty());
+
+        let reader = store.reader();
+        let title_field = reader.schema().get_field_by_name("title").unwrap();
+        let mut stats = RocksDBStatisticsReader::new(&reader);
+        assert_eq!(stats.total_docs(title_field).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_term_dictionary_per_field() {
+        remove_dir_all("test_indices/test_term_dictionary_per_field");
+
+        make_test_store("test_indices/test_term_dictionary_per_field");
+
+        let store = RocksDBIndexStore::open("test_indices/test_term_dictionary_per_field").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+
+        // Selecting terms only returns terms from the requested field
+        let all_terms = TermSelector::Prefix("".to_string());
+        assert_eq!(store.reader().select_terms(title_field, &all_terms).len(), 4);
+        assert_eq!(store.reader().select_terms(body_field, &all_terms).len(), 3);
+
+        assert!(store.reader().term_ref(title_field, &Term::from_string("hello")).unwrap().is_some());
+        assert_eq!(store.reader().term_ref(body_field, &Term::from_string("hello")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_term_dictionary_migration() {
+        remove_dir_all("test_indices/test_term_dictionary_migration");
+
+        let store = make_test_store("test_indices/test_term_dictionary_migration");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        // Convert the term dictionary back into the format shared by all fields
+        let mut write_batch = WriteBatch::default();
+        let mut iter = store.db.iterator();
+        iter.seek(b"f");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'f' {
+                break;
+            }
+
+            let separator = k.iter().position(|c| *c == b'/').unwrap();
+            let kb = KeyBuilder::legacy_term_dict_mapping(&k[separator + 1..]);
+            write_batch.put(&kb.key(), &iter.value().unwrap()).unwrap();
+            write_batch.delete(&k).unwrap();
+        }
+        store.db.write(write_batch).unwrap();
+        drop(iter);
+        drop(store);
+
+        // The dictionary should be migrated when the index is opened
+        let store = RocksDBIndexStore::open("test_indices/test_term_dictionary_migration").unwrap();
+        assert_eq!(store.reader().select_terms(title_field, &TermSelector::Prefix("h".to_string())).len(), 2);
+
+        let query = Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+
+        // The legacy dictionary should be removed
+        let mut iter = store.db.iterator();
+        iter.seek(b"t");
+        assert!(!iter.next() || iter.key().unwrap()[0] != b't');
+    }
+
+    #[test]
+    fn test_segment_term_dictionaries() {
+        remove_dir_all("test_indices/test_segment_term_dictionaries");
+
+        let store = make_test_store("test_indices/test_segment_term_dictionaries");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        // The merged segment should contain the terms of both source segments
+        let term_refs = store.reader().select_terms(title_field, &TermSelector::Prefix("h".to_string()));
+        assert_eq!(term_refs.len(), 2);
+        assert!(term_refs.contains(&store.reader().term_ref(title_field, &Term::from_string("hello")).unwrap().unwrap()));
+        assert!(term_refs.contains(&store.reader().term_ref(title_field, &Term::from_string("howdy")).unwrap().unwrap()));
+        assert!(store.reader().select_terms(title_field, &TermSelector::Prefix("x".to_string())).is_empty());
+
+        // Purged segments shouldn't have a term dictionary
+        let mut iter = store.db.iterator();
+        iter.seek(&KeyBuilder::segment_term_dict_prefix(1).key());
+        assert!(!iter.next() || !iter.key().unwrap().starts_with(KeyBuilder::segment_term_dict_prefix(1).key()));
+    }
+
+    #[test]
+    fn test_segment_term_dictionaries_migration() {
+        remove_dir_all("test_indices/test_segment_term_dictionaries_migration");
+
+        let store = make_test_store("test_indices/test_segment_term_dictionaries_migration");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+
+        // Remove the segment term dictionaries
+        let mut write_batch = WriteBatch::default();
+        let mut iter = store.db.iterator();
+        iter.seek(b"e");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'e' {
+                break;
+            }
+
+            write_batch.delete(&k).unwrap();
+        }
+        write_batch.delete(b".segment_term_dictionaries").unwrap();
+        store.db.write(write_batch).unwrap();
+        drop(iter);
+        drop(store);
+
+        // They should be rebuilt from the term directories when the index is opened
+        let store = RocksDBIndexStore::open("test_indices/test_segment_term_dictionaries_migration").unwrap();
+        assert_eq!(store.reader().select_terms(body_field, &TermSelector::Prefix("".to_string())).len(), 3);
+        assert_eq!(store.reader().select_terms(body_field, &TermSelector::Prefix("lo".to_string())).len(), 1);
+    }
+
+    #[test]
+    fn test_large_segment() {
+        remove_dir_all("test_indices/test_large_segment");
+
+        let mut store = RocksDBIndexStore::create("test_indices/test_large_segment").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Segments can hold more than 65536 documents
+        let docs = (0..70000).map(|i| {
+            Document {
+                key: format!("doc{}", i),
+                indexed_fields: hashmap! {
+                    title_field => vec![
+                        Token { term: Term::from_string("hello"), position: 1 },
+                    ],
+                },
+                stored_fields: hashmap! {},
+            }
+        }).collect::<Vec<_>>();
+        store.insert_or_update_documents(&docs).unwrap();
+        assert_eq!(store.get_segment_statistics().unwrap().len(), 1);
+
+        let doc_ref = store.reader().find_document_by_key("doc69999").unwrap();
+        assert_eq!(doc_ref.ord(), 69999);
+
+        let query = Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 70000);
+
+        store.remove_document_by_key("doc69999").unwrap();
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::new_all()).unwrap();
+        assert_eq!(collector.get_total_count(), 69999);
+    }
+
+    #[test]
+    fn test_format_upgrade() {
+        remove_dir_all("test_indices/test_format_upgrade");
+
+        let store = make_test_store("test_indices/test_format_upgrade");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        store.remove_document_by_key("another_test_doc").unwrap();
+
+        // Convert the index back to version 1, which used two byte document ordinals
+        let mut write_batch = WriteBatch::default();
+        for prefix in [b'd', b'x'].iter() {
+            let mut iter = store.db.iterator();
+            iter.seek(&[*prefix]);
+            while iter.next() {
+                let k = iter.key().unwrap();
+
+                if k[0] != *prefix {
+                    break;
+                }
+
+                let doc_id_set = DocIdSet::from_bytes(iter.value().unwrap().to_vec()).unwrap();
+                let mut old_value = Vec::new();
+                for doc_id in doc_id_set.iter() {
+                    old_value.push((doc_id >> 8) as u8);
+                    old_value.push(doc_id as u8);
+                }
+                write_batch.put(&k, &old_value).unwrap();
+            }
+        }
+        write_batch.delete(b".format_version").unwrap();
+        store.db.write(write_batch).unwrap();
+        drop(store);
+
+        // The index should be upgraded when it's opened
+        let store = RocksDBIndexStore::open("test_indices/test_format_upgrade").unwrap();
+
+        let query = Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = DocIdCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        let doc_refs = collector.into_vec().into_iter().map(DocRef::from_u64).collect::<Vec<_>>();
+        assert_eq!(doc_refs, vec![store.reader().find_document_by_key("test_doc").unwrap()]);
+    }
+
+    #[test]
+    fn test_legacy_term_frequencies_are_converted() {
+        remove_dir_all("test_indices/test_legacy_term_frequencies_are_converted");
+
+        let store = make_test_store("test_indices/test_legacy_term_frequencies_are_converted");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let pk_field = store.schema.get_field_by_name("pk").unwrap();
+        let doc_ref = store.reader().find_document_by_key("test_doc").unwrap();
+        let term_ref = store.reader().term_ref(title_field, &Term::from_string("hello")).unwrap().unwrap();
+
+        // Add a term frequency the way they were stored before term frequency lists
+        let mut tf_value_type = b"tf".to_vec();
+        tf_value_type.extend(term_ref.ord().to_string().as_bytes());
+        let tf_kb = KeyBuilder::stored_field_value(doc_ref.segment(), doc_ref.ord(), title_field.ord(), &tf_value_type);
+        let mut write_batch = WriteBatch::default();
+        write_batch.put(&tf_kb.key(), &[0, 0, 0, 0, 0, 0, 0, 2]).unwrap();
+        write_batch.put(b".format_version", b"5").unwrap();
+        store.db.write(write_batch).unwrap();
+        drop(store);
+
+        // It should be moved into the term's frequency list when the index is opened,
+        // leaving the document's other values
+        let store = RocksDBIndexStore::open("test_indices/test_legacy_term_frequencies_are_converted").unwrap();
+        assert!(store.db.get(&tf_kb.key()).unwrap().is_none());
+        assert!(store.reader().read_stored_field(pk_field, doc_ref).unwrap().is_some());
+        assert_eq!(format::read_format_version(&store.db).unwrap(), format::FORMAT_VERSION);
+
+        let reader = store.reader();
+        let segment = RocksDBSegment::new(&reader, doc_ref.segment());
+        assert_eq!(segment.load_term_frequencies(title_field, term_ref).unwrap(), Some(vec![(doc_ref.ord(), 2)]));
+    }
+
+    #[test]
+    fn test_legacy_field_lengths_resume() {
+        remove_dir_all("test_indices/test_legacy_field_lengths_resume");
+
+        let store = make_test_store("test_indices/test_legacy_field_lengths_resume");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let first_doc_ref = store.reader().find_document_by_key("test_doc").unwrap();
+        let second_doc_ref = store.reader().find_document_by_key("another_test_doc").unwrap();
+
+        // Add the field lengths the way they were stored before field norms, as if the
+        // upgrade to version 5 was interrupted after the norms were written and the
+        // first document's length was deleted
+        let first_kb = KeyBuilder::stored_field_value(first_doc_ref.segment(), first_doc_ref.ord(), title_field.ord(), b"len");
+        let second_kb = KeyBuilder::stored_field_value(second_doc_ref.segment(), second_doc_ref.ord(), title_field.ord(), b"len");
+        let mut write_batch = WriteBatch::default();
+        write_batch.put(&second_kb.key(), &[encode_length_byte(2)]).unwrap();
+        write_batch.put(b".format_version", b"4").unwrap();
+        write_batch.put(b".format_upgrade_resume_key", &first_kb.key()).unwrap();
+        store.db.write(write_batch).unwrap();
+        drop(store);
+
+        // The remaining length should be deleted without rebuilding the norms
+        let store = RocksDBIndexStore::open("test_indices/test_legacy_field_lengths_resume").unwrap();
+        assert!(store.db.get(&second_kb.key()).unwrap().is_none());
+        assert!(store.db.get(b".format_upgrade_resume_key").unwrap().is_none());
+        assert_eq!(format::read_format_version(&store.db).unwrap(), format::FORMAT_VERSION);
+
+        let reader = store.reader();
+        let segment = RocksDBSegment::new(&reader, first_doc_ref.segment());
+        let title_norms = segment.load_field_norms(title_field).unwrap().unwrap();
+        assert_eq!(title_norms.norms(), Norms::Short);
+        assert_eq!(title_norms.token_count(first_doc_ref.ord()), 2);
+        assert_eq!(title_norms.token_count(second_doc_ref.ord()), 2);
+    }
+
+    #[test]
+    fn test_format_upgrade_resume() {
+        remove_dir_all("test_indices/test_format_upgrade_resume");
+
+        let store = make_test_store("test_indices/test_format_upgrade_resume");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+
+        // Convert the index back to version 1, except for the first term directory which
+        // is left as if the upgrade to version 2 was interrupted just after converting it
+        let mut write_batch = WriteBatch::default();
+        let mut resume_key = None;
+        for prefix in [b'd', b'x'].iter() {
+            let mut iter = store.db.iterator();
+            iter.seek(&[*prefix]);
+            while iter.next() {
+                let k = iter.key().unwrap();
+
+                if k[0] != *prefix {
+                    break;
+                }
+
+                let doc_id_set = DocIdSet::from_bytes(iter.value().unwrap().to_vec()).unwrap();
+                let mut old_value = Vec::new();
+                for doc_id in doc_id_set.iter() {
+                    if resume_key.is_none() {
+                        old_value.extend_from_slice(&[0, 0, (doc_id >> 8) as u8, doc_id as u8]);
+                    } else {
+                        old_value.extend_from_slice(&[(doc_id >> 8) as u8, doc_id as u8]);
+                    }
+                }
+                write_batch.put(&k, &old_value).unwrap();
+
+                if resume_key.is_none() {
+                    resume_key = Some(k.to_vec());
+                }
+            }
+        }
+        write_batch.put(b".format_version", b"1").unwrap();
+        write_batch.put(b".format_upgrade_resume_key", &resume_key.unwrap()).unwrap();
+        store.db.write(write_batch).unwrap();
+        drop(store);
+
+        // The upgrade should carry on after the key that was already converted
+        let store = RocksDBIndexStore::open("test_indices/test_format_upgrade_resume").unwrap();
+        assert!(store.db.get(b".format_upgrade_resume_key").unwrap().is_none());
+
+        let query = Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+    }
+
+    #[test]
+    fn test_format_upgrade_merge_operands() {
+        remove_dir_all("test_indices/test_format_upgrade_merge_operands");
+
+        let store = make_test_store("test_indices/test_format_upgrade_merge_operands");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        store.remove_document_by_key("another_test_doc").unwrap();
+
+        // Convert the index back to version 1, where each document ordinal was appended
+        // to term directories and deletion lists with a two byte merge operand
+        let mut write_batch = WriteBatch::default();
+        for prefix in [b'd', b'x'].iter() {
+            let mut iter = store.db.iterator();
+            iter.seek(&[*prefix]);
+            while iter.next() {
+                let k = iter.key().unwrap();
+
+                if k[0] != *prefix {
+                    break;
+                }
+
+                let doc_id_set = DocIdSet::from_bytes(iter.value().unwrap().to_vec()).unwrap();
+                write_batch.delete(&k).unwrap();
+                for doc_id in doc_id_set.iter() {
+                    write_batch.merge(&k, &[(doc_id >> 8) as u8, doc_id as u8]).unwrap();
+                }
+            }
+        }
+        write_batch.delete(b".format_version").unwrap();
+        store.db.write(write_batch).unwrap();
+        drop(store);
+
+        // The operands should be merged the way they were written, so the deleted
+        // document stays deleted after the upgrade
+        let store = RocksDBIndexStore::open("test_indices/test_format_upgrade_merge_operands").unwrap();
+
+        let query = Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = DocIdCollector::new();
+        store.reader().search(&mut collector, &query).unwrap();
+        let doc_refs = collector.into_vec().into_iter().map(DocRef::from_u64).collect::<Vec<_>>();
+        assert_eq!(doc_refs, vec![store.reader().find_document_by_key("test_doc").unwrap()]);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::new_all()).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_merge_keeps_unreadable_doc_id_sets() {
+        remove_dir_all("test_indices/test_merge_keeps_unreadable_doc_id_sets");
+
+        let store = RocksDBIndexStore::create("test_indices/test_merge_keeps_unreadable_doc_id_sets").unwrap();
+        let doc_id_set_bytes = vec![3].into_iter().collect::<DocIdSet>().to_bytes();
+
+        // A value that isn't a DocIdSet must not be lost when a DocIdSet is merged into it
+        store.db.put(b"x1", &[0, 1]).unwrap();
+        store.db.merge(b"x1", &doc_id_set_bytes).unwrap();
+
+        let mut expected = vec![0, 1];
+        expected.extend_from_slice(&doc_id_set_bytes);
+        assert_eq!(store.db.get(b"x1").unwrap().unwrap().to_vec(), expected);
+    }
 }
@@ -0,0 +1,375 @@
+//! Merge policies decide which segments should be merged together
+//!
+//! Every write to the index creates a new segment, so segments must be merged
+//! periodically to keep the number of segments that each search has to visit down.
+//! Merging is also the only way deleted documents are removed from disk.
+
+use std::cmp;
+
+use segment_stats::SegmentStatistics;
+
+
+pub trait MergePolicy {
+    /// Chooses a group of active segments to merge together
+    ///
+    /// Returns None if the segments don't currently need merging.
+    fn find_merge(&self, segments: &[(u32, SegmentStatistics)]) -> Option<Vec<u32>>;
+}
+
+
+/// A merge policy that never merges any segments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoMergePolicy;
+
+
+impl MergePolicy for NoMergePolicy {
+    fn find_merge(&self, _segments: &[(u32, SegmentStatistics)]) -> Option<Vec<u32>> {
+        None
+    }
+}
+
+
+/// Merges segments of a similar size together once there are too many of them
+///
+/// Segments are sorted into tiers by the number of live documents they have, each
+/// tier holding segments `min(segments_per_tier, max_merge_at_once)` times larger
+/// than the previous one. Once there are more segments than the tiers allow, the
+/// group of segments that is cheapest to merge is chosen. Merges that combine
+/// similarly sized segments and that reclaim the most deleted documents are
+/// preferred.
+///
+/// Segments with a large proportion of deleted documents are also rewritten on
+/// their own, so deleted documents in segments that are too large to be merged
+/// again are eventually removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TieredMergePolicy {
+    /// The number of segments allowed in each tier
+    ///
+    /// Lower values mean fewer segments to search but more merging.
+    pub segments_per_tier: usize,
+
+    /// The maximum number of segments that may be merged at once
+    pub max_merge_at_once: usize,
+
+    /// The largest segment (in live documents) that merges will create
+    ///
+    /// Segments can hold many more documents than this but merging very large segments
+    /// rewrites a lot of data for little benefit.
+    pub max_merged_segment_docs: i64,
+
+    /// Segments smaller than this are treated as if they were this size
+    ///
+    /// This prevents lots of tiny tiers being created for very small segments.
+    pub floor_segment_docs: i64,
+
+    /// The percentage of documents in a segment that may be deleted before that
+    /// segment is rewritten to remove them
+    pub deletes_pct_allowed: f64,
+}
+
+
+impl Default for TieredMergePolicy {
+    fn default() -> TieredMergePolicy {
+        TieredMergePolicy {
+            segments_per_tier: 10,
+            max_merge_at_once: 10,
+            max_merged_segment_docs: 5000000,
+            floor_segment_docs: 1000,
+            deletes_pct_allowed: 33.0,
+        }
+    }
+}
+
+
+/// A segment that the tiered merge policy may choose to merge
+struct MergeCandidate {
+    segment: u32,
+    total_docs: i64,
+    live_docs: i64,
+}
+
+
+impl MergeCandidate {
+    fn deletes_pct(&self) -> f64 {
+        if self.total_docs > 0 {
+            (self.total_docs - self.live_docs) as f64 * 100.0 / self.total_docs as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+
+impl TieredMergePolicy {
+    fn floor_size(&self, live_docs: i64) -> i64 {
+        cmp::max(live_docs, self.floor_segment_docs)
+    }
+
+    /// The number of segments that the tiers allow for an index of the given size
+    fn allowed_segment_count(&self, smallest_segment_docs: i64, total_live_docs: i64) -> f64 {
+        // Guard against settings that would never finish filling the tiers
+        let segments_per_tier = cmp::max(self.segments_per_tier, 1);
+        let merge_factor = cmp::max(cmp::min(segments_per_tier, self.max_merge_at_once), 2);
+
+        let mut tier_size = cmp::max(self.floor_size(smallest_segment_docs), 1);
+        let mut remaining_docs = total_live_docs;
+        let mut allowed_segment_count = 0.0;
+
+        loop {
+            let segments_in_tier = remaining_docs as f64 / tier_size as f64;
+
+            if segments_in_tier < segments_per_tier as f64 || tier_size >= self.max_merged_segment_docs {
+                allowed_segment_count += segments_in_tier.ceil();
+                break;
+            }
+
+            allowed_segment_count += segments_per_tier as f64;
+            remaining_docs -= segments_per_tier as i64 * tier_size;
+            tier_size *= merge_factor as i64;
+        }
+
+        // Always allow a full tier of segments, otherwise small indices would be
+        // merged down to a single segment after every write
+        if allowed_segment_count < segments_per_tier as f64 {
+            segments_per_tier as f64
+        } else {
+            allowed_segment_count
+        }
+    }
+
+    /// Scores a potential merge, lower is better
+    ///
+    /// Merges of similarly sized segments score best as merging a small segment into
+    /// a large one rewrites the large one for little benefit. Merges that reclaim a lot
+    /// of deleted documents are favoured.
+    fn score_merge(&self, merge: &[&MergeCandidate], hit_max_merge_size: bool) -> f64 {
+        let mut total_docs = 0;
+        let mut total_live_docs = 0;
+        let mut total_floored_docs = 0;
+
+        for candidate in merge.iter() {
+            total_docs += candidate.total_docs;
+            total_live_docs += candidate.live_docs;
+            total_floored_docs += self.floor_size(candidate.live_docs);
+        }
+
+        // Candidates are sorted by size, so the first segment is the largest
+        let skew = if hit_max_merge_size {
+            // The merge creates a segment that is as large as we allow, so it
+            // isn't worth penalising it for being uneven
+            1.0 / self.max_merge_at_once as f64
+        } else {
+            self.floor_size(merge[0].live_docs) as f64 / total_floored_docs as f64
+        };
+
+        // Gently favour smaller merges
+        let mut score = skew * (total_live_docs as f64).powf(0.05);
+
+        // Favour merges that reclaim deleted documents
+        let non_deleted_ratio = total_live_docs as f64 / total_docs as f64;
+        score *= non_deleted_ratio * non_deleted_ratio;
+
+        score
+    }
+}
+
+
+impl MergePolicy for TieredMergePolicy {
+    fn find_merge(&self, segments: &[(u32, SegmentStatistics)]) -> Option<Vec<u32>> {
+        let mut candidates = segments.iter().map(|&(segment, ref stats)| {
+            MergeCandidate {
+                segment: segment,
+                total_docs: stats.total_docs(),
+                live_docs: stats.total_docs() - stats.deleted_docs(),
+            }
+        }).collect::<Vec<_>>();
+
+        // Segments with no live documents are dropped by merging them
+        let empty_segments = candidates.iter().filter(|candidate| candidate.live_docs <= 0).map(|candidate| candidate.segment).collect::<Vec<_>>();
+        if !empty_segments.is_empty() {
+            return Some(empty_segments);
+        }
+
+        // Sort segments by size in descending order
+        candidates.sort_by(|a, b| b.live_docs.cmp(&a.live_docs));
+
+        // Segments that are already over half the maximum size can't be merged with
+        // anything but the smallest segments, so they are left alone unless they have
+        // a lot of deleted documents
+        let eligible = candidates.iter().filter(|candidate| {
+            candidate.live_docs <= self.max_merged_segment_docs / 2 || candidate.deletes_pct() > self.deletes_pct_allowed
+        }).collect::<Vec<_>>();
+
+        if !eligible.is_empty() {
+            let total_live_docs = eligible.iter().map(|candidate| candidate.live_docs).sum::<i64>();
+            let smallest_segment_docs = eligible[eligible.len() - 1].live_docs;
+
+            if eligible.len() as f64 > self.allowed_segment_count(smallest_segment_docs, total_live_docs) {
+                // Too many segments, find the best merge
+                let mut best_merge: Option<(f64, Vec<u32>)> = None;
+
+                for start in 0..eligible.len() {
+                    let mut merge = Vec::new();
+                    let mut merge_live_docs = 0;
+                    let mut hit_max_merge_size = false;
+
+                    for candidate in eligible[start..].iter() {
+                        if merge.len() >= self.max_merge_at_once {
+                            break;
+                        }
+
+                        if merge_live_docs + candidate.live_docs > self.max_merged_segment_docs {
+                            // No space for this segment but there may be for a smaller one
+                            hit_max_merge_size = true;
+                            continue;
+                        }
+
+                        merge.push(*candidate);
+                        merge_live_docs += candidate.live_docs;
+                    }
+
+                    if merge.len() < 2 {
+                        continue;
+                    }
+
+                    let score = self.score_merge(&merge, hit_max_merge_size);
+                    let is_better = match best_merge {
+                        Some((best_score, _)) => score < best_score,
+                        None => true,
+                    };
+
+                    if is_better {
+                        best_merge = Some((score, merge.iter().map(|candidate| candidate.segment).collect()));
+                    }
+                }
+
+                if let Some((_, merge)) = best_merge {
+                    return Some(merge);
+                }
+            }
+        }
+
+        // Rewrite the segment with the most deletions if it has too many
+        let mut most_deletes: Option<&MergeCandidate> = None;
+        for candidate in candidates.iter() {
+            if candidate.deletes_pct() <= self.deletes_pct_allowed {
+                continue;
+            }
+
+            let is_more = match most_deletes {
+                Some(most_deletes) => candidate.deletes_pct() > most_deletes.deletes_pct(),
+                None => true,
+            };
+
+            if is_more {
+                most_deletes = Some(candidate);
+            }
+        }
+
+        most_deletes.map(|candidate| vec![candidate.segment])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use segment_stats::SegmentStatistics;
+
+    use super::{MergePolicy, TieredMergePolicy, NoMergePolicy};
+
+    fn make_segments(sizes: &[(i64, i64)]) -> Vec<(u32, SegmentStatistics)> {
+        sizes.iter().enumerate().map(|(segment, &(total_docs, deleted_docs))| {
+            (segment as u32 + 1, SegmentStatistics::new(total_docs, deleted_docs))
+        }).collect()
+    }
+
+    #[test]
+    fn test_no_merge_policy() {
+        let segments = make_segments(&[(1, 0), (1, 0), (1, 0), (1, 1)]);
+
+        assert_eq!(NoMergePolicy.find_merge(&segments), None);
+    }
+
+    #[test]
+    fn test_few_segments_arent_merged() {
+        let policy = TieredMergePolicy::default();
+        let segments = make_segments(&[(1, 0), (10, 0), (100, 0)]);
+
+        assert_eq!(policy.find_merge(&segments), None);
+    }
+
+    #[test]
+    fn test_similar_segments_are_merged() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 3,
+            max_merge_at_once: 3,
+            ..TieredMergePolicy::default()
+        };
+
+        // The three small segments should be merged together rather than into the large one
+        let segments = make_segments(&[(500, 0), (10, 0), (12, 0), (11, 0)]);
+        let mut merge = policy.find_merge(&segments).unwrap();
+        merge.sort();
+
+        assert_eq!(merge, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_max_merge_at_once() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 2,
+            max_merge_at_once: 4,
+            ..TieredMergePolicy::default()
+        };
+
+        let segments = make_segments(&[(1, 0); 10]);
+
+        assert_eq!(policy.find_merge(&segments).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_max_merged_segment_docs() {
+        let policy = TieredMergePolicy {
+            segments_per_tier: 2,
+            max_merge_at_once: 10,
+            max_merged_segment_docs: 100,
+            floor_segment_docs: 1,
+            ..TieredMergePolicy::default()
+        };
+
+        // Only two of these segments fit in a merged segment
+        let segments = make_segments(&[(40, 0), (40, 0), (40, 0), (40, 0)]);
+
+        assert_eq!(policy.find_merge(&segments).unwrap().len(), 2);
+
+        // Segments larger than half the maximum are left alone
+        let segments = make_segments(&[(60, 0), (60, 0), (60, 0)]);
+
+        assert_eq!(policy.find_merge(&segments), None);
+    }
+
+    #[test]
+    fn test_segments_with_many_deletions_are_rewritten() {
+        let policy = TieredMergePolicy {
+            max_merged_segment_docs: 100,
+            ..TieredMergePolicy::default()
+        };
+
+        let segments = make_segments(&[(100, 10), (100, 50), (100, 60)]);
+
+        assert_eq!(policy.find_merge(&segments), Some(vec![3]));
+
+        // Below the threshold
+        let segments = make_segments(&[(100, 10), (100, 30)]);
+
+        assert_eq!(policy.find_merge(&segments), None);
+    }
+
+    #[test]
+    fn test_fully_deleted_segments_are_dropped() {
+        let policy = TieredMergePolicy::default();
+        let segments = make_segments(&[(100, 0), (5, 5), (100, 0), (0, 0)]);
+
+        assert_eq!(policy.find_merge(&segments), Some(vec![2, 4]));
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::Mutex;
+
+use kite::query::Query;
+use kite::collectors::MergeableCollector;
+use rayon::{self, ThreadPool, Configuration};
+
+use RocksDBIndexReader;
+use segment::RocksDBSegment;
+use search::{SegmentSearch, ScoreFloor};
+use search::statistics::{StatisticsReader, RocksDBStatisticsReader};
+use search::planner::{SearchPlan, plan_query};
+
+
+/// Runs searches on a pool of threads, searching the segments of an index in parallel
+///
+/// Each segment is searched into its own empty copy of the collector, these are merged
+/// together when the search has finished. The copies share their score thresholds (see
+/// `ScoreFloor`) so every segment can skip documents that can't make it into the results.
+pub struct SearchExecutor {
+    pool: ThreadPool,
+}
+
+
+impl SearchExecutor {
+    /// Creates an executor with a thread for each CPU
+    pub fn new() -> Result<SearchExecutor, String> {
+        SearchExecutor::with_configuration(Configuration::new())
+    }
+
+    /// Creates an executor with the given number of threads
+    pub fn with_threads(num_threads: usize) -> Result<SearchExecutor, String> {
+        SearchExecutor::with_configuration(Configuration::new().set_num_threads(num_threads))
+    }
+
+    fn with_configuration(configuration: Configuration) -> Result<SearchExecutor, String> {
+        let pool = try!(ThreadPool::new(configuration).map_err(|e| format!("failed to start search threads: {:?}", e)));
+
+        Ok(SearchExecutor {
+            pool: pool,
+        })
+    }
+
+    pub fn search<C: MergeableCollector>(&self, index_reader: &RocksDBIndexReader, collector: &mut C, query: &Query) -> Result<(), String> {
+        // Initialise statistics reader
+        let mut stats = RocksDBStatisticsReader::new(index_reader);
+
+        self.search_with_statistics(index_reader, collector, query, &mut stats)
+    }
+
+    /// Runs a search using the provided statistics reader for scoring
+    ///
+    /// The statistics reader is shared by all of the threads. It is only used while
+    /// preparing to search each segment so threads rarely have to wait for it.
+    pub fn search_with_statistics<C: MergeableCollector, R: StatisticsReader + Send>(&self, index_reader: &RocksDBIndexReader, collector: &mut C, query: &Query, stats: &mut R) -> Result<(), String> {
+        // Plan query
+        let plan = try!(plan_query(index_reader, query, collector.needs_score()));
+
+        // Run query on each segment
+        let segments = index_reader.store.segments.iter_active(index_reader).collect::<Vec<_>>();
+        let stats = Mutex::new(stats);
+        let score_floor = ScoreFloor::new();
+        let segments_collector = {
+            let collector = &*collector;
+            try!(self.pool.install(|| search_segments(collector, &plan, &segments, &stats, &score_floor)))
+        };
+
+        collector.merge(segments_collector);
+
+        Ok(())
+    }
+}
+
+
+/// Searches a list of segments, splitting it in half and searching each half on a
+/// different thread until there is only one segment left
+fn search_segments<C: MergeableCollector, R: StatisticsReader + Send>(collector: &C, plan: &SearchPlan, segments: &[RocksDBSegment], stats: &Mutex<&mut R>, score_floor: &ScoreFloor) -> Result<C, String> {
+    match segments.len() {
+        0 => Ok(collector.new_empty()),
+        1 => {
+            let mut segment_collector = collector.new_empty();
+
+            let search = {
+                let mut stats = stats.lock().unwrap();
+                try!(SegmentSearch::new(plan, &segments[0], &mut **stats))
+            };
+            try!(search.run(&mut segment_collector, Some(score_floor)));
+
+            Ok(segment_collector)
+        }
+        num_segments => {
+            let (left, right) = segments.split_at(num_segments / 2);
+            let (left_collector, right_collector) = rayon::join(
+                || search_segments(collector, plan, left, stats, score_floor),
+                || search_segments(collector, plan, right, stats, score_floor)
+            );
+
+            let mut left_collector = try!(left_collector);
+            left_collector.merge(try!(right_collector));
+            Ok(left_collector)
+        }
+    }
+}
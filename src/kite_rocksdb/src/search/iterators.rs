@@ -0,0 +1,474 @@
+use kite::doc_id_set::DocIdSet;
+
+
+/// Streams document ids in ascending order
+///
+/// Iterators are positioned on their first document when they are created.
+pub trait DocIterator {
+    /// The document the iterator is currently positioned on, or None if there
+    /// are no more documents
+    fn doc(&self) -> Option<u32>;
+
+    /// Moves to the next document
+    fn advance(&mut self);
+
+    /// Moves to the first document that is greater than or equal to the target
+    ///
+    /// Does nothing if the iterator is already past the target.
+    fn seek(&mut self, target: u32) {
+        while let Some(doc) = self.doc() {
+            if doc >= target {
+                break;
+            }
+
+            self.advance();
+        }
+    }
+}
+
+
+/// An iterator that matches no documents
+pub struct EmptyIterator;
+
+
+impl DocIterator for EmptyIterator {
+    fn doc(&self) -> Option<u32> {
+        None
+    }
+
+    fn advance(&mut self) {}
+}
+
+
+/// An iterator that matches every document in a segment
+pub struct AllDocsIterator {
+    current_doc: u32,
+    total_docs: u32,
+}
+
+
+impl AllDocsIterator {
+    pub fn new(total_docs: u32) -> AllDocsIterator {
+        AllDocsIterator {
+            current_doc: 0,
+            total_docs: total_docs,
+        }
+    }
+}
+
+
+impl DocIterator for AllDocsIterator {
+    fn doc(&self) -> Option<u32> {
+        if self.current_doc < self.total_docs {
+            Some(self.current_doc)
+        } else {
+            None
+        }
+    }
+
+    fn advance(&mut self) {
+        if self.current_doc < self.total_docs {
+            self.current_doc += 1;
+        }
+    }
+
+    fn seek(&mut self, target: u32) {
+        if target > self.current_doc {
+            self.current_doc = if target < self.total_docs { target } else { self.total_docs };
+        }
+    }
+}
+
+
+/// Iterates the documents of a posting list (a term directory or deletion list)
+///
+/// The list is decoded once so seeking is a binary search.
+pub struct PostingsIterator {
+    doc_ids: Vec<u32>,
+    position: usize,
+}
+
+
+impl PostingsIterator {
+    pub fn new(doc_id_set: &DocIdSet) -> PostingsIterator {
+        PostingsIterator {
+            doc_ids: doc_id_set.iter().collect(),
+            position: 0,
+        }
+    }
+}
+
+
+impl DocIterator for PostingsIterator {
+    fn doc(&self) -> Option<u32> {
+        self.doc_ids.get(self.position).cloned()
+    }
+
+    fn advance(&mut self) {
+        if self.position < self.doc_ids.len() {
+            self.position += 1;
+        }
+    }
+
+    fn seek(&mut self, target: u32) {
+        if let Some(doc) = self.doc() {
+            if doc >= target {
+                return;
+            }
+        }
+
+        self.position += match self.doc_ids[self.position..].binary_search(&target) {
+            Ok(offset) => offset,
+            Err(offset) => offset,
+        };
+    }
+}
+
+
+/// Matches documents that are in every one of a set of iterators
+pub struct ConjunctionIterator {
+    iterators: Vec<Box<DocIterator>>,
+}
+
+
+impl ConjunctionIterator {
+    pub fn new(iterators: Vec<Box<DocIterator>>) -> ConjunctionIterator {
+        let mut conjunction = ConjunctionIterator {
+            iterators: iterators,
+        };
+        conjunction.align();
+        conjunction
+    }
+
+    /// Advances the iterators until they are all positioned on the same document
+    /// (or any of them run out of documents)
+    fn align(&mut self) {
+        if self.iterators.is_empty() {
+            return;
+        }
+
+        let mut target = match self.iterators[0].doc() {
+            Some(doc) => doc,
+            None => return,
+        };
+
+        loop {
+            let mut aligned = true;
+
+            for iterator in self.iterators.iter_mut() {
+                iterator.seek(target);
+
+                match iterator.doc() {
+                    Some(doc) if doc == target => {}
+                    Some(doc) => {
+                        // This iterator has skipped past the target, all the other
+                        // iterators must catch up with it
+                        target = doc;
+                        aligned = false;
+                        break;
+                    }
+                    None => return,
+                }
+            }
+
+            if aligned {
+                return;
+            }
+        }
+    }
+}
+
+
+impl DocIterator for ConjunctionIterator {
+    fn doc(&self) -> Option<u32> {
+        if self.iterators.is_empty() {
+            return None;
+        }
+
+        let doc = self.iterators[0].doc();
+        for iterator in self.iterators[1..].iter() {
+            if iterator.doc() != doc {
+                return None;
+            }
+        }
+
+        doc
+    }
+
+    fn advance(&mut self) {
+        if self.iterators.is_empty() {
+            return;
+        }
+
+        self.iterators[0].advance();
+        self.align();
+    }
+
+    fn seek(&mut self, target: u32) {
+        if self.iterators.is_empty() {
+            return;
+        }
+
+        self.iterators[0].seek(target);
+        self.align();
+    }
+}
+
+
+/// Matches documents that are in any of a set of iterators
+pub struct DisjunctionIterator {
+    iterators: Vec<Box<DocIterator>>,
+    current_doc: Option<u32>,
+}
+
+
+impl DisjunctionIterator {
+    pub fn new(iterators: Vec<Box<DocIterator>>) -> DisjunctionIterator {
+        let mut disjunction = DisjunctionIterator {
+            iterators: iterators,
+            current_doc: None,
+        };
+        disjunction.update_current_doc();
+        disjunction
+    }
+
+    fn update_current_doc(&mut self) {
+        self.current_doc = self.iterators.iter().filter_map(|iterator| iterator.doc()).min();
+    }
+}
+
+
+impl DocIterator for DisjunctionIterator {
+    fn doc(&self) -> Option<u32> {
+        self.current_doc
+    }
+
+    fn advance(&mut self) {
+        let current_doc = match self.current_doc {
+            Some(doc) => doc,
+            None => return,
+        };
+
+        for iterator in self.iterators.iter_mut() {
+            if iterator.doc() == Some(current_doc) {
+                iterator.advance();
+            }
+        }
+
+        self.update_current_doc();
+    }
+
+    fn seek(&mut self, target: u32) {
+        for iterator in self.iterators.iter_mut() {
+            iterator.seek(target);
+        }
+
+        self.update_current_doc();
+    }
+}
+
+
+/// Matches documents that are in one iterator but not in another
+pub struct ExclusionIterator {
+    include: Box<DocIterator>,
+    exclude: Box<DocIterator>,
+}
+
+
+impl ExclusionIterator {
+    pub fn new(include: Box<DocIterator>, exclude: Box<DocIterator>) -> ExclusionIterator {
+        let mut exclusion = ExclusionIterator {
+            include: include,
+            exclude: exclude,
+        };
+        exclusion.skip_excluded();
+        exclusion
+    }
+
+    /// Advances the include iterator past any documents that are excluded
+    fn skip_excluded(&mut self) {
+        while let Some(doc) = self.include.doc() {
+            self.exclude.seek(doc);
+
+            if self.exclude.doc() != Some(doc) {
+                return;
+            }
+
+            self.include.advance();
+        }
+    }
+}
+
+
+impl DocIterator for ExclusionIterator {
+    fn doc(&self) -> Option<u32> {
+        self.include.doc()
+    }
+
+    fn advance(&mut self) {
+        self.include.advance();
+        self.skip_excluded();
+    }
+
+    fn seek(&mut self, target: u32) {
+        self.include.seek(target);
+        self.skip_excluded();
+    }
+}
+
+
+/// Streams the frequencies of a term alongside its postings
+///
+/// Documents must be looked up in ascending order.
+pub struct TermFrequencyIterator {
+    postings: PostingsIterator,
+    term_frequencies: Vec<(u32, u32)>,
+    position: usize,
+}
+
+
+impl TermFrequencyIterator {
+    pub fn new(postings: PostingsIterator, term_frequencies: Vec<(u32, u32)>) -> TermFrequencyIterator {
+        TermFrequencyIterator {
+            postings: postings,
+            term_frequencies: term_frequencies,
+            position: 0,
+        }
+    }
+
+    /// Returns the frequency of the term in the document, or None if the document
+    /// doesn't contain the term
+    pub fn term_frequency(&mut self, doc: u32) -> Option<u32> {
+        self.postings.seek(doc);
+
+        if self.postings.doc() != Some(doc) {
+            return None;
+        }
+
+        while let Some(&(tf_doc, frequency)) = self.term_frequencies.get(self.position) {
+            if tf_doc > doc {
+                break;
+            }
+
+            self.position += 1;
+
+            if tf_doc == doc {
+                return Some(frequency);
+            }
+        }
+
+        Some(1)
+    }
+}
+
+
+impl DocIterator for TermFrequencyIterator {
+    fn doc(&self) -> Option<u32> {
+        self.postings.doc()
+    }
+
+    fn advance(&mut self) {
+        self.postings.advance();
+    }
+
+    fn seek(&mut self, target: u32) {
+        self.postings.seek(target);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::doc_id_set::DocIdSet;
+
+    use super::{DocIterator, EmptyIterator, AllDocsIterator, PostingsIterator, ConjunctionIterator, DisjunctionIterator, ExclusionIterator, TermFrequencyIterator};
+
+    fn postings(doc_ids: Vec<u32>) -> Box<DocIterator> {
+        Box::new(PostingsIterator::new(&doc_ids.into_iter().collect::<DocIdSet>()))
+    }
+
+    fn collect<I: DocIterator>(mut iterator: I) -> Vec<u32> {
+        let mut docs = Vec::new();
+        while let Some(doc) = iterator.doc() {
+            docs.push(doc);
+            iterator.advance();
+        }
+        docs
+    }
+
+    #[test]
+    fn test_postings_seek() {
+        let mut iterator = PostingsIterator::new(&vec![1, 5, 10, 100000].into_iter().collect::<DocIdSet>());
+
+        iterator.seek(6);
+        assert_eq!(iterator.doc(), Some(10));
+
+        // Seeking backwards does nothing
+        iterator.seek(2);
+        assert_eq!(iterator.doc(), Some(10));
+
+        iterator.seek(100000);
+        assert_eq!(iterator.doc(), Some(100000));
+
+        iterator.seek(100001);
+        assert_eq!(iterator.doc(), None);
+    }
+
+    #[test]
+    fn test_all_docs() {
+        assert_eq!(collect(AllDocsIterator::new(3)), vec![0, 1, 2]);
+        assert_eq!(collect(AllDocsIterator::new(0)), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_conjunction() {
+        let iterator = ConjunctionIterator::new(vec![
+            postings(vec![1, 2, 3, 5, 8, 13]),
+            postings(vec![2, 3, 5, 7, 11, 13]),
+            postings(vec![1, 3, 5, 7, 9, 11, 13]),
+        ]);
+
+        assert_eq!(collect(iterator), vec![3, 5, 13]);
+    }
+
+    #[test]
+    fn test_conjunction_with_empty() {
+        let iterator = ConjunctionIterator::new(vec![
+            postings(vec![1, 2, 3]),
+            Box::new(EmptyIterator),
+        ]);
+
+        assert_eq!(collect(iterator), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_disjunction() {
+        let iterator = DisjunctionIterator::new(vec![
+            postings(vec![1, 5, 9]),
+            postings(vec![2, 5, 10]),
+            Box::new(EmptyIterator),
+        ]);
+
+        assert_eq!(collect(iterator), vec![1, 2, 5, 9, 10]);
+    }
+
+    #[test]
+    fn test_exclusion() {
+        let iterator = ExclusionIterator::new(Box::new(AllDocsIterator::new(6)), postings(vec![0, 2, 3]));
+
+        assert_eq!(collect(iterator), vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn test_term_frequencies() {
+        let postings = PostingsIterator::new(&vec![1, 5, 10].into_iter().collect::<DocIdSet>());
+        let mut iterator = TermFrequencyIterator::new(postings, vec![(5, 3), (10, 2)]);
+
+        assert_eq!(iterator.term_frequency(0), None);
+        assert_eq!(iterator.term_frequency(1), Some(1));
+        assert_eq!(iterator.term_frequency(4), None);
+        assert_eq!(iterator.term_frequency(5), Some(3));
+        assert_eq!(iterator.term_frequency(10), Some(2));
+        assert_eq!(iterator.term_frequency(11), None);
+    }
+}
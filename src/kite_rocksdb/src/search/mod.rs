@@ -1,56 +1,104 @@
-mod statistics;
+pub mod statistics;
+pub mod executor;
 mod planner;
+mod iterators;
 
+use std::sync::Mutex;
+use std::collections::HashMap;
+
+use kite::term::TermRef;
+use kite::schema::FieldRef;
 use kite::doc_id_set::DocIdSet;
+use kite::norms::FieldNorms;
+use kite::similarity::average_length;
 use kite::segment::Segment;
 use kite::query::Query;
 use kite::collectors::{Collector, DocumentMatch};
-use byteorder::{ByteOrder, BigEndian};
 
 use super::RocksDBIndexReader;
 use search::statistics::{StatisticsReader, RocksDBStatisticsReader};
 use search::planner::{SearchPlan, plan_query};
 use search::planner::boolean_query::BooleanQueryOp;
 use search::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+use search::iterators::{DocIterator, EmptyIterator, AllDocsIterator, PostingsIterator, ConjunctionIterator, DisjunctionIterator, ExclusionIterator, TermFrequencyIterator};
+
+
+/// The term directories of a segment that a search uses, None for terms that aren't in
+/// the segment
+type TermDirectories = HashMap<(FieldRef, TermRef), Option<DocIdSet>>;
 
 
-fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated: bool, segment: &S) -> Result<DocIdSet, String> {
-    // Execute boolean query
-    let mut stack = Vec::new();
+/// Loads each term directory that a search plan uses from a segment
+///
+/// The boolean query and the score function usually refer to the same terms, so the
+/// directories are loaded once here and shared by both.
+fn load_term_directories<S: Segment>(plan: &SearchPlan, segment: &S) -> Result<TermDirectories, String> {
+    let boolean_query_terms = plan.boolean_query.iter().filter_map(|op| {
+        match *op {
+            BooleanQueryOp::PushTermDirectory(field_ref, term_ref) => Some((field_ref, term_ref)),
+            _ => None,
+        }
+    });
+
+    let score_function_terms = plan.score_function.iter().filter_map(|op| {
+        match *op {
+            ScoreFunctionOp::TermScorer(field_ref, term_ref, _) => Some((field_ref, term_ref)),
+            _ => None,
+        }
+    });
+
+    let mut term_directories = HashMap::new();
+    for (field_ref, term_ref) in boolean_query_terms.chain(score_function_terms) {
+        if !term_directories.contains_key(&(field_ref, term_ref)) {
+            let doc_id_set = try!(segment.load_term_directory(field_ref, term_ref));
+            term_directories.insert((field_ref, term_ref), doc_id_set);
+        }
+    }
+
+    Ok(term_directories)
+}
+
+
+fn build_boolean_query_iterator<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated: bool, segment: &S, term_directories: &TermDirectories) -> Result<Box<DocIterator>, String> {
+    let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0) as u32;
+
+    // Build an iterator for the boolean query
+    // Documents are streamed through the iterators in order
+    let mut stack: Vec<Box<DocIterator>> = Vec::new();
     for op in boolean_query.iter() {
         match *op {
             BooleanQueryOp::PushEmpty => {
-                stack.push(DocIdSet::new_filled(0));
+                stack.push(Box::new(EmptyIterator));
             }
             BooleanQueryOp::PushFull => {
-                stack.push(DocIdSet::new_filled(65536));
+                stack.push(Box::new(AllDocsIterator::new(total_docs)));
             }
             BooleanQueryOp::PushTermDirectory(field_ref, term_ref) => {
-                match try!(segment.load_term_directory(field_ref, term_ref)) {
-                    Some(doc_id_set) => stack.push(doc_id_set),
-                    None => stack.push(DocIdSet::new_filled(0)),
+                match term_directories.get(&(field_ref, term_ref)) {
+                    Some(&Some(ref doc_id_set)) => stack.push(Box::new(PostingsIterator::new(doc_id_set))),
+                    _ => stack.push(Box::new(EmptyIterator)),
                 }
             }
             BooleanQueryOp::PushDeletionList => {
-                    match try!(segment.load_deletion_list()) {
-                    Some(doc_id_set) => stack.push(doc_id_set),
-                    None => stack.push(DocIdSet::new_filled(0)),
+                match try!(segment.load_deletion_list()) {
+                    Some(doc_id_set) => stack.push(Box::new(PostingsIterator::new(&doc_id_set))),
+                    None => stack.push(Box::new(EmptyIterator)),
                 }
             }
             BooleanQueryOp::And => {
                 let b = stack.pop().expect("boolean query executor: stack underflow");
                 let a = stack.pop().expect("boolean query executor: stack underflow");
-                stack.push(a.intersection(&b));
+                stack.push(Box::new(ConjunctionIterator::new(vec![a, b])));
             }
             BooleanQueryOp::Or => {
                 let b = stack.pop().expect("boolean query executor: stack underflow");
                 let a = stack.pop().expect("boolean query executor: stack underflow");
-                stack.push(a.union(&b));
+                stack.push(Box::new(DisjunctionIterator::new(vec![a, b])));
             }
             BooleanQueryOp::AndNot => {
                 let b = stack.pop().expect("boolean query executor: stack underflow");
                 let a = stack.pop().expect("boolean query executor: stack underflow");
-                stack.push(a.exclusion(&b));
+                stack.push(Box::new(ExclusionIterator::new(a, b)));
             }
         }
     }
@@ -61,123 +109,407 @@ fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated
     }
     let mut matches = stack.pop().unwrap();
 
-    // Invert the list if the query is negated
+    // Invert the matches if the query is negated
     if is_negated {
-        let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
-        let all_docs = DocIdSet::new_filled(total_docs as u32);
-        matches = all_docs.exclusion(&matches);
+        matches = Box::new(ExclusionIterator::new(Box::new(AllDocsIterator::new(total_docs)), matches));
     }
 
     Ok(matches)
 }
 
 
-fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<ScoreFunctionOp>, segment: &S, mut stats: &mut R) -> Result<f64, String> {
-    // Execute score function
-    let mut stack = Vec::new();
-    for op in score_function.iter() {
-        match *op {
-            ScoreFunctionOp::Literal(val) => stack.push(val),
-            ScoreFunctionOp::TermScorer(field_ref, term_ref, ref scorer) => {
-                // TODO: Check this isn't really slow
-                match try!(segment.load_term_directory(field_ref, term_ref)) {
-                    Some(doc_id_set) => {
-                        if doc_id_set.contains_doc(doc_id) {
-                            // Read field length
-                            // TODO: we only need this for BM25
-                            let field_length_raw = try!(segment.load_stored_field_value_raw(doc_id, field_ref, b"len"));
-                            let field_length = match field_length_raw {
-                                Some(value) => {
-                                    let length_sqrt = (value[0] as f64) / 3.0 + 1.0;
-                                    length_sqrt * length_sqrt
-                                }
-                                None => 1.0
+/// A score function that adds up weighted term scores
+///
+/// The score of a document is the constant plus the sum of each term's score multiplied
+/// by its weight.
+struct LinearScoreFunction {
+    constant: f64,
+
+    /// The weight of each op in the score function (0.0 for ops that aren't TermScorers)
+    weights: Vec<f64>,
+}
+
+
+impl LinearScoreFunction {
+    /// Finds the weight of each term in a score function
+    ///
+    /// Returns None if the score function isn't linear (such as when it takes the
+    /// maximum of some scores).
+    fn new(score_function: &Vec<ScoreFunctionOp>) -> Option<LinearScoreFunction> {
+        // Each stack item is the constant and weighted term ops of a score
+        let mut stack: Vec<(f64, Vec<(usize, f64)>)> = Vec::new();
+        for (op_index, op) in score_function.iter().enumerate() {
+            match *op {
+                ScoreFunctionOp::Literal(val) => stack.push((val, Vec::new())),
+                ScoreFunctionOp::TermScorer(..) => stack.push((0.0f64, vec![(op_index, 1.0f64)])),
+                ScoreFunctionOp::CombinatorScorer(num_vals, CombinatorScorer::Avg) => {
+                    let mut constant = 0.0f64;
+                    let mut terms = Vec::new();
+
+                    for _ in 0..num_vals {
+                        let (val_constant, val_terms) = stack.pop().expect("document scorer: stack underflow");
+                        constant += val_constant / num_vals as f64;
+                        terms.extend(val_terms.into_iter().map(|(op_index, weight)| (op_index, weight / num_vals as f64)));
+                    }
+
+                    stack.push((constant, terms));
+                }
+                ScoreFunctionOp::CombinatorScorer(_, CombinatorScorer::Max) => return None,
+            }
+        }
+
+        let (constant, terms) = stack.pop().expect("document scorer: stack underflow");
+        let mut weights = vec![0.0f64; score_function.len()];
+        for (op_index, weight) in terms {
+            weights[op_index] += weight;
+        }
+
+        Some(LinearScoreFunction {
+            constant: constant,
+            weights: weights,
+        })
+    }
+}
+
+
+/// The state of a term scorer while scoring the documents of a segment
+struct TermScorerState {
+    term_frequencies: TermFrequencyIterator,
+    total_tokens: u64,
+    total_docs: u64,
+    term_document_frequency: u64,
+
+    /// The highest score any document in the segment could get for the term
+    max_score: f64,
+}
+
+
+/// Scores the documents of a segment
+///
+/// Documents must be scored in ascending order. This allows the term directories
+/// and term frequencies to be loaded once and then read alongside the matches.
+struct SegmentScorer<'a> {
+    score_function: &'a Vec<ScoreFunctionOp>,
+
+    /// State of each TermScorer op in the score function (indexed by op)
+    /// None if the op isn't a TermScorer or the term isn't in the segment
+    term_scorers: Vec<Option<TermScorerState>>,
+
+    /// The lengths of each field that is scored, None if the field has no stored lengths
+    field_norms: HashMap<FieldRef, Option<FieldNorms>>,
+}
+
+
+impl<'a> SegmentScorer<'a> {
+    fn new<S: Segment, R: StatisticsReader>(score_function: &'a Vec<ScoreFunctionOp>, segment: &S, term_directories: &TermDirectories, stats: &mut R) -> Result<SegmentScorer<'a>, String> {
+        let mut term_scorers = Vec::with_capacity(score_function.len());
+        let mut field_norms = HashMap::new();
+
+        for op in score_function.iter() {
+            let term_scorer = match *op {
+                ScoreFunctionOp::TermScorer(field_ref, term_ref, ref scorer) => {
+                    if !field_norms.contains_key(&field_ref) {
+                        field_norms.insert(field_ref, try!(segment.load_field_norms(field_ref)));
+                    }
+
+                    match term_directories.get(&(field_ref, term_ref)) {
+                        Some(&Some(ref doc_id_set)) => {
+                            let term_frequencies = try!(segment.load_term_frequencies(field_ref, term_ref)).unwrap_or_else(Vec::new);
+                            let total_tokens = try!(stats.total_tokens(field_ref)) as u64;
+                            let total_docs = try!(stats.total_docs(field_ref)) as u64;
+                            let term_document_frequency = try!(stats.term_document_frequency(field_ref, term_ref)) as u64;
+
+                            // Work out the highest score a document could get for this term
+                            // Segments written before score bounds were stored don't have them,
+                            // so use the highest frequency in the term frequency list and the
+                            // shortest possible field length instead
+                            let (max_term_frequency, min_token_count) = match try!(segment.load_term_score_bound(field_ref, term_ref)) {
+                                Some(term_score_bound) => term_score_bound,
+                                None => (term_frequencies.iter().map(|&(_, frequency)| frequency).max().unwrap_or(1), 1),
                             };
 
-                            // Read term frequency
-                            let mut value_type = vec![b't', b'f'];
-                            value_type.extend(term_ref.ord().to_string().as_bytes());
-                            let term_frequency_raw = try!(segment.load_stored_field_value_raw(doc_id, field_ref, &value_type));
-                            let term_frequency = match term_frequency_raw {
-                                Some(value) => BigEndian::read_i64(&value),
-                                None => 1,
+                            // The length must be rounded the same way as the lengths of the documents
+                            let min_field_length = match field_norms[&field_ref] {
+                                Some(ref norms) => norms.length_of(min_token_count),
+                                None => average_length(total_tokens, total_docs),
                             };
+                            let max_score = scorer.similarity_model.score(max_term_frequency, min_field_length, total_tokens, total_docs, term_document_frequency) * scorer.boost;
 
-                            let score = scorer.similarity_model.score(term_frequency as u32, field_length, try!(stats.total_tokens(field_ref)) as u64, try!(stats.total_docs(field_ref)) as u64, try!(stats.term_document_frequency(field_ref, term_ref)) as u64);
-                            stack.push(score * scorer.boost);
-                        } else {
-                            stack.push(0.0f64);
+                            Some(TermScorerState {
+                                term_frequencies: TermFrequencyIterator::new(PostingsIterator::new(doc_id_set), term_frequencies),
+                                total_tokens: total_tokens,
+                                total_docs: total_docs,
+                                term_document_frequency: term_document_frequency,
+                                max_score: if max_score > 0.0 { max_score } else { 0.0 },
+                            })
                         }
+                        _ => None,
                     }
-                    None => stack.push(0.0f64),
+                }
+                _ => None,
+            };
+
+            term_scorers.push(term_scorer);
+        }
+
+        Ok(SegmentScorer {
+            score_function: score_function,
+            term_scorers: term_scorers,
+            field_norms: field_norms,
+        })
+    }
+
+    /// The length of a field in a document
+    ///
+    /// Fields that don't have their lengths stored are given the average length.
+    fn field_length(&self, doc_id: u32, field_ref: FieldRef, total_tokens: u64, total_docs: u64) -> f64 {
+        match self.field_norms.get(&field_ref) {
+            Some(&Some(ref norms)) => norms.length(doc_id),
+            _ => average_length(total_tokens, total_docs),
+        }
+    }
+
+    /// Finds the first document from the target onwards that could score at least
+    /// the threshold, or None if there aren't any
+    ///
+    /// This is the pivot selection step of the WAND algorithm. The terms are sorted by
+    /// the next document they're in and their highest possible scores are added up
+    /// until they reach the threshold. Documents before this "pivot" document can only
+    /// contain the terms that were added before it, so they can't reach the threshold.
+    fn next_competitive_doc(&mut self, target: u32, threshold: f64, linear_score_function: &LinearScoreFunction) -> Option<u32> {
+        let mut cursors = Vec::new();
+        for (op_index, term_scorer) in self.term_scorers.iter_mut().enumerate() {
+            if let Some(ref mut term_scorer) = *term_scorer {
+                term_scorer.term_frequencies.seek(target);
+
+                if let Some(doc) = term_scorer.term_frequencies.doc() {
+                    cursors.push((doc, linear_score_function.weights[op_index] * term_scorer.max_score));
                 }
             }
-            ScoreFunctionOp::CombinatorScorer(num_vals, ref scorer) => {
-                let score = match *scorer {
-                    CombinatorScorer::Avg => {
-                        let mut total_score = 0.0f64;
+        }
 
-                        for _ in 0..num_vals {
-                            total_score += stack.pop().expect("document scorer: stack underflow");
-                        }
+        cursors.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut max_score = linear_score_function.constant;
+        for (doc, max_term_score) in cursors {
+            max_score += max_term_score;
+
+            if max_score >= threshold {
+                return Some(doc);
+            }
+        }
 
-                        total_score / num_vals as f64
+        None
+    }
+
+    fn score(&mut self, doc_id: u32) -> Result<f64, String> {
+        // Execute score function
+        let score_function = self.score_function;
+        let mut stack = Vec::new();
+        for (op_index, op) in score_function.iter().enumerate() {
+            match *op {
+                ScoreFunctionOp::Literal(val) => stack.push(val),
+                ScoreFunctionOp::TermScorer(field_ref, _, ref scorer) => {
+                    let term_frequency = match self.term_scorers[op_index] {
+                        Some(ref mut term_scorer) => term_scorer.term_frequencies.term_frequency(doc_id),
+                        None => None,
+                    };
+
+                    match term_frequency {
+                        Some(term_frequency) => {
+                            let term_scorer = self.term_scorers[op_index].as_ref().unwrap();
+                            let field_length = self.field_length(doc_id, field_ref, term_scorer.total_tokens, term_scorer.total_docs);
+
+                            let score = scorer.similarity_model.score(term_frequency, field_length, term_scorer.total_tokens, term_scorer.total_docs, term_scorer.term_document_frequency);
+                            stack.push(score * scorer.boost);
+                        }
+                        None => stack.push(0.0f64),
                     }
-                    CombinatorScorer::Max => {
-                        let mut max_score = 0.0f64;
+                }
+                ScoreFunctionOp::CombinatorScorer(num_vals, ref scorer) => {
+                    let score = match *scorer {
+                        CombinatorScorer::Avg => {
+                            let mut total_score = 0.0f64;
 
-                        for _ in 0..num_vals {
-                            let score = stack.pop().expect("document scorer: stack underflow");
-                            if score > max_score {
-                                max_score = score
+                            for _ in 0..num_vals {
+                                total_score += stack.pop().expect("document scorer: stack underflow");
                             }
+
+                            total_score / num_vals as f64
                         }
+                        CombinatorScorer::Max => {
+                            let mut max_score = 0.0f64;
 
-                        max_score
-                    }
-                };
+                            for _ in 0..num_vals {
+                                let score = stack.pop().expect("document scorer: stack underflow");
+                                if score > max_score {
+                                    max_score = score
+                                }
+                            }
+
+                            max_score
+                        }
+                    };
 
-                stack.push(score);
+                    stack.push(score);
+                }
             }
         }
+
+        if !stack.len() == 1 {
+            // This shouldn't be possible unless there's a bug in the planner
+            panic!("document scorer: stack size too big ({})", stack.len());
+        }
+
+        Ok(stack.pop().expect("document scorer: stack underflow"))
     }
+}
 
-    if !stack.len() == 1 {
-        // This shouldn't be possible unless there's a bug in the planner
-        panic!("document scorer: stack size too big ({})", stack.len());
+
+/// How many documents a segment search collects between checks of the shared score floor
+const SCORE_FLOOR_SYNC_INTERVAL: usize = 256;
+
+
+/// The highest `min_competitive_score` that any of the collectors of a parallel search
+/// have reached
+///
+/// Each segment is searched into its own collector which only sees that segment's
+/// documents. A document that can't get into one of these collectors can't get into the
+/// merged collector either, so every segment may skip documents that can't reach the
+/// highest threshold of any of them. Segment searches only check this every
+/// `SCORE_FLOOR_SYNC_INTERVAL` documents so the threads rarely have to wait for it.
+pub struct ScoreFloor {
+    floor: Mutex<Option<f64>>,
+}
+
+
+impl ScoreFloor {
+    pub fn new() -> ScoreFloor {
+        ScoreFloor {
+            floor: Mutex::new(None),
+        }
     }
 
-    Ok(stack.pop().expect("document scorer: stack underflow"))
+    /// Raises the floor to `score` if it's higher, returns the new floor
+    pub fn raise(&self, score: Option<f64>) -> Option<f64> {
+        let mut floor = self.floor.lock().unwrap();
+        *floor = max_threshold(*floor, score);
+        *floor
+    }
 }
 
 
-fn search_segment<C: Collector, S: Segment, R: StatisticsReader>(collector: &mut C, plan: &SearchPlan, segment: &S, mut stats: &mut R) -> Result<(), String> {
-    let matches = try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, segment));
+fn max_threshold(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a > b { a } else { b }),
+        (Some(a), None) => Some(a),
+        (None, b) => b,
+    }
+}
+
 
-    // Score documents and pass to collector
-    for doc in matches.iter() {
-        let score = try!(score_doc(doc, &plan.score_function, segment, stats));
+/// A search of a single segment
+struct SegmentSearch<'a, S: Segment + 'a> {
+    segment: &'a S,
+    matches: Box<DocIterator>,
+    scorer: SegmentScorer<'a>,
 
-        let doc_ref = segment.doc_ref(doc);
-        let doc_match = DocumentMatch::new_scored(doc_ref.as_u64(), score);
-        collector.collect(doc_match);
+    /// If the score function adds up term scores, we can skip documents that can't
+    /// score highly enough to get into the collector
+    linear_score_function: Option<LinearScoreFunction>,
+}
+
+
+impl<'a, S: Segment + 'a> SegmentSearch<'a, S> {
+    /// Loads the term directories and statistics that are needed to search the segment
+    fn new<R: StatisticsReader>(plan: &'a SearchPlan, segment: &'a S, stats: &mut R) -> Result<SegmentSearch<'a, S>, String> {
+        let term_directories = try!(load_term_directories(plan, segment));
+
+        Ok(SegmentSearch {
+            segment: segment,
+            matches: try!(build_boolean_query_iterator(&plan.boolean_query, plan.boolean_query_is_negated, segment, &term_directories)),
+            scorer: try!(SegmentScorer::new(&plan.score_function, segment, &term_directories, stats)),
+            linear_score_function: LinearScoreFunction::new(&plan.score_function),
+        })
+    }
+
+    /// Scores the matching documents and passes them to the collector
+    ///
+    /// If the segment is being searched in parallel with others, `score_floor` shares
+    /// the collectors' thresholds between them.
+    fn run<C: Collector>(mut self, collector: &mut C, score_floor: Option<&ScoreFloor>) -> Result<(), String> {
+        let mut shared_threshold = score_floor.and_then(|score_floor| score_floor.raise(None));
+        let mut docs_since_sync = 0;
+
+        while let Some(doc) = self.matches.doc() {
+            if let Some(ref linear_score_function) = self.linear_score_function {
+                if let Some(threshold) = max_threshold(collector.min_competitive_score(), shared_threshold) {
+                    // Documents that don't contain any of the terms score the constant,
+                    // we can only skip documents if this isn't enough
+                    if linear_score_function.constant < threshold {
+                        match self.scorer.next_competitive_doc(doc, threshold, linear_score_function) {
+                            Some(competitive_doc) if competitive_doc > doc => {
+                                self.matches.seek(competitive_doc);
+                                continue;
+                            }
+                            Some(_) => {}
+                            None => break,
+                        }
+                    }
+                }
+            }
+
+            let score = try!(self.scorer.score(doc));
+
+            let doc_ref = self.segment.doc_ref(doc);
+            let doc_match = DocumentMatch::new_scored(doc_ref.as_u64(), score);
+            collector.collect(doc_match);
+
+            if let Some(score_floor) = score_floor {
+                docs_since_sync += 1;
+                if docs_since_sync >= SCORE_FLOOR_SYNC_INTERVAL {
+                    shared_threshold = score_floor.raise(collector.min_competitive_score());
+                    docs_since_sync = 0;
+                }
+            }
+
+            self.matches.advance();
+        }
+
+        // Pass this segment's final threshold on to the segments that are still running
+        if let Some(score_floor) = score_floor {
+            score_floor.raise(collector.min_competitive_score());
+        }
+
+        Ok(())
     }
+}
 
-    Ok(())
+
+fn search_segment<C: Collector, S: Segment, R: StatisticsReader>(collector: &mut C, plan: &SearchPlan, segment: &S, mut stats: &mut R) -> Result<(), String> {
+    try!(SegmentSearch::new(plan, segment, stats)).run(collector, None)
 }
 
 
 impl<'a> RocksDBIndexReader<'a> {
     pub fn search<C: Collector>(&self, collector: &mut C, query: &Query) -> Result<(), String> {
-        // Plan query
-        let plan = plan_query(&self, query, collector.needs_score());
-
         // Initialise statistics reader
         let mut stats = RocksDBStatisticsReader::new(&self);
 
+        self.search_with_statistics(collector, query, &mut stats)
+    }
+
+    /// Runs a search using the provided statistics reader for scoring
+    ///
+    /// This allows searches across many indices to be scored using statistics that were
+    /// aggregated from all of them.
+    pub fn search_with_statistics<C: Collector, R: StatisticsReader>(&self, collector: &mut C, query: &Query, stats: &mut R) -> Result<(), String> {
+        // Plan query
+        let plan = try!(plan_query(&self, query, collector.needs_score()));
+
         // Run query on each segment
         for segment in self.store.segments.iter_active(&self) {
-            try!(search_segment(collector, &plan, &segment, &mut stats));
+            try!(search_segment(collector, &plan, &segment, stats));
         }
 
         Ok(())
@@ -341,28 +341,30 @@ impl BooleanQueryBuilder {
 }
 
 
-fn plan_boolean_query_combinator<J: Fn(&mut BooleanQueryBuilder) -> ()> (index_reader: &RocksDBIndexReader, mut builder: &mut BooleanQueryBuilder, queries: &Vec<Query>, join_cb: J) {
+fn plan_boolean_query_combinator<J: Fn(&mut BooleanQueryBuilder) -> ()> (index_reader: &RocksDBIndexReader, mut builder: &mut BooleanQueryBuilder, queries: &Vec<Query>, join_cb: J) -> Result<(), String> {
     match queries.len() {
         0 => {
             builder.push_empty();
         }
-        1 =>  plan_boolean_query(index_reader, &mut builder, &queries[0]),
+        1 =>  try!(plan_boolean_query(index_reader, &mut builder, &queries[0])),
         _ => {
             let mut query_iter = queries.iter();
-            plan_boolean_query(index_reader, &mut builder, query_iter.next().unwrap());
+            try!(plan_boolean_query(index_reader, &mut builder, query_iter.next().unwrap()));
 
             for query in query_iter {
-                plan_boolean_query(index_reader, &mut builder, query);
+                try!(plan_boolean_query(index_reader, &mut builder, query));
 
                 // Add the join operation
                 join_cb(&mut builder);
             }
         }
     }
+
+    Ok(())
 }
 
 
-pub fn plan_boolean_query(index_reader: &RocksDBIndexReader, mut builder: &mut BooleanQueryBuilder, query: &Query) {
+pub fn plan_boolean_query(index_reader: &RocksDBIndexReader, mut builder: &mut BooleanQueryBuilder, query: &Query) -> Result<(), String> {
     match *query {
         Query::All{..} => {
             builder.push_full();
@@ -372,12 +374,12 @@ pub fn plan_boolean_query(index_reader: &RocksDBIndexReader, mut builder: &mut B
         }
         Query::Term{field, ref term, ..} => {
             // Get term
-            let term_ref = match index_reader.store.term_dictionary.get(term) {
+            let term_ref = match try!(index_reader.term_ref(field, term)) {
                 Some(term_ref) => term_ref,
                 None => {
                     // Term doesn't exist, so will never match
                     builder.push_empty();
-                    return
+                    return Ok(());
                 }
             };
 
@@ -386,29 +388,31 @@ pub fn plan_boolean_query(index_reader: &RocksDBIndexReader, mut builder: &mut B
         Query::MultiTerm{field, ref term_selector, ..} => {
             // Get terms
             builder.push_empty();
-            for term_ref in index_reader.store.term_dictionary.select(term_selector) {
+            for term_ref in index_reader.select_terms(field, term_selector) {
                 builder.push_term_directory(field, term_ref);
                 builder.or_combinator();
             }
         }
         Query::Conjunction{ref queries} => {
-            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.and_combinator());
+            try!(plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.and_combinator()));
         }
         Query::Disjunction{ref queries} => {
-            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
+            try!(plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator()));
         }
         Query::DisjunctionMax{ref queries} => {
-            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
+            try!(plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator()));
         }
         Query::Filter{ref query, ref filter} => {
-            plan_boolean_query(index_reader, &mut builder, query);
-            plan_boolean_query(index_reader, &mut builder, filter);
+            try!(plan_boolean_query(index_reader, &mut builder, query));
+            try!(plan_boolean_query(index_reader, &mut builder, filter));
             builder.and_combinator();
         }
         Query::Exclude{ref query, ref exclude} => {
-            plan_boolean_query(index_reader, &mut builder, query);
-            plan_boolean_query(index_reader, &mut builder, exclude);
+            try!(plan_boolean_query(index_reader, &mut builder, query));
+            try!(plan_boolean_query(index_reader, &mut builder, exclude));
             builder.andnot_combinator();
         }
     }
+
+    Ok(())
 }
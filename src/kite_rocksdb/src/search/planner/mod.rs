@@ -27,12 +27,12 @@ impl SearchPlan {
 }
 
 
-pub fn plan_query(index_reader: &RocksDBIndexReader, query: &Query, score: bool) -> SearchPlan {
+pub fn plan_query(index_reader: &RocksDBIndexReader, query: &Query, score: bool) -> Result<SearchPlan, String> {
     let mut plan = SearchPlan::new();
 
     // Plan boolean query
     let mut builder = BooleanQueryBuilder::new();
-    plan_boolean_query(index_reader, &mut builder, query);
+    try!(plan_boolean_query(index_reader, &mut builder, query));
 
     // Add operations to exclude deleted documents to boolean query
     builder.push_deletion_list();
@@ -44,10 +44,10 @@ pub fn plan_query(index_reader: &RocksDBIndexReader, query: &Query, score: bool)
 
     // Plan score function
     if score {
-        plan_score_function(index_reader, &mut plan.score_function, query);
+        try!(plan_score_function(index_reader, &mut plan.score_function, query));
     } else {
         plan.score_function.push(ScoreFunctionOp::Literal(0.0f64));
     }
 
-    plan
+    Ok(plan)
 }
@@ -21,27 +21,29 @@ pub enum ScoreFunctionOp {
 }
 
 
-fn plan_score_function_combinator(index_reader: &RocksDBIndexReader, mut score_function: &mut Vec<ScoreFunctionOp>, queries: &Vec<Query>, scorer: CombinatorScorer) {
+fn plan_score_function_combinator(index_reader: &RocksDBIndexReader, mut score_function: &mut Vec<ScoreFunctionOp>, queries: &Vec<Query>, scorer: CombinatorScorer) -> Result<(), String> {
     match queries.len() {
         0 => {
             score_function.push(ScoreFunctionOp::Literal(0.0f64));
         }
-        1 =>  plan_score_function(index_reader, &mut score_function, &queries[0]),
+        1 =>  try!(plan_score_function(index_reader, &mut score_function, &queries[0])),
         _ => {
             let mut query_iter = queries.iter();
-            plan_score_function(index_reader, &mut score_function, query_iter.next().unwrap());
+            try!(plan_score_function(index_reader, &mut score_function, query_iter.next().unwrap()));
 
             for query in query_iter {
-                plan_score_function(index_reader, &mut score_function, query);
+                try!(plan_score_function(index_reader, &mut score_function, query));
             }
         }
     }
 
     score_function.push(ScoreFunctionOp::CombinatorScorer(queries.len() as u32, scorer));
+
+    Ok(())
 }
 
 
-pub fn plan_score_function(index_reader: &RocksDBIndexReader, mut score_function: &mut Vec<ScoreFunctionOp>, query: &Query) {
+pub fn plan_score_function(index_reader: &RocksDBIndexReader, mut score_function: &mut Vec<ScoreFunctionOp>, query: &Query) -> Result<(), String> {
     match *query {
         Query::All{ref score} => {
             score_function.push(ScoreFunctionOp::Literal(*score));
@@ -51,12 +53,12 @@ pub fn plan_score_function(index_reader: &RocksDBIndexReader, mut score_function
         }
         Query::Term{field, ref term, ref scorer} => {
             // Get term
-            let term_ref = match index_reader.store.term_dictionary.get(term) {
+            let term_ref = match try!(index_reader.term_ref(field, term)) {
                 Some(term_ref) => term_ref,
                 None => {
                     // Term doesn't exist, so will never match
                     score_function.push(ScoreFunctionOp::Literal(0.0f64));
-                    return
+                    return Ok(());
                 }
             };
 
@@ -65,7 +67,7 @@ pub fn plan_score_function(index_reader: &RocksDBIndexReader, mut score_function
         Query::MultiTerm{field, ref term_selector, ref scorer} => {
             // Get terms
             let mut total_terms = 0;
-            for term_ref in index_reader.store.term_dictionary.select(term_selector) {
+            for term_ref in index_reader.select_terms(field, term_selector) {
                 score_function.push(ScoreFunctionOp::TermScorer(field, term_ref, scorer.clone()));
                 total_terms += 1;
             }
@@ -82,19 +84,21 @@ pub fn plan_score_function(index_reader: &RocksDBIndexReader, mut score_function
             }
         }
         Query::Conjunction{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
+            try!(plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg));
         }
         Query::Disjunction{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
+            try!(plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg));
         }
         Query::DisjunctionMax{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max);
+            try!(plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max));
         }
         Query::Filter{ref query, ..} => {
-            plan_score_function(index_reader, &mut score_function, query);
+            try!(plan_score_function(index_reader, &mut score_function, query));
         }
         Query::Exclude{ref query, ..} => {
-            plan_score_function(index_reader, &mut score_function, query);
+            try!(plan_score_function(index_reader, &mut score_function, query));
         }
     }
+
+    Ok(())
 }
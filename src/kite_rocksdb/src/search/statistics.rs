@@ -3,9 +3,12 @@ use std::collections::HashMap;
 use kite::schema::FieldRef;
 use kite::term::TermRef;
 use kite::segment::Segment;
+use kite::doc_id_set::DocIdSet;
+use byteorder::{ByteOrder, BigEndian};
 
 use RocksDBIndexReader;
 use key_builder::KeyBuilder;
+use segment::RocksDBSegment;
 
 
 pub trait StatisticsReader {
@@ -15,8 +18,16 @@ pub trait StatisticsReader {
 }
 
 
+/// Reads the index statistics used for scoring
+///
+/// Deleting a document only adds it to its segment's deletion list, the index
+/// statistics aren't corrected until the segment is merged. To keep deleted documents
+/// out of the scores before then, the deleted documents of each field are subtracted
+/// from `total_docs` and `total_tokens` as they are read. Term document frequencies
+/// are left as they are, so they still count deleted documents until the next merge.
 pub struct RocksDBStatisticsReader<'a> {
     index_reader: &'a RocksDBIndexReader<'a>,
+    deletion_lists: Option<Vec<(RocksDBSegment<'a>, DocIdSet)>>,
     total_docs: HashMap<FieldRef, i64>,
     total_tokens: HashMap<FieldRef, i64>,
     term_document_frequencies: HashMap<(FieldRef, TermRef), i64>,
@@ -27,22 +38,71 @@ impl<'a> RocksDBStatisticsReader<'a> {
     pub fn new(index_reader: &'a RocksDBIndexReader) -> RocksDBStatisticsReader<'a> {
         RocksDBStatisticsReader {
             index_reader: index_reader,
+            deletion_lists: None,
             total_docs: HashMap::new(),
             total_tokens: HashMap::new(),
             term_document_frequencies: HashMap::new(),
         }
     }
 
+    /// Reads an index statistic, these are aggregated across all active segments
+    /// (see `KeyBuilder::index_stat`)
     fn get_statistic(&self, name: &[u8]) -> Result<i64, String> {
-        let mut val = 0;
+        let kb = KeyBuilder::index_stat(name);
+        let val = try!(self.index_reader.snapshot.get(&kb.key())).map(|val| BigEndian::read_i64(&val));
+        Ok(val.unwrap_or(0))
+    }
 
-        for segment in self.index_reader.store.segments.iter_active(&self.index_reader) {
-            if let Some(new_val) = try!(segment.load_statistic(name)) {
-                val += new_val;
+    /// Loads the deletion lists of the active segments that have deleted documents
+    fn load_deletion_lists(&mut self) -> Result<&Vec<(RocksDBSegment<'a>, DocIdSet)>, String> {
+        if self.deletion_lists.is_none() {
+            let mut deletion_lists = Vec::new();
+
+            for segment in self.index_reader.store.segments.iter_active(self.index_reader) {
+                if let Some(deletion_list) = try!(segment.load_deletion_list()) {
+                    if deletion_list.iter().next().is_some() {
+                        deletion_lists.push((segment, deletion_list));
+                    }
+                }
             }
+
+            self.deletion_lists = Some(deletion_lists);
         }
 
-        Ok(val)
+        Ok(self.deletion_lists.as_ref().unwrap())
+    }
+
+    /// Counts the deleted documents and their tokens in a field
+    ///
+    /// These are counted from the field norms. If a segment has no norms for the field,
+    /// every deleted document in it is assumed to have the field (up to the number of
+    /// documents in the segment that have it) and their tokens aren't counted. Tokens
+    /// counted from `Norms::Byte` norms are rounded, like they are when segments are merged.
+    fn deleted_field_statistics(&mut self, field_ref: FieldRef) -> Result<(i64, i64), String> {
+        let mut deleted_docs = 0;
+        let mut deleted_tokens = 0;
+
+        for &(ref segment, ref deletion_list) in try!(self.load_deletion_lists()).iter() {
+            match try!(segment.load_field_norms(field_ref)) {
+                Some(field_norms) => {
+                    for doc_id in deletion_list.iter() {
+                        let token_count = field_norms.token_count(doc_id);
+                        if token_count > 0 {
+                            deleted_docs += 1;
+                            deleted_tokens += token_count as i64;
+                        }
+                    }
+                }
+                None => {
+                    let stat_name = KeyBuilder::segment_stat_total_field_docs_stat_name(field_ref.ord());
+                    let field_docs = try!(segment.load_statistic(&stat_name)).unwrap_or(0);
+                    let segment_deleted_docs = deletion_list.iter().count() as i64;
+                    deleted_docs += if segment_deleted_docs < field_docs { segment_deleted_docs } else { field_docs };
+                }
+            }
+        }
+
+        Ok((deleted_docs, deleted_tokens))
     }
 }
 
@@ -54,7 +114,8 @@ impl<'a> StatisticsReader for RocksDBStatisticsReader<'a> {
         }
 
         let stat_name = KeyBuilder::segment_stat_total_field_docs_stat_name(field_ref.ord());
-        let val = try!(self.get_statistic(&stat_name));
+        let (deleted_docs, _) = try!(self.deleted_field_statistics(field_ref));
+        let val = try!(self.get_statistic(&stat_name)) - deleted_docs;
         self.total_docs.insert(field_ref, val);
         Ok(val)
     }
@@ -65,7 +126,8 @@ impl<'a> StatisticsReader for RocksDBStatisticsReader<'a> {
         }
 
         let stat_name = KeyBuilder::segment_stat_total_field_tokens_stat_name(field_ref.ord());
-        let val = try!(self.get_statistic(&stat_name));
+        let (_, deleted_tokens) = try!(self.deleted_field_statistics(field_ref));
+        let val = try!(self.get_statistic(&stat_name)) - deleted_tokens;
         self.total_tokens.insert(field_ref, val);
         Ok(val)
     }
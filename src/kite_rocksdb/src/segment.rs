@@ -2,10 +2,15 @@ use kite::segment::Segment;
 use kite::schema::FieldRef;
 use kite::term::TermRef;
 use kite::doc_id_set::DocIdSet;
+use kite::norms::FieldNorms;
+use kite::query::term_selector::TermSelector;
 use byteorder::{ByteOrder, BigEndian};
 
 use RocksDBIndexReader;
 use key_builder::KeyBuilder;
+use segment_term_dictionary;
+use term_frequency_list;
+use term_score_bound::TermScoreBound;
 
 
 pub struct RocksDBSegment<'a> {
@@ -21,6 +26,12 @@ impl<'a> RocksDBSegment<'a> {
             id: id,
         }
     }
+
+    /// Finds the terms in a field of this segment that match the selector
+    pub fn select_terms(&self, field_ref: FieldRef, term_selector: &TermSelector) -> Vec<TermRef> {
+        let mut iter = self.reader.snapshot.iterator();
+        segment_term_dictionary::select_terms(&mut iter, self.id, field_ref.ord(), term_selector)
+    }
 }
 
 
@@ -35,7 +46,7 @@ impl<'a> Segment for RocksDBSegment<'a> {
         Ok(val)
     }
 
-    fn load_stored_field_value_raw(&self, doc_ord: u16, field_ref: FieldRef, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    fn load_stored_field_value_raw(&self, doc_ord: u32, field_ref: FieldRef, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
         let kb = KeyBuilder::stored_field_value(self.id, doc_ord, field_ref.ord(), value_type);
         let val = try!(self.reader.snapshot.get(&kb.key()));
         Ok(val.map(|v| v.to_vec()))
@@ -43,13 +54,37 @@ impl<'a> Segment for RocksDBSegment<'a> {
 
     fn load_term_directory(&self, field_ref: FieldRef, term_ref: TermRef) -> Result<Option<DocIdSet>, String> {
         let kb = KeyBuilder::segment_dir_list(self.id, field_ref.ord(), term_ref.ord());
-        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| DocIdSet::from_bytes(doc_id_set.to_vec()));
-        Ok(doc_id_set)
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(doc_id_set) => Ok(Some(try!(DocIdSet::from_bytes(doc_id_set.to_vec()).map_err(|e| format!("failed to read term directory: {:?}", e))))),
+            None => Ok(None),
+        }
+    }
+
+    fn load_term_frequencies(&self, field_ref: FieldRef, term_ref: TermRef) -> Result<Option<Vec<(u32, u32)>>, String> {
+        let kb = KeyBuilder::segment_term_frequency_list(self.id, field_ref.ord(), term_ref.ord());
+        let term_frequencies = try!(self.reader.snapshot.get(&kb.key())).map(|term_frequencies| term_frequency_list::decode(&term_frequencies));
+        Ok(term_frequencies)
+    }
+
+    fn load_term_score_bound(&self, field_ref: FieldRef, term_ref: TermRef) -> Result<Option<(u32, u32)>, String> {
+        let kb = KeyBuilder::segment_term_score_bound(self.id, field_ref.ord(), term_ref.ord());
+        let term_score_bound = try!(self.reader.snapshot.get(&kb.key())).map(|term_score_bound| TermScoreBound::from_bytes(&term_score_bound));
+        Ok(term_score_bound.map(|bound| (bound.max_term_frequency, bound.min_field_length)))
+    }
+
+    fn load_field_norms(&self, field_ref: FieldRef) -> Result<Option<FieldNorms>, String> {
+        let kb = KeyBuilder::segment_field_norms(self.id, field_ref.ord());
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(field_norms) => Ok(Some(try!(FieldNorms::from_bytes(&field_norms).map_err(|e| format!("failed to read field norms: {:?}", e))))),
+            None => Ok(None),
+        }
     }
 
     fn load_deletion_list(&self) -> Result<Option<DocIdSet>, String> {
         let kb = KeyBuilder::segment_del_list(self.id);
-        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| DocIdSet::from_bytes(doc_id_set.to_vec()));
-        Ok(doc_id_set)
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(doc_id_set) => Ok(Some(try!(DocIdSet::from_bytes(doc_id_set.to_vec()).map_err(|e| format!("failed to read deletion list: {:?}", e))))),
+            None => Ok(None),
+        }
     }
 }
@@ -2,19 +2,23 @@ use std::collections::HashMap;
 
 use kite::{Document, Term, TermRef};
 use kite::schema::FieldRef;
-use byteorder::{BigEndian, WriteBytesExt};
 
 use key_builder::KeyBuilder;
+use term_score_bound::TermScoreBound;
 
 
 #[derive(Debug)]
 pub struct SegmentBuilder {
-    current_doc: u16,
+    current_doc: u32,
     pub term_dictionary: HashMap<Term, TermRef>,
     current_term_ref: u32,
-    pub term_directories: HashMap<(FieldRef, TermRef), Vec<u16>>,
+    pub term_directories: HashMap<(FieldRef, TermRef), Vec<u32>>,
+    pub term_frequency_lists: HashMap<(FieldRef, TermRef), Vec<(u32, u32)>>,
+    pub term_score_bounds: HashMap<(FieldRef, TermRef), TermScoreBound>,
+    /// The number of tokens in each field of each document, indexed by document ordinal
+    pub field_lengths: HashMap<FieldRef, Vec<u32>>,
     pub statistics: HashMap<Vec<u8>, i64>,
-    pub stored_field_values: HashMap<(FieldRef, u16, Vec<u8>), Vec<u8>>,
+    pub stored_field_values: HashMap<(FieldRef, u32, Vec<u8>), Vec<u8>>,
 }
 
 
@@ -32,6 +36,9 @@ impl SegmentBuilder {
             term_dictionary: HashMap::new(),
             current_term_ref: 0,
             term_directories: HashMap::new(),
+            term_frequency_lists: HashMap::new(),
+            term_score_bounds: HashMap::new(),
+            field_lengths: HashMap::new(),
             statistics: HashMap::new(),
             stored_field_values: HashMap::new(),
         }
@@ -51,7 +58,7 @@ impl SegmentBuilder {
     }
 
     // TODO: Need to translate field names to field refs and terms to term refs
-    pub fn add_document(&mut self, doc: &Document) -> Result<u16, DocumentInsertError> {
+    pub fn add_document(&mut self, doc: &Document) -> Result<u32, DocumentInsertError> {
         // Get document ord
         let doc_id = self.current_doc;
         self.current_doc += 1;
@@ -79,31 +86,23 @@ impl SegmentBuilder {
             // Term frequencies
             for (term_ref, frequency) in term_frequencies.drain() {
                 // Write term frequency
-                // 1 is by far the most common frequency. At search time, we interpret a missing
-                // key as meaning there is a term frequency of 1
+                // 1 is by far the most common frequency. At search time, we interpret a
+                // document missing from the list as meaning there is a term frequency of 1
                 if frequency != 1 {
-                    let mut value_type = vec![b't', b'f'];
-                    value_type.extend(term_ref.ord().to_string().as_bytes());
-
-                    let mut frequency_bytes: Vec<u8> = Vec::new();
-                    frequency_bytes.write_i64::<BigEndian>(frequency).unwrap();
-
-                    self.stored_field_values.insert((*field, doc_id, value_type), frequency_bytes);
+                    self.term_frequency_lists.entry((*field, term_ref)).or_insert_with(Vec::new).push((doc_id, frequency));
                 }
 
-                // Increment term document frequency
-                let stat_name = KeyBuilder::segment_stat_term_doc_frequency_stat_name(field.ord(), term_ref.ord());
-                let mut stat = self.statistics.entry(stat_name).or_insert(0);
-                *stat += 1;
+                // Widen the term's score bound to include this document
+                self.term_score_bounds.entry((*field, term_ref))
+                    .or_insert_with(|| TermScoreBound::new(frequency, field_token_count as u32))
+                    .add(frequency, field_token_count as u32);
             }
 
             // Field length
-            // Used by the BM25 similarity model
-            let length = ((field_token_count as f64).sqrt() - 1.0) * 3.0;
-            let length = if length > 255.0 { 255.0 } else { length } as u8;
-            if length != 0 {
-                self.stored_field_values.insert((*field, doc_id, b"len".to_vec()), vec![length]);
-            }
+            // Used by the BM25 similarity model. Documents without the field are left as 0
+            let field_lengths = self.field_lengths.entry(*field).or_insert_with(Vec::new);
+            field_lengths.resize(doc_id as usize, 0);
+            field_lengths.push(field_token_count as u32);
 
             // Increment total field docs
             {
@@ -1,19 +1,27 @@
 use std::str;
-use std::collections::{HashMap, BTreeSet};
+use std::u32;
+use std::collections::{HashMap, BTreeMap, BTreeSet};
 
 use rocksdb::{self, WriteBatch, WriteOptions};
-use kite::doc_id_set::DocIdSet;
+use kite::doc_id_set::{DocIdSet, DocIdSetReadError};
 use kite::document::DocRef;
-use byteorder::{ByteOrder, BigEndian, WriteBytesExt};
+use kite::norms::{FieldNorms, FieldNormsReadError};
+use byteorder::{ByteOrder, BigEndian};
 
 use RocksDBIndexStore;
 use key_builder::KeyBuilder;
+use segment_stats::parse_statistic_key;
+use segment_term_dictionary;
+use term_frequency_list;
+use term_score_bound::TermScoreBound;
 
 
 #[derive(Debug)]
 pub enum SegmentMergeError {
     TooManyDocs,
     RocksDBError(rocksdb::Error),
+    DocIdSetReadError(DocIdSetReadError),
+    FieldNormsReadError(FieldNormsReadError),
 }
 
 
@@ -24,18 +32,81 @@ impl From<rocksdb::Error> for SegmentMergeError {
 }
 
 
+impl From<DocIdSetReadError> for SegmentMergeError {
+    fn from(e: DocIdSetReadError) -> SegmentMergeError {
+        SegmentMergeError::DocIdSetReadError(e)
+    }
+}
+
+
+impl From<FieldNormsReadError> for SegmentMergeError {
+    fn from(e: FieldNormsReadError) -> SegmentMergeError {
+        SegmentMergeError::FieldNormsReadError(e)
+    }
+}
+
+
 impl From<SegmentMergeError> for String {
     fn from(e: SegmentMergeError) -> String {
         match e {
             SegmentMergeError::TooManyDocs => "Too many docs".to_string(),
             SegmentMergeError::RocksDBError(e) => e.into(),
+            SegmentMergeError::DocIdSetReadError(e) => format!("failed to read document id set: {:?}", e),
+            SegmentMergeError::FieldNormsReadError(e) => format!("failed to read field norms: {:?}", e),
+        }
+    }
+}
+
+
+/// Writes the merged term directories of a new segment and counts their statistics
+struct TermDirectoryMerge<'a> {
+    store: &'a RocksDBIndexStore,
+    write_options: &'a WriteOptions,
+    dest_segment: u32,
+    statistics: HashMap<Vec<u8>, i64>,
+    field_docs: HashMap<u32, DocIdSet>,
+}
+
+
+impl<'a> TermDirectoryMerge<'a> {
+    fn new(store: &'a RocksDBIndexStore, write_options: &'a WriteOptions, dest_segment: u32) -> TermDirectoryMerge<'a> {
+        TermDirectoryMerge {
+            store: store,
+            write_options: write_options,
+            dest_segment: dest_segment,
+            statistics: HashMap::new(),
+            field_docs: HashMap::new(),
         }
     }
+
+    /// Writes a merged term directory and counts its statistics
+    fn write_term_directory(&mut self, field: u32, term: u32, doc_ids: &mut Vec<u32>) -> Result<(), SegmentMergeError> {
+        if doc_ids.is_empty() {
+            // All of the documents were dropped
+            return Ok(());
+        }
+
+        let num_docs = doc_ids.len() as i64;
+        let doc_id_set = doc_ids.drain(..).collect::<DocIdSet>();
+
+        let kb = KeyBuilder::segment_dir_list(self.dest_segment, field, term);
+        try!(self.store.db.put_opt(&kb.key(), &doc_id_set.to_bytes(), self.write_options));
+
+        self.statistics.insert(KeyBuilder::segment_stat_term_doc_frequency_stat_name(field, term), num_docs);
+        self.field_docs.entry(field).or_insert_with(|| DocIdSet::new_filled(0)).union_with(&doc_id_set);
+
+        Ok(())
+    }
 }
 
 
 impl RocksDBIndexStore {
-    fn merge_segment_data(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_ref_mapping: &HashMap<DocRef, u16>) -> Result<(), SegmentMergeError> {
+    /// Writes the data of the merged segment
+    ///
+    /// Documents that are missing from the mapping were deleted before the merge
+    /// started, these are dropped from the new segment. Returns the amount that each
+    /// index statistic must be changed by when the merge is committed.
+    fn merge_segment_data(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_ref_mapping: &HashMap<DocRef, u32>) -> Result<HashMap<Vec<u8>, i64>, SegmentMergeError> {
         // Put source_segments in a BTreeSet as this is much faster for performing contains queries against
         let source_segments_btree = source_segments.iter().collect::<BTreeSet<_>>();
 
@@ -58,6 +129,15 @@ impl RocksDBIndexStore {
 
         let mut current_td_key: Option<(u32, u32)> = None;
         let mut current_td = Vec::new();
+        let mut term_directories = TermDirectoryMerge::new(self, &write_options, dest_segment);
+
+        // The number of tokens in each field that belong to dropped documents
+        // Each dropped document contains at least one occurrence of the term, any extra
+        // occurrences are counted from the term frequency lists
+        let mut dropped_tokens: HashMap<u32, i64> = HashMap::new();
+
+        // The number of source segments that contain each term, used when merging term score bounds
+        let mut term_segment_counts: HashMap<(u32, u32), usize> = HashMap::new();
 
         let mut iter = self.db.iterator();
         iter.seek(b"d");
@@ -75,31 +155,162 @@ impl RocksDBIndexStore {
                 if current_td_key != Some((field, term)) {
                     // Finished current term directory. Write it to the DB and start the next one
                     if let Some((field, term)) = current_td_key {
-                        let kb = KeyBuilder::segment_dir_list(dest_segment, field, term);
-                        try!(self.db.put_opt(&kb.key(), &current_td, &write_options));
-                        current_td.clear();
+                        try!(term_directories.write_term_directory(field, term, &mut current_td));
                     }
 
                     current_td_key = Some((field, term));
                 }
 
+                *term_segment_counts.entry((field, term)).or_insert(0) += 1;
+
                 // Merge term directory into the new one (and remap the doc ids)
-                let doc_id_set = DocIdSet::from_bytes(iter.value().unwrap().to_vec());
+                let doc_id_set = try!(DocIdSet::from_bytes(iter.value().unwrap().to_vec()));
                 for doc_id in doc_id_set.iter() {
                     let doc_ref = DocRef::from_segment_ord(segment, doc_id);
-                    let new_doc_id = doc_ref_mapping.get(&doc_ref).unwrap();
-                    current_td.write_u16::<BigEndian>(*new_doc_id).unwrap();
+                    match doc_ref_mapping.get(&doc_ref) {
+                        Some(new_doc_id) => current_td.push(*new_doc_id),
+                        None => *dropped_tokens.entry(field).or_insert(0) += 1,
+                    }
                 }
             }
         }
 
         // All done, write the last term directory
         if let Some((field, term)) = current_td_key {
-            let kb = KeyBuilder::segment_dir_list(dest_segment, field, term);
-            try!(self.db.put_opt(&kb.key(), &current_td, &write_options));
-            current_td.clear();
+            try!(term_directories.write_term_directory(field, term, &mut current_td));
+        }
+
+        let mut statistics = term_directories.statistics;
+        let field_docs = term_directories.field_docs;
+
+        // Merge the term frequency lists
+        // These are keyed the same way as term directories so they're merged in the same way
+
+        let mut current_tf_key: Option<(u32, u32)> = None;
+        let mut current_tf = Vec::new();
+
+        let mut iter = self.db.iterator();
+        iter.seek(b"p");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'p' {
+                // No more term frequency lists to merge
+                break;
+            }
+
+            let (field, term, segment) = parse_term_directory_key(&k);
+
+            if source_segments_btree.contains(&segment) {
+                if current_tf_key != Some((field, term)) {
+                    // Finished current term frequency list. Write it to the DB and start the next one
+                    if let Some((field, term)) = current_tf_key {
+                        if !current_tf.is_empty() {
+                            let kb = KeyBuilder::segment_term_frequency_list(dest_segment, field, term);
+                            try!(self.db.put_opt(&kb.key(), &term_frequency_list::encode(&current_tf), &write_options));
+                            current_tf.clear();
+                        }
+                    }
+
+                    current_tf_key = Some((field, term));
+                }
+
+                // Merge term frequency list into the new one (and remap the doc ids)
+                for (doc_id, frequency) in term_frequency_list::decode(&iter.value().unwrap()) {
+                    let doc_ref = DocRef::from_segment_ord(segment, doc_id);
+                    match doc_ref_mapping.get(&doc_ref) {
+                        Some(new_doc_id) => current_tf.push((*new_doc_id, frequency)),
+                        None => *dropped_tokens.entry(field).or_insert(0) += frequency as i64 - 1,
+                    }
+                }
+            }
         }
 
+        // All done, write the last term frequency list
+        if let Some((field, term)) = current_tf_key {
+            if !current_tf.is_empty() {
+                let kb = KeyBuilder::segment_term_frequency_list(dest_segment, field, term);
+                try!(self.db.put_opt(&kb.key(), &term_frequency_list::encode(&current_tf), &write_options));
+            }
+        }
+
+        // Merge the term score bounds
+        // The merged bound of each term is wide enough to include the documents of
+        // all the source segments. Segments written before score bounds were added
+        // don't have them, if any of the source segments that contain a term are
+        // missing its bound, the merged segment can't have a bound for it either.
+        //
+        // The merged field lengths are the token counts that were read back from the
+        // source segments' norms, these are rounded down if the norms weren't precise
+        // enough to store them exactly. The minimum field length of each bound is rounded
+        // the same way so it's never longer than the field of any document in the new
+        // segment.
+
+        let mut term_score_bounds: HashMap<(u32, u32), (TermScoreBound, usize)> = HashMap::new();
+        let mut source_field_norms: HashMap<(u32, u32), Option<FieldNorms>> = HashMap::new();
+
+        let mut iter = self.db.iterator();
+        iter.seek(b"b");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'b' {
+                // No more term score bounds to merge
+                break;
+            }
+
+            let (field, term, segment) = parse_term_directory_key(&k);
+
+            if source_segments_btree.contains(&segment) {
+                let mut term_score_bound = TermScoreBound::from_bytes(&iter.value().unwrap());
+
+                if !source_field_norms.contains_key(&(segment, field)) {
+                    let kb = KeyBuilder::segment_field_norms(segment, field);
+                    let field_norms = match try!(self.db.get(&kb.key())) {
+                        Some(field_norms) => Some(try!(FieldNorms::from_bytes(&field_norms))),
+                        None => None,
+                    };
+                    source_field_norms.insert((segment, field), field_norms);
+                }
+
+                if let Some(ref field_norms) = source_field_norms[&(segment, field)] {
+                    term_score_bound.min_field_length = field_norms.stored_token_count(term_score_bound.min_field_length);
+                }
+
+                let mut merged = term_score_bounds.entry((field, term)).or_insert((term_score_bound, 0));
+                merged.0.merge(&term_score_bound);
+                merged.1 += 1;
+            }
+        }
+
+        for (&(field, term), &(term_score_bound, num_segments)) in term_score_bounds.iter() {
+            if term_segment_counts.get(&(field, term)) != Some(&num_segments) {
+                continue;
+            }
+
+            let kb = KeyBuilder::segment_term_score_bound(dest_segment, field, term);
+            try!(self.db.put_opt(&kb.key(), &term_score_bound.to_bytes(), &write_options));
+        }
+
+        // Merge the term dictionaries
+        // TermRefs are the same in every segment so the new segment's term dictionary for
+        // each field is the union of the source segments' term dictionaries.
+
+        let mut term_dictionaries = BTreeMap::new();
+
+        for source_segment in source_segments.iter() {
+            let mut iter = self.db.iterator();
+            for (field_ord, terms) in segment_term_dictionary::read_term_dictionaries(&mut iter, *source_segment) {
+                term_dictionaries.entry(field_ord).or_insert_with(BTreeMap::new).extend(terms);
+            }
+        }
+
+        let mut write_batch = WriteBatch::default();
+        for (field_ord, terms) in term_dictionaries.iter() {
+            try!(segment_term_dictionary::write_term_dictionary(&mut write_batch, dest_segment, *field_ord, terms));
+        }
+        try!(self.db.write(write_batch));
+
         // Merge the stored values
         // All stored value keys start with the segment id. So we need to:
         // - Iterate all stored value keys that are prefixed by one of the stored segment ids
@@ -137,8 +348,11 @@ impl RocksDBIndexStore {
                 }
 
                 // Remap doc id
-                let doc_ref = DocRef::from_segment_ord(segment, doc_id as u16);
-                let new_doc_id = doc_ref_mapping.get(&doc_ref).unwrap();
+                let doc_ref = DocRef::from_segment_ord(segment, doc_id);
+                let new_doc_id = match doc_ref_mapping.get(&doc_ref) {
+                    Some(new_doc_id) => new_doc_id,
+                    None => continue,
+                };
 
                 // Write value into new segment
                 let kb = KeyBuilder::stored_field_value(dest_segment, *new_doc_id, field, &value_type);
@@ -146,22 +360,82 @@ impl RocksDBIndexStore {
             }
         }
 
-        // Merge the statistics
-        // Like stored values, these start with segment ids. But instead of just rewriting the
-        // key, we need to sum up all the statistics across the segments being merged.
+        // Merge the field lengths
+        // The lengths of each field are copied into a new array in the order of the new
+        // document ids. They are stored with the precision that is currently set for the
+        // field, so changes to the precision apply to older documents once they're merged.
 
-        let mut statistics = HashMap::new();
+        let mut field_lengths: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        let mut field_sources: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
 
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
+        for source_segment in source_segments.iter() {
+            let kb = KeyBuilder::segment_norms_prefix(*source_segment);
+            let mut iter = self.db.iterator();
+            iter.seek(&kb.key());
+            while iter.next() {
+                let k = iter.key().unwrap();
 
-            (segment, statistic_name)
+                if !k.starts_with(kb.key()) {
+                    // Segment finished
+                    break;
+                }
+
+                let field = str::from_utf8(&k[kb.key().len()..]).unwrap().parse::<u32>().unwrap();
+                let field_norms = try!(FieldNorms::from_bytes(&iter.value().unwrap()));
+                let lengths = field_lengths.entry(field).or_insert_with(|| vec![0; doc_ref_mapping.len()]);
+
+                for doc_id in 0..field_norms.num_docs() {
+                    let doc_ref = DocRef::from_segment_ord(*source_segment, doc_id);
+                    if let Some(new_doc_id) = doc_ref_mapping.get(&doc_ref) {
+                        lengths[*new_doc_id as usize] = field_norms.token_count(doc_id);
+                    }
+                }
+
+                field_sources.entry(field).or_insert_with(BTreeSet::new).insert(*source_segment);
+            }
         }
 
-        // Fetch and merge statistics
+        for (field, lengths) in field_lengths.iter() {
+            // If any of the source segments have the field but don't have its lengths
+            // (norms were disabled when it was written), we don't know the lengths of
+            // all the documents. Leave the lengths out so they're all treated the same.
+            let mut is_complete = true;
+            for source_segment in source_segments.iter() {
+                if field_sources[field].contains(source_segment) {
+                    continue;
+                }
+
+                let kb = KeyBuilder::segment_stat(*source_segment, &KeyBuilder::segment_stat_total_field_docs_stat_name(*field));
+                if let Some(total_field_docs) = try!(self.db.get(&kb.key())) {
+                    if BigEndian::read_i64(&total_field_docs) > 0 {
+                        is_complete = false;
+                        break;
+                    }
+                }
+            }
+
+            if !is_complete {
+                continue;
+            }
+
+            let norms = match self.schema.iter().find(|&(field_ref, _)| field_ref.ord() == *field) {
+                Some((_, field_info)) => field_info.norms(),
+                None => continue,
+            };
+
+            if let Some(field_norms) = FieldNorms::new(norms, lengths) {
+                let kb = KeyBuilder::segment_field_norms(dest_segment, *field);
+                try!(self.db.put_opt(&kb.key(), &field_norms.to_bytes(), &write_options));
+            }
+        }
+
+        // Write the statistics
+        // The statistics of the source segments are read so the index statistics can be
+        // adjusted by the difference between them and the new segment's statistics when
+        // the merge is committed. This subtracts the documents that were dropped.
+
+        let mut source_statistics: HashMap<Vec<u8>, i64> = HashMap::new();
+
         for source_segment in source_segments.iter() {
             let kb = KeyBuilder::segment_stat_prefix(*source_segment);
             let mut iter = self.db.iterator();
@@ -169,7 +443,7 @@ impl RocksDBIndexStore {
             while iter.next() {
                 let k = iter.key().unwrap();
                 if k[0] != b's' {
-                    // No more statistics to merge
+                    // No more statistics to read
                     break;
                 }
 
@@ -180,13 +454,37 @@ impl RocksDBIndexStore {
                     break;
                 }
 
+                if statistic_name == b"deleted_docs" {
+                    // Not an index statistic, this is set for the new segment when the merge is committed
+                    continue;
+                }
+
+                *source_statistics.entry(statistic_name).or_insert(0) += BigEndian::read_i64(&iter.value().unwrap());
+            }
+        }
 
-                let mut stat = statistics.entry(statistic_name).or_insert(0);
-                *stat += BigEndian::read_i64(&iter.value().unwrap());
+        for (field, doc_id_set) in field_docs {
+            let stat_name = KeyBuilder::segment_stat_total_field_docs_stat_name(field);
+            statistics.insert(stat_name, doc_id_set.iter().count() as i64);
+
+            // The token totals of the source segments are carried forward, less the tokens of
+            // the dropped documents. They can't be recounted from the term frequency lists as
+            // segments written before those were added don't have them. For these segments,
+            // only the first occurrence of each term in the dropped documents is subtracted.
+            let stat_name = KeyBuilder::segment_stat_total_field_tokens_stat_name(field);
+            if let Some(source_tokens) = source_statistics.get(&stat_name).cloned() {
+                let dropped = dropped_tokens.get(&field).cloned().unwrap_or(0);
+                statistics.insert(stat_name, source_tokens - dropped);
             }
         }
 
-        // Write merged statistics to new segment
+        statistics.insert(b"total_docs".to_vec(), doc_ref_mapping.len() as i64);
+
+        let mut statistics_delta = statistics.clone();
+        for (stat_name, stat_value) in source_statistics {
+            *statistics_delta.entry(stat_name).or_insert(0) -= stat_value;
+        }
+
         for (stat_name, stat_value) in statistics {
             let kb = KeyBuilder::segment_stat(dest_segment, &stat_name);
             let mut val_bytes = [0; 8];
@@ -199,15 +497,31 @@ impl RocksDBIndexStore {
         // before merging them so they can't be altered during merge. we cannot lock
         // this until the commit phase though.
 
-        Ok(())
+        Ok(statistics_delta)
     }
 
-    fn commit_segment_merge(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_ref_mapping: &HashMap<DocRef, u16>) -> Result<(), SegmentMergeError> {
+    fn commit_segment_merge(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_ref_mapping: &HashMap<DocRef, u32>, statistics_delta: &HashMap<Vec<u8>, i64>) -> Result<(), SegmentMergeError> {
         let mut write_batch = WriteBatch::default();
 
+        // Update index statistics
+        // The new segment's statistics replace the source segments' statistics
+        for (stat_name, stat_delta) in statistics_delta.iter() {
+            if *stat_delta == 0 {
+                continue;
+            }
+
+            let kb = KeyBuilder::index_stat(stat_name);
+            let mut delta_bytes = [0; 8];
+            BigEndian::write_i64(&mut delta_bytes, *stat_delta);
+            try!(write_batch.merge(&kb.key(), &delta_bytes));
+        }
+
         // Activate new segment
-        let kb = KeyBuilder::segment_active(dest_segment);
-        try!(write_batch.put(&kb.key(), b""));
+        // If every document in the source segments was deleted, there's nothing to activate
+        if !doc_ref_mapping.is_empty() {
+            let kb = KeyBuilder::segment_active(dest_segment);
+            try!(write_batch.put(&kb.key(), b""));
+        }
 
         // Deactivate old segments
         for source_segment in source_segments.iter() {
@@ -223,7 +537,11 @@ impl RocksDBIndexStore {
         Ok(())
     }
 
-    pub fn merge_segments(&self, source_segments: &Vec<u32>) -> Result<u32, SegmentMergeError> {
+    /// Merges the given segments into a new segment
+    ///
+    /// Returns the id of the new segment, or None if every document in the source
+    /// segments was deleted so there was nothing to keep.
+    pub fn merge_segments(&self, source_segments: &Vec<u32>) -> Result<Option<u32>, SegmentMergeError> {
         let dest_segment = try!(self.segments.new_segment(&self.db));
 
         // Generate a mapping between the ids of the documents in the old segments to the new one
@@ -232,9 +550,11 @@ impl RocksDBIndexStore {
         //  - The first segment's ids will be the same as before
         //  - The second segment's ids will be remapped to 100 - 199
         //  - The third segment's ids will be remapped to 200 - 299
+        // Documents that have already been deleted aren't given an id in the new segment,
+        // so they are dropped by the merge.
 
-        let mut doc_ref_mapping: HashMap<DocRef, u16> = HashMap::new();
-        let mut current_ord: u32 = 0;
+        let mut doc_ref_mapping: HashMap<DocRef, u32> = HashMap::new();
+        let mut current_ord: u64 = 0;
 
         for source_segment in source_segments.iter() {
             let kb = KeyBuilder::segment_stat(*source_segment, b"total_docs");
@@ -245,13 +565,23 @@ impl RocksDBIndexStore {
                 None => continue,
             };
 
+            let kb = KeyBuilder::segment_del_list(*source_segment);
+            let deletion_list = match try!(self.db.get(&kb.key())) {
+                Some(deletion_list) => try!(DocIdSet::from_bytes(deletion_list.to_vec())),
+                None => DocIdSet::new_filled(0),
+            };
+
             for source_ord in 0..total_docs {
-                if current_ord >= 65536 {
+                if deletion_list.contains_doc(source_ord as u32) {
+                    continue;
+                }
+
+                if current_ord > u32::MAX as u64 {
                     return Err(SegmentMergeError::TooManyDocs);
                 }
 
-                let from = DocRef::from_segment_ord(*source_segment, source_ord as u16);
-                doc_ref_mapping.insert(from, current_ord as u16);
+                let from = DocRef::from_segment_ord(*source_segment, source_ord as u32);
+                doc_ref_mapping.insert(from, current_ord as u32);
                 current_ord += 1;
             }
         }
@@ -263,7 +593,7 @@ impl RocksDBIndexStore {
         // This means that nothing bad will happen if it crashes half way through -- the
         // worst that could happen is we're left with a partially-written segment that we
         // have to clean up.
-        try!(self.merge_segment_data(&source_segments, dest_segment, &doc_ref_mapping));
+        let statistics_delta = try!(self.merge_segment_data(&source_segments, dest_segment, &doc_ref_mapping));
 
         // Commit the merge
         // This activates the new segment and updates the document index. Effectively committing
@@ -272,9 +602,15 @@ impl RocksDBIndexStore {
         // prevent documents in the source segments being deleted/updated so we don't accidentally
         // undelete them (this will block until the merge is complete so they delete/update from
         // the new segment).
-        try!(self.commit_segment_merge(&source_segments, dest_segment, &doc_ref_mapping));
+        try!(self.commit_segment_merge(&source_segments, dest_segment, &doc_ref_mapping, &statistics_delta));
 
-        Ok(dest_segment)
+        // Clean up the new segment if it was never activated
+        if doc_ref_mapping.is_empty() {
+            try!(self.purge_segments(&vec![dest_segment]));
+            return Ok(None);
+        }
+
+        Ok(Some(dest_segment))
     }
 
     pub fn purge_segments(&self, segments: &Vec<u32>) -> Result<(), rocksdb::Error> {
@@ -310,6 +646,42 @@ impl RocksDBIndexStore {
             }
         }
 
+        // Purge term score bounds
+        let mut iter = self.db.iterator();
+        iter.seek(b"b");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'b' {
+                // No more term score bounds to delete
+                break;
+            }
+
+            let (_, _, segment) = parse_term_directory_key(&k);
+
+            if segments_btree.contains(&segment) {
+                try!(self.db.delete(&k));
+            }
+        }
+
+        // Purge term frequency lists
+        let mut iter = self.db.iterator();
+        iter.seek(b"p");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'p' {
+                // No more term frequency lists to delete
+                break;
+            }
+
+            let (_, _, segment) = parse_term_directory_key(&k);
+
+            if segments_btree.contains(&segment) {
+                try!(self.db.delete(&k));
+            }
+        }
+
 
         // Purge the stored values
 
@@ -349,15 +721,6 @@ impl RocksDBIndexStore {
 
         // Purge the statistics
 
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
-
-            (segment, statistic_name)
-        }
-
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_stat_prefix(*source_segment);
             let mut iter = self.db.iterator();
@@ -381,6 +744,40 @@ impl RocksDBIndexStore {
             }
         }
 
+        // Purge the field lengths
+        for source_segment in segments.iter() {
+            let kb = KeyBuilder::segment_norms_prefix(*source_segment);
+            let mut iter = self.db.iterator();
+            iter.seek(&kb.key());
+            while iter.next() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(kb.key()) {
+                    // Segment finished
+                    break;
+                }
+
+                try!(self.db.delete_opt(&k, &write_options));
+            }
+        }
+
+        // Purge the term dictionaries
+        for source_segment in segments.iter() {
+            let kb = KeyBuilder::segment_term_dict_prefix(*source_segment);
+            let mut iter = self.db.iterator();
+            iter.seek(&kb.key());
+            while iter.next() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(kb.key()) {
+                    // Segment finished
+                    break;
+                }
+
+                try!(self.db.delete_opt(&k, &write_options));
+            }
+        }
+
         // Purge the deletion lists
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_del_list(*source_segment);
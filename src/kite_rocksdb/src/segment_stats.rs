@@ -1,3 +1,5 @@
+use std::str;
+
 use kite::segment::Segment;
 
 use RocksDBIndexStore;
@@ -11,14 +13,18 @@ pub struct SegmentStatistics {
 
 
 impl SegmentStatistics {
+    pub fn new(total_docs: i64, deleted_docs: i64) -> SegmentStatistics {
+        SegmentStatistics {
+            total_docs: total_docs,
+            deleted_docs: deleted_docs,
+        }
+    }
+
     fn read<S: Segment>(segment: &S) -> Result<SegmentStatistics, String> {
         let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
         let deleted_docs = try!(segment.load_statistic(b"deleted_docs")).unwrap_or(0);
 
-        Ok(SegmentStatistics {
-            total_docs: total_docs,
-            deleted_docs: deleted_docs,
-        })
+        Ok(SegmentStatistics::new(total_docs, deleted_docs))
     }
 
     #[inline]
@@ -33,6 +39,16 @@ impl SegmentStatistics {
 }
 
 
+/// Converts statistic key strings "s1/total_docs" into a tuple of the segment id and the statistic name (1, b"total_docs")
+pub fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
+    let mut parts_iter = key[1..].split(|b| *b == b'/');
+    let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let statistic_name = parts_iter.next().unwrap().to_vec();
+
+    (segment, statistic_name)
+}
+
+
 impl RocksDBIndexStore {
     pub fn get_segment_statistics(&self) -> Result<Vec<(u32, SegmentStatistics)>, String> {
         let mut segment_stats = Vec::new();
@@ -0,0 +1,173 @@
+use std::str;
+use std::collections::BTreeMap;
+
+use rocksdb::{self, WriteBatch, DBIterator};
+use kite::{Term, TermRef};
+use kite::query::term_selector::TermSelector;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use key_builder::KeyBuilder;
+
+
+/// The maximum number of terms to store in each block
+pub const TERMS_PER_BLOCK: usize = 32;
+
+
+/// Encodes a sorted run of terms into a block
+///
+/// Terms are front coded, each term only stores the bytes that differ from the
+/// previous term in the block.
+fn encode_block(terms: &[(&Term, TermRef)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    block.write_u32::<BigEndian>(terms.len() as u32).unwrap();
+
+    let mut previous_term: &[u8] = b"";
+    for &(term, term_ref) in terms.iter() {
+        let term = term.as_bytes();
+        let prefix_len = previous_term.iter().zip(term.iter()).take_while(|&(a, b)| a == b).count();
+
+        block.write_u32::<BigEndian>(prefix_len as u32).unwrap();
+        block.write_u32::<BigEndian>((term.len() - prefix_len) as u32).unwrap();
+        block.extend_from_slice(&term[prefix_len..]);
+        block.write_u32::<BigEndian>(term_ref.ord()).unwrap();
+
+        previous_term = term;
+    }
+
+    block
+}
+
+
+/// Decodes a block that was encoded by `encode_block`
+fn decode_block(mut block: &[u8]) -> Vec<(Term, TermRef)> {
+    let num_terms = block.read_u32::<BigEndian>().unwrap() as usize;
+    let mut terms = Vec::with_capacity(num_terms);
+
+    let mut term = Vec::new();
+    for _ in 0..num_terms {
+        let prefix_len = block.read_u32::<BigEndian>().unwrap() as usize;
+        let suffix_len = block.read_u32::<BigEndian>().unwrap() as usize;
+
+        term.truncate(prefix_len);
+        term.extend_from_slice(&block[..suffix_len]);
+        block = &block[suffix_len..];
+
+        let term_ref = TermRef::new(block.read_u32::<BigEndian>().unwrap());
+        terms.push((Term::from_bytes(&term), term_ref));
+    }
+
+    terms
+}
+
+
+/// Writes the term dictionary of a field in a segment
+///
+/// Segment term dictionaries are immutable. They are written once when the segment
+/// is created and only contain the terms that are used in the segment.
+pub fn write_term_dictionary(write_batch: &mut WriteBatch, segment: u32, field_ord: u32, terms: &BTreeMap<Term, TermRef>) -> Result<(), rocksdb::Error> {
+    let terms = terms.iter().map(|(term, term_ref)| (term, *term_ref)).collect::<Vec<_>>();
+
+    for block_terms in terms.chunks(TERMS_PER_BLOCK) {
+        let last_term = block_terms.last().unwrap().0;
+        let kb = KeyBuilder::segment_term_dict_block(segment, field_ord, last_term.as_bytes());
+        try!(write_batch.put(&kb.key(), &encode_block(block_terms)));
+    }
+
+    Ok(())
+}
+
+
+/// Reads the term dictionaries of every field in a segment
+pub fn read_term_dictionaries(iter: &mut DBIterator, segment: u32) -> BTreeMap<u32, BTreeMap<Term, TermRef>> {
+    let kb = KeyBuilder::segment_term_dict_prefix(segment);
+    let mut dictionaries = BTreeMap::new();
+
+    iter.seek(&kb.key());
+    while iter.next() {
+        let k = iter.key().unwrap();
+
+        if !k.starts_with(kb.key()) {
+            break;
+        }
+
+        let field_ord_len = k[kb.key().len()..].iter().position(|c| *c == b'/').unwrap();
+        let field_ord = str::from_utf8(&k[kb.key().len()..kb.key().len() + field_ord_len]).unwrap().parse::<u32>().unwrap();
+
+        let mut dictionary = dictionaries.entry(field_ord).or_insert_with(BTreeMap::new);
+        for (term, term_ref) in decode_block(&iter.value().unwrap()) {
+            dictionary.insert(term, term_ref);
+        }
+    }
+
+    dictionaries
+}
+
+
+/// Finds terms in a field of a segment that match the selector
+///
+/// Only the blocks that may contain matching terms are read.
+pub fn select_terms(iter: &mut DBIterator, segment: u32, field_ord: u32, term_selector: &TermSelector) -> Vec<TermRef> {
+    let kb = KeyBuilder::segment_term_dict_field_prefix(segment, field_ord);
+    let mut term_refs = Vec::new();
+
+    match *term_selector {
+        TermSelector::Prefix(ref prefix) => {
+            let prefix = prefix.as_bytes();
+
+            // Blocks are keyed by their last term so this finds the first block
+            // that could contain a term starting with the prefix
+            let start_kb = KeyBuilder::segment_term_dict_block(segment, field_ord, prefix);
+            iter.seek(&start_kb.key());
+            while iter.next() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(kb.key()) {
+                    break;
+                }
+
+                for (term, term_ref) in decode_block(&iter.value().unwrap()) {
+                    if term.as_bytes().starts_with(prefix) {
+                        term_refs.push(term_ref);
+                    } else if term.as_bytes() > prefix {
+                        // Terms with the prefix are all next to each other, so we've found them all
+                        return term_refs;
+                    }
+                }
+            }
+        }
+    }
+
+    term_refs
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::{Term, TermRef};
+
+    use super::{encode_block, decode_block};
+
+    #[test]
+    fn test_encode_decode_block() {
+        let terms = vec![
+            Term::from_string("hello"),
+            Term::from_string("help"),
+            Term::from_string("helpful"),
+            Term::from_string("world"),
+        ];
+        let block_terms = terms.iter().enumerate().map(|(i, term)| (term, TermRef::new(i as u32 + 1))).collect::<Vec<_>>();
+
+        let decoded = decode_block(&encode_block(&block_terms));
+
+        assert_eq!(decoded.len(), 4);
+        for (i, &(ref term, term_ref)) in decoded.iter().enumerate() {
+            assert_eq!(term, &terms[i]);
+            assert_eq!(term_ref, TermRef::new(i as u32 + 1));
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_empty_block() {
+        assert!(decode_block(&encode_block(&[])).is_empty());
+    }
+}
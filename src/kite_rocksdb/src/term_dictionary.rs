@@ -1,13 +1,47 @@
 use std::str;
-use std::sync::{Mutex, RwLock};
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
-use rocksdb::{self, DB};
+use rocksdb::{self, DB, WriteBatch};
 use kite::{Term, TermRef};
-use kite::query::term_selector::TermSelector;
+use kite::schema::FieldRef;
 
 use key_builder::KeyBuilder;
+use segment_term_dictionary;
+
+
+/// Removes the escaping that KeyBuilder adds to "/" and "\" characters
+fn unescape_key_part(key_part: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(key_part.len());
+    let mut escaped = false;
+
+    for c in key_part.iter() {
+        if *c == b'\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+
+        unescaped.push(*c);
+        escaped = false;
+    }
+
+    unescaped
+}
+
+
+/// Parses a TermRef from a term dictionary value
+pub fn parse_term_ref(value: &[u8]) -> TermRef {
+    TermRef::new(str::from_utf8(value).unwrap().parse::<u32>().unwrap())
+}
+
+
+/// Parses a term dictionary key ("f<field>/<term>") into a field ord and term
+fn parse_term_dict_key(key: &[u8]) -> (u32, Term) {
+    let separator = key.iter().position(|c| *c == b'/').unwrap();
+    let field_ord = str::from_utf8(&key[1..separator]).unwrap().parse::<u32>().unwrap();
+    (field_ord, Term::from_bytes(&unescape_key_part(&key[separator + 1..])))
+}
 
 
 /// Manages the index's "term dictionary"
@@ -16,10 +50,15 @@ use key_builder::KeyBuilder;
 /// keys. We generate a unique number for each one to use instead.
 ///
 /// The term dictionary is a mapping between terms and their internal IDs
-/// (aka. TermRef). It is entirely held in memory and persisted to the disk.
+/// (aka. TermRef). Each field has its own dictionary. The dictionary is only
+/// stored on disk and terms are looked up when they are needed, so it doesn't
+/// need to fit in memory.
+///
+/// Each segment also has an immutable dictionary of the terms it contains, this
+/// is used for finding terms that match a `TermSelector`
+/// (see `segment_term_dictionary`).
 pub struct TermDictionaryManager {
     next_term_ref: AtomicUsize,
-    terms: RwLock<BTreeMap<Term, TermRef>>,
     write_lock: Mutex<i32>,
 }
 
@@ -31,9 +70,11 @@ impl TermDictionaryManager {
         // Next term ref
         try!(db.put(b".next_term_ref", b"1"));
 
+        // New indices always have segment term dictionaries
+        try!(db.put(b".segment_term_dictionaries", b""));
+
         Ok(TermDictionaryManager {
             next_term_ref: AtomicUsize::new(1),
-            terms: RwLock::new(BTreeMap::new()),
             write_lock: Mutex::new(0),
         })
     }
@@ -47,8 +88,26 @@ impl TermDictionaryManager {
             None => 1,  // TODO: error
         };
 
-        // Read dictionary
-        let mut terms = BTreeMap::new();
+        try!(TermDictionaryManager::migrate_legacy_dictionary(db));
+
+        if try!(db.get(b".segment_term_dictionaries")).is_none() {
+            try!(TermDictionaryManager::build_segment_term_dictionaries(db));
+        }
+
+        Ok(TermDictionaryManager {
+            next_term_ref: AtomicUsize::new(next_term_ref as usize),
+            write_lock: Mutex::new(0),
+        })
+    }
+
+    /// Converts a term dictionary that is shared by all fields into per-field ones
+    ///
+    /// Each term is added to the dictionary of every field that has a term directory
+    /// for it. TermRefs are kept the same so the term directories and statistics don't
+    /// need to be rewritten. Terms that aren't in any term directory are dropped.
+    fn migrate_legacy_dictionary(db: &DB) -> Result<(), rocksdb::Error> {
+        // Read legacy dictionary
+        let mut legacy_terms = HashMap::new();
         let mut iter = db.iterator();
         iter.seek(b"t");
         while iter.next() {
@@ -58,68 +117,129 @@ impl TermDictionaryManager {
                 break;
             }
 
-            let term_ref = TermRef::new(str::from_utf8(&iter.value().unwrap()).unwrap().parse::<u32>().unwrap());
-            terms.insert(Term::from_bytes(&k[1..]), term_ref);
+            let term_ord = parse_term_ref(&iter.value().unwrap()).ord();
+            legacy_terms.insert(term_ord, Term::from_bytes(&unescape_key_part(&k[1..])));
         }
 
-        Ok(TermDictionaryManager {
-            next_term_ref: AtomicUsize::new(next_term_ref as usize),
-            terms: RwLock::new(terms),
-            write_lock: Mutex::new(0),
-        })
+        if legacy_terms.is_empty() {
+            return Ok(());
+        }
+
+        let mut write_batch = WriteBatch::default();
+
+        // Find which fields each term is used in
+        let mut iter = db.iterator();
+        iter.seek(b"d");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'd' {
+                break;
+            }
+
+            let mut nums_iter = k[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
+            let field_ord = nums_iter.next().unwrap();
+            let term_ord = nums_iter.next().unwrap();
+
+            if let Some(term) = legacy_terms.get(&term_ord) {
+                let kb = KeyBuilder::term_dict_mapping(field_ord, term.as_bytes());
+                try!(write_batch.put(&kb.key(), term_ord.to_string().as_bytes()));
+            }
+        }
+
+        // Remove legacy dictionary
+        for term in legacy_terms.values() {
+            let kb = KeyBuilder::legacy_term_dict_mapping(term.as_bytes());
+            try!(write_batch.delete(&kb.key()));
+        }
+
+        db.write(write_batch)
     }
 
-    /// Retrieves the TermRef for the given term
-    pub fn get(&self, term: &Term) -> Option<TermRef> {
-        self.terms.read().unwrap().get(term).cloned()
+    /// Writes term dictionaries for segments that were created before segments
+    /// had their own term dictionary
+    fn build_segment_term_dictionaries(db: &DB) -> Result<(), rocksdb::Error> {
+        // Read the term dictionary
+        let mut terms = HashMap::new();
+        let mut iter = db.iterator();
+        iter.seek(b"f");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'f' {
+                break;
+            }
+
+            let (field_ord, term) = parse_term_dict_key(&k);
+            let term_ord = parse_term_ref(&iter.value().unwrap()).ord();
+            terms.insert((field_ord, term_ord), term);
+        }
+
+        // Find the terms in each segment from the term directories
+        let mut segment_terms: HashMap<(u32, u32), BTreeMap<Term, TermRef>> = HashMap::new();
+        let mut iter = db.iterator();
+        iter.seek(b"d");
+        while iter.next() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'd' {
+                break;
+            }
+
+            let mut nums_iter = k[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
+            let field_ord = nums_iter.next().unwrap();
+            let term_ord = nums_iter.next().unwrap();
+            let segment = nums_iter.next().unwrap();
+
+            if let Some(term) = terms.get(&(field_ord, term_ord)) {
+                segment_terms.entry((segment, field_ord)).or_insert_with(BTreeMap::new).insert(term.clone(), TermRef::new(term_ord));
+            }
+        }
+
+        let mut write_batch = WriteBatch::default();
+        for (&(segment, field_ord), terms) in segment_terms.iter() {
+            try!(segment_term_dictionary::write_term_dictionary(&mut write_batch, segment, field_ord, terms));
+        }
+        try!(write_batch.put(b".segment_term_dictionaries", b""));
+
+        db.write(write_batch)
     }
 
-    /// Iterates over terms in the dictionary which match the selector
-    pub fn select(&self, term_selector: &TermSelector) -> Vec<TermRef> {
-        self.terms.read().unwrap().iter()
-            .filter(|&(term, _term_ref)| {
-                term_selector.matches(term)
-            })
-            .map(|(_term, term_ref)| *term_ref)
-            .collect()
+    /// Retrieves the TermRef for the given term in a field
+    pub fn get(&self, db: &DB, field_ref: FieldRef, term: &Term) -> Result<Option<TermRef>, rocksdb::Error> {
+        let kb = KeyBuilder::term_dict_mapping(field_ref.ord(), term.as_bytes());
+        Ok(try!(db.get(kb.key())).map(|term_ref| parse_term_ref(&term_ref)))
     }
 
-    /// Retrieves the TermRef for the given term, adding the term to the
-    /// dictionary if it doesn't exist
-    pub fn get_or_create(&self, db: &DB, term: &Term) -> Result<TermRef, rocksdb::Error> {
-        if let Some(term_ref) = self.get(term) {
+    /// Retrieves the TermRef for the given term in a field, adding the term to
+    /// the field's dictionary if it doesn't exist
+    pub fn get_or_create(&self, db: &DB, field_ref: FieldRef, term: &Term) -> Result<TermRef, rocksdb::Error> {
+        if let Some(term_ref) = try!(self.get(db, field_ref, term)) {
             return Ok(term_ref);
         }
 
         // Term doesn't exist in the term dictionary
 
-        // Increment next_term_ref
-        let next_term_ref = self.next_term_ref.fetch_add(1, Ordering::SeqCst) as u32;
-        try!(db.put(b".next_term_ref", (next_term_ref + 1).to_string().as_bytes()));
-
-        // Create term ref
-        let term_ref = TermRef::new(next_term_ref);
-
         // Get write lock
-        // Note: We have a separate lock so we don't need to keep an exclusive
-        // lock on the in-memory term dictionary while writing to disk, as this
-        // blocks readers.
+        // Note: This must be held while checking and writing the term so two threads
+        // can't create different TermRefs for the same term.
         let _guard = self.write_lock.lock().unwrap();
 
         // It's possible that another thread has written the term to the dictionary
-        // since we checked earlier. If this is the case, We should forget about
-        // writing our TermRef and use the one that has been inserted already.
-        if let Some(term_ref) = self.terms.read().unwrap().get(term) {
-            return Ok(*term_ref);
+        // since we checked earlier. If this is the case, use the one that has been
+        // inserted already.
+        if let Some(term_ref) = try!(self.get(db, field_ref, term)) {
+            return Ok(term_ref);
         }
 
+        // Increment next_term_ref
+        let next_term_ref = self.next_term_ref.fetch_add(1, Ordering::SeqCst) as u32;
+        try!(db.put(b".next_term_ref", (next_term_ref + 1).to_string().as_bytes()));
+
         // Write it to the on-disk term dictionary
-        let kb = KeyBuilder::term_dict_mapping(term.as_bytes());
+        let kb = KeyBuilder::term_dict_mapping(field_ref.ord(), term.as_bytes());
         try!(db.put(kb.key(), next_term_ref.to_string().as_bytes()));
 
-        // Write it to the term dictionary
-        self.terms.write().unwrap().insert(term.clone(), term_ref);;
-
-        Ok(term_ref)
+        Ok(TermRef::new(next_term_ref))
     }
 }
@@ -0,0 +1,47 @@
+use byteorder::{ByteOrder, BigEndian, WriteBytesExt};
+
+
+/// Encodes the term frequencies of a term in a segment
+///
+/// Only documents where the term occurs more than once are listed, any document
+/// in the term directory that isn't in this list has a term frequency of 1. Each
+/// entry is a four byte document ordinal followed by a four byte frequency, sorted
+/// by document ordinal so they can be read alongside the term directory.
+pub fn encode(term_frequencies: &[(u32, u32)]) -> Vec<u8> {
+    let mut sorted_term_frequencies = term_frequencies.to_vec();
+    sorted_term_frequencies.sort();
+
+    let mut bytes = Vec::with_capacity(sorted_term_frequencies.len() * 8);
+    for &(doc_id, frequency) in sorted_term_frequencies.iter() {
+        bytes.write_u32::<BigEndian>(doc_id).unwrap();
+        bytes.write_u32::<BigEndian>(frequency).unwrap();
+    }
+
+    bytes
+}
+
+
+/// Decodes a list that was encoded by `encode`
+pub fn decode(bytes: &[u8]) -> Vec<(u32, u32)> {
+    bytes.chunks(8).map(|entry| (BigEndian::read_u32(&entry[0..4]), BigEndian::read_u32(&entry[4..8]))).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, decode};
+
+    #[test]
+    fn test_encode_decode() {
+        let term_frequencies = vec![(1, 2), (5, 10), (100000, 3)];
+
+        assert_eq!(decode(&encode(&term_frequencies)), term_frequencies);
+    }
+
+    #[test]
+    fn test_encode_sorts_by_doc_id() {
+        let term_frequencies = vec![(100000, 3), (1, 2), (5, 10)];
+
+        assert_eq!(decode(&encode(&term_frequencies)), vec![(1, 2), (5, 10), (100000, 3)]);
+    }
+}
@@ -0,0 +1,76 @@
+use byteorder::{ByteOrder, BigEndian};
+
+
+/// The extremes of the values used to score a term in a segment
+///
+/// Similarity models give higher scores to documents with more occurrences of a term
+/// and shorter fields. So no document in the segment can score higher for the term
+/// than a document with the maximum term frequency and minimum field length. This
+/// is used to skip documents that can't get a high enough score at search time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TermScoreBound {
+    pub max_term_frequency: u32,
+    pub min_field_length: u32,
+}
+
+
+impl TermScoreBound {
+    pub fn new(term_frequency: u32, field_length: u32) -> TermScoreBound {
+        TermScoreBound {
+            max_term_frequency: term_frequency,
+            min_field_length: field_length,
+        }
+    }
+
+    /// Widens the bound to include a document with the given term frequency and field length
+    pub fn add(&mut self, term_frequency: u32, field_length: u32) {
+        if term_frequency > self.max_term_frequency {
+            self.max_term_frequency = term_frequency;
+        }
+
+        if field_length < self.min_field_length {
+            self.min_field_length = field_length;
+        }
+    }
+
+    /// Widens the bound to include all the documents of another bound
+    pub fn merge(&mut self, other: &TermScoreBound) {
+        self.add(other.max_term_frequency, other.min_field_length);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 8];
+        BigEndian::write_u32(&mut bytes[0..4], self.max_term_frequency);
+        BigEndian::write_u32(&mut bytes[4..8], self.min_field_length);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> TermScoreBound {
+        TermScoreBound {
+            max_term_frequency: BigEndian::read_u32(&bytes[0..4]),
+            min_field_length: BigEndian::read_u32(&bytes[4..8]),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::TermScoreBound;
+
+    #[test]
+    fn test_add() {
+        let mut bound = TermScoreBound::new(2, 10);
+        bound.add(1, 5);
+        bound.add(3, 20);
+
+        assert_eq!(bound, TermScoreBound::new(3, 5));
+    }
+
+    #[test]
+    fn test_to_from_bytes() {
+        let bound = TermScoreBound::new(3, 100000);
+
+        assert_eq!(TermScoreBound::from_bytes(&bound.to_bytes()), bound);
+    }
+}
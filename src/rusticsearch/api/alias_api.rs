@@ -1,10 +1,24 @@
-use std::collections::HashMap;
+use std::io::Read;
+use std::collections::BTreeMap;
+
+use serde_json;
+use serde_json::value::ToJson;
+
+use index::registry::{AliasOptions, IndexRef, wildcard_match};
+use query_parser::parse as parse_query;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
 use api::utils::json_response;
+use api::index_api::delete_index;
+
+
+/// Converts alias options into JSON for API responses
+fn alias_options_json(options: &AliasOptions) -> serde_json::Value {
+    options.to_json().unwrap_or_else(|_| json!({}))
+}
 
 
 pub fn view_get_global_alias(req: &mut Request) -> IronResult<Response> {
@@ -15,7 +29,7 @@ pub fn view_get_global_alias(req: &mut Request) -> IronResult<Response> {
     let indices = system.indices.read().unwrap();
 
     // Find alias
-    let mut found_aliases = HashMap::new();
+    let mut found_aliases = BTreeMap::new();
 
     for index_ref in indices.names.find(alias_name) {
         let index = match indices.get(&index_ref) {
@@ -23,11 +37,17 @@ pub fn view_get_global_alias(req: &mut Request) -> IronResult<Response> {
             None => continue,
         };
 
-        let mut inner_map = HashMap::new();
-        let mut inner_inner_map = HashMap::new();
-        inner_inner_map.insert(alias_name, HashMap::<String, String>::new());
-        inner_map.insert("aliases".to_owned(), inner_inner_map);
-        found_aliases.insert(index.canonical_name().clone(), inner_map);
+        let options_json = match indices.names.get_alias_options(alias_name, index_ref) {
+            Some(options) => alias_options_json(options),
+            None => json!({}),
+        };
+
+        let mut aliases_json = BTreeMap::new();
+        aliases_json.insert(alias_name.to_string(), options_json);
+
+        found_aliases.insert(index.canonical_name().to_string(), json!({
+            "aliases": aliases_json,
+        }));
     }
 
     if !found_aliases.is_empty() {
@@ -38,15 +58,37 @@ pub fn view_get_global_alias(req: &mut Request) -> IronResult<Response> {
 }
 
 
-pub fn view_get_alias_list(_req: &mut Request) -> IronResult<Response> {
-    // let ref system = get_system!(req);
-    // let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+pub fn view_get_alias_list(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    // Get indices
+    let selected_indices = get_indices_or_404!(indices, *index_selector);
+
+    // Find aliases of each index
+    let mut indices_json = BTreeMap::new();
+    for (index, _) in selected_indices {
+        let index_ref = indices.names.find_canonical(index.canonical_name()).unwrap();
 
-    // TODO
+        let mut aliases_json = BTreeMap::new();
+        for alias_name in indices.names.iter_index_aliases(index_ref) {
+            if let Some(options) = indices.names.get_alias_options(alias_name, index_ref) {
+                aliases_json.insert(alias_name.to_string(), alias_options_json(options));
+            }
+        }
+
+        indices_json.insert(index.canonical_name().to_string(), json!({
+            "aliases": aliases_json,
+        }));
+    }
 
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(status::Ok, json!(indices_json)));
 }
 
+
 pub fn view_get_alias(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
@@ -62,10 +104,21 @@ pub fn view_get_alias(req: &mut Request) -> IronResult<Response> {
     };
 
     // Find alias
-    if indices.names.iter_index_aliases(index_ref).any(|name| &name == alias_name) {
-        return Ok(json_response(status::Ok, json!({})));
-    } else {
-        return Ok(json_response(status::NotFound, json!({})));
+    match indices.names.get_alias_options(alias_name, index_ref) {
+        Some(options) => {
+            let mut aliases_json = BTreeMap::new();
+            aliases_json.insert(alias_name.to_string(), alias_options_json(options));
+
+            let mut indices_json = BTreeMap::new();
+            indices_json.insert(index_name.to_string(), json!({
+                "aliases": aliases_json,
+            }));
+
+            return Ok(json_response(status::Ok, json!(indices_json)));
+        }
+        None => {
+            return Ok(json_response(status::NotFound, json!({})));
+        }
     }
 }
 
@@ -75,23 +128,311 @@ pub fn view_put_alias(req: &mut Request) -> IronResult<Response> {
     let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
     let ref alias_name = read_path_parameter!(req, "alias").unwrap_or("");
 
+    // Load alias options
+    let options = match json_from_request_body!(req) {
+        Some(data) => {
+            match parse_alias_options(&data) {
+                Ok(options) => options,
+                Err(message) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": message})));
+                }
+            }
+        }
+        None => AliasOptions::default(),
+    };
+
     // Lock index array
     let mut indices = system.indices.write().unwrap();
 
     // Insert alias into names registry
     let index_refs = indices.names.find(*index_selector);
-    match indices.names.insert_or_replace_alias(alias_name.to_string(), index_refs) {
-        Ok(true) => {
-            system.log.info("[api] created alias", b!("index" => *index_selector, "alias" => *alias_name));
+    if index_refs.is_empty() {
+        return Ok(json_response(status::NotFound, json!({"message": "Index not found"})));
+    }
+
+    for index_ref in index_refs {
+        match indices.names.add_to_alias(alias_name.to_string(), index_ref, options.clone()) {
+            Ok(true) => {
+                system.log.info("[api] created alias", b!("index" => *index_selector, "alias" => *alias_name));
+            }
+            Ok(false) => {
+                system.log.info("[api] updated alias", b!("index" => *index_selector, "alias" => *alias_name));
+            }
+            Err(_) => {
+                return Ok(json_response(status::BadRequest, json!({
+                    "message": "an index exists with the same name as the alias"
+                })));
+            }
+        }
+    }
+
+    if let Err(error) = system.save_aliases(&indices) {
+        system.log.error("[api] failed to save aliases", b!("error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to save aliases"})));
+    }
+
+    Ok(json_response(status::Ok, json!({"acknowledged": true})))
+}
+
+
+pub fn view_delete_alias(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+    let ref alias_selector = read_path_parameter!(req, "alias").unwrap_or("");
+
+    // Lock index array
+    let mut indices = system.indices.write().unwrap();
+
+    let index_refs = indices.names.find(*index_selector);
+    if index_refs.is_empty() {
+        return Ok(json_response(status::NotFound, json!({"message": "Index not found"})));
+    }
+
+    // Remove the indices from any matching aliases
+    let mut found = false;
+    for index_ref in index_refs {
+        let alias_names = matching_aliases(&indices.names.iter_index_aliases(index_ref).collect::<Vec<&str>>(), *alias_selector);
+
+        for alias_name in alias_names {
+            found = true;
+            if indices.names.delete_alias(&alias_name, index_ref).unwrap() {
+                system.log.info("[api] deleted alias", b!("alias" => alias_name, "reason" => "no indices left"));
+            }
+        }
+    }
+
+    if !found {
+        return Ok(json_response(status::NotFound, json!({"message": "Alias not found"})));
+    }
+
+    if let Err(error) = system.save_aliases(&indices) {
+        system.log.error("[api] failed to save aliases", b!("error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to save aliases"})));
+    }
+
+    Ok(json_response(status::Ok, json!({"acknowledged": true})))
+}
+
+
+/// Finds the alias names that match a comma separated list of names/wildcards
+fn matching_aliases(alias_names: &[&str], selector: &str) -> Vec<String> {
+    alias_names.iter()
+        .filter(|alias_name| {
+            selector.split(',').any(|pattern| {
+                let pattern = pattern.trim();
+                pattern == "_all" || wildcard_match(pattern, alias_name)
+            })
+        })
+        .map(|alias_name| alias_name.to_string())
+        .collect()
+}
+
+
+/// Parses alias options, making sure the filter is a valid query
+fn parse_alias_options(json: &serde_json::Value) -> Result<AliasOptions, String> {
+    let options = try!(AliasOptions::from_json(json));
+
+    if let Some(ref filter) = options.filter {
+        if let Err(error) = parse_query(filter) {
+            return Err(format!("invalid alias filter: {:?}", error));
+        }
+    }
+
+    Ok(options)
+}
+
+
+#[derive(Debug)]
+enum AliasAction {
+    Add {
+        indices: Vec<String>,
+        aliases: Vec<String>,
+        options: AliasOptions,
+    },
+    Remove {
+        indices: Vec<String>,
+        aliases: Vec<String>,
+    },
+    RemoveIndex {
+        indices: Vec<String>,
+    },
+}
+
+
+/// Reads a list of names from either a singular key (eg, "index") or a plural key (eg, "indices")
+fn parse_action_names(object: &serde_json::Map<String, serde_json::Value>, singular: &str, plural: &str) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+
+    if let Some(name) = object.get(singular) {
+        match name.as_str() {
+            Some(name) => names.push(name.to_string()),
+            None => return Err(format!("{} must be a string", singular)),
+        }
+    }
+
+    if let Some(array) = object.get(plural) {
+        match array.as_array() {
+            Some(array) => {
+                for name in array.iter() {
+                    match name.as_str() {
+                        Some(name) => names.push(name.to_string()),
+                        None => return Err(format!("{} must be an array of strings", plural)),
+                    }
+                }
+            }
+            None => return Err(format!("{} must be an array of strings", plural)),
+        }
+    }
+
+    if names.is_empty() {
+        return Err(format!("{} is missing", singular));
+    }
+
+    Ok(names)
+}
+
+
+fn parse_alias_action(json: &serde_json::Value) -> Result<AliasAction, String> {
+    let object = try!(json.as_object().ok_or("action must be an object".to_string()));
+
+    let (action_type, action_json) = if object.len() == 1 {
+        object.iter().next().unwrap()
+    } else {
+        return Err("action must have a single key".to_string());
+    };
+
+    let action_object = try!(action_json.as_object().ok_or(format!("{} action must be an object", action_type)));
+
+    match action_type.as_ref() {
+        "add" => {
+            // Everything other than the index and alias names are options
+            let mut options_object = action_object.clone();
+            for key in &["index", "indices", "alias", "aliases"] {
+                options_object.remove(*key);
+            }
+
+            Ok(AliasAction::Add {
+                indices: try!(parse_action_names(action_object, "index", "indices")),
+                aliases: try!(parse_action_names(action_object, "alias", "aliases")),
+                options: try!(parse_alias_options(&serde_json::Value::Object(options_object))),
+            })
+        }
+        "remove" => {
+            Ok(AliasAction::Remove {
+                indices: try!(parse_action_names(action_object, "index", "indices")),
+                aliases: try!(parse_action_names(action_object, "alias", "aliases")),
+            })
         }
-        Ok(false) => {
-            system.log.info("[api] updated alias", b!("index" => *index_selector, "alias" => *alias_name));
+        "remove_index" => {
+            Ok(AliasAction::RemoveIndex {
+                indices: try!(parse_action_names(action_object, "index", "indices")),
+            })
         }
-        Err(_) => {
-            // TODO
-            return Ok(json_response(status::Ok, json!({"acknowledged": false})));
+        _ => Err(format!("unrecognised action: {}", action_type)),
+    }
+}
+
+
+pub fn view_post_aliases(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+
+    // Parse actions
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => return Ok(json_response(status::BadRequest, json!({"message": "Missing actions"}))),
+    };
+
+    let actions_json = match data.as_object().and_then(|object| object.get("actions")).and_then(|actions| actions.as_array()) {
+        Some(actions_json) => actions_json.clone(),
+        None => return Ok(json_response(status::BadRequest, json!({"message": "actions must be an array"}))),
+    };
+
+    let mut actions = Vec::with_capacity(actions_json.len());
+    for action_json in actions_json.iter() {
+        match parse_alias_action(action_json) {
+            Ok(action) => actions.push(action),
+            Err(message) => return Ok(json_response(status::BadRequest, json!({"message": message}))),
+        }
+    }
+
+    // Lock index array
+    // The lock is held until all actions have been applied so other requests never see a partial update
+    let mut indices = system.indices.write().unwrap();
+
+    // Apply the actions to a copy of the names registry. This is only swapped in if all actions succeed
+    let mut names = indices.names.clone();
+    let mut indices_to_remove: Vec<IndexRef> = Vec::new();
+
+    for action in actions {
+        match action {
+            AliasAction::Add{indices: index_selectors, aliases, options} => {
+                for index_selector in index_selectors {
+                    let index_refs = names.find(&index_selector);
+                    if index_refs.is_empty() {
+                        return Ok(json_response(status::NotFound, json!({"message": "Index not found", "index": index_selector})));
+                    }
+
+                    for index_ref in index_refs {
+                        for alias_name in aliases.iter() {
+                            if names.add_to_alias(alias_name.clone(), index_ref, options.clone()).is_err() {
+                                return Ok(json_response(status::BadRequest, json!({
+                                    "message": "an index exists with the same name as the alias",
+                                    "alias": alias_name,
+                                })));
+                            }
+                        }
+                    }
+                }
+            }
+            AliasAction::Remove{indices: index_selectors, aliases} => {
+                for index_selector in index_selectors {
+                    let index_refs = names.find(&index_selector);
+                    if index_refs.is_empty() {
+                        return Ok(json_response(status::NotFound, json!({"message": "Index not found", "index": index_selector})));
+                    }
+
+                    for index_ref in index_refs {
+                        let mut alias_names = Vec::new();
+                        for alias_selector in aliases.iter() {
+                            alias_names.extend(matching_aliases(&names.iter_index_aliases(index_ref).collect::<Vec<&str>>(), alias_selector));
+                        }
+
+                        if alias_names.is_empty() {
+                            return Ok(json_response(status::NotFound, json!({"message": "Alias not found", "alias": aliases.join(",")})));
+                        }
+
+                        for alias_name in alias_names {
+                            names.delete_alias(&alias_name, index_ref).unwrap();
+                        }
+                    }
+                }
+            }
+            AliasAction::RemoveIndex{indices: index_selectors} => {
+                for index_selector in index_selectors {
+                    let index_refs = names.find(&index_selector);
+                    if index_refs.is_empty() {
+                        return Ok(json_response(status::NotFound, json!({"message": "Index not found", "index": index_selector})));
+                    }
+
+                    indices_to_remove.extend(index_refs);
+                }
+            }
         }
     }
 
+    // All actions succeeded, apply them
+    indices.names = names;
+
+    for index_ref in indices_to_remove {
+        delete_index(system, &mut indices, index_ref);
+    }
+
+    if let Err(error) = system.save_aliases(&indices) {
+        system.log.error("[api] failed to save aliases", b!("error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to save aliases"})));
+    }
+
+    system.log.info("[api] updated aliases", b!());
+
     Ok(json_response(status::Ok, json!({"acknowledged": true})))
 }
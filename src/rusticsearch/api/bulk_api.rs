@@ -4,15 +4,18 @@ use std::collections::HashMap;
 use serde_json;
 
 use document::DocumentSource;
+use index::Index;
+use index::write_buffer::WriteConditions;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
-use api::utils::{json_response};
+use api::utils::{json_response, index_closed_response};
 
 
 pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
+    let refresh_policy = get_refresh_policy_or_400!(req);
 
     // Lock index array
     let indices = system.indices.read().unwrap();
@@ -22,6 +25,7 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
     req.body.read_to_string(&mut payload).unwrap();
 
     let mut items = Vec::new();
+    let mut changed_indices: Vec<&Index> = Vec::new();
 
     // Iterate
     let mut payload_lines = payload.split('\n');
@@ -58,6 +62,10 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
 
                 // Find index
                 let index = get_index_or_404!(indices, doc_index);
+                if !index.is_open() {
+                    return Ok(index_closed_response(index.canonical_name()));
+                }
+
                 let index_metadata = index.metadata.read().unwrap();
 
                 let doc = {
@@ -72,17 +80,31 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
                     // Create document
                     let document_source = DocumentSource {
                         key: doc_id.to_string(),
+                        mapping_name: doc_type.to_string(),
                         data: doc_json,
                     };
-                    document_source.prepare(mapping)
+                    index.prepare_document(&document_source, mapping)
+                };
+
+                let version = match index.insert_or_update_document(doc, &WriteConditions::default()) {
+                    Ok((version, _)) => version,
+                    Err(error) => {
+                        system.log.error("[api] failed to write document", b!("index" => index.canonical_name(), "error" => format!("{:?}", error)));
+                        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to write document"})));
+                    }
                 };
 
-                index.store.insert_or_update_document(&doc).unwrap();
+                if !changed_indices.iter().any(|changed_index: &&Index| changed_index.id() == index.id()) {
+                    changed_indices.push(index);
+                }
 
                 // Insert into "items" array
                 let mut item = HashMap::new();
+                let mut item_params = action_params.clone();
+                item_params.insert("_version".to_string(), json!(version.version));
+                item_params.insert("_seq_no".to_string(), json!(version.seq_no));
                 // TODO: "create" may not always be right
-                item.insert("create", action_params.clone());
+                item.insert("create", item_params);
                 items.push(item);
             }
             _ => {
@@ -91,6 +113,14 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    // Make the changes visible all at once
+    for index in changed_indices {
+        if let Err(error) = index.apply_refresh_policy(refresh_policy) {
+            system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+            return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+        }
+    }
+
     return Ok(json_response(status::Ok,
                             json!({
                                 "took": items.len(),
@@ -0,0 +1,282 @@
+use std::io::Read;
+use std::time::Instant;
+
+use serde_json;
+use kite::query::Query;
+
+use index::meta_fields::MetaFields;
+use index::write_buffer::{WriteConditions, WriteError};
+use query_parser::{QueryBuildContext, parse as parse_query};
+use search::{IndexSearcher, find_all_docs};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, elapsed_ms};
+use api::search_api::{apply_alias_filters, alias_filter_error_response};
+
+
+/// Runs the query in the request body over a snapshot of each selected index
+///
+/// The query defaults to matching all documents if there is no body. The readers are
+/// stored in `$readers` as they must outlive the searchers that are returned.
+macro_rules! find_docs_by_query {
+    ($system: expr, $indices: expr, $index_selector: expr, $readers: ident, $req: expr) => {{
+        let query = match json_from_request_body!($req) {
+            Some(data) => {
+                match data.as_object().and_then(|data| data.get("query")) {
+                    Some(query_json) => {
+                        match parse_query(query_json) {
+                            Ok(query) => Some(query),
+                            Err(_) => {
+                                return Ok(json_response(status::BadRequest, json!({"message": "Query error"})));
+                            }
+                        }
+                    }
+                    None => None,
+                }
+            }
+            None => None,
+        };
+
+        let selected_indices = get_open_indices_or_404!($indices, $index_selector);
+
+        let mut index_readers = Vec::with_capacity(selected_indices.len());
+        for &(index, _) in selected_indices.iter() {
+            index_readers.push(get_store_or_400!(index).reader());
+        }
+        $readers = index_readers;
+
+        let mut matches = Vec::new();
+        for ((index, filters), index_reader) in selected_indices.into_iter().zip($readers.iter()) {
+            let index_query = {
+                let index_metadata = index.metadata.read().unwrap();
+                let context = QueryBuildContext::new().set_index_metadata(&index_metadata).no_score();
+                let index_query = match query {
+                    Some(ref query) => query.build(&context, &index_reader.schema()),
+                    None => Query::new_all(),
+                };
+
+                match apply_alias_filters(index_query, &filters, &context, &index_reader.schema()) {
+                    Ok(index_query) => index_query,
+                    Err(_) => return Ok(alias_filter_error_response()),
+                }
+            };
+
+            let searcher = IndexSearcher::new(index, index_reader, index_query);
+            match find_all_docs(&searcher) {
+                Ok(doc_refs) => matches.push((searcher, doc_refs)),
+                Err(error) => {
+                    $system.log.error("[api] search failed", b!("index" => index.canonical_name(), "error" => error));
+                    return Ok(json_response(status::InternalServerError, json!({"message": "Search failed"})));
+                }
+            }
+        }
+
+        matches
+    }}
+}
+
+
+pub fn view_post_delete_by_query(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+    let scroll_size = get_scroll_size_or_400!(req);
+    let start_time = Instant::now();
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    let readers;
+    let matches = find_docs_by_query!(system, indices, *index_selector, readers, req);
+
+    // Delete the matching documents in batches. Deletes go through the write buffer, like
+    // any other write, and each batch is written into the index with a single refresh
+    let mut total = 0;
+    let mut deleted = 0;
+    let mut version_conflicts = 0;
+    let mut batches = 0;
+    let mut failures = Vec::new();
+
+    for &(ref searcher, ref doc_refs) in matches.iter() {
+        let index = searcher.index;
+        total += doc_refs.len();
+
+        let meta_fields = match MetaFields::from_schema(&searcher.reader.schema()) {
+            Some(meta_fields) => meta_fields,
+            None => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Index doesn't store document keys", "index": index.canonical_name()})));
+            }
+        };
+
+        for batch in doc_refs.chunks(scroll_size) {
+            for doc_ref in batch.iter() {
+                let doc_key = match meta_fields.read_key(searcher.reader, *doc_ref) {
+                    Ok(Some(doc_key)) => doc_key,
+                    Ok(None) => {
+                        failures.push(json!({"index": index.canonical_name(), "cause": "Document has no stored key"}));
+                        continue;
+                    }
+                    Err(error) => {
+                        failures.push(json!({"index": index.canonical_name(), "cause": error}));
+                        continue;
+                    }
+                };
+
+                // Skip documents that have been changed since the search was run. This checks the
+                // sequence number the document had in the snapshot, not its doc ref, as merges move
+                // documents into new segments without changing them
+                let conditions = match searcher.reader.find_document_in_snapshot(&doc_key) {
+                    Ok(Some((_, version))) => {
+                        WriteConditions {
+                            if_seq_no: Some(version.seq_no),
+                            .. WriteConditions::default()
+                        }
+                    }
+                    Ok(None) => {
+                        version_conflicts += 1;
+                        continue;
+                    }
+                    Err(error) => {
+                        failures.push(json!({"index": index.canonical_name(), "_id": doc_key, "cause": format!("{:?}", error)}));
+                        continue;
+                    }
+                };
+
+                match index.remove_document_by_key(&doc_key, &conditions) {
+                    Ok(Some(_)) => deleted += 1,
+                    Ok(None) => {}
+                    Err(WriteError::VersionConflict(_)) => version_conflicts += 1,
+                    Err(WriteError::StoreError(error)) => {
+                        failures.push(json!({"index": index.canonical_name(), "_id": doc_key, "cause": error}));
+                    }
+                }
+            }
+
+            if let Err(error) = index.refresh() {
+                system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+                return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+            }
+
+            batches += 1;
+        }
+
+        system.log.info("[api] deleted documents by query", b!("index" => index.canonical_name(), "deleted" => deleted, "total" => total));
+    }
+
+    return Ok(json_response(status::Ok, json!({
+        "took": elapsed_ms(start_time),
+        "total": total,
+        "deleted": deleted,
+        "batches": batches,
+        "version_conflicts": version_conflicts,
+        "failures": failures,
+    })));
+}
+
+
+pub fn view_post_update_by_query(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+    let scroll_size = get_scroll_size_or_400!(req);
+    let start_time = Instant::now();
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    let readers;
+    let matches = find_docs_by_query!(system, indices, *index_selector, readers, req);
+
+    // Reindex the matching documents from their stored source using the current mappings.
+    // Each batch is written into the index as a single segment
+    let mut total = 0;
+    let mut updated = 0;
+    let mut version_conflicts = 0;
+    let mut batches = 0;
+    let mut failures = Vec::new();
+
+    for &(ref searcher, ref doc_refs) in matches.iter() {
+        let index = searcher.index;
+        total += doc_refs.len();
+
+        let meta_fields = match MetaFields::from_schema(&searcher.reader.schema()) {
+            Some(meta_fields) => meta_fields,
+            None => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Index doesn't store document sources", "index": index.canonical_name()})));
+            }
+        };
+
+        for batch in doc_refs.chunks(scroll_size) {
+            {
+                let index_metadata = index.metadata.read().unwrap();
+
+                for doc_ref in batch.iter() {
+                    let doc_source = match meta_fields.read_source(searcher.reader, *doc_ref) {
+                        Ok(Some(doc_source)) => doc_source,
+                        Ok(None) => {
+                            failures.push(json!({"index": index.canonical_name(), "cause": "Document has no stored source"}));
+                            continue;
+                        }
+                        Err(error) => {
+                            failures.push(json!({"index": index.canonical_name(), "cause": error}));
+                            continue;
+                        }
+                    };
+
+                    let mapping = match index_metadata.mappings.get(&doc_source.mapping_name) {
+                        Some(mapping) => mapping,
+                        None => {
+                            failures.push(json!({"index": index.canonical_name(), "_id": doc_source.key, "_type": doc_source.mapping_name, "cause": "Mapping not found"}));
+                            continue;
+                        }
+                    };
+
+                    // Skip documents that have been changed since the search was run (see view_post_delete_by_query)
+                    let conditions = match searcher.reader.find_document_in_snapshot(&doc_source.key) {
+                        Ok(Some((_, version))) => {
+                            WriteConditions {
+                                if_seq_no: Some(version.seq_no),
+                                .. WriteConditions::default()
+                            }
+                        }
+                        Ok(None) => {
+                            version_conflicts += 1;
+                            continue;
+                        }
+                        Err(error) => {
+                            failures.push(json!({"index": index.canonical_name(), "_id": doc_source.key, "_type": doc_source.mapping_name, "cause": format!("{:?}", error)}));
+                            continue;
+                        }
+                    };
+
+                    match index.insert_or_update_document(index.prepare_document(&doc_source, mapping), &conditions) {
+                        Ok(_) => updated += 1,
+                        Err(WriteError::VersionConflict(_)) => version_conflicts += 1,
+                        Err(WriteError::StoreError(error)) => {
+                            failures.push(json!({"index": index.canonical_name(), "_id": doc_source.key, "_type": doc_source.mapping_name, "cause": error}));
+                        }
+                    }
+                }
+            }
+
+            if let Err(error) = index.refresh() {
+                system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+                return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+            }
+
+            batches += 1;
+        }
+
+        system.log.info("[api] updated documents by query", b!("index" => index.canonical_name(), "updated" => updated, "total" => total));
+    }
+
+    return Ok(json_response(status::Ok, json!({
+        "took": elapsed_ms(start_time),
+        "total": total,
+        "updated": updated,
+        "batches": batches,
+        "version_conflicts": version_conflicts,
+        "failures": failures,
+    })));
+}
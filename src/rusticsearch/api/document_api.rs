@@ -2,26 +2,33 @@ use std::io::Read;
 
 use serde_json;
 
-use document::DocumentSource;
+use system::System;
+use document::{DocumentSource, merge_json};
+use index::Index;
+use index::write_buffer::WriteError;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, index_closed_response, version_conflict_response};
 
 
 pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
-    // let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
     // Lock index array
     let indices = system.indices.read().unwrap();
 
     // Get index
     let index = get_index_or_404!(indices, *index_name);
+    if !index.is_open() {
+        return Ok(index_closed_response(index.canonical_name()));
+    }
+
     let index_metadata = index.metadata.read().unwrap();
 
     // Check that the mapping exists
@@ -30,25 +37,53 @@ pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
     }
 
     // Find document
-    /*
-    let index_reader = index.store.reader();
-    let doc = match index_reader.get_document_by_key(doc_key) {
-        Some(doc) => doc,
-        None => {
-            return Ok(json_response(status::NotFound, "{\"message\": \"Document not found\"}"));
+    let doc = match index.get_document(doc_key) {
+        Ok(Some((doc_source, version))) => {
+            if doc_source.mapping_name == *mapping_name {
+                Some((doc_source, version))
+            } else {
+                None
+            }
+        }
+        Ok(None) => None,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
         }
     };
-    */
 
+    match doc {
+        Some((doc_source, version)) => {
+            Ok(json_response(status::Ok, json!({
+                "_index": index.canonical_name(),
+                "_type": *mapping_name,
+                "_id": *doc_key,
+                "_version": version.version,
+                "_seq_no": version.seq_no,
+                "found": true,
+                "_source": doc_source.data,
+            })))
+        }
+        None => {
+            Ok(json_response(status::NotFound, json!({
+                "_index": index.canonical_name(),
+                "_type": *mapping_name,
+                "_id": *doc_key,
+                "found": false,
+            })))
+        }
+    }
+}
 
-    // Build JSON document
-    // TODO: This is probably completely wrong
-    // let json_object = BTreeMap::new();
-    // FIXME: for (field_name, field_value) in doc.fields.iter() {
-    // FIXME:     json_object.insert(field_name.clone(), Json::Array(field_value.iter().map(|v| v.term.as_json()).collect::<Vec<_>>()));
-    // FIXME: }
 
-    return Ok(json_response(status::Ok, json!({})));
+/// Converts an error from writing a document into a response
+fn write_error_response(system: &System, index: &Index, doc_key: &str, error: WriteError) -> Response {
+    match error {
+        WriteError::VersionConflict(current_version) => version_conflict_response(doc_key, current_version),
+        WriteError::StoreError(error) => {
+            system.log.error("[api] failed to write document", b!("index" => index.canonical_name(), "error" => error));
+            json_response(status::InternalServerError, json!({"message": "Failed to write document"}))
+        }
+    }
 }
 
 
@@ -57,12 +92,18 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
     let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let refresh_policy = get_refresh_policy_or_400!(req);
+    let conditions = get_write_conditions_or_400!(req);
 
     // Lock index array
     let indices = system.indices.read().unwrap();
 
     // Get index
     let index = get_index_or_404!(indices, *index_name);
+    if !index.is_open() {
+        return Ok(index_closed_response(index.canonical_name()));
+    }
+
     let index_metadata = index.metadata.read().unwrap();
 
     let doc = {
@@ -78,18 +119,34 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
         if let Some(data) = json_from_request_body!(req) {
             let document_source = DocumentSource {
                 key: doc_key.to_string(),
+                mapping_name: mapping_name.to_string(),
                 data: data,
             };
-            document_source.prepare(mapping)
+            index.prepare_document(&document_source, mapping)
         } else {
             return Ok(json_response(status::NotFound, json!({"message": "No data"})));
         }
     };
 
-    index.store.insert_or_update_document(&doc).unwrap();
+    let (version, created) = match index.insert_or_update_document(doc, &conditions) {
+        Ok(result) => result,
+        Err(error) => return Ok(write_error_response(system, index, doc_key, error)),
+    };
+
+    if let Err(error) = index.apply_refresh_policy(refresh_policy) {
+        system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+    }
 
-    // TODO: {"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5378","_version":1,"created":true}
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(if created { status::Created } else { status::Ok }, json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "_version": version.version,
+        "_seq_no": version.seq_no,
+        "created": created,
+        "result": if created { "created" } else { "updated" },
+    })));
 }
 
 
@@ -98,12 +155,18 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
     let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let refresh_policy = get_refresh_policy_or_400!(req);
+    let conditions = get_write_conditions_or_400!(req);
 
     // Lock index array
     let indices = system.indices.read().unwrap();
 
     // Get index
     let index = get_index_or_404!(indices, *index_name);
+    if !index.is_open() {
+        return Ok(index_closed_response(index.canonical_name()));
+    }
+
     let index_metadata = index.metadata.read().unwrap();
 
     // Check that the mapping exists
@@ -111,13 +174,152 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
-    // Make sure the document exists
-    if !index.store.reader().contains_document_key(doc_key) {
+    // Delete document
+    let version = match index.remove_document_by_key(doc_key, &conditions) {
+        Ok(Some(version)) => version,
+        Ok(None) => {
+            return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
+        }
+        Err(error) => return Ok(write_error_response(system, index, doc_key, error)),
+    };
+
+    if let Err(error) = index.apply_refresh_policy(refresh_policy) {
+        system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+    }
+
+    return Ok(json_response(status::Ok, json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "_version": version.version,
+        "_seq_no": version.seq_no,
+        "found": true,
+        "result": "deleted",
+    })));
+}
+
+
+#[derive(Debug, PartialEq)]
+enum UpdateResult {
+    Created,
+    Updated,
+    Noop,
+    NotFound,
+}
+
+
+pub fn view_post_update_doc(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let refresh_policy = get_refresh_policy_or_400!(req);
+    let conditions = get_write_conditions_or_400!(req);
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
+
+    let patch = data.as_object().and_then(|data| data.get("doc"));
+    let upsert = data.as_object().and_then(|data| data.get("upsert"));
+    let doc_as_upsert = data.as_object().and_then(|data| data.get("doc_as_upsert")).and_then(|value| value.as_bool()).unwrap_or(false);
+
+    if patch.is_none() && upsert.is_none() {
+        return Ok(json_response(status::BadRequest, json!({"message": "Update requires either doc or upsert"})));
+    }
+
+    if !patch.map_or(true, |patch| patch.is_object()) || !upsert.map_or(true, |upsert| upsert.is_object()) {
+        return Ok(json_response(status::BadRequest, json!({"message": "doc and upsert must be objects"})));
+    }
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    // Get index
+    let index = get_index_or_404!(indices, *index_name);
+    if !index.is_open() {
+        return Ok(index_closed_response(index.canonical_name()));
+    }
+
+    let index_metadata = index.metadata.read().unwrap();
+
+    // Find mapping
+    let mapping = match index_metadata.mappings.get(*mapping_name) {
+        Some(mapping) => mapping,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
+        }
+    };
+
+    // Merge the patch into the current source and replace the document
+    let result = index.update_document(doc_key, &conditions, |doc_source| {
+        match doc_source {
+            Some(mut doc_source) => {
+                let patch = match patch {
+                    Some(patch) => patch,
+                    None => return (None, UpdateResult::Noop),
+                };
+
+                let original_data = doc_source.data.clone();
+                merge_json(&mut doc_source.data, patch);
+
+                if doc_source.data == original_data {
+                    return (None, UpdateResult::Noop);
+                }
+
+                doc_source.mapping_name = mapping_name.to_string();
+                (Some(index.prepare_document(&doc_source, mapping)), UpdateResult::Updated)
+            }
+            None => {
+                let data = if doc_as_upsert { patch } else { upsert };
+
+                match data {
+                    Some(data) => {
+                        let doc_source = DocumentSource {
+                            key: doc_key.to_string(),
+                            mapping_name: mapping_name.to_string(),
+                            data: data.clone(),
+                        };
+
+                        (Some(index.prepare_document(&doc_source, mapping)), UpdateResult::Created)
+                    }
+                    None => (None, UpdateResult::NotFound),
+                }
+            }
+        }
+    });
+
+    let (result, version) = match result {
+        Ok(result) => result,
+        Err(error) => return Ok(write_error_response(system, index, doc_key, error)),
+    };
+
+    if result == UpdateResult::NotFound {
         return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
     }
 
-    // Delete document
-    index.store.remove_document_by_key(doc_key).unwrap();
+    if let Err(error) = index.apply_refresh_policy(refresh_policy) {
+        system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+    }
+
+    let result_name = match result {
+        UpdateResult::Created => "created",
+        UpdateResult::Updated => "updated",
+        UpdateResult::Noop | UpdateResult::NotFound => "noop",
+    };
 
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(status::Ok, json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "_version": version.map(|version| version.version),
+        "_seq_no": version.map(|version| version.seq_no),
+        "result": result_name,
+    })));
 }
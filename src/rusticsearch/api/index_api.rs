@@ -1,20 +1,126 @@
 use std::fs;
+use std::collections::BTreeMap;
 use std::io::Read;
+use std::sync::Arc;
 
 use serde_json;
 use serde_json::value::ToJson;
 use kite_rocksdb::RocksDBIndexStore;
-use uuid::Uuid;
 
+use system::System;
 use index::Index;
+use index::registry::{IndexRegistry, IndexRef};
 use index::metadata::IndexMetaData;
+use index::settings::IndexSettings;
 use index::metadata::parse::parse as parse_index_metadata;
+use index::meta_fields::add_meta_fields;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, index_not_found_response, index_in_use_response};
+
+
+/// Removes an index from the registry, along with its name, aliases and data
+pub fn delete_index(system: &System, indices: &mut IndexRegistry, index_ref: IndexRef) {
+    // Get the index name
+    let index_name = {
+        if let Some(index) = indices.get(&index_ref) {
+            index.canonical_name().to_string()
+        } else {
+            // Index doesn't exist
+            return;
+        }
+    };
+
+    // Remove index from array
+    indices.remove(&index_ref);
+
+    // Delete canonical name
+    indices.names.delete_canonical(&index_name, index_ref).unwrap();
+
+    // Delete file
+    let mut indices_dir = system.get_indices_dir();
+    indices_dir.push(&index_name);
+    match fs::remove_dir_all(&indices_dir) {
+        Ok(()) => {},
+        Err(e) => {
+            system.log.warn("[api] failed to delete index data", b!("index" => format!("{}", index_name), "error" => format!("{}", e)));
+        }
+    }
+
+    system.log.info("[api] deleted index", b!("index" => format!("{}", index_name)));
+
+    // Delete aliases
+    let alias_names = indices.names.iter_index_aliases(index_ref).map(|n| n.to_string()).collect::<Vec<String>>();
+    for alias_name in alias_names {
+        let alias_deleted = indices.names.delete_alias(&alias_name, index_ref).unwrap();
+
+        // If this was the only index being referenced by the alias, the alias would be deleted
+        if alias_deleted {
+             system.log.info("[api] deleted alias", b!("alias" => format!("{}", alias_name), "reason" => "no indices left"));
+        }
+    }
+}
+
+
+/// Serialises the metadata of an index, with its settings merged into "settings.index"
+fn index_to_json(index: &Index) -> Result<serde_json::Value, serde_json::Error> {
+    let mut json = try!(index.metadata.read().unwrap().to_json());
+    let settings_json = try!(index.settings.read().unwrap().to_json());
+
+    if let Some(index_settings) = json.as_object_mut()
+            .and_then(|json| json.get_mut("settings"))
+            .and_then(|settings| settings.as_object_mut())
+            .and_then(|settings| settings.get_mut("index"))
+            .and_then(|index_settings| index_settings.as_object_mut()) {
+        if let Some(settings_json) = settings_json.as_object() {
+            for (key, value) in settings_json.iter() {
+                index_settings.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    Ok(json)
+}
+
+
+/// Applies the "settings" object of a request to an existing index and saves it
+///
+/// Returns a message describing the problem if the settings are invalid.
+fn update_index_settings(index: &Index, data: &serde_json::Value) -> Result<(), String> {
+    // Metadata must be locked before settings, the document APIs lock them in this order
+    let mut index_metadata = index.metadata.write().unwrap();
+    let mut index_settings = index.settings.write().unwrap();
+
+    // Dynamic settings
+    let mut settings = index_settings.clone();
+    if let Err(error) = settings.update(data) {
+        return Err(format!("Invalid index settings: {:?}", error));
+    }
+
+    // Similarities and analysis
+    let mut metadata = index_metadata.clone();
+    if let Err(error) = parse_index_metadata(&mut metadata, json!({"settings": data.clone()})) {
+        return Err(format!("Invalid index settings: {:?}", error));
+    }
+
+    if index.is_open() && index_metadata.analysis_changed_in(&metadata) {
+        return Err("Existing analyzers, tokenizers and filters can only be changed while the index is closed".to_string());
+    }
+
+    // Fields may be using a similarity that was just changed
+    metadata.resolve_similarities();
+
+    try!(settings.save(index.settings_path()));
+    try!(metadata.save(index.metadata_path()));
+
+    *index_settings = settings;
+    *index_metadata = metadata;
+
+    Ok(())
+}
 
 
 pub fn view_get_index(req: &mut Request) -> IronResult<Response> {
@@ -27,20 +133,79 @@ pub fn view_get_index(req: &mut Request) -> IronResult<Response> {
     // Get index
     let index = get_index_or_404!(indices, *index_name);
 
-    // Serialise index metadata
-    let json = {
-        let index_metadata = index.metadata.read().unwrap();
-        match index_metadata.to_json() {
-            Ok(json) => json,
+    match index_to_json(index) {
+        Ok(json) => Ok(json_response(status::Ok, json)),
+        Err(_) => {
+            Ok(json_response(status::InternalServerError, json!({
+                "message": "unable to serialise index"
+            })))
+        }
+    }
+}
+
+
+pub fn view_get_index_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("_all");
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    // Get indices
+    let selected_indices = get_indices_or_404!(indices, *index_selector);
+
+    let mut response = BTreeMap::new();
+    for &(index, _) in selected_indices.iter() {
+        let settings_json = match index_to_json(index) {
+            Ok(json) => json.as_object().and_then(|json| json.get("settings")).cloned().unwrap_or(serde_json::Value::Null),
             Err(_) => {
                 return Ok(json_response(status::InternalServerError, json!({
-                    "message": "unable to serialise index metadata"
+                    "message": "unable to serialise index settings"
                 })));
             }
+        };
+
+        response.insert(index.canonical_name().to_string(), json!({
+            "settings": settings_json,
+        }));
+    }
+
+    return Ok(json_response(status::Ok, json!(response)));
+}
+
+
+pub fn view_put_index_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("_all");
+
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No settings given"})));
         }
     };
 
-    return Ok(json_response(status::Ok, json));
+    // Settings may also be wrapped in a "settings" object
+    let data = match data.as_object().and_then(|data| data.get("settings")) {
+        Some(settings_data) => settings_data.clone(),
+        None => data.clone(),
+    };
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    // Get indices
+    let selected_indices = get_indices_or_404!(indices, *index_selector);
+
+    for &(index, _) in selected_indices.iter() {
+        if let Err(error) = update_index_settings(index, &data) {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+
+        system.log.info("[api] updated index settings", b!("index" => index.canonical_name()));
+    }
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
 }
 
 
@@ -55,16 +220,39 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
     let index_ref = indices.names.find_canonical(&index_name);
 
     match index_ref {
-        Some(_) => {
+        Some(index_ref) => {
             // Update existing index
-            // TODO
+            let index = match indices.get(&index_ref) {
+                Some(index) => index,
+                None => return Ok(index_not_found_response()),
+            };
+
+            let settings_data = json_from_request_body!(req)
+                .and_then(|data| data.as_object().and_then(|data| data.get("settings")).cloned());
+
+            if let Some(settings_data) = settings_data {
+                if let Err(error) = update_index_settings(index, &settings_data) {
+                    return Ok(json_response(status::BadRequest, json!({"message": error})));
+                }
+            }
 
             system.log.info("[api] updated index", b!("index" => *index_name));
         }
         None => {
+            let data = json_from_request_body!(req);
+
+            // Load settings
+            let mut settings = IndexSettings::new();
+            if let Some(settings_data) = data.as_ref().and_then(|data| data.as_object()).and_then(|data| data.get("settings")) {
+                if let Err(_) = settings.update(settings_data) {
+                    // TODO: better error
+                    return Ok(json_response(status::BadRequest, json!({"message": "Couldn't parse index settings"})));
+                }
+            }
+
             // Load metadata
             let mut metadata = IndexMetaData::default();
-            match json_from_request_body!(req).map(|data| parse_index_metadata(&mut metadata, data)) {
+            match data.map(|data| parse_index_metadata(&mut metadata, data)) {
                 Some(Ok(())) | None => {}
                 Some(Err(_)) => {
                     // TODO: better error
@@ -75,9 +263,12 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
             // Create index
             let mut indices_dir = system.get_indices_dir();
             indices_dir.push(index_name);
-            let index = Index::new(Uuid::new_v4(), index_name.clone().to_owned(), metadata, RocksDBIndexStore::create(indices_dir).unwrap());
+            let mut store = RocksDBIndexStore::create(&indices_dir).unwrap();
+            add_meta_fields(&mut store).unwrap();
+            let index = Index::new(index_name.clone().to_owned(), indices_dir, settings, metadata, Some(store));
+            index.settings.read().unwrap().save(index.settings_path()).unwrap();
             index.metadata.read().unwrap().save(index.metadata_path()).unwrap();
-            let index_ref = indices.insert(index);
+            let index_ref = indices.insert(index).unwrap();
 
             // If there's an alias with the new indexes name, delete it.
             let alias_deleted = indices.names.delete_alias_whole(index_name).unwrap();
@@ -89,6 +280,14 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
             indices.names.insert_canonical(index_name.clone().to_owned(), index_ref).unwrap();
 
             system.log.info("[api] created index", b!("index" => *index_name));
+
+            // The alias would come back on restart if its deletion isn't saved
+            if alias_deleted {
+                if let Err(error) = system.save_aliases(&indices) {
+                    system.log.error("[api] failed to save aliases", b!("error" => error));
+                    return Ok(json_response(status::InternalServerError, json!({"message": "Failed to save aliases"})));
+                }
+            }
         }
     }
 
@@ -108,43 +307,76 @@ pub fn view_delete_index(req: &mut Request) -> IronResult<Response> {
 
     // Remove indices
     for index_ref in indices.names.find(*index_selector) {
-        // Get the index name
-        let index_name = {
-            if let Some(index) = indices.get(&index_ref) {
-                index.canonical_name().to_string()
-            } else {
-                // Index doesn't exist
-                continue;
-            }
-        };
+        delete_index(system, &mut indices, index_ref);
+    }
+
+    // Aliases pointing at the deleted indices may have been changed
+    if let Err(error) = system.save_aliases(&indices) {
+        system.log.error("[api] failed to save aliases", b!("error" => error));
+        return Ok(json_response(status::InternalServerError, json!({"message": "Failed to save aliases"})));
+    }
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+pub fn view_post_refresh_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("_all");
 
-        // Remove index from array
-        indices.remove(&index_ref);
+    // Lock index array
+    let indices = system.indices.read().unwrap();
 
-        // Delete canonical name
-        indices.names.delete_canonical(&index_name, index_ref).unwrap();
+    // Get indices
+    let selected_indices = get_indices_or_404!(indices, *index_selector);
 
-        // Delete file
-        let mut indices_dir = system.get_indices_dir();
-        indices_dir.push(&index_name);
-        match fs::remove_dir_all(&indices_dir) {
-            Ok(()) => {},
-            Err(e) => {
-                system.log.warn("[api] failed to delete index data", b!("index" => format!("{}", index_name), "error" => format!("{}", e)));
+    let mut successful = 0;
+    for &(index, _) in selected_indices.iter() {
+        match index.refresh() {
+            Ok(()) => successful += 1,
+            Err(error) => {
+                system.log.error("[api] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
             }
         }
+    }
 
-        system.log.info("[api] deleted index", b!("index" => format!("{}", index_name)));
+    return Ok(json_response(status::Ok, json!({
+        "_shards": {
+            "total": selected_indices.len(),
+            "successful": successful,
+            "failed": selected_indices.len() - successful,
+        }
+    })));
+}
 
-        // Delete aliases
-        let alias_names = indices.names.iter_index_aliases(index_ref).map(|n| n.to_string()).collect::<Vec<String>>();
-        for alias_name in alias_names {
-            let alias_deleted = indices.names.delete_alias(&alias_name, index_ref).unwrap();
 
-            // If this was the only index being referenced by the alias, the alias would be deleted
-            if alias_deleted {
-                 system.log.info("[api] deleted alias", b!("alias" => format!("{}", alias_name), "reason" => "no indices left"));
+pub fn view_post_close_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Lock index array
+    let mut indices = system.indices.write().unwrap();
+
+    // Make sure the index exists
+    get_index_or_404!(indices, *index_selector);
+
+    for index_ref in indices.names.find(*index_selector) {
+        if let Some(index) = indices.get_mut(&index_ref) {
+            if !index.is_open() {
+                continue;
+            }
+
+            if Arc::get_mut(index).is_none() {
+                return Ok(index_in_use_response(index.canonical_name()));
             }
+            let index = Arc::get_mut(index).unwrap();
+
+            if let Err(error) = index.close() {
+                system.log.error("[api] failed to close index", b!("index" => index.canonical_name(), "error" => error));
+                return Ok(json_response(status::InternalServerError, json!({"message": "Failed to close index"})));
+            }
+
+            system.log.info("[api] closed index", b!("index" => index.canonical_name()));
         }
     }
 
@@ -152,14 +384,35 @@ pub fn view_delete_index(req: &mut Request) -> IronResult<Response> {
 }
 
 
-pub fn view_post_refresh_index(_req: &mut Request) -> IronResult<Response> {
-    // let ref system = get_system!(req);
-    // let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+pub fn view_post_open_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("");
 
     // Lock index array
-    // TODO
-    // let mut indices = system.indices.write().unwrap();
+    let mut indices = system.indices.write().unwrap();
+
+    // Make sure the index exists
+    get_index_or_404!(indices, *index_selector);
+
+    for index_ref in indices.names.find(*index_selector) {
+        if let Some(index) = indices.get_mut(&index_ref) {
+            if index.is_open() {
+                continue;
+            }
+
+            if Arc::get_mut(index).is_none() {
+                return Ok(index_in_use_response(index.canonical_name()));
+            }
+            let index = Arc::get_mut(index).unwrap();
+
+            if let Err(error) = index.open() {
+                system.log.error("[api] failed to open index", b!("index" => index.canonical_name(), "error" => error));
+                return Ok(json_response(status::InternalServerError, json!({"message": "Failed to open index"})));
+            }
+
+            system.log.info("[api] opened index", b!("index" => index.canonical_name()));
+        }
+    }
 
-    // TODO: {"_shards":{"total":10,"successful":5,"failed":0}}
     return Ok(json_response(status::Ok, json!({"acknowledged": true})));
 }
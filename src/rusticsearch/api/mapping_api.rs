@@ -2,7 +2,7 @@ use std::io::Read;
 use std::collections::HashMap;
 
 use serde_json;
-use kite::schema::{FieldType, FieldFlags, FIELD_INDEXED, FIELD_STORED};
+use kite::schema::{FieldType, FieldFlags, Norms, FIELD_INDEXED, FIELD_STORED};
 
 use mapping::{self, MappingProperty};
 use mapping::parse::parse as parse_mapping;
@@ -11,7 +11,7 @@ use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, index_closed_response};
 
 
 pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
@@ -25,6 +25,11 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
     // Get index
     let mut index = get_index_or_404_mut!(indices, *index_name);
 
+    // Mappings can't be changed while the index is closed
+    if !index.is_open() {
+        return Ok(index_closed_response(index.canonical_name()));
+    }
+
     // Load data from body
     let data = json_from_request_body!(req);
 
@@ -46,18 +51,27 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
             return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
         }
     };
-    let mut index_metadata = index.metadata.write().unwrap();
-    let mut mapping = mapping_builder.build(&index_metadata);
+    let (mapping, is_updating) = {
+        let index_metadata = index.metadata.read().unwrap();
+        (mapping_builder.build(&index_metadata), index_metadata.mappings.contains_key(*mapping_name))
+    };
+    let mut mapping = match mapping {
+        Ok(mapping) => mapping,
+        Err(_) => {
+            // TODO: Better error
+            return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
+        }
+    };
     debug!("{:#?}", mapping);
-    let is_updating = index_metadata.mappings.contains_key(*mapping_name);
 
-    // Find list of new fields that need to be added to the store
-    let new_fields = {
-        let index_reader = index.store.reader();
+    // Find list of new fields that need to be added to the store and fields which need their norms changed
+    let (new_fields, changed_norms) = {
+        let index_reader = get_store_or_400!(index).reader();
         let schema = index_reader.schema();
         let mut new_fields: HashMap<String, (FieldType, FieldFlags)>  = HashMap::new();
-        for (name, property) in mapping.properties.iter() {
-            if let MappingProperty::Field(ref field_mapping) = *property {
+        let mut changed_norms: HashMap<String, Norms> = HashMap::new();
+        for (name, property) in mapping.properties.iter_mut() {
+            if let MappingProperty::Field(ref mut field_mapping) = *property {
                 let field_type = match field_mapping.data_type {
                     mapping::FieldType::String => FieldType::Text,
                     mapping::FieldType::Integer => FieldType::I64,
@@ -81,7 +95,18 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
                     let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldRef");
 
                     // Field already exists. Check for conflicting type or flags, otherwise ignore.
+                    // Norms can be changed at any time as they only affect new segments. If the
+                    // mapping doesn't set them, the field keeps the norms it already has
                     if field_info.field_type == field_type && field_info.field_flags == field_flags {
+                        match field_mapping.norms {
+                            Some(norms) => {
+                                if field_info.norms() != norms {
+                                    changed_norms.insert(name.clone(), norms);
+                                }
+                            }
+                            None => field_mapping.norms = Some(field_info.norms()),
+                        }
+
                         continue;
                     } else {
                         // Conflict!
@@ -91,10 +116,16 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
                 }
 
                 new_fields.insert(name.clone(), (field_type, field_flags));
+
+                if let Some(norms) = field_mapping.norms {
+                    if norms != Norms::default() {
+                        changed_norms.insert(name.clone(), norms);
+                    }
+                }
             }
         }
 
-        new_fields
+        (new_fields, changed_norms)
     };
 
     // Add new fields into the store
@@ -103,21 +134,31 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
         let stored_yesno = if field_flags.contains(FIELD_STORED) { "yes" } else { "no" };
         system.log.info("[api] adding field", b!("index" => *index_name, "field" => field_name, "type" => format!("{:?}", field_type), "indexed" => indexed_yesno, "stored" => stored_yesno));
 
-        index.store.add_field(field_name, field_type, field_flags).unwrap();
+        if let Some(store) = index.store_mut() {
+            store.add_field(field_name, field_type, field_flags).unwrap();
+        }
     }
 
-    // Link the mapping
-    {
-        let index_reader = index.store.reader();
-        let schema = index_reader.schema();
+    // Change norms
+    for (field_name, norms) in changed_norms {
+        system.log.info("[api] changing field norms", b!("index" => *index_name, "field" => field_name, "norms" => format!("{:?}", norms)));
 
-        for (name, property) in mapping.properties.iter_mut() {
-            if let MappingProperty::Field(ref mut field_mapping) = *property {
-                field_mapping.index_ref = schema.get_field_by_name(&name)
+        if let Some(store) = index.store_mut() {
+            let field_ref = store.reader().schema().get_field_by_name(&field_name);
+
+            if let Some(field_ref) = field_ref {
+                store.set_field_norms(&field_ref, norms);
             }
         }
     }
 
+    // Link the mapping
+    {
+        let index_reader = get_store_or_400!(index).reader();
+        mapping.link_fields(&index_reader.schema());
+    }
+
+    let mut index_metadata = index.metadata.write().unwrap();
     index_metadata.mappings.insert(mapping_name.clone().to_owned(), mapping);
     index_metadata.save(index.metadata_path()).unwrap();
 
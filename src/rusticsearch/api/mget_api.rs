@@ -0,0 +1,326 @@
+use std::io::Read;
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json;
+use url::form_urlencoded;
+
+use index::Index;
+use index::registry::IndexRef;
+use index::meta_fields::MetaFields;
+use index::write_buffer::{DocumentSnapshot, SnapshotDocument};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::json_response;
+
+
+/// A document that was requested
+struct MgetItem {
+    index_name: String,
+    mapping_name: Option<String>,
+    doc_key: String,
+    stored_fields: Vec<String>,
+    fetch_source: bool,
+}
+
+
+/// An index that documents are being fetched from
+///
+/// All documents from the same index are read from the same snapshot, which includes
+/// changes that haven't been refreshed yet.
+struct MgetIndex<'a> {
+    index: &'a Index,
+    snapshot: DocumentSnapshot<'a>,
+    meta_fields: Option<MetaFields>,
+}
+
+
+fn parse_string_list(value: &serde_json::Value) -> Option<Vec<String>> {
+    match *value {
+        serde_json::Value::String(ref string) => Some(string.split(',').map(|s| s.to_string()).collect()),
+        serde_json::Value::Array(ref array) => {
+            let mut strings = Vec::with_capacity(array.len());
+            for item in array.iter() {
+                match item.as_str() {
+                    Some(string) => strings.push(string.to_string()),
+                    None => return None,
+                }
+            }
+
+            Some(strings)
+        }
+        _ => None,
+    }
+}
+
+
+fn parse_items(data: &serde_json::Value, default_index: Option<&str>, default_stored_fields: &[String], default_fetch_source: bool) -> Result<Vec<MgetItem>, String> {
+    let data = match data.as_object() {
+        Some(data) => data,
+        None => return Err("Request body must be an object".to_string()),
+    };
+
+    let mut items = Vec::new();
+
+    if let Some(docs) = data.get("docs") {
+        let docs = match docs.as_array() {
+            Some(docs) => docs,
+            None => return Err("docs must be an array".to_string()),
+        };
+
+        for doc in docs.iter() {
+            let doc = match doc.as_object() {
+                Some(doc) => doc,
+                None => return Err("Each item in docs must be an object".to_string()),
+            };
+
+            let index_name = match doc.get("_index").and_then(|index| index.as_str()).or(default_index) {
+                Some(index_name) => index_name.to_string(),
+                None => return Err("Each item in docs must have an _index".to_string()),
+            };
+
+            let doc_key = match doc.get("_id") {
+                Some(&serde_json::Value::String(ref doc_key)) => doc_key.clone(),
+                Some(&serde_json::Value::Number(ref doc_key)) => doc_key.to_string(),
+                _ => return Err("Each item in docs must have an _id".to_string()),
+            };
+
+            let stored_fields = match doc.get("stored_fields") {
+                Some(stored_fields) => {
+                    match parse_string_list(stored_fields) {
+                        Some(stored_fields) => stored_fields,
+                        None => return Err("stored_fields must be a string or an array of strings".to_string()),
+                    }
+                }
+                None => default_stored_fields.to_vec(),
+            };
+
+            let fetch_source = match doc.get("_source") {
+                Some(fetch_source) => {
+                    match fetch_source.as_bool() {
+                        Some(fetch_source) => fetch_source,
+                        None => return Err("_source must be a boolean".to_string()),
+                    }
+                }
+                None => default_fetch_source,
+            };
+
+            items.push(MgetItem {
+                index_name: index_name,
+                mapping_name: doc.get("_type").and_then(|mapping| mapping.as_str()).map(|mapping| mapping.to_string()),
+                doc_key: doc_key,
+                stored_fields: stored_fields,
+                fetch_source: fetch_source,
+            });
+        }
+    } else if let Some(ids) = data.get("ids") {
+        let index_name = match default_index {
+            Some(index_name) => index_name,
+            None => return Err("ids can only be used when the index is in the URL".to_string()),
+        };
+
+        let ids = match parse_string_list(ids) {
+            Some(ids) => ids,
+            None => return Err("ids must be an array of strings".to_string()),
+        };
+
+        for doc_key in ids {
+            items.push(MgetItem {
+                index_name: index_name.to_string(),
+                mapping_name: None,
+                doc_key: doc_key,
+                stored_fields: default_stored_fields.to_vec(),
+                fetch_source: default_fetch_source,
+            });
+        }
+    } else {
+        return Err("Request body must contain either docs or ids".to_string());
+    }
+
+    Ok(items)
+}
+
+
+fn get_item(mget_index: &MgetIndex, item: &MgetItem) -> serde_json::Value {
+    let not_found = || {
+        json!({
+            "_index": mget_index.index.canonical_name(),
+            "_type": item.mapping_name,
+            "_id": item.doc_key,
+            "found": false,
+        })
+    };
+
+    let error_response = |error: String| {
+        json!({
+            "_index": mget_index.index.canonical_name(),
+            "_type": item.mapping_name,
+            "_id": item.doc_key,
+            "error": error,
+        })
+    };
+
+    let reader = mget_index.snapshot.reader();
+
+    // Find the document in the write buffer or the primary key index
+    let (doc, version) = match mget_index.snapshot.get_document(&item.doc_key) {
+        Ok(Some(doc)) => doc,
+        Ok(None) => return not_found(),
+        Err(error) => return error_response(error),
+    };
+
+    // Read source
+    let doc_source = match mget_index.meta_fields {
+        Some(ref meta_fields) => {
+            let doc_source = match doc {
+                SnapshotDocument::Buffered(doc) => meta_fields.document_source(doc),
+                SnapshotDocument::Stored(doc_ref) => meta_fields.read_source(reader, doc_ref),
+            };
+
+            match doc_source {
+                Ok(doc_source) => doc_source,
+                Err(error) => return error_response(error),
+            }
+        }
+        None => None,
+    };
+
+    let mapping_name = doc_source.as_ref().map(|doc_source| doc_source.mapping_name.clone());
+    if item.mapping_name.is_some() && mapping_name != item.mapping_name {
+        return not_found();
+    }
+
+    // Read stored fields
+    let mut field_values = BTreeMap::new();
+    for field_name in item.stored_fields.iter() {
+        let field_ref = match reader.schema().get_field_by_name(field_name) {
+            Some(field_ref) => field_ref,
+            None => continue,
+        };
+
+        let value = match doc {
+            SnapshotDocument::Buffered(doc) => doc.stored_fields.get(&field_ref).cloned(),
+            SnapshotDocument::Stored(doc_ref) => reader.read_stored_field(field_ref, doc_ref).ok().and_then(|value| value),
+        };
+
+        if let Some(value) = value {
+            field_values.insert(field_name.clone(), vec![value]);
+        }
+    }
+
+    let mut response = BTreeMap::new();
+    response.insert("_index".to_string(), json!(mget_index.index.canonical_name()));
+    response.insert("_type".to_string(), json!(mapping_name));
+    response.insert("_id".to_string(), json!(item.doc_key));
+    response.insert("_version".to_string(), json!(version.version));
+    response.insert("_seq_no".to_string(), json!(version.seq_no));
+    response.insert("found".to_string(), json!(true));
+
+    if item.fetch_source {
+        if let Some(doc_source) = doc_source {
+            response.insert("_source".to_string(), doc_source.data);
+        }
+    }
+
+    if !field_values.is_empty() {
+        response.insert("fields".to_string(), json!(field_values));
+    }
+
+    json!(response)
+}
+
+
+pub fn view_mget(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let default_index = read_path_parameter!(req, "index").map(|index| index.to_string());
+
+    // URL parameters apply to every item that doesn't override them
+    let mut default_stored_fields = Vec::new();
+    let mut default_fetch_source = true;
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "stored_fields" => {
+                    for field_name in value.split(",") {
+                        default_stored_fields.push(field_name.to_owned());
+                    }
+                }
+                "_source" => {
+                    default_fetch_source = value.as_ref() != "false";
+                }
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
+        }
+    }
+
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
+
+    let items = match parse_items(&data, default_index.as_ref().map(|index| index.as_str()), &default_stored_fields, default_fetch_source) {
+        Ok(items) => items,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+    };
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    // Open a snapshot of each index that is used
+    // Snapshots are kept by index rather than by name as an index may be requested through
+    // several aliases and its write buffer can only be locked once
+    let mut mget_indices: HashMap<IndexRef, MgetIndex> = HashMap::new();
+    let mut index_names: HashMap<String, Result<IndexRef, &'static str>> = HashMap::new();
+    for item in items.iter() {
+        if index_names.contains_key(&item.index_name) {
+            continue;
+        }
+
+        let index = indices.names.find_canonical(&item.index_name).and_then(|index_ref| indices.get(&index_ref).map(|index| (index_ref, index)));
+        let index_ref = match index {
+            Some((index_ref, _)) if mget_indices.contains_key(&index_ref) => Ok(index_ref),
+            Some((index_ref, index)) => {
+                match index.document_snapshot() {
+                    Some(snapshot) => {
+                        mget_indices.insert(index_ref, MgetIndex {
+                            index: index,
+                            snapshot: snapshot,
+                            meta_fields: index.meta_fields(),
+                        });
+
+                        Ok(index_ref)
+                    }
+                    None => Err("Index is closed"),
+                }
+            }
+            None => Err("Index not found"),
+        };
+
+        index_names.insert(item.index_name.clone(), index_ref);
+    }
+
+    // Fetch the documents
+    let mut docs = Vec::with_capacity(items.len());
+    for item in items.iter() {
+        match index_names.get(&item.index_name) {
+            Some(&Ok(ref index_ref)) => docs.push(get_item(&mget_indices[index_ref], item)),
+            Some(&Err(error)) => {
+                docs.push(json!({
+                    "_index": item.index_name,
+                    "_type": item.mapping_name,
+                    "_id": item.doc_key,
+                    "error": error,
+                }));
+            }
+            None => unreachable!(),
+        }
+    }
+
+    return Ok(json_response(status::Ok, json!({"docs": docs})));
+}
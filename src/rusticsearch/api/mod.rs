@@ -10,6 +10,9 @@ mod document_api;
 mod index_api;
 mod mapping_api;
 mod bulk_api;
+mod reindex_api;
+mod by_query_api;
+mod mget_api;
 
 use std::sync::Arc;
 
@@ -35,23 +38,46 @@ fn view_home(_: &mut Request) -> IronResult<Response> {
 
 fn get_router() -> Router {
     router!(get "/" => view_home,
+            get "/_count" => search_api::view_count,
+            post "/_count" => search_api::view_count,
+            get "/_search" => search_api::view_search,
+            post "/_search" => search_api::view_search,
             get "/:index/_count" => search_api::view_count,
             post "/:index/_count" => search_api::view_count,
             get "/:index/_search" => search_api::view_search,
             post "/:index/_search" => search_api::view_search,
+            post "/_msearch" => search_api::view_msearch,
+            post "/:index/_msearch" => search_api::view_msearch,
             get "/_alias/:alias" => alias_api::view_get_global_alias,
             get "/:index/_alias" => alias_api::view_get_alias_list,
             get "/:index/_alias/:alias" => alias_api::view_get_alias,
             put "/:index/_alias/:alias" => alias_api::view_put_alias,
+            delete "/:index/_alias/:alias" => alias_api::view_delete_alias,
+            post "/_aliases" => alias_api::view_post_aliases,
+            get "/_mget" => mget_api::view_mget,
+            post "/_mget" => mget_api::view_mget,
+            get "/:index/_mget" => mget_api::view_mget,
+            post "/:index/_mget" => mget_api::view_mget,
             get "/:index/:mapping/:doc" => document_api::view_get_doc,
             put "/:index/:mapping/:doc" => document_api::view_put_doc,
             delete "/:index/:mapping/:doc" => document_api::view_delete_doc,
+            post "/:index/:mapping/:doc/_update" => document_api::view_post_update_doc,
+            get "/_settings" => index_api::view_get_index_settings,
+            get "/:index/_settings" => index_api::view_get_index_settings,
+            put "/_settings" => index_api::view_put_index_settings,
+            put "/:index/_settings" => index_api::view_put_index_settings,
             get "/:index" => index_api::view_get_index,
             put "/:index" => index_api::view_put_index,
             delete "/:index" => index_api::view_delete_index,
+            post "/_refresh" => index_api::view_post_refresh_index,
             post "/:index/_refresh" => index_api::view_post_refresh_index,
+            post "/:index/_close" => index_api::view_post_close_index,
+            post "/:index/_open" => index_api::view_post_open_index,
+            post "/:index/_delete_by_query" => by_query_api::view_post_delete_by_query,
+            post "/:index/_update_by_query" => by_query_api::view_post_update_by_query,
             put "/:index/_mapping/:mapping" => mapping_api::view_put_mapping,
-            post "/_bulk" => bulk_api::view_post_bulk)
+            post "/_bulk" => bulk_api::view_post_bulk,
+            post "/_reindex" => reindex_api::view_post_reindex)
 }
 
 
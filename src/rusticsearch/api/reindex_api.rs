@@ -0,0 +1,211 @@
+use std::io::Read;
+use std::time::Instant;
+
+use serde_json;
+use kite::query::Query;
+
+use index::meta_fields::MetaFields;
+use index::write_buffer::{WriteConditions, WriteError};
+use query_parser::{QueryBuildContext, parse as parse_query};
+use search::{IndexSearcher, find_all_docs};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, index_closed_response, elapsed_ms, DEFAULT_BATCH_SIZE};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpType {
+    /// Insert documents, replacing any that already exist in the destination
+    Index,
+
+    /// Only insert documents that don't already exist in the destination
+    Create,
+}
+
+
+fn bad_request_response(message: &str) -> Response {
+    json_response(status::BadRequest, json!({"message": message}))
+}
+
+
+pub fn view_post_reindex(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let start_time = Instant::now();
+
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => return Ok(bad_request_response("No data")),
+    };
+
+    // Parse source
+    let source_json = match data.as_object().and_then(|data| data.get("source")).and_then(|source| source.as_object()) {
+        Some(source_json) => source_json,
+        None => return Ok(bad_request_response("Missing source")),
+    };
+
+    let source_index_name = match source_json.get("index").and_then(|index| index.as_str()) {
+        Some(source_index_name) => source_index_name,
+        None => return Ok(bad_request_response("Missing source index")),
+    };
+
+    let query = match source_json.get("query") {
+        Some(query_json) => {
+            match parse_query(query_json) {
+                Ok(query) => Some(query),
+                Err(_) => return Ok(bad_request_response("Query error")),
+            }
+        }
+        None => None,
+    };
+
+    let batch_size = match source_json.get("size") {
+        Some(size) => {
+            match size.as_u64() {
+                Some(size) if size > 0 => size as usize,
+                _ => return Ok(bad_request_response("Invalid source size")),
+            }
+        }
+        None => DEFAULT_BATCH_SIZE,
+    };
+
+    // Parse dest
+    let dest_json = match data.as_object().and_then(|data| data.get("dest")).and_then(|dest| dest.as_object()) {
+        Some(dest_json) => dest_json,
+        None => return Ok(bad_request_response("Missing dest")),
+    };
+
+    let dest_index_name = match dest_json.get("index").and_then(|index| index.as_str()) {
+        Some(dest_index_name) => dest_index_name,
+        None => return Ok(bad_request_response("Missing dest index")),
+    };
+
+    let op_type = match dest_json.get("op_type").map(|op_type| op_type.as_str()) {
+        Some(Some("index")) | None => OpType::Index,
+        Some(Some("create")) => OpType::Create,
+        Some(_) => return Ok(bad_request_response("Invalid dest op_type")),
+    };
+
+    // Get indices
+    // Reindexing can take a long time so the index array isn't kept locked, the indices
+    // are kept alive by their references instead
+    let (source_index, dest_index) = {
+        let indices = system.indices.read().unwrap();
+
+        (get_index_or_404!(indices, source_index_name).clone(), get_index_or_404!(indices, dest_index_name).clone())
+    };
+
+    if source_index.id() == dest_index.id() {
+        return Ok(bad_request_response("Source and dest must be different indices"));
+    }
+
+    if !dest_index.is_open() {
+        return Ok(index_closed_response(dest_index.canonical_name()));
+    }
+
+    // Find the documents to copy. The reader is a snapshot so changes made to the source
+    // index while reindexing won't be picked up
+    let source_reader = get_store_or_400!(source_index).reader();
+    let source_meta_fields = match MetaFields::from_schema(&source_reader.schema()) {
+        Some(meta_fields) => meta_fields,
+        None => return Ok(bad_request_response("Source index doesn't store document sources")),
+    };
+
+    let source_query = {
+        let index_metadata = source_index.metadata.read().unwrap();
+        let context = QueryBuildContext::new().set_index_metadata(&index_metadata).no_score();
+        match query {
+            Some(ref query) => query.build(&context, &source_reader.schema()),
+            None => Query::new_all(),
+        }
+    };
+
+    let searcher = IndexSearcher::new(&source_index, &source_reader, source_query);
+    let doc_refs = match find_all_docs(&searcher) {
+        Ok(doc_refs) => doc_refs,
+        Err(error) => {
+            system.log.error("[api] reindex search failed", b!("index" => source_index.canonical_name(), "error" => error));
+            return Ok(json_response(status::InternalServerError, json!({"message": "Search failed"})));
+        }
+    };
+
+    system.log.info("[api] starting reindex", b!("source" => source_index.canonical_name(), "dest" => dest_index.canonical_name(), "total" => doc_refs.len()));
+
+    // Copy documents in batches, each batch is written into the destination as a single segment
+    let mut created = 0;
+    let mut updated = 0;
+    let mut version_conflicts = 0;
+    let mut batches = 0;
+    let mut failures = Vec::new();
+
+    for batch in doc_refs.chunks(batch_size) {
+        {
+            let dest_metadata = dest_index.metadata.read().unwrap();
+
+            for doc_ref in batch.iter() {
+                let doc_source = match source_meta_fields.read_source(searcher.reader, *doc_ref) {
+                    Ok(Some(doc_source)) => doc_source,
+                    Ok(None) => {
+                        failures.push(json!({"cause": "Document has no stored source"}));
+                        continue;
+                    }
+                    Err(error) => {
+                        failures.push(json!({"cause": error}));
+                        continue;
+                    }
+                };
+
+                let mapping = match dest_metadata.mappings.get(&doc_source.mapping_name) {
+                    Some(mapping) => mapping,
+                    None => {
+                        failures.push(json!({"_id": doc_source.key, "_type": doc_source.mapping_name, "cause": "Mapping not found"}));
+                        continue;
+                    }
+                };
+
+                let conditions = WriteConditions {
+                    create_only: op_type == OpType::Create,
+                    .. WriteConditions::default()
+                };
+
+                match dest_index.insert_or_update_document(dest_index.prepare_document(&doc_source, mapping), &conditions) {
+                    Ok((_, was_created)) => {
+                        if was_created {
+                            created += 1;
+                        } else {
+                            updated += 1;
+                        }
+                    }
+                    Err(WriteError::VersionConflict(_)) => {
+                        version_conflicts += 1;
+                    }
+                    Err(WriteError::StoreError(error)) => {
+                        failures.push(json!({"_id": doc_source.key, "_type": doc_source.mapping_name, "cause": error}));
+                    }
+                }
+            }
+        }
+
+        if let Err(error) = dest_index.refresh() {
+            system.log.error("[api] failed to refresh index", b!("index" => dest_index.canonical_name(), "error" => error));
+            return Ok(json_response(status::InternalServerError, json!({"message": "Failed to refresh index"})));
+        }
+
+        batches += 1;
+        system.log.info("[api] reindex progress", b!("dest" => dest_index.canonical_name(), "batches" => batches, "created" => created, "updated" => updated, "total" => doc_refs.len()));
+    }
+
+    system.log.info("[api] finished reindex", b!("source" => source_index.canonical_name(), "dest" => dest_index.canonical_name(), "created" => created, "updated" => updated, "failures" => failures.len()));
+
+    return Ok(json_response(status::Ok, json!({
+        "took": elapsed_ms(start_time),
+        "total": doc_refs.len(),
+        "created": created,
+        "updated": updated,
+        "batches": batches,
+        "version_conflicts": version_conflicts,
+        "failures": failures
+    })));
+}
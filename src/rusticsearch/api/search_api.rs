@@ -1,14 +1,16 @@
 use std::io::Read;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde_json;
 use url::form_urlencoded;
 use kite::document::DocRef;
 use kite::query::Query;
-use kite::collectors::top_score::TopScoreCollector;
-use kite::collectors::total_count::TotalCountCollector;
+use kite::schema::Schema;
+use kite_rocksdb::{RocksDBIndexReader, SearchExecutor};
 
-use query_parser::{QueryBuildContext, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, parse as parse_query};
+use search::{IndexSearcher, search_top_docs, count_docs};
+use index::registry::{IndexRegistry, IndexRef};
 
 use api::persistent;
 use api::iron::prelude::*;
@@ -17,30 +19,49 @@ use api::router::Router;
 use api::utils::json_response;
 
 
+/// Restricts a query to documents that match at least one of the given alias filters
+pub fn apply_alias_filters(query: Query, filters: &[serde_json::Value], context: &QueryBuildContext, schema: &Schema) -> Result<Query, QueryParseError> {
+    if filters.is_empty() {
+        return Ok(query);
+    }
+
+    let mut filter_queries = Vec::with_capacity(filters.len());
+    for filter in filters.iter() {
+        let filter = try!(parse_query(filter));
+        filter_queries.push(filter.build(&context.clone().no_score(), schema));
+    }
+
+    Ok(Query::Filter {
+        query: Box::new(query),
+        filter: Box::new(Query::new_disjunction(filter_queries)),
+    })
+}
+
+
+pub fn alias_filter_error_response() -> Response {
+    json_response(status::BadRequest, json!({"message": "Alias filter error"}))
+}
+
+
 pub fn view_count(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
-    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("_all");
 
     // Lock index array
     let indices = system.indices.read().unwrap();
 
-    // Get index
-    let index = get_index_or_404!(indices, *index_name);
-    let index_reader = index.store.reader();
-    let index_metadata = index.metadata.read().unwrap();
+    // Get indices
+    let selected_indices = get_open_indices_or_404!(indices, *index_selector);
 
-    let count = match json_from_request_body!(req) {
+    let query_json = json_from_request_body!(req);
+    let query = match query_json {
         Some(query_json) => {
             // Parse query
             let query = parse_query(query_json.as_object().unwrap().get("query").unwrap());
             debug!("{:#?}", query);
 
             match query {
-                Ok(query) => {
-                    let mut collector = TotalCountCollector::new();
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).no_score(), &index_reader.schema())).unwrap();
-                    collector.get_total_count()
-                }
+                Ok(query) => Some(query),
                 Err(_) => {
                     // TODO: What specifically is bad about the Query?
                     let mut response = Response::with((status::BadRequest,
@@ -50,119 +71,423 @@ pub fn view_count(req: &mut Request) -> IronResult<Response> {
                 }
             }
         }
-        None => {
-            let mut collector = TotalCountCollector::new();
-            index_reader.search(&mut collector, &Query::new_all()).unwrap();
-            collector.get_total_count()
-        }
+        None => None,
     };
 
+    // Open a reader for each index
+    let mut readers = Vec::with_capacity(selected_indices.len());
+    for &(index, _) in selected_indices.iter() {
+        readers.push(get_store_or_400!(index).reader());
+    }
+
+    // Build the query for each index
+    let mut searchers = Vec::with_capacity(selected_indices.len());
+    for ((index, filters), index_reader) in selected_indices.into_iter().zip(readers.iter()) {
+        let index_query = {
+            let index_metadata = index.metadata.read().unwrap();
+            let context = QueryBuildContext::new().set_index_metadata(&index_metadata).no_score();
+            let index_query = match query {
+                Some(ref query) => query.build(&context, &index_reader.schema()),
+                None => Query::new_all(),
+            };
+
+            match apply_alias_filters(index_query, &filters, &context, &index_reader.schema()) {
+                Ok(index_query) => index_query,
+                Err(_) => return Ok(alias_filter_error_response()),
+            }
+        };
+
+        searchers.push(IndexSearcher::new(index, index_reader, index_query));
+    }
+
+    let count = count_docs(&system.search_executor, &searchers).unwrap();
+
     return Ok(json_response(status::Ok, json!({"count": count})));
 }
 
 
+/// Options that control which hits are returned by a search
+#[derive(Debug)]
+struct SearchOptions {
+    from: usize,
+    size: usize,
+    fields: Vec<String>,
+}
+
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            from: 0,
+            size: 10,
+            fields: Vec::new(),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+enum SearchError {
+    IndexNotFound,
+    IndexClosed(String),
+    MissingQuery,
+    QueryError,
+    AliasFilterError,
+    ResultWindowTooLarge {
+        index_name: String,
+        max_result_window: usize,
+        result_window: usize,
+    },
+    SearchFailed(String),
+}
+
+
+impl SearchError {
+    fn status(&self) -> status::Status {
+        match *self {
+            SearchError::IndexNotFound => status::NotFound,
+            SearchError::SearchFailed(_) => status::InternalServerError,
+            _ => status::BadRequest,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match *self {
+            SearchError::IndexNotFound => json!({"message": "Index not found"}),
+            SearchError::IndexClosed(ref index_name) => json!({"message": "Index is closed", "index": index_name}),
+            SearchError::MissingQuery => json!({"message": "Missing query"}),
+            SearchError::QueryError => json!({"message": "Query error"}),
+            SearchError::AliasFilterError => json!({"message": "Alias filter error"}),
+            SearchError::ResultWindowTooLarge { ref index_name, max_result_window, result_window } => {
+                json!({
+                    "message": format!("Result window is too large, from + size must be less than or equal to: [{}] but was [{}]", max_result_window, result_window),
+                    "index": index_name,
+                })
+            }
+            SearchError::SearchFailed(ref error) => json!({"message": "Search failed", "error": error}),
+        }
+    }
+
+    fn to_response(&self) -> Response {
+        json_response(self.status(), self.to_json())
+    }
+}
+
+
+/// Opens a reader for each open index that is selected, unless one is already open
+fn open_readers<'a>(indices: &'a IndexRegistry, index_selector: &str, readers: &mut HashMap<IndexRef, RocksDBIndexReader<'a>>) {
+    for (index_ref, _) in indices.names.find_with_filters(index_selector) {
+        if readers.contains_key(&index_ref) {
+            continue;
+        }
+
+        if let Some(store) = indices.get(&index_ref).and_then(|index| index.store()) {
+            readers.insert(index_ref, store.reader());
+        }
+    }
+}
+
+
+/// Runs a search against the selected indices and builds the response
+///
+/// This is shared by the search and multi-search APIs. The readers must have been opened
+/// with `open_readers` beforehand, searches that use the same readers see the same
+/// snapshot of each index.
+fn run_search(executor: &SearchExecutor, indices: &IndexRegistry, readers: &HashMap<IndexRef, RocksDBIndexReader>, index_selector: &str, query_json: &serde_json::Value, options: &SearchOptions) -> Result<serde_json::Value, SearchError> {
+    // Get indices, closed indices are skipped unless they were selected by name
+    let mut selected_indices = Vec::new();
+    let mut closed_index = None;
+    for (index_ref, filters) in indices.names.find_with_filters(index_selector) {
+        let index = match indices.get(&index_ref) {
+            Some(index) => index,
+            None => continue,
+        };
+
+        if index.is_open() {
+            selected_indices.push((index_ref, index, filters));
+        } else if index_selector.split(',').any(|name| name.trim() == index.canonical_name()) {
+            return Err(SearchError::IndexClosed(index.canonical_name().to_string()));
+        } else if closed_index.is_none() {
+            closed_index = Some(index.canonical_name().to_string());
+        }
+    }
+
+    if selected_indices.is_empty() {
+        // Searching nothing but closed indices is an error, even through an alias or wildcard
+        return match closed_index {
+            Some(index_name) => Err(SearchError::IndexClosed(index_name)),
+            None => Err(SearchError::IndexNotFound),
+        };
+    }
+
+    // Parse query
+    let query = match query_json.as_object().and_then(|query_json| query_json.get("query")) {
+        Some(query) => try!(parse_query(query).map_err(|_| SearchError::QueryError)),
+        None => return Err(SearchError::MissingQuery),
+    };
+    debug!("{:#?}", query);
+
+    // Check that the result window is allowed by every index
+    for &(_, index, _) in selected_indices.iter() {
+        let max_result_window = index.settings.read().unwrap().max_result_window;
+
+        if options.from + options.size > max_result_window {
+            return Err(SearchError::ResultWindowTooLarge {
+                index_name: index.canonical_name().to_string(),
+                max_result_window: max_result_window,
+                result_window: options.from + options.size,
+            });
+        }
+    }
+
+    // Build the query for each index
+    let mut searchers = Vec::with_capacity(selected_indices.len());
+    for (index_ref, index, filters) in selected_indices {
+        let index_reader = match readers.get(&index_ref) {
+            Some(index_reader) => index_reader,
+            None => return Err(SearchError::IndexClosed(index.canonical_name().to_string())),
+        };
+        let index_query = {
+            let index_metadata = index.metadata.read().unwrap();
+            let context = QueryBuildContext::new().set_index_metadata(&index_metadata);
+            let index_query = query.build(&context, &index_reader.schema());
+
+            try!(apply_alias_filters(index_query, &filters, &context, &index_reader.schema()).map_err(|_| SearchError::AliasFilterError))
+        };
+
+        searchers.push(IndexSearcher::new(index, index_reader, index_query));
+    }
+
+    // Do the search
+    let top_docs = try!(search_top_docs(executor, &searchers, options.from + options.size).map_err(SearchError::SearchFailed));
+
+    // Convert hits into JSON
+    let mut hits = Vec::new();
+    for &(searcher_id, ref doc_match) in top_docs.iter().skip(options.from) {
+        let searcher = &searchers[searcher_id];
+        let mut field_values = BTreeMap::new();
+
+        for field_name in options.fields.iter() {
+            let field_ref = match searcher.reader.schema().get_field_by_name(field_name) {
+                Some(field_ref) => field_ref,
+                None => {
+                    warn!("unknown field {:?}", field_name);
+                    continue;
+                }
+            };
+
+            let value = match searcher.reader.read_stored_field(field_ref, DocRef::from_u64(doc_match.doc_id())) {
+                Ok(Some(value)) => vec![value],
+                Ok(None) => vec![],
+                Err(_) => vec![],
+            };
+
+            field_values.insert(field_name.clone(), value);
+        }
+
+        hits.push(json!({
+            "_index": searcher.index.canonical_name(),
+            "_score": doc_match.score().unwrap(),
+            "fields": field_values,
+        }));
+    }
+
+    // TODO: {"took":5,"timed_out":false,"_shards":{"total":5,"successful":5,"failed":0},"hits":{"total":4,"max_score":1.0,"hits":[{"_index":"wagtail","_type":"searchtests_searchtest_searchtests_searchtestchild","_id":"searchtests_searchtest:5380","_score":1.0,"fields":{"pk":["5380"]}},{"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5379","_score":1.0,"fields":{"pk":["5379"]}}]}}
+    Ok(json!({
+        "hits": {
+            "total": hits.len(),
+            "hits": hits
+        }
+    }))
+}
+
+
 pub fn view_search(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
-    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref index_selector = read_path_parameter!(req, "index").unwrap_or("_all");
+
+    let mut options = SearchOptions::default();
+
+    // TODO: Rewrite this
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "from" => {
+                    options.from = value.as_ref().parse().expect("need a number");
+                }
+                "size" => {
+                    options.size = value.as_ref().parse().expect("need a number");
+                }
+                "fields" => {
+                    for field_name in value.split(",") {
+                        options.fields.push(field_name.to_owned());
+                    }
+                }
+                // terminate_after
+                // explain
+                // version
+                // timeout
+                // fielddata_fields
+                // track_scores
+                // stats
+                // suggest_field
+                _ => warn!("unrecognised GET parameter {:?}", key),
+            }
+        }
+    }
+
+    let query_json = match json_from_request_body!(req) {
+        Some(query_json) => query_json,
+        None => return Ok(SearchError::MissingQuery.to_response()),
+    };
 
     // Lock index array
     let indices = system.indices.read().unwrap();
 
-    // Get index
-    let index = get_index_or_404!(indices, *index_name);
-    let index_reader = index.store.reader();
-    let index_metadata = index.metadata.read().unwrap();
+    let mut readers = HashMap::new();
+    open_readers(&indices, *index_selector, &mut readers);
 
-    match json_from_request_body!(req) {
-        Some(query_json) => {
-            // Parse query
-            let query = parse_query(query_json.as_object().unwrap().get("query").unwrap());
-            debug!("{:#?}", query);
+    match run_search(&system.search_executor, &indices, &readers, *index_selector, &query_json, &options) {
+        Ok(response) => Ok(json_response(status::Ok, response)),
+        Err(error) => Ok(error.to_response()),
+    }
+}
 
-            match query {
-                Ok(query) => {
-                    let mut from = 0;
-                    let mut size = 10;
-                    let mut fields = Vec::new();
-
-                    // TODO: Rewrite this
-                    if let Some(ref url_query) = req.url.query() {
-                        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
-                            match key.as_ref() {
-                                "from" => {
-                                    from = value.as_ref().parse().expect("need a number");
-                                }
-                                "size" => {
-                                    size = value.as_ref().parse().expect("need a number");
-                                }
-                                "fields" => {
-                                    for field_name in value.split(",") {
-                                        let field_ref = match index_reader.schema().get_field_by_name(field_name) {
-                                            Some(field_ref) => field_ref,
-                                            None => {
-                                                warn!("unknown field {:?}", field_name);
-                                                continue;
-                                            }
-                                        };
-
-                                        fields.push((field_name.to_owned(), field_ref));
-                                    }
-                                }
-                                // terminate_after
-                                // explain
-                                // version
-                                // timeout
-                                // fielddata_fields
-                                // track_scores
-                                // stats
-                                // suggest_field
-                                _ => warn!("unrecognised GET parameter {:?}", key),
-                            }
-                        }
-                    }
 
-                    // Do the search
-                    let mut collector = TopScoreCollector::new(from + size);
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &index_reader.schema())).unwrap();
-
-                    // Convert hits into JSON
-                    let mut hits = Vec::new();
-                    for doc_match in collector.into_sorted_vec().iter().skip(from) {
-                        let mut field_values = BTreeMap::new();
-
-                        for &(ref field_name, field_ref) in fields.iter() {
-                            let value = match index_reader.read_stored_field(field_ref, DocRef::from_u64(doc_match.doc_id())) {
-                                Ok(Some(value)) => vec![value],
-                                Ok(None) => vec![],
-                                Err(_) => vec![],
-                            };
-
-                            field_values.insert(field_name.clone(), value);
-                        }
-
-                        hits.push(json!({
-                            "_score": doc_match.score().unwrap(),
-                            "fields": field_values,
-                        }));
-                    }
+/// Reads the options of a search in a multi-search request from its body
+fn parse_msearch_options(body: &serde_json::Value) -> Result<SearchOptions, String> {
+    let mut options = SearchOptions::default();
+
+    if let Some(from) = body.as_object().and_then(|body| body.get("from")) {
+        match from.as_u64() {
+            Some(from) => options.from = from as usize,
+            None => return Err("from must be a number".to_string()),
+        }
+    }
 
-                    // TODO: {"took":5,"timed_out":false,"_shards":{"total":5,"successful":5,"failed":0},"hits":{"total":4,"max_score":1.0,"hits":[{"_index":"wagtail","_type":"searchtests_searchtest_searchtests_searchtestchild","_id":"searchtests_searchtest:5380","_score":1.0,"fields":{"pk":["5380"]}},{"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5379","_score":1.0,"fields":{"pk":["5379"]}}]}}
-                    Ok(json_response(status::Ok,
-                                     json!({
-                                         "hits": {
-                                             "total": hits.len(),
-                                             "hits": hits
-                                        }})))
+    if let Some(size) = body.as_object().and_then(|body| body.get("size")) {
+        match size.as_u64() {
+            Some(size) => options.size = size as usize,
+            None => return Err("size must be a number".to_string()),
+        }
+    }
+
+    let fields = body.as_object().and_then(|body| body.get("stored_fields").or_else(|| body.get("fields")));
+    if let Some(fields) = fields {
+        match *fields {
+            serde_json::Value::String(ref field_name) => options.fields.push(field_name.clone()),
+            serde_json::Value::Array(ref field_names) => {
+                for field_name in field_names.iter() {
+                    match field_name.as_str() {
+                        Some(field_name) => options.fields.push(field_name.to_string()),
+                        None => return Err("fields must be strings".to_string()),
+                    }
                 }
-                Err(_) => {
-                    // TODO: What specifically is bad about the Query?
-                    let mut response = Response::with((status::BadRequest,
-                                                       "{\"message\": \"Query error\"}"));
-                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
-                    Ok(response)
+            }
+            _ => return Err("fields must be a string or an array of strings".to_string()),
+        }
+    }
+
+    Ok(options)
+}
+
+
+pub fn view_msearch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let default_index_selector = read_path_parameter!(req, "index").unwrap_or("_all").to_string();
+
+    // Load data from body
+    let mut payload = String::new();
+    if req.body.read_to_string(&mut payload).is_err() {
+        return Ok(json_response(status::BadRequest, json!({"message": "Couldn't read request body"})));
+    }
+
+    // The body is made up of pairs of lines, a header which selects the indices to search
+    // followed by the search request itself. Each search succeeds or fails on its own
+    let mut searches = Vec::new();
+    let mut payload_lines = payload.lines().filter(|line| !line.trim().is_empty());
+    loop {
+        let header_line = match payload_lines.next() {
+            Some(header_line) => header_line,
+            None => break,
+        };
+
+        let body_line = match payload_lines.next() {
+            Some(body_line) => body_line,
+            None => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Search header must be followed by a search body"})));
+            }
+        };
+
+        let header: serde_json::Value = match serde_json::from_str(header_line) {
+            Ok(header) => header,
+            Err(_) => {
+                searches.push(Err(json!({"error": {"message": "Couldn't parse JSON"}, "status": 400})));
+                continue;
+            }
+        };
+
+        let body: serde_json::Value = match serde_json::from_str(body_line) {
+            Ok(body) => body,
+            Err(_) => {
+                searches.push(Err(json!({"error": {"message": "Couldn't parse JSON"}, "status": 400})));
+                continue;
+            }
+        };
+
+        let index_selector = match header.as_object().and_then(|header| header.get("index")) {
+            Some(&serde_json::Value::String(ref index_selector)) => index_selector.clone(),
+            Some(&serde_json::Value::Array(ref index_names)) => {
+                index_names.iter().filter_map(|index_name| index_name.as_str()).collect::<Vec<_>>().join(",")
+            }
+            _ => default_index_selector.clone(),
+        };
+
+        let options = match parse_msearch_options(&body) {
+            Ok(options) => options,
+            Err(error) => {
+                searches.push(Err(json!({"error": {"message": error}, "status": 400})));
+                continue;
+            }
+        };
+
+        searches.push(Ok((index_selector, body, options)));
+    }
+
+    // Lock index array
+    let indices = system.indices.read().unwrap();
+
+    // Open each index once so that searches against the same index share a snapshot
+    let mut readers = HashMap::new();
+    for search in searches.iter() {
+        if let Ok((ref index_selector, _, _)) = *search {
+            open_readers(&indices, index_selector, &mut readers);
+        }
+    }
+
+    let mut responses = Vec::with_capacity(searches.len());
+    for search in searches {
+        let (index_selector, body, options) = match search {
+            Ok(search) => search,
+            Err(response) => {
+                responses.push(response);
+                continue;
+            }
+        };
+
+        match run_search(&system.search_executor, &indices, &readers, &index_selector, &body, &options) {
+            Ok(mut response) => {
+                if let serde_json::Value::Object(ref mut response) = response {
+                    response.insert("status".to_string(), json!(200));
                 }
+
+                responses.push(response);
+            }
+            Err(error) => {
+                responses.push(json!({"error": error.to_json(), "status": error.status().to_u16()}));
             }
         }
-        None => Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
     }
+
+    return Ok(json_response(status::Ok, json!({"responses": responses})));
 }
@@ -1,4 +1,11 @@
+use std::time::Instant;
+
 use serde_json;
+use url::form_urlencoded;
+
+use kite_rocksdb::DocumentVersion;
+
+use index::write_buffer::{RefreshPolicy, WriteConditions};
 
 use api::iron::prelude::*;
 use api::iron::status;
@@ -27,11 +34,163 @@ pub fn json_response(status: status::Status, content: serde_json::Value) -> Resp
 }
 
 
+/// Reads the "refresh" URL parameter of a write request
+///
+/// Returns `None` if the value isn't recognised
+pub fn get_refresh_policy(req: &Request) -> Option<RefreshPolicy> {
+    if let Some(url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key.as_ref() == "refresh" {
+                return match value.as_ref() {
+                    "" | "true" => Some(RefreshPolicy::Immediate),
+                    "false" => Some(RefreshPolicy::None),
+                    "wait_for" => Some(RefreshPolicy::WaitFor),
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    Some(RefreshPolicy::None)
+}
+
+
+macro_rules! get_refresh_policy_or_400 {
+    ($req: expr) => {{
+        use api::utils::{json_response, get_refresh_policy};
+
+        match get_refresh_policy(&$req) {
+            Some(refresh_policy) => refresh_policy,
+            None => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Invalid value for refresh parameter"})));
+            }
+        }
+    }}
+}
+
+
+/// Reads the "version", "if_seq_no" and "op_type" URL parameters of a write request
+///
+/// Returns `None` if any of the values aren't recognised
+pub fn get_write_conditions(req: &Request) -> Option<WriteConditions> {
+    let mut conditions = WriteConditions::default();
+
+    if let Some(url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            match key.as_ref() {
+                "version" => {
+                    match value.as_ref().parse() {
+                        Ok(version) => conditions.version = Some(version),
+                        Err(_) => return None,
+                    }
+                }
+                "if_seq_no" => {
+                    match value.as_ref().parse() {
+                        Ok(seq_no) => conditions.if_seq_no = Some(seq_no),
+                        Err(_) => return None,
+                    }
+                }
+                "op_type" => {
+                    match value.as_ref() {
+                        "index" => conditions.create_only = false,
+                        "create" => conditions.create_only = true,
+                        _ => return None,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Some(conditions)
+}
+
+
+macro_rules! get_write_conditions_or_400 {
+    ($req: expr) => {{
+        use api::utils::{json_response, get_write_conditions};
+
+        match get_write_conditions(&$req) {
+            Some(conditions) => conditions,
+            None => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Invalid value for version, if_seq_no or op_type parameter"})));
+            }
+        }
+    }}
+}
+
+
+pub fn version_conflict_response(doc_key: &str, current_version: Option<DocumentVersion>) -> Response {
+    json_response(status::Conflict, json!({
+        "message": "Version conflict",
+        "_id": doc_key,
+        "current_version": current_version.map(|current_version| current_version.version),
+        "current_seq_no": current_version.map(|current_version| current_version.seq_no),
+    }))
+}
+
+
+/// Number of documents that are processed at a time by APIs that work through many documents
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+
+/// Reads the "scroll_size" URL parameter, which sets how many documents are processed at a time
+///
+/// Returns `None` if the value isn't a positive number
+pub fn get_scroll_size(req: &Request) -> Option<usize> {
+    if let Some(url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key.as_ref() == "scroll_size" {
+                return match value.as_ref().parse() {
+                    Ok(0) | Err(_) => None,
+                    Ok(scroll_size) => Some(scroll_size),
+                };
+            }
+        }
+    }
+
+    Some(DEFAULT_BATCH_SIZE)
+}
+
+
+macro_rules! get_scroll_size_or_400 {
+    ($req: expr) => {{
+        use api::utils::{json_response, get_scroll_size};
+
+        match get_scroll_size(&$req) {
+            Some(scroll_size) => scroll_size,
+            None => {
+                return Ok(json_response(status::BadRequest, json!({"message": "Invalid value for scroll_size parameter"})));
+            }
+        }
+    }}
+}
+
+
+/// Milliseconds since the given time, for the "took" field of responses
+pub fn elapsed_ms(start_time: Instant) -> u64 {
+    let elapsed = start_time.elapsed();
+    elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1000000
+}
+
+
 pub fn index_not_found_response() -> Response {
     json_response(status::NotFound, json!({"message": "Index not found"}))
 }
 
 
+pub fn index_closed_response(index_name: &str) -> Response {
+    json_response(status::BadRequest, json!({"message": "Index is closed", "index": index_name}))
+}
+
+
+/// The response given when an index can't be changed as another request (such as a
+/// reindex) is still using it
+pub fn index_in_use_response(index_name: &str) -> Response {
+    json_response(status::Conflict, json!({"message": "Index is in use by another request", "index": index_name}))
+}
+
+
 macro_rules! get_index_or_404 {
     ($indices: expr, $index_name: expr) => {{
         use api::utils::index_not_found_response;
@@ -55,7 +214,8 @@ macro_rules! get_index_or_404 {
 
 macro_rules! get_index_or_404_mut {
     ($indices: expr, $index_name: expr) => {{
-        use api::utils::index_not_found_response;
+        use std::sync::Arc;
+        use api::utils::{index_not_found_response, index_in_use_response};
 
         let index_ref = match $indices.names.find_canonical($index_name) {
             Some(index_ref) => index_ref,
@@ -65,7 +225,13 @@ macro_rules! get_index_or_404_mut {
         };
 
         match $indices.get_mut(&index_ref) {
-            Some(index) => index,
+            Some(index) => {
+                if Arc::get_mut(index).is_none() {
+                    return Ok(index_in_use_response(index.canonical_name()));
+                }
+
+                Arc::get_mut(index).unwrap()
+            }
             None => {
                 return Ok(index_not_found_response());
             }
@@ -74,6 +240,69 @@ macro_rules! get_index_or_404_mut {
 }
 
 
+macro_rules! get_indices_or_404 {
+    ($indices: expr, $index_selector: expr) => {{
+        use api::utils::index_not_found_response;
+
+        let selected_indices = $indices.names.find_with_filters($index_selector).into_iter()
+            .filter_map(|(index_ref, filters)| $indices.get(&index_ref).map(|index| (index, filters)))
+            .collect::<Vec<_>>();
+
+        if selected_indices.is_empty() {
+            return Ok(index_not_found_response());
+        }
+
+        selected_indices
+    }}
+}
+
+
+/// Like get_indices_or_404 but leaves out closed indices
+///
+/// Closed indices that were selected by their name cause an error to be returned, closed
+/// indices that were selected by a wildcard or an alias are skipped. An error is also
+/// returned if every selected index is closed.
+macro_rules! get_open_indices_or_404 {
+    ($indices: expr, $index_selector: expr) => {{
+        use api::utils::index_closed_response;
+
+        let mut open_indices = Vec::new();
+        let mut closed_index = None;
+        for (index, filters) in get_indices_or_404!($indices, $index_selector) {
+            if index.is_open() {
+                open_indices.push((index, filters));
+            } else if $index_selector.split(',').any(|name| name.trim() == index.canonical_name()) {
+                return Ok(index_closed_response(index.canonical_name()));
+            } else if closed_index.is_none() {
+                closed_index = Some(index.canonical_name());
+            }
+        }
+
+        if open_indices.is_empty() {
+            if let Some(closed_index) = closed_index {
+                return Ok(index_closed_response(closed_index));
+            }
+        }
+
+        open_indices
+    }}
+}
+
+
+macro_rules! get_store_or_400 {
+    ($index: expr) => {{
+        use api::utils::index_closed_response;
+
+        match $index.store() {
+            Some(store) => store,
+            None => {
+                return Ok(index_closed_response($index.canonical_name()));
+            }
+        }
+    }}
+}
+
+
 macro_rules! parse_json {
     ($string: expr) => {{
         use api::utils::json_response;
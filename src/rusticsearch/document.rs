@@ -9,6 +9,7 @@ use mapping::{Mapping, MappingProperty};
 #[derive(Debug)]
 pub struct DocumentSource {
     pub key: String,
+    pub mapping_name: String,
     pub data: serde_json::Value,
 }
 
@@ -104,3 +105,84 @@ impl DocumentSource {
         }
     }
 }
+
+
+/// Deep-merges a partial document into a document's source
+///
+/// Objects are merged recursively, any other value in the patch replaces the existing value.
+pub fn merge_json(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    if let serde_json::Value::Object(ref patch_map) = *patch {
+        if let serde_json::Value::Object(ref mut target_map) = *target {
+            for (key, patch_value) in patch_map.iter() {
+                if let Some(target_value) = target_map.get_mut(key) {
+                    merge_json(target_value, patch_value);
+                    continue;
+                }
+
+                target_map.insert(key.clone(), patch_value.clone());
+            }
+
+            return;
+        }
+    }
+
+    *target = patch.clone();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::merge_json;
+
+    #[test]
+    fn test_merge_json() {
+        let mut source = json!({
+            "title": "Hello",
+            "views": 1,
+            "author": {
+                "name": "Alice",
+                "email": "alice@example.com"
+            }
+        });
+
+        merge_json(&mut source, &json!({
+            "views": 2,
+            "author": {
+                "email": "alice@example.org"
+            },
+            "tags": ["a", "b"]
+        }));
+
+        assert_eq!(source, json!({
+            "title": "Hello",
+            "views": 2,
+            "author": {
+                "name": "Alice",
+                "email": "alice@example.org"
+            },
+            "tags": ["a", "b"]
+        }));
+    }
+
+    #[test]
+    fn test_merge_json_replaces_non_objects() {
+        let mut source = json!({
+            "tags": ["a", "b"],
+            "author": "Alice"
+        });
+
+        merge_json(&mut source, &json!({
+            "tags": ["c"],
+            "author": {
+                "name": "Bob"
+            }
+        }));
+
+        assert_eq!(source, json!({
+            "tags": ["c"],
+            "author": {
+                "name": "Bob"
+            }
+        }));
+    }
+}
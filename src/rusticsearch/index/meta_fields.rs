@@ -0,0 +1,150 @@
+//! Fields that every index has for storing information about each document
+//!
+//! Mappings only describe the fields that are indexed for searching, so the original key,
+//! mapping name and JSON source of each document are kept in these fields so that documents
+//! can be read back out of the index (for example, when reindexing).
+
+use serde_json;
+use kite::{DocRef, Document};
+use kite::document::FieldValue;
+use kite::schema::{Schema, FieldType, FieldRef, FIELD_STORED};
+use kite_rocksdb::{RocksDBIndexStore, RocksDBIndexReader};
+
+use document::DocumentSource;
+use mapping::Mapping;
+use index::Index;
+
+
+pub const ID_FIELD_NAME: &'static str = "_id";
+pub const TYPE_FIELD_NAME: &'static str = "_type";
+pub const SOURCE_FIELD_NAME: &'static str = "_source";
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct MetaFields {
+    pub id: FieldRef,
+    pub mapping: FieldRef,
+    pub source: FieldRef,
+}
+
+
+impl MetaFields {
+    /// Finds the meta fields in a schema, returns `None` if any of them are missing
+    pub fn from_schema(schema: &Schema) -> Option<MetaFields> {
+        let id = match schema.get_field_by_name(ID_FIELD_NAME) {
+            Some(field_ref) => field_ref,
+            None => return None,
+        };
+
+        let mapping = match schema.get_field_by_name(TYPE_FIELD_NAME) {
+            Some(field_ref) => field_ref,
+            None => return None,
+        };
+
+        let source = match schema.get_field_by_name(SOURCE_FIELD_NAME) {
+            Some(field_ref) => field_ref,
+            None => return None,
+        };
+
+        Some(MetaFields {
+            id: id,
+            mapping: mapping,
+            source: source,
+        })
+    }
+
+    /// Stores the key, mapping name and source of a document into its stored fields
+    pub fn store_source(&self, source: &DocumentSource, doc: &mut Document) {
+        doc.stored_fields.insert(self.id, FieldValue::String(source.key.clone()));
+        doc.stored_fields.insert(self.mapping, FieldValue::String(source.mapping_name.clone()));
+        doc.stored_fields.insert(self.source, FieldValue::String(source.data.to_string()));
+    }
+
+    /// Reads the source of a document back out of the index
+    ///
+    /// Returns `None` if the document was indexed without its source.
+    pub fn read_source(&self, reader: &RocksDBIndexReader, doc_ref: DocRef) -> Result<Option<DocumentSource>, String> {
+        let key = try!(self.read_string(reader, self.id, doc_ref));
+        let mapping_name = try!(self.read_string(reader, self.mapping, doc_ref));
+        let data = try!(self.read_string(reader, self.source, doc_ref));
+
+        make_source(key, mapping_name, data)
+    }
+
+    /// Reads the key of a document, returns `None` if it was indexed without one
+    pub fn read_key(&self, reader: &RocksDBIndexReader, doc_ref: DocRef) -> Result<Option<String>, String> {
+        self.read_string(reader, self.id, doc_ref)
+    }
+
+    /// Gets the source of a document that hasn't been written into the index yet
+    pub fn document_source(&self, doc: &Document) -> Result<Option<DocumentSource>, String> {
+        let get_string = |field_ref: FieldRef| {
+            match doc.stored_fields.get(&field_ref) {
+                Some(&FieldValue::String(ref string)) => Some(string.clone()),
+                _ => None,
+            }
+        };
+
+        make_source(get_string(self.id), get_string(self.mapping), get_string(self.source))
+    }
+
+    fn read_string(&self, reader: &RocksDBIndexReader, field_ref: FieldRef, doc_ref: DocRef) -> Result<Option<String>, String> {
+        match try!(reader.read_stored_field(field_ref, doc_ref).map_err(|e| format!("failed to read stored field: {:?}", e))) {
+            Some(FieldValue::String(string)) => Ok(Some(string)),
+            Some(_) => Err("meta field contains a non-string value".to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+
+fn make_source(key: Option<String>, mapping_name: Option<String>, data: Option<String>) -> Result<Option<DocumentSource>, String> {
+    match (key, mapping_name, data) {
+        (Some(key), Some(mapping_name), Some(data)) => {
+            let data = try!(serde_json::from_str(&data).map_err(|e| format!("failed to parse source: {}", e)));
+
+            Ok(Some(DocumentSource {
+                key: key,
+                mapping_name: mapping_name,
+                data: data,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+
+/// Adds any meta fields that are missing from the store
+///
+/// Stores created before the meta fields existed have them added when they are opened.
+/// Documents indexed before then don't have a stored source.
+pub fn add_meta_fields(store: &mut RocksDBIndexStore) -> Result<MetaFields, String> {
+    for field_name in &[ID_FIELD_NAME, TYPE_FIELD_NAME, SOURCE_FIELD_NAME] {
+        if store.reader().schema().get_field_by_name(field_name).is_some() {
+            continue;
+        }
+
+        try!(store.add_field(field_name.to_string(), FieldType::Text, FIELD_STORED).map_err(|e| format!("failed to add {} field: {:?}", field_name, e)));
+    }
+
+    Ok(MetaFields::from_schema(&store.reader().schema()).expect("meta fields were just added"))
+}
+
+
+impl Index {
+    /// Returns the meta fields of the index, or `None` if the index is closed
+    pub fn meta_fields(&self) -> Option<MetaFields> {
+        self.meta_fields
+    }
+
+    /// Converts a document source into a document that can be inserted into this index
+    pub fn prepare_document(&self, source: &DocumentSource, mapping: &Mapping) -> Document {
+        let mut doc = source.prepare(mapping);
+
+        if let Some(meta_fields) = self.meta_fields() {
+            meta_fields.store_source(source, &mut doc);
+        }
+
+        doc
+    }
+}
@@ -5,18 +5,22 @@ use std::collections::{HashMap, BTreeMap};
 
 use serde_json;
 use serde_json::value::ToJson;
+use kite::schema::Schema;
+use kite::similarity::SimilarityModel;
 
 use analysis::AnalyzerSpec;
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
-use mapping::{Mapping, MappingProperty, FieldMapping};
+use mapping::{Mapping, MappingProperty, FieldMapping, get_default_similarity_model};
+use index::metadata::parse::similarity::to_json as similarity_to_json;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IndexMetaData {
     analyzers: HashMap<String, AnalyzerSpec>,
     tokenizers: HashMap<String, TokenizerSpec>,
     filters: HashMap<String, FilterSpec>,
+    similarities: HashMap<String, SimilarityModel>,
     pub mappings: HashMap<String, Mapping>,
 }
 
@@ -27,6 +31,7 @@ impl Default for IndexMetaData {
             analyzers: HashMap::new(),
             tokenizers: HashMap::new(),
             filters: HashMap::new(),
+            similarities: HashMap::new(),
             mappings: HashMap::new(),
         };
 
@@ -46,6 +51,10 @@ impl Default for IndexMetaData {
             ]
         });
 
+        // Builtin similarities
+        metadata.insert_similarity("BM25".to_string(), get_default_similarity_model());
+        metadata.insert_similarity("classic".to_string(), SimilarityModel::TfIdf);
+
         metadata
     }
 }
@@ -106,8 +115,52 @@ impl IndexMetaData {
         })
     }
 
+    // Similarity helpers
+
+    pub fn insert_similarity(&mut self, name: String, similarity: SimilarityModel) -> Option<SimilarityModel> {
+        self.similarities.insert(name, similarity)
+    }
+
+    pub fn similarities(&self) -> &HashMap<String, SimilarityModel> {
+        &self.similarities
+    }
+
+    pub fn get_default_similarity(&self) -> SimilarityModel {
+        self.similarities().get("default").cloned().unwrap_or_else(get_default_similarity_model)
+    }
+
+    /// Updates the similarity model of every field after the similarities have changed
+    pub fn resolve_similarities(&mut self) {
+        let default_similarity = self.get_default_similarity();
+
+        for mapping in self.mappings.values_mut() {
+            mapping.resolve_similarities(&self.similarities, &default_similarity);
+        }
+    }
+
+    /// Checks if any analyzers, tokenizers or filters in this metadata have been
+    /// removed or changed in the other metadata
+    ///
+    /// New analysis may be added to an open index, but existing analysis can only be
+    /// changed while the index is closed as changing it would leave existing documents
+    /// analyzed differently to new ones.
+    pub fn analysis_changed_in(&self, other: &IndexMetaData) -> bool {
+        self.analyzers.iter().any(|(name, analyzer)| other.analyzers.get(name) != Some(analyzer))
+            || self.tokenizers.iter().any(|(name, tokenizer)| other.tokenizers.get(name) != Some(tokenizer))
+            || self.filters.iter().any(|(name, filter)| other.filters.get(name) != Some(filter))
+    }
+
     // Mapping helpers
 
+    /// Links the fields in every mapping to the fields in the index schema
+    ///
+    /// This must be called after the metadata is loaded from disk, as field refs are not saved.
+    pub fn link_fields(&mut self, schema: &Schema) {
+        for mapping in self.mappings.values_mut() {
+            mapping.link_fields(schema);
+        }
+    }
+
     pub fn get_field_mapping(&self, name: &str) -> Option<&FieldMapping> {
         for mapping in self.mappings.values() {
             if let Some(property) = mapping.properties.get(name) {
@@ -136,6 +189,12 @@ impl ToJson for IndexMetaData {
             filters_json.insert(name.to_string(), try!(filter.to_json()));
         }
 
+        // Similarities
+        let mut similarities_json = BTreeMap::new();
+        for (name, similarity) in self.similarities.iter() {
+            similarities_json.insert(name.to_string(), similarity_to_json(similarity));
+        }
+
         // Mappings
         let mut mappings_json = BTreeMap::new();
         for (name, mapping) in self.mappings.iter() {
@@ -144,6 +203,9 @@ impl ToJson for IndexMetaData {
 
         Ok(json!({
             "settings": {
+                "index": {
+                    "similarity": similarities_json,
+                },
                 "analysis": {
                     "tokenizers": tokenizers_json,
                     "filters": filters_json,
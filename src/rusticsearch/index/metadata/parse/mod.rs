@@ -1,15 +1,18 @@
 pub mod analysis_tokenizer;
 pub mod analysis_filter;
 pub mod analysis_analyzer;
+pub mod similarity;
 
 use serde_json;
 
 use index::metadata::IndexMetaData;
 use mapping::parse::{MappingParseError, parse as parse_mapping};
+use mapping::build::MappingBuildError;
 
 use self::analysis_tokenizer::{TokenizerParseError, parse as parse_tokenizer};
 use self::analysis_filter::{FilterParseError, parse as parse_filter};
 use self::analysis_analyzer::{AnalyzerParseError, parse as parse_analyzer};
+use self::similarity::{SimilarityParseError, parse as parse_similarity};
 
 
 #[derive(Debug, PartialEq)]
@@ -18,7 +21,9 @@ pub enum IndexMetaDataParseError {
     TokenizerParseError(String, TokenizerParseError),
     FilterParseError(String, FilterParseError),
     AnalyzerParseError(String, AnalyzerParseError),
+    SimilarityParseError(String, SimilarityParseError),
     MappingParseError(String, MappingParseError),
+    MappingBuildError(String, MappingBuildError),
 }
 
 
@@ -36,6 +41,35 @@ pub fn parse(metadata: &mut IndexMetaData, data: serde_json::Value) -> Result<()
             None => return Err(IndexMetaDataParseError::ExpectedObject),
         };
 
+        // Similarities
+        // These may be either in "settings.similarity" or "settings.index.similarity"
+        let index_settings = match settings.get("index") {
+            Some(index_settings) => {
+                match index_settings.as_object() {
+                    Some(object) => Some(object),
+                    None => return Err(IndexMetaDataParseError::ExpectedObject),
+                }
+            }
+            None => None,
+        };
+
+        let similarity_data = settings.get("similarity").or_else(|| index_settings.and_then(|index_settings| index_settings.get("similarity")));
+        if let Some(similarity_data) = similarity_data {
+            let similarity_data = match similarity_data.as_object() {
+                Some(object) => object,
+                None => return Err(IndexMetaDataParseError::ExpectedObject),
+            };
+
+            for (name, data) in similarity_data {
+                let similarity = match parse_similarity(data) {
+                    Ok(similarity) => similarity,
+                    Err(e) => return Err(IndexMetaDataParseError::SimilarityParseError(name.to_string(), e)),
+                };
+
+                metadata.insert_similarity(name.clone(), similarity);
+            }
+        }
+
         if let Some(analysis) = settings.get("analysis") {
             let analysis = match analysis.as_object() {
                 Some(object) => object,
@@ -106,7 +140,10 @@ pub fn parse(metadata: &mut IndexMetaData, data: serde_json::Value) -> Result<()
                 Ok(mapping) => mapping,
                 Err(e) => return Err(IndexMetaDataParseError::MappingParseError(name.to_string(), e)),
             };
-            let mapping = mapping_builder.build(&metadata);
+            let mapping = match mapping_builder.build(&metadata) {
+                Ok(mapping) => mapping,
+                Err(e) => return Err(IndexMetaDataParseError::MappingBuildError(name.to_string(), e)),
+            };
             metadata.mappings.insert(name.clone(), mapping);
         }
     }
@@ -118,12 +155,14 @@ pub fn parse(metadata: &mut IndexMetaData, data: serde_json::Value) -> Result<()
 #[cfg(test)]
 mod tests {
     use serde_json;
+    use kite::similarity::SimilarityModel;
 
     use analysis::ngram_generator::Edge;
     use analysis::tokenizers::TokenizerSpec;
     use analysis::filters::FilterSpec;
     use analysis::AnalyzerSpec;
     use mapping::parse::MappingParseError;
+    use mapping::build::MappingBuildError;
     use index::metadata::IndexMetaData;
 
     use super::{parse, IndexMetaDataParseError};
@@ -326,6 +365,45 @@ mod tests {
         assert_eq!(error, IndexMetaDataParseError::FilterParseError("bad_filter".to_string(), FilterParseError::UnrecognisedType("foo".to_string())));
     }
 
+    #[test]
+    fn test_similarity() {
+        let mut metadata = IndexMetaData::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "index": {
+                    "similarity": {
+                        "title_similarity": {
+                            "type": "BM25",
+                            "k1": 1.5,
+                            "b": 0.1,
+                        }
+                    }
+                }
+            },
+            "mappings": {
+                "test_mapping": {
+                    "properties": {
+                        "title": {
+                            "type": "string",
+                            "similarity": "title_similarity",
+                        }
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.similarities().get("title_similarity"), Some(&SimilarityModel::Bm25 {
+            k1: 1.5,
+            b: 0.1,
+        }));
+
+        let field_mapping = metadata.get_field_mapping("title").expect("'title' field wasn't created");
+        assert_eq!(field_mapping.get_search_options().similarity_model, SimilarityModel::Bm25 {
+            k1: 1.5,
+            b: 0.1,
+        });
+    }
+
     #[test]
     fn test_mapping() {
         let mut metadata = IndexMetaData::default();
@@ -357,4 +435,23 @@ mod tests {
 
         assert_eq!(error, IndexMetaDataParseError::MappingParseError("test_mapping".to_string(), MappingParseError::UnrecognisedKeys(vec!["foo".to_string()])));
     }
+
+    #[test]
+    fn test_mapping_unrecognised_similarity() {
+        let mut metadata = IndexMetaData::default();
+        let error = parse(&mut metadata, json!({
+            "mappings": {
+                "test_mapping": {
+                    "properties": {
+                        "title": {
+                            "type": "string",
+                            "similarity": "my_similarity"
+                        }
+                    }
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetaDataParseError::MappingBuildError("test_mapping".to_string(), MappingBuildError::UnrecognisedSimilarity("my_similarity".to_string())));
+    }
 }
@@ -0,0 +1,178 @@
+use serde_json;
+use kite::similarity::SimilarityModel;
+
+
+#[derive(Debug, PartialEq)]
+pub enum SimilarityParseError {
+    ExpectedObject,
+    ExpectedString,
+
+    /// k1 must be a number that is zero or greater
+    InvalidK1,
+
+    /// b must be a number between 0 and 1
+    InvalidB,
+
+    ExpectedKey(String),
+    UnrecognisedType(String),
+    UnrecognisedKey(String),
+}
+
+
+pub fn parse(json: &serde_json::Value) -> Result<SimilarityModel, SimilarityParseError> {
+    let data = try!(json.as_object().ok_or(SimilarityParseError::ExpectedObject));
+
+    // Get type
+    let similarity_type_json = try!(data.get("type").ok_or(SimilarityParseError::ExpectedKey("type".to_string())));
+    let similarity_type = try!(similarity_type_json.as_str().ok_or(SimilarityParseError::ExpectedString));
+
+    match similarity_type {
+        "BM25" => {
+            let mut k1 = 1.2;
+            let mut b = 0.75;
+
+            for (key, value) in data.iter() {
+                match key.as_ref() {
+                    "type" => {}
+                    "k1" => {
+                        k1 = match value.as_f64() {
+                            Some(num) if num >= 0.0 && num.is_finite() => num,
+                            _ => return Err(SimilarityParseError::InvalidK1),
+                        };
+                    }
+                    "b" => {
+                        // b is the weight given to length normalisation. Values outside
+                        // of this range would give negative scores
+                        b = match value.as_f64() {
+                            Some(num) if num >= 0.0 && num <= 1.0 => num,
+                            _ => return Err(SimilarityParseError::InvalidB),
+                        };
+                    }
+                    _ => return Err(SimilarityParseError::UnrecognisedKey(key.clone())),
+                }
+            }
+
+            Ok(SimilarityModel::Bm25 {
+                k1: k1,
+                b: b,
+            })
+        }
+        "classic" | "TF-IDF" => {
+            for key in data.keys() {
+                if key != "type" {
+                    return Err(SimilarityParseError::UnrecognisedKey(key.clone()));
+                }
+            }
+
+            Ok(SimilarityModel::TfIdf)
+        }
+        _ => Err(SimilarityParseError::UnrecognisedType(similarity_type.to_string())),
+    }
+}
+
+
+pub fn to_json(similarity: &SimilarityModel) -> serde_json::Value {
+    match *similarity {
+        SimilarityModel::Bm25{k1, b} => {
+            json!({
+                "type": "BM25",
+                "k1": k1,
+                "b": b,
+            })
+        }
+        SimilarityModel::TfIdf => {
+            json!({
+                "type": "classic",
+            })
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite::similarity::SimilarityModel;
+
+    use super::{parse, to_json, SimilarityParseError};
+
+    #[test]
+    fn test_bm25() {
+        let similarity = parse(&json!({
+            "type": "BM25",
+            "k1": 1.5,
+            "b": 0.3,
+        }));
+
+        assert_eq!(similarity, Ok(SimilarityModel::Bm25 {
+            k1: 1.5,
+            b: 0.3,
+        }));
+    }
+
+    #[test]
+    fn test_bm25_defaults() {
+        let similarity = parse(&json!({
+            "type": "BM25",
+        }));
+
+        assert_eq!(similarity, Ok(SimilarityModel::Bm25 {
+            k1: 1.2,
+            b: 0.75,
+        }));
+    }
+
+    #[test]
+    fn test_bm25_invalid_k1() {
+        let similarity = parse(&json!({
+            "type": "BM25",
+            "k1": -1.0,
+        }));
+
+        assert_eq!(similarity, Err(SimilarityParseError::InvalidK1));
+    }
+
+    #[test]
+    fn test_bm25_invalid_b() {
+        let similarity = parse(&json!({
+            "type": "BM25",
+            "b": 1.5,
+        }));
+
+        assert_eq!(similarity, Err(SimilarityParseError::InvalidB));
+
+        let similarity = parse(&json!({
+            "type": "BM25",
+            "b": -0.1,
+        }));
+
+        assert_eq!(similarity, Err(SimilarityParseError::InvalidB));
+    }
+
+    #[test]
+    fn test_classic() {
+        let similarity = parse(&json!({
+            "type": "classic",
+        }));
+
+        assert_eq!(similarity, Ok(SimilarityModel::TfIdf));
+    }
+
+    #[test]
+    fn test_unrecognised_type() {
+        let similarity = parse(&json!({
+            "type": "foo",
+        }));
+
+        assert_eq!(similarity, Err(SimilarityParseError::UnrecognisedType("foo".to_string())));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let similarity = SimilarityModel::Bm25 {
+            k1: 2.0,
+            b: 0.5,
+        };
+
+        assert_eq!(parse(&to_json(&similarity)), Ok(similarity));
+    }
+}
@@ -1,32 +1,58 @@
 pub mod maintenance;
 pub mod registry;
 pub mod metadata;
+pub mod settings;
+pub mod write_buffer;
+pub mod meta_fields;
 
-use std::sync::RwLock;
+use std::sync::{RwLock, Mutex};
 use std::path::PathBuf;
 
 use kite_rocksdb::RocksDBIndexStore;
 use uuid::Uuid;
 
 use index::metadata::IndexMetaData;
+use index::settings::IndexSettings;
+use index::write_buffer::WriteBuffer;
+use index::meta_fields::{MetaFields, add_meta_fields};
 
 
 #[derive(Debug)]
 pub struct Index {
     id: Uuid,
     canonical_name: String,
+    pub settings: RwLock<IndexSettings>,
     pub metadata: RwLock<IndexMetaData>,
-    pub store: RocksDBIndexStore,
+    path: PathBuf,
+
+    /// The store of the index, `None` while the index is closed
+    ///
+    /// Indices are always stored in RocksDB. The in-memory store in `kite::store` doesn't
+    /// support versions, snapshots or merging, which the APIs need.
+    store: Option<RocksDBIndexStore>,
+    write_buffer: Mutex<WriteBuffer>,
+
+    /// The meta fields of the store, `None` while the index is closed
+    ///
+    /// These are looked up once when the store is opened as their refs never change.
+    meta_fields: Option<MetaFields>,
 }
 
 
 impl Index {
-    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetaData, store: RocksDBIndexStore) -> Index {
+    /// Creates an index, `store` must be `None` if the index is closed
+    pub fn new(canonical_name: String, path: PathBuf, settings: IndexSettings, metadata: IndexMetaData, store: Option<RocksDBIndexStore>) -> Index {
+        let meta_fields = store.as_ref().and_then(|store| MetaFields::from_schema(&store.reader().schema()));
+
         Index {
-            id: id,
+            id: settings.uuid,
             canonical_name: canonical_name,
+            settings: RwLock::new(settings),
             metadata: RwLock::new(metadata),
+            path: path,
             store: store,
+            write_buffer: Mutex::new(WriteBuffer::new()),
+            meta_fields: meta_fields,
         }
     }
 
@@ -39,8 +65,63 @@ impl Index {
     }
 
     pub fn metadata_path(&self) -> PathBuf {
-        let mut path = self.store.path().to_path_buf();
+        let mut path = self.path.clone();
         path.push("metadata.json");
         path
     }
+
+    pub fn settings_path(&self) -> PathBuf {
+        let mut path = self.path.clone();
+        path.push("settings.json");
+        path
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// Returns the store of the index, or `None` if the index is closed
+    pub fn store(&self) -> Option<&RocksDBIndexStore> {
+        self.store.as_ref()
+    }
+
+    pub fn store_mut(&mut self) -> Option<&mut RocksDBIndexStore> {
+        self.store.as_mut()
+    }
+
+    /// Writes out any buffered changes and then releases the store
+    pub fn close(&mut self) -> Result<(), String> {
+        if !self.is_open() {
+            return Ok(());
+        }
+
+        try!(self.refresh());
+        self.store = None;
+        self.meta_fields = None;
+
+        let mut settings = self.settings.write().unwrap();
+        settings.closed = true;
+        try!(settings.save(self.settings_path()));
+
+        Ok(())
+    }
+
+    /// Reopens the store of a closed index
+    pub fn open(&mut self) -> Result<(), String> {
+        if self.is_open() {
+            return Ok(());
+        }
+
+        let mut store = try!(RocksDBIndexStore::open(&self.path));
+        let meta_fields = try!(add_meta_fields(&mut store));
+        self.metadata.write().unwrap().link_fields(&store.reader().schema());
+        self.store = Some(store);
+        self.meta_fields = Some(meta_fields);
+
+        let mut settings = self.settings.write().unwrap();
+        settings.closed = false;
+        try!(settings.save(self.settings_path()));
+
+        Ok(())
+    }
 }
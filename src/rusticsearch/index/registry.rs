@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use std::collections::hash_map::Iter as HashMapIter;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
+use serde_json;
+use serde_json::value::ToJson;
 use uuid::Uuid;
 
 use index::Index;
@@ -18,17 +21,107 @@ impl IndexRef {
 }
 
 
-#[derive(Debug)]
+/// Settings for an index that an alias points to
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AliasOptions {
+    /// A query that gets combined with every search made through the alias
+    pub filter: Option<serde_json::Value>,
+
+    /// Routing values to use when indexing/searching through the alias
+    /// Note: these are stored for compatibility but have no effect as indices are not sharded
+    pub index_routing: Option<String>,
+    pub search_routing: Option<String>,
+}
+
+
+impl AliasOptions {
+    /// Reads alias options from an Elasticsearch-style alias definition
+    ///
+    /// The "routing" key sets both "index_routing" and "search_routing". The filter is not
+    /// validated here.
+    pub fn from_json(json: &serde_json::Value) -> Result<AliasOptions, String> {
+        fn parse_routing(json: &serde_json::Value) -> Result<String, String> {
+            // Routing values may be given as strings or numbers
+            match *json {
+                serde_json::Value::String(ref string) => Ok(string.clone()),
+                serde_json::Value::Number(ref number) => Ok(format!("{}", number)),
+                _ => Err("routing must be a string".to_string()),
+            }
+        }
+
+        let object = try!(json.as_object().ok_or("alias options must be an object".to_string()));
+        let mut options = AliasOptions::default();
+        let mut routing = None;
+        let mut index_routing = None;
+        let mut search_routing = None;
+
+        for (key, value) in object.iter() {
+            match key.as_ref() {
+                "filter" => options.filter = Some(value.clone()),
+                "routing" => routing = Some(try!(parse_routing(value))),
+                "index_routing" => index_routing = Some(try!(parse_routing(value))),
+                "search_routing" => search_routing = Some(try!(parse_routing(value))),
+                _ => return Err(format!("unrecognised alias option: {}", key)),
+            }
+        }
+
+        options.index_routing = index_routing.or_else(|| routing.clone());
+        options.search_routing = search_routing.or(routing);
+
+        Ok(options)
+    }
+}
+
+
+impl ToJson for AliasOptions {
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let mut json = BTreeMap::new();
+
+        if let Some(ref filter) = self.filter {
+            json.insert("filter".to_string(), filter.clone());
+        }
+
+        if let Some(ref index_routing) = self.index_routing {
+            json.insert("index_routing".to_string(), serde_json::Value::String(index_routing.clone()));
+        }
+
+        if let Some(ref search_routing) = self.search_routing {
+            json.insert("search_routing".to_string(), serde_json::Value::String(search_routing.clone()));
+        }
+
+        Ok(json!(json))
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct AliasTarget {
+    index_ref: IndexRef,
+    options: AliasOptions,
+}
+
+
+impl AliasTarget {
+    fn new(index_ref: IndexRef) -> AliasTarget {
+        AliasTarget {
+            index_ref: index_ref,
+            options: AliasOptions::default(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
 enum Name {
     /// This is the canonical name of an index
     Canonical(IndexRef),
 
     /// This is an alias
-    Alias(Vec<IndexRef>),
+    Alias(Vec<AliasTarget>),
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NameRegistry {
     names: HashMap<String, Name>,
 }
@@ -62,7 +155,7 @@ impl NameRegistry {
             return Err(());
         }
 
-        self.names.insert(name, Name::Alias(indices));
+        self.names.insert(name, Name::Alias(indices.into_iter().map(AliasTarget::new).collect()));
         Ok(())
     }
 
@@ -72,7 +165,7 @@ impl NameRegistry {
             return Err(());
         }
 
-        let old_indices = self.names.insert(name, Name::Alias(indices));
+        let old_indices = self.names.insert(name, Name::Alias(indices.into_iter().map(AliasTarget::new).collect()));
         match old_indices {
             Some(Name::Alias(_)) => {
                  Ok(false)
@@ -86,20 +179,58 @@ impl NameRegistry {
         }
     }
 
+    /// Adds an index to an alias, creating the alias if it doesn't exist
+    ///
+    /// If the index is already in the alias, its options are replaced. Returns true if the
+    /// alias was created.
+    pub fn add_to_alias(&mut self, name: String, index_ref: IndexRef, options: AliasOptions) -> Result<bool, ()> {
+        match self.names.get_mut(&name) {
+            Some(&mut Name::Alias(ref mut targets)) => {
+                match targets.iter().position(|target| target.index_ref == index_ref) {
+                    Some(position) => {
+                        targets[position].options = options;
+                    }
+                    None => {
+                        targets.push(AliasTarget {
+                            index_ref: index_ref,
+                            options: options,
+                        });
+                    }
+                }
+
+                return Ok(false);
+            }
+            Some(&mut Name::Canonical(_)) => {
+                // Cannot add to an alias if it is a canonical name
+                return Err(());
+            }
+            None => {}
+        }
+
+        self.names.insert(name, Name::Alias(vec![
+            AliasTarget {
+                index_ref: index_ref,
+                options: options,
+            }
+        ]));
+
+        Ok(true)
+    }
+
     pub fn delete_alias(&mut self, name: &str, index_ref: IndexRef) -> Result<bool, ()> {
         let mut remove_alias = false;
 
         match self.names.get_mut(name) {
-            Some(&mut Name::Alias(ref mut indices)) => {
+            Some(&mut Name::Alias(ref mut targets)) => {
                 // Remove index from alias
-                let index = match indices.iter().position(|ir| *ir == index_ref) {
+                let index = match targets.iter().position(|target| target.index_ref == index_ref) {
                     Some(index) => index,
                     None => return Ok(false),
                 };
 
-                indices.remove(index);
+                targets.remove(index);
 
-                if indices.is_empty() {
+                if targets.is_empty() {
                     remove_alias = true;
                 }
             }
@@ -125,21 +256,190 @@ impl NameRegistry {
         Ok(alias.is_some())
     }
 
-    pub fn find(&self, selector: &str) -> Vec<IndexRef> {
-        let mut indices = Vec::new();
+    /// Returns the options for an index in an alias, or None if the alias doesn't point to the index
+    pub fn get_alias_options(&self, name: &str, index_ref: IndexRef) -> Option<&AliasOptions> {
+        match self.names.get(name) {
+            Some(&Name::Alias(ref targets)) => {
+                targets.iter()
+                    .find(|target| target.index_ref == index_ref)
+                    .map(|target| &target.options)
+            }
+            Some(&Name::Canonical(_)) | None => None,
+        }
+    }
+
+    /// Finds the canonical name of an index
+    pub fn canonical_name_of(&self, index_ref: IndexRef) -> Option<&str> {
+        for (name, entry) in self.names.iter() {
+            if let Name::Canonical(ir) = *entry {
+                if ir == index_ref {
+                    return Some(name.as_str());
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Serialises all aliases into JSON so they can be saved to disk
+    ///
+    /// Indices are referenced by their canonical names as index refs don't survive restarts.
+    pub fn aliases_to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let mut aliases_json = BTreeMap::new();
+
+        for (name, entry) in self.names.iter() {
+            if let Name::Alias(ref targets) = *entry {
+                let mut targets_json = BTreeMap::new();
+
+                for target in targets.iter() {
+                    if let Some(index_name) = self.canonical_name_of(target.index_ref) {
+                        targets_json.insert(index_name.to_string(), try!(target.options.to_json()));
+                    }
+                }
+
+                aliases_json.insert(name.to_string(), targets_json);
+            }
+        }
+
+        Ok(json!(aliases_json))
+    }
+
+    /// Adds aliases from JSON that was created by aliases_to_json
+    ///
+    /// Indices must be registered before this is called. Any alias entry that cannot be loaded
+    /// (for example, if its index no longer exists) is skipped and returned as an
+    /// (alias name, index name, reason) tuple.
+    pub fn load_aliases_json(&mut self, json: &serde_json::Value) -> Result<Vec<(String, String, String)>, String> {
+        let aliases_json = try!(json.as_object().ok_or("aliases must be an object".to_string()));
+        let mut skipped = Vec::new();
+
+        for (alias_name, targets_json) in aliases_json.iter() {
+            let targets_json = try!(targets_json.as_object().ok_or(format!("alias {} must be an object", alias_name)));
+
+            for (index_name, options_json) in targets_json.iter() {
+                let index_ref = match self.find_canonical(index_name) {
+                    Some(index_ref) => index_ref,
+                    None => {
+                        skipped.push((alias_name.clone(), index_name.clone(), "index doesn't exist".to_string()));
+                        continue;
+                    }
+                };
+
+                let options = match AliasOptions::from_json(options_json) {
+                    Ok(options) => options,
+                    Err(error) => {
+                        skipped.push((alias_name.clone(), index_name.clone(), error));
+                        continue;
+                    }
+                };
+
+                if self.add_to_alias(alias_name.clone(), index_ref, options).is_err() {
+                    skipped.push((alias_name.clone(), index_name.clone(), "name is used by an index".to_string()));
+                }
+            }
+        }
 
-        // Find name
-        let name = self.names.get(selector);
+        Ok(skipped)
+    }
 
-        // Resolve the name if we have one
-        if let Some(name) = name {
-            match *name {
-                Name::Canonical(ref index_ref) => indices.push(*index_ref),
-                Name::Alias(ref alias_indices) => indices.append(&mut alias_indices.clone()),
+    /// Resolves a name, adding the indices it points to into "indices" along with any filters
+    ///
+    /// A filter of None means that the index was selected without a filter. If an index is
+    /// selected more than once, the filters are combined and any unfiltered selection
+    /// overrides them all.
+    fn resolve_name(&self, name: &str, indices: &mut Vec<(IndexRef, Option<Vec<serde_json::Value>>)>) {
+        fn add_index(indices: &mut Vec<(IndexRef, Option<Vec<serde_json::Value>>)>, index_ref: IndexRef, filter: Option<&serde_json::Value>) {
+            match indices.iter().position(|&(ir, _)| ir == index_ref) {
+                Some(position) => {
+                    let ref mut existing_filters = indices[position].1;
+                    match filter {
+                        Some(filter) => {
+                            if let Some(ref mut existing_filters) = *existing_filters {
+                                existing_filters.push(filter.clone());
+                            }
+                        }
+                        None => {
+                            *existing_filters = None;
+                        }
+                    }
+                }
+                None => {
+                    indices.push((index_ref, filter.map(|filter| vec![filter.clone()])));
+                }
             }
         }
 
-        indices
+        match self.names.get(name) {
+            Some(&Name::Canonical(index_ref)) => {
+                add_index(indices, index_ref, None);
+            }
+            Some(&Name::Alias(ref targets)) => {
+                for target in targets.iter() {
+                    add_index(indices, target.index_ref, target.options.filter.as_ref());
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Resolves an index selector into a list of indices along with the filters of any
+    /// aliases that were used to select them
+    ///
+    /// The selector may be a single index or alias name, a comma separated list of names,
+    /// a wildcard pattern (eg, "logs-*") or "_all". Each index is only returned once.
+    ///
+    /// If the filter list for an index isn't empty, searches on that index must only match
+    /// documents that match at least one of the filters.
+    pub fn find_with_filters(&self, selector: &str) -> Vec<(IndexRef, Vec<serde_json::Value>)> {
+        let mut indices = Vec::new();
+
+        for name_selector in selector.split(',') {
+            let name_selector = name_selector.trim();
+
+            if name_selector == "_all" {
+                // All indices (aliases are not needed as they can only point at indices)
+                let mut names = self.names.iter()
+                    .filter(|&(_, name)| {
+                        match *name {
+                            Name::Canonical(_) => true,
+                            Name::Alias(_) => false,
+                        }
+                    })
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<&str>>();
+                names.sort();
+
+                for name in names {
+                    self.resolve_name(name, &mut indices);
+                }
+            } else if name_selector.contains('*') {
+                // Wildcard, match against index names and aliases
+                let mut names = self.names.keys()
+                    .filter(|name| wildcard_match(name_selector, name))
+                    .map(|name| name.as_str())
+                    .collect::<Vec<&str>>();
+                names.sort();
+
+                for name in names {
+                    self.resolve_name(name, &mut indices);
+                }
+            } else {
+                self.resolve_name(name_selector, &mut indices);
+            }
+        }
+
+        indices.into_iter()
+            .map(|(index_ref, filters)| (index_ref, filters.unwrap_or_else(Vec::new)))
+            .collect()
+    }
+
+    /// Resolves an index selector into a list of indices
+    ///
+    /// See find_with_filters for the selector syntax. Alias filters are ignored.
+    pub fn find(&self, selector: &str) -> Vec<IndexRef> {
+        self.find_with_filters(selector).into_iter()
+            .map(|(index_ref, _)| index_ref)
+            .collect()
     }
 
     pub fn find_canonical(&self, name: &str) -> Option<IndexRef> {
@@ -160,6 +460,41 @@ impl NameRegistry {
 }
 
 
+/// Checks if a name matches a pattern which may contain "*" wildcards
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    // The first part must be a prefix of the name
+    let first_part = parts.next().unwrap_or("");
+    if !name.starts_with(first_part) {
+        return false;
+    }
+    let mut remaining = &name[first_part.len()..];
+
+    let parts = parts.collect::<Vec<&str>>();
+    let (last_part, middle_parts) = match parts.split_last() {
+        Some((last_part, middle_parts)) => (*last_part, middle_parts),
+        None => {
+            // No wildcards in pattern, name must match exactly
+            return remaining.is_empty();
+        }
+    };
+
+    // The middle parts must appear in order
+    for part in middle_parts {
+        match remaining.find(part) {
+            Some(position) => {
+                remaining = &remaining[position + part.len()..];
+            }
+            None => return false,
+        }
+    }
+
+    // The last part must be a suffix of what's left
+    remaining.ends_with(last_part)
+}
+
+
 pub struct IndexAliasesIterator<'a> {
     index_ref: IndexRef,
     names_iterator: HashMapIter<'a, String, Name>,
@@ -172,8 +507,8 @@ impl<'a> Iterator for IndexAliasesIterator<'a> {
     fn next(&mut self) -> Option<&'a str> {
         loop {
             match self.names_iterator.next() {
-                Some((name, &Name::Alias(ref indices))) => {
-                    if indices.iter().any(|ir| *ir == self.index_ref) {
+                Some((name, &Name::Alias(ref targets))) => {
+                    if targets.iter().any(|target| target.index_ref == self.index_ref) {
                         return Some(name);
                     }
                 }
@@ -187,7 +522,9 @@ impl<'a> Iterator for IndexAliasesIterator<'a> {
 
 #[derive(Debug)]
 pub struct IndexRegistry {
-    indices: HashMap<IndexRef, Index>,
+    /// Indices are reference counted so long running requests (such as reindexing) can
+    /// hold on to them without keeping the registry locked
+    indices: HashMap<IndexRef, Arc<Index>>,
     pub names: NameRegistry,
 }
 
@@ -202,26 +539,189 @@ impl IndexRegistry {
         }
     }
 
-    pub fn insert(&mut self, index: Index) -> IndexRef {
+    /// Adds an index to the registry
+    ///
+    /// Returns an error if an index with the same UUID is already registered.
+    pub fn insert(&mut self, index: Index) -> Result<IndexRef, Index> {
         let index_ref = IndexRef(index.id().clone());
-        self.indices.insert(index_ref, index);
+        if self.indices.contains_key(&index_ref) {
+            return Err(index);
+        }
+
+        self.indices.insert(index_ref, Arc::new(index));
 
-        index_ref
+        Ok(index_ref)
     }
 }
 
 
 impl Deref for IndexRegistry {
-    type Target = HashMap<IndexRef, Index>;
+    type Target = HashMap<IndexRef, Arc<Index>>;
 
-    fn deref(&self) -> &HashMap<IndexRef, Index> {
+    fn deref(&self) -> &HashMap<IndexRef, Arc<Index>> {
         &self.indices
     }
 }
 
 
 impl DerefMut for IndexRegistry {
-    fn deref_mut(&mut self) -> &mut HashMap<IndexRef, Index> {
+    fn deref_mut(&mut self) -> &mut HashMap<IndexRef, Arc<Index>> {
         &mut self.indices
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{IndexRegistry, IndexRef, AliasOptions, wildcard_match};
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("logs-*", "logs-2016-10"));
+        assert!(wildcard_match("*-10", "logs-2016-10"));
+        assert!(wildcard_match("logs-*-10", "logs-2016-10"));
+        assert!(wildcard_match("*", "logs-2016-10"));
+        assert!(wildcard_match("logs", "logs"));
+        assert!(!wildcard_match("logs-*", "metrics-2016-10"));
+        assert!(!wildcard_match("logs-*-11", "logs-2016-10"));
+        assert!(!wildcard_match("logs", "logs-2016-10"));
+    }
+
+    #[test]
+    fn test_find() {
+        let mut registry = IndexRegistry::new();
+        let logs_1 = IndexRef(Uuid::new_v4());
+        let logs_2 = IndexRef(Uuid::new_v4());
+        let metrics = IndexRef(Uuid::new_v4());
+        registry.names.insert_canonical("logs-2016-09".to_string(), logs_1).unwrap();
+        registry.names.insert_canonical("logs-2016-10".to_string(), logs_2).unwrap();
+        registry.names.insert_canonical("metrics".to_string(), metrics).unwrap();
+        registry.names.insert_alias("logs".to_string(), vec![logs_1, logs_2]).unwrap();
+
+        // Single names
+        assert_eq!(registry.names.find("metrics"), vec![metrics]);
+        assert_eq!(registry.names.find("logs"), vec![logs_1, logs_2]);
+        assert_eq!(registry.names.find("foo"), vec![]);
+
+        // Lists (with duplicates removed)
+        assert_eq!(registry.names.find("metrics,logs-2016-10"), vec![metrics, logs_2]);
+        assert_eq!(registry.names.find("logs,logs-2016-10"), vec![logs_1, logs_2]);
+
+        // Wildcards
+        assert_eq!(registry.names.find("logs-*"), vec![logs_1, logs_2]);
+        assert_eq!(registry.names.find("_all"), vec![logs_1, logs_2, metrics]);
+    }
+
+    #[test]
+    fn test_find_with_filters() {
+        let mut registry = IndexRegistry::new();
+        let logs_1 = IndexRef(Uuid::new_v4());
+        let logs_2 = IndexRef(Uuid::new_v4());
+        registry.names.insert_canonical("logs-2016-09".to_string(), logs_1).unwrap();
+        registry.names.insert_canonical("logs-2016-10".to_string(), logs_2).unwrap();
+
+        let errors_filter = json!({"term": {"level": "error"}});
+        let warnings_filter = json!({"term": {"level": "warning"}});
+        registry.names.add_to_alias("errors".to_string(), logs_1, AliasOptions {
+            filter: Some(errors_filter.clone()),
+            .. AliasOptions::default()
+        }).unwrap();
+        registry.names.add_to_alias("errors".to_string(), logs_2, AliasOptions {
+            filter: Some(errors_filter.clone()),
+            .. AliasOptions::default()
+        }).unwrap();
+        registry.names.add_to_alias("warnings".to_string(), logs_2, AliasOptions {
+            filter: Some(warnings_filter.clone()),
+            .. AliasOptions::default()
+        }).unwrap();
+
+        // Filtered alias
+        assert_eq!(registry.names.find_with_filters("errors"), vec![
+            (logs_1, vec![errors_filter.clone()]),
+            (logs_2, vec![errors_filter.clone()]),
+        ]);
+
+        // Two filtered aliases on the same index should combine their filters
+        assert_eq!(registry.names.find_with_filters("errors,warnings"), vec![
+            (logs_1, vec![errors_filter.clone()]),
+            (logs_2, vec![errors_filter.clone(), warnings_filter.clone()]),
+        ]);
+
+        // Selecting the index directly removes the filter
+        assert_eq!(registry.names.find_with_filters("errors,logs-2016-10"), vec![
+            (logs_1, vec![errors_filter.clone()]),
+            (logs_2, vec![]),
+        ]);
+    }
+
+    #[test]
+    fn test_add_to_alias() {
+        let mut registry = IndexRegistry::new();
+        let index = IndexRef(Uuid::new_v4());
+        registry.names.insert_canonical("index".to_string(), index).unwrap();
+
+        // Creates the alias
+        assert_eq!(registry.names.add_to_alias("alias".to_string(), index, AliasOptions::default()), Ok(true));
+        assert_eq!(registry.names.get_alias_options("alias", index), Some(&AliasOptions::default()));
+
+        // Replaces options
+        let options = AliasOptions {
+            index_routing: Some("1".to_string()),
+            .. AliasOptions::default()
+        };
+        assert_eq!(registry.names.add_to_alias("alias".to_string(), index, options.clone()), Ok(false));
+        assert_eq!(registry.names.get_alias_options("alias", index), Some(&options));
+
+        // Can't add to an index name
+        assert_eq!(registry.names.add_to_alias("index".to_string(), index, AliasOptions::default()), Err(()));
+    }
+
+    #[test]
+    fn test_aliases_json_round_trip() {
+        let mut registry = IndexRegistry::new();
+        let logs_1 = IndexRef(Uuid::new_v4());
+        let logs_2 = IndexRef(Uuid::new_v4());
+        registry.names.insert_canonical("logs-2016-09".to_string(), logs_1).unwrap();
+        registry.names.insert_canonical("logs-2016-10".to_string(), logs_2).unwrap();
+        registry.names.insert_alias("logs".to_string(), vec![logs_1, logs_2]).unwrap();
+        let errors_options = AliasOptions {
+            filter: Some(json!({"term": {"level": "error"}})),
+            index_routing: Some("1".to_string()),
+            search_routing: Some("1,2".to_string()),
+        };
+        registry.names.add_to_alias("errors".to_string(), logs_2, errors_options.clone()).unwrap();
+
+        let aliases_json = registry.names.aliases_to_json().unwrap();
+
+        // Simulate a restart, indices get new refs and one of them has been deleted
+        let mut new_registry = IndexRegistry::new();
+        let new_logs_2 = IndexRef(Uuid::new_v4());
+        new_registry.names.insert_canonical("logs-2016-10".to_string(), new_logs_2).unwrap();
+
+        let skipped = new_registry.names.load_aliases_json(&aliases_json).unwrap();
+        assert_eq!(skipped, vec![
+            ("logs".to_string(), "logs-2016-09".to_string(), "index doesn't exist".to_string()),
+        ]);
+
+        assert_eq!(new_registry.names.find("logs"), vec![new_logs_2]);
+        assert_eq!(new_registry.names.get_alias_options("logs", new_logs_2), Some(&AliasOptions::default()));
+        assert_eq!(new_registry.names.get_alias_options("errors", new_logs_2), Some(&errors_options));
+    }
+
+    #[test]
+    fn test_alias_options_from_json() {
+        let options = AliasOptions::from_json(&json!({
+            "filter": {"term": {"user": "kimchy"}},
+            "routing": "1",
+            "search_routing": "1,2",
+        })).unwrap();
+
+        assert_eq!(options, AliasOptions {
+            filter: Some(json!({"term": {"user": "kimchy"}})),
+            index_routing: Some("1".to_string()),
+            search_routing: Some("1,2".to_string()),
+        });
+    }
+}
@@ -0,0 +1,631 @@
+//! Index-level settings that are stored alongside the index data
+//!
+//! The identity of the index (uuid and creation date) is generated once and must never
+//! change for the life of the index. The remaining settings may be configured by users.
+
+use std::path::Path;
+use std::str::FromStr;
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+use serde_json::value::ToJson;
+use atomicwrites::{self, AtomicFile, AllowOverwrite};
+use uuid::Uuid;
+use kite_rocksdb::{MergePolicy, TieredMergePolicy, NoMergePolicy};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSettings {
+    /// Identifier of the index, this is stable across restarts
+    pub uuid: Uuid,
+
+    /// The time the index was created in milliseconds since the Unix epoch
+    pub creation_date: i64,
+
+    /// How often buffered changes are made visible to searches. `None` disables
+    /// periodic refreshing
+    pub refresh_interval: Option<Duration>,
+
+    /// The maximum value of `from + size` for searches on this index
+    pub max_result_window: usize,
+
+    /// Decides which segments get merged (see `build_merge_policy`)
+    pub merge_policy: MergePolicyType,
+
+    /// The number of similarly sized segments allowed in each tier before they are merged
+    pub merge_segments_per_tier: usize,
+
+    /// The maximum number of segments that may be merged at once
+    pub merge_max_merge_at_once: usize,
+
+    /// The largest segment (in live documents) that merges will create
+    pub merge_max_merged_segment_docs: i64,
+
+    /// Segments smaller than this are merged as if they were this size
+    pub merge_floor_segment_docs: i64,
+
+    /// The percentage of deleted documents a segment may have before it's rewritten
+    pub merge_deletes_pct_allowed: f64,
+
+    /// Closed indices keep their data on disk but can't be read from or written to
+    ///
+    /// This is saved with the settings but it's not returned by the settings API or
+    /// changed through it.
+    pub closed: bool,
+}
+
+
+/// The merge policies that can be selected with the "merge.policy.type" setting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergePolicyType {
+    /// Merges similarly sized segments and rewrites segments with many deletions
+    Tiered,
+
+    /// Never merges segments. Useful while bulk loading an index
+    None,
+}
+
+
+impl FromStr for MergePolicyType {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<MergePolicyType, ()> {
+        match name {
+            "tiered" => Ok(MergePolicyType::Tiered),
+            "none" => Ok(MergePolicyType::None),
+            _ => Err(()),
+        }
+    }
+}
+
+
+impl MergePolicyType {
+    fn name(&self) -> &'static str {
+        match *self {
+            MergePolicyType::Tiered => "tiered",
+            MergePolicyType::None => "none",
+        }
+    }
+}
+
+
+/// Refresh interval given to new indices and indices that don't have one saved
+pub fn get_default_refresh_interval() -> Option<Duration> {
+    Some(Duration::new(1, 0))
+}
+
+
+/// Parses a time value, such as "1s" or "500ms"
+///
+/// Numbers are taken as milliseconds. "-1" returns `None`, which is used to disable
+/// periodic tasks.
+pub fn parse_time_value(data: &serde_json::Value) -> Option<Option<Duration>> {
+    if let Some(millis) = data.as_i64() {
+        if millis == -1 {
+            return Some(None);
+        }
+
+        if millis < 0 {
+            return None;
+        }
+
+        return Some(Some(Duration::from_millis(millis as u64)));
+    }
+
+    let value = match data.as_str() {
+        Some(value) => value.trim(),
+        None => return None,
+    };
+
+    if value == "-1" {
+        return Some(None);
+    }
+
+    // Split the number from the unit
+    let unit_start = value.find(|c: char| !c.is_digit(10)).unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number = match number.parse::<u64>() {
+        Ok(number) => number,
+        Err(_) => return None,
+    };
+
+    let millis = match unit {
+        "ms" | "" => number,
+        "s" => number * 1000,
+        "m" => number * 60 * 1000,
+        "h" => number * 60 * 60 * 1000,
+        "d" => number * 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+
+    Some(Some(Duration::from_millis(millis)))
+}
+
+
+/// Parses a positive integer, which may be given as a number or a string
+fn parse_count(data: &serde_json::Value) -> Option<usize> {
+    match data.as_u64() {
+        Some(value) => Some(value as usize),
+        None => data.as_str().and_then(|value| value.parse::<usize>().ok()),
+    }
+}
+
+
+/// Parses a number, which may be given as a number or a string
+fn parse_number(data: &serde_json::Value) -> Option<f64> {
+    match data.as_f64() {
+        Some(value) => Some(value),
+        None => data.as_str().and_then(|value| value.parse::<f64>().ok()),
+    }
+}
+
+
+/// Looks up a setting by its dotted name
+fn find_setting<'a>(data: &'a serde_json::Map<String, serde_json::Value>, name: &str) -> Option<&'a serde_json::Value> {
+    find_setting_path(data, name)
+        .or_else(|| data.get("index").and_then(|index_data| index_data.as_object()).and_then(|index_data| find_setting_path(index_data, name)))
+        .or_else(|| data.get(&format!("index.{}", name)))
+}
+
+
+fn find_setting_path<'a>(data: &'a serde_json::Map<String, serde_json::Value>, name: &str) -> Option<&'a serde_json::Value> {
+    if let Some(value) = data.get(name) {
+        return Some(value);
+    }
+
+    // Look for the first part of the name as a nested object
+    let mut parts = name.splitn(2, '.');
+    let first = parts.next().unwrap_or("");
+    match parts.next() {
+        Some(rest) => {
+            data.get(first)
+                .and_then(|nested| nested.as_object())
+                .and_then(|nested| find_setting_path(nested, rest))
+        }
+        None => None,
+    }
+}
+
+
+/// Formats a time value in the same format that `parse_time_value` accepts
+pub fn format_time_value(value: Option<Duration>) -> String {
+    match value {
+        Some(value) => {
+            let millis = value.as_secs() * 1000 + (value.subsec_nanos() / 1_000_000) as u64;
+
+            if millis % 1000 == 0 {
+                format!("{}s", millis / 1000)
+            } else {
+                format!("{}ms", millis)
+            }
+        }
+        None => "-1".to_string(),
+    }
+}
+
+
+impl IndexSettings {
+    /// Creates settings for a brand new index
+    pub fn new() -> IndexSettings {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        // The merge settings start at the defaults of the merge policy
+        let tiered_merge_policy = TieredMergePolicy::default();
+
+        IndexSettings {
+            uuid: Uuid::new_v4(),
+            creation_date: now.as_secs() as i64 * 1000 + (now.subsec_nanos() / 1_000_000) as i64,
+            refresh_interval: get_default_refresh_interval(),
+            max_result_window: 10000,
+            merge_policy: MergePolicyType::Tiered,
+            merge_segments_per_tier: tiered_merge_policy.segments_per_tier,
+            merge_max_merge_at_once: tiered_merge_policy.max_merge_at_once,
+            merge_max_merged_segment_docs: tiered_merge_policy.max_merged_segment_docs,
+            merge_floor_segment_docs: tiered_merge_policy.floor_segment_docs,
+            merge_deletes_pct_allowed: tiered_merge_policy.deletes_pct_allowed,
+            closed: false,
+        }
+    }
+
+    /// Applies user-configurable settings from the "settings" object of a request
+    ///
+    /// Settings may be given either directly, nested in an "index" object or prefixed
+    /// with "index.". Dotted names, such as "merge.policy.segments_per_tier", may also be
+    /// given as nested objects. Settings that are not given are left unchanged.
+    pub fn update(&mut self, data: &serde_json::Value) -> Result<(), IndexSettingsParseError> {
+        let data = try!(data.as_object().ok_or(IndexSettingsParseError::ExpectedObject));
+
+        if let Some(refresh_interval) = find_setting(data, "refresh_interval") {
+            self.refresh_interval = try!(parse_time_value(refresh_interval).ok_or(IndexSettingsParseError::InvalidRefreshInterval));
+        }
+
+        if let Some(max_result_window) = find_setting(data, "max_result_window") {
+            self.max_result_window = try!(parse_count(max_result_window).ok_or(IndexSettingsParseError::InvalidMaxResultWindow));
+        }
+
+        if let Some(merge_policy) = find_setting(data, "merge.policy.type") {
+            let merge_policy = merge_policy.as_str().and_then(|name| name.parse::<MergePolicyType>().ok());
+            self.merge_policy = try!(merge_policy.ok_or(IndexSettingsParseError::InvalidMergePolicySetting("type")));
+        }
+
+        if let Some(segments_per_tier) = find_setting(data, "merge.policy.segments_per_tier") {
+            self.merge_segments_per_tier = try!(parse_count(segments_per_tier).ok_or(IndexSettingsParseError::InvalidMergePolicySetting("segments_per_tier")));
+        }
+
+        if let Some(max_merge_at_once) = find_setting(data, "merge.policy.max_merge_at_once") {
+            self.merge_max_merge_at_once = try!(parse_count(max_merge_at_once).ok_or(IndexSettingsParseError::InvalidMergePolicySetting("max_merge_at_once")));
+        }
+
+        if let Some(max_merged_segment_docs) = find_setting(data, "merge.policy.max_merged_segment_docs") {
+            self.merge_max_merged_segment_docs = try!(parse_count(max_merged_segment_docs).ok_or(IndexSettingsParseError::InvalidMergePolicySetting("max_merged_segment_docs"))) as i64;
+        }
+
+        if let Some(floor_segment_docs) = find_setting(data, "merge.policy.floor_segment_docs") {
+            self.merge_floor_segment_docs = try!(parse_count(floor_segment_docs).ok_or(IndexSettingsParseError::InvalidMergePolicySetting("floor_segment_docs"))) as i64;
+        }
+
+        if let Some(deletes_pct_allowed) = find_setting(data, "merge.policy.deletes_pct_allowed") {
+            self.merge_deletes_pct_allowed = try!(parse_number(deletes_pct_allowed).ok_or(IndexSettingsParseError::InvalidMergePolicySetting("deletes_pct_allowed")));
+        }
+
+        // Merging less than two segments at a time would never reduce the number of segments
+        if self.merge_segments_per_tier < 2 {
+            return Err(IndexSettingsParseError::InvalidMergePolicySetting("segments_per_tier"));
+        }
+
+        if self.merge_max_merge_at_once < 2 {
+            return Err(IndexSettingsParseError::InvalidMergePolicySetting("max_merge_at_once"));
+        }
+
+        if self.merge_max_merged_segment_docs < 1 {
+            return Err(IndexSettingsParseError::InvalidMergePolicySetting("max_merged_segment_docs"));
+        }
+
+        // NaN isn't rejected by the range check as comparisons with it are always false
+        if !self.merge_deletes_pct_allowed.is_finite() || self.merge_deletes_pct_allowed < 0.0 || self.merge_deletes_pct_allowed > 100.0 {
+            return Err(IndexSettingsParseError::InvalidMergePolicySetting("deletes_pct_allowed"));
+        }
+
+        Ok(())
+    }
+
+    /// Creates the merge policy that's configured for the index
+    pub fn build_merge_policy(&self) -> Box<MergePolicy> {
+        match self.merge_policy {
+            MergePolicyType::Tiered => {
+                Box::new(TieredMergePolicy {
+                    segments_per_tier: self.merge_segments_per_tier,
+                    max_merge_at_once: self.merge_max_merge_at_once,
+                    max_merged_segment_docs: self.merge_max_merged_segment_docs,
+                    floor_segment_docs: self.merge_floor_segment_docs,
+                    deletes_pct_allowed: self.merge_deletes_pct_allowed,
+                })
+            }
+            MergePolicyType::None => Box::new(NoMergePolicy),
+        }
+    }
+}
+
+
+impl ToJson for IndexSettings {
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        // Elasticsearch returns these as strings
+        Ok(json!({
+            "uuid": format!("{}", self.uuid),
+            "creation_date": format!("{}", self.creation_date),
+            "refresh_interval": format_time_value(self.refresh_interval),
+            "max_result_window": format!("{}", self.max_result_window),
+            "merge": {
+                "policy": {
+                    "type": self.merge_policy.name(),
+                    "segments_per_tier": format!("{}", self.merge_segments_per_tier),
+                    "max_merge_at_once": format!("{}", self.merge_max_merge_at_once),
+                    "max_merged_segment_docs": format!("{}", self.merge_max_merged_segment_docs),
+                    "floor_segment_docs": format!("{}", self.merge_floor_segment_docs),
+                    "deletes_pct_allowed": format!("{}", self.merge_deletes_pct_allowed),
+                },
+            },
+        }))
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum IndexSettingsParseError {
+    ExpectedObject,
+    ExpectedKey(&'static str),
+    InvalidUuid,
+    InvalidCreationDate,
+    InvalidRefreshInterval,
+    InvalidMaxResultWindow,
+    InvalidMergePolicySetting(&'static str),
+    InvalidState,
+}
+
+
+pub fn parse(data: &serde_json::Value) -> Result<IndexSettings, IndexSettingsParseError> {
+    let data = try!(data.as_object().ok_or(IndexSettingsParseError::ExpectedObject));
+
+    let uuid = match data.get("uuid") {
+        Some(uuid) => {
+            let uuid = try!(uuid.as_str().ok_or(IndexSettingsParseError::InvalidUuid));
+            try!(Uuid::parse_str(uuid).map_err(|_| IndexSettingsParseError::InvalidUuid))
+        }
+        None => return Err(IndexSettingsParseError::ExpectedKey("uuid")),
+    };
+
+    let creation_date = match data.get("creation_date") {
+        Some(creation_date) => {
+            let creation_date = try!(creation_date.as_str().ok_or(IndexSettingsParseError::InvalidCreationDate));
+            try!(creation_date.parse::<i64>().map_err(|_| IndexSettingsParseError::InvalidCreationDate))
+        }
+        None => return Err(IndexSettingsParseError::ExpectedKey("creation_date")),
+    };
+
+    // Settings files written by older versions may not contain every setting so start
+    // from the defaults
+    let mut settings = IndexSettings::new();
+    settings.uuid = uuid;
+    settings.creation_date = creation_date;
+
+    settings.closed = match data.get("state").and_then(|state| state.as_str()) {
+        Some("open") | None => false,
+        Some("close") => true,
+        Some(_) => return Err(IndexSettingsParseError::InvalidState),
+    };
+
+    let data = serde_json::Value::Object(data.clone());
+    try!(settings.update(&data));
+
+    Ok(settings)
+}
+
+
+#[derive(Debug)]
+pub enum SaveIndexSettingsError {
+    JsonEncoderError(serde_json::Error),
+    IoError(atomicwrites::Error<io::Error>),
+}
+
+
+impl From<SaveIndexSettingsError> for String {
+    fn from(e: SaveIndexSettingsError) -> String {
+        match e {
+            SaveIndexSettingsError::JsonEncoderError(e) => format!("failed to save index settings: {}", e).to_string(),
+            SaveIndexSettingsError::IoError(e) => format!("failed to save index settings: {}", e).to_string(),
+        }
+    }
+}
+
+
+impl From<serde_json::Error> for SaveIndexSettingsError {
+    fn from(e: serde_json::Error) -> SaveIndexSettingsError {
+        SaveIndexSettingsError::JsonEncoderError(e)
+    }
+}
+
+
+impl From<atomicwrites::Error<io::Error>> for SaveIndexSettingsError {
+    fn from(e: atomicwrites::Error<io::Error>) -> SaveIndexSettingsError {
+        SaveIndexSettingsError::IoError(e)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum LoadIndexSettingsError {
+    IndexSettingsParseError(IndexSettingsParseError),
+    JsonParserError(serde_json::Error),
+    IoError(io::Error),
+}
+
+
+impl From<LoadIndexSettingsError> for String {
+    fn from(e: LoadIndexSettingsError) -> String {
+        match e {
+            LoadIndexSettingsError::IndexSettingsParseError(e) => format!("failed to load index settings: {:?}", e).to_string(),
+            LoadIndexSettingsError::JsonParserError(e) => format!("failed to load index settings: {}", e).to_string(),
+            LoadIndexSettingsError::IoError(e) => format!("failed to load index settings: {}", e).to_string(),
+        }
+    }
+}
+
+
+impl From<IndexSettingsParseError> for LoadIndexSettingsError {
+    fn from(e: IndexSettingsParseError) -> LoadIndexSettingsError {
+        LoadIndexSettingsError::IndexSettingsParseError(e)
+    }
+}
+
+
+impl From<serde_json::Error> for LoadIndexSettingsError {
+    fn from(e: serde_json::Error) -> LoadIndexSettingsError {
+        LoadIndexSettingsError::JsonParserError(e)
+    }
+}
+
+
+impl From<io::Error> for LoadIndexSettingsError {
+    fn from(e: io::Error) -> LoadIndexSettingsError {
+        LoadIndexSettingsError::IoError(e)
+    }
+}
+
+
+impl IndexSettings {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SaveIndexSettingsError> {
+        // Encode to JSON
+        let mut json = try!(self.to_json());
+        if let Some(object) = json.as_object_mut() {
+            let state = if self.closed { "close" } else { "open" };
+            object.insert("state".to_string(), serde_json::Value::String(state.to_string()));
+        }
+        let s = format!("{}", json);
+
+        // Write to file
+        let file = AtomicFile::new(path, AllowOverwrite);
+        try!(file.write(|f| {
+            f.write_all(s.as_bytes())
+        }));
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<IndexSettings, LoadIndexSettingsError> {
+        let mut file = try!(File::open(path));
+        let mut s = String::new();
+        try!(file.read_to_string(&mut s));
+
+        Ok(try!(parse(&try!(serde_json::from_str(&s)))))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::value::ToJson;
+    use uuid::Uuid;
+    use kite_rocksdb::TieredMergePolicy;
+
+    use super::{IndexSettings, IndexSettingsParseError, MergePolicyType, parse, parse_time_value, format_time_value};
+
+    #[test]
+    fn test_round_trip() {
+        let settings = IndexSettings::new();
+        let json = settings.to_json().unwrap();
+
+        assert_eq!(parse(&json), Ok(settings));
+    }
+
+    #[test]
+    fn test_parse() {
+        let settings = parse(&json!({
+            "uuid": "0b4f0e6f-3d8c-4a5c-9d4b-0e4a3e8b2f61",
+            "creation_date": "1476000000000",
+        })).expect("parse() returned an error");
+
+        // Merge settings that aren't saved use the defaults of the merge policy
+        let tiered_merge_policy = TieredMergePolicy::default();
+
+        assert_eq!(settings, IndexSettings {
+            uuid: Uuid::parse_str("0b4f0e6f-3d8c-4a5c-9d4b-0e4a3e8b2f61").unwrap(),
+            creation_date: 1476000000000,
+            refresh_interval: Some(Duration::new(1, 0)),
+            max_result_window: 10000,
+            merge_policy: MergePolicyType::Tiered,
+            merge_segments_per_tier: tiered_merge_policy.segments_per_tier,
+            merge_max_merge_at_once: tiered_merge_policy.max_merge_at_once,
+            merge_max_merged_segment_docs: tiered_merge_policy.max_merged_segment_docs,
+            merge_floor_segment_docs: tiered_merge_policy.floor_segment_docs,
+            merge_deletes_pct_allowed: tiered_merge_policy.deletes_pct_allowed,
+            closed: false,
+        });
+    }
+
+    #[test]
+    fn test_parse_closed() {
+        let settings = parse(&json!({
+            "uuid": "0b4f0e6f-3d8c-4a5c-9d4b-0e4a3e8b2f61",
+            "creation_date": "1476000000000",
+            "state": "close",
+        })).expect("parse() returned an error");
+
+        assert!(settings.closed);
+    }
+
+    #[test]
+    fn test_parse_bad_uuid() {
+        let error = parse(&json!({
+            "uuid": "foo",
+            "creation_date": "1476000000000",
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexSettingsParseError::InvalidUuid);
+    }
+
+    #[test]
+    fn test_update_refresh_interval() {
+        let mut settings = IndexSettings::new();
+
+        settings.update(&json!({"index": {"refresh_interval": "30s"}})).unwrap();
+        assert_eq!(settings.refresh_interval, Some(Duration::new(30, 0)));
+
+        settings.update(&json!({"index.refresh_interval": "-1"})).unwrap();
+        assert_eq!(settings.refresh_interval, None);
+
+        let error = settings.update(&json!({"refresh_interval": "soon"})).err();
+        assert_eq!(error, Some(IndexSettingsParseError::InvalidRefreshInterval));
+    }
+
+    #[test]
+    fn test_update_nested_and_dotted_settings() {
+        let mut settings = IndexSettings::new();
+
+        settings.update(&json!({
+            "index": {
+                "max_result_window": 500,
+                "merge": {
+                    "policy": {
+                        "segments_per_tier": "5",
+                    },
+                },
+            },
+            "index.merge.policy.max_merge_at_once": "20",
+        })).unwrap();
+
+        assert_eq!(settings.max_result_window, 500);
+        assert_eq!(settings.merge_segments_per_tier, 5);
+        assert_eq!(settings.merge_max_merge_at_once, 20);
+
+        // Other settings must be left alone
+        assert_eq!(settings.refresh_interval, Some(Duration::new(1, 0)));
+    }
+
+    #[test]
+    fn test_update_invalid_merge_setting() {
+        let mut settings = IndexSettings::new();
+
+        let error = settings.update(&json!({"merge.policy.segments_per_tier": 1})).err();
+        assert_eq!(error, Some(IndexSettingsParseError::InvalidMergePolicySetting("segments_per_tier")));
+    }
+
+    #[test]
+    fn test_update_merge_policy() {
+        let mut settings = IndexSettings::new();
+
+        settings.update(&json!({
+            "index.merge.policy.type": "none",
+            "index.merge.policy.deletes_pct_allowed": "20.5",
+            "index.merge.policy.floor_segment_docs": 100,
+        })).unwrap();
+
+        assert_eq!(settings.merge_policy, MergePolicyType::None);
+        assert_eq!(settings.merge_deletes_pct_allowed, 20.5);
+        assert_eq!(settings.merge_floor_segment_docs, 100);
+
+        let error = settings.update(&json!({"merge.policy.type": "random"})).err();
+        assert_eq!(error, Some(IndexSettingsParseError::InvalidMergePolicySetting("type")));
+
+        let error = settings.update(&json!({"merge.policy.deletes_pct_allowed": 150})).err();
+        assert_eq!(error, Some(IndexSettingsParseError::InvalidMergePolicySetting("deletes_pct_allowed")));
+
+        let error = settings.update(&json!({"merge.policy.deletes_pct_allowed": "NaN"})).err();
+        assert_eq!(error, Some(IndexSettingsParseError::InvalidMergePolicySetting("deletes_pct_allowed")));
+    }
+
+    #[test]
+    fn test_time_value_round_trip() {
+        for value in vec!["1s", "500ms", "-1"] {
+            let parsed = parse_time_value(&json!(value)).unwrap();
+            assert_eq!(format_time_value(parsed), value);
+        }
+
+        assert_eq!(parse_time_value(&json!("2m")), Some(Some(Duration::new(120, 0))));
+        assert_eq!(parse_time_value(&json!(250)), Some(Some(Duration::from_millis(250))));
+        assert_eq!(parse_time_value(&json!("1 fortnight")), None);
+    }
+}
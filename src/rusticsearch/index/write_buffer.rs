@@ -0,0 +1,470 @@
+//! Buffers changes to documents in memory until the index is refreshed
+//!
+//! Writing every change straight into the store creates a new segment each time, which
+//! then has to be merged. Instead, changes are collected here and written into a single
+//! segment when the index is refreshed. Searches only see changes that have been refreshed.
+
+use std::time::Instant;
+use std::sync::MutexGuard;
+use std::collections::BTreeMap;
+
+use kite::{Document, DocRef};
+use kite_rocksdb::{DocumentVersion, RocksDBIndexReader};
+
+use document::DocumentSource;
+use index::Index;
+
+
+#[derive(Debug)]
+struct BufferedChange {
+    /// The new version of the document, `None` if the document was deleted
+    doc: Option<Document>,
+
+    version: DocumentVersion,
+}
+
+
+#[derive(Debug)]
+pub struct WriteBuffer {
+    /// The latest change to each document key
+    changes: BTreeMap<String, BufferedChange>,
+
+    last_refresh: Instant,
+}
+
+
+impl WriteBuffer {
+    pub fn new() -> WriteBuffer {
+        WriteBuffer {
+            changes: BTreeMap::new(),
+            last_refresh: Instant::now(),
+        }
+    }
+}
+
+
+/// Controls when a change made through the API becomes visible to searches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefreshPolicy {
+    /// Leave the change to be picked up by the next scheduled refresh
+    None,
+
+    /// Refresh the index straight away
+    Immediate,
+
+    /// Wait until the change is visible to searches before returning
+    WaitFor,
+}
+
+
+/// A document as it is seen by a `DocumentSnapshot`
+#[derive(Debug, Clone, Copy)]
+pub enum SnapshotDocument<'a> {
+    /// The document is in the write buffer and hasn't been refreshed yet
+    Buffered(&'a Document),
+
+    /// The document is in the store
+    Stored(DocRef),
+}
+
+
+/// A view of an index's documents that includes changes that haven't been refreshed yet
+///
+/// The write buffer stays locked for as long as this is held and everything else is read
+/// from a single snapshot of the store, so all documents are seen as they were at the same
+/// point in time.
+pub struct DocumentSnapshot<'a> {
+    write_buffer: MutexGuard<'a, WriteBuffer>,
+    reader: RocksDBIndexReader<'a>,
+}
+
+
+impl<'a> DocumentSnapshot<'a> {
+    /// The reader of the store snapshot, used to read stored documents
+    pub fn reader(&self) -> &RocksDBIndexReader<'a> {
+        &self.reader
+    }
+
+    /// Finds the latest version of a document, returns `None` if it doesn't exist
+    pub fn get_document(&self, doc_key: &str) -> Result<Option<(SnapshotDocument, DocumentVersion)>, String> {
+        match self.write_buffer.changes.get(doc_key) {
+            Some(&BufferedChange { doc: Some(ref doc), version }) => Ok(Some((SnapshotDocument::Buffered(doc), version))),
+            Some(&BufferedChange { doc: None, .. }) => Ok(None),
+            None => {
+                match try!(self.reader.find_document_in_snapshot(doc_key).map_err(|e| format!("failed to read primary key index: {:?}", e))) {
+                    Some((doc_ref, version)) => Ok(Some((SnapshotDocument::Stored(doc_ref), version))),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+
+/// Conditions that the current version of a document must meet for a write to go ahead
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WriteConditions {
+    /// The document must not exist yet
+    pub create_only: bool,
+
+    /// The document must currently be at this version
+    pub version: Option<u64>,
+
+    /// The document must currently be at this sequence number
+    pub if_seq_no: Option<u64>,
+}
+
+
+impl WriteConditions {
+    pub fn check(&self, current_version: Option<DocumentVersion>) -> bool {
+        if self.create_only && current_version.is_some() {
+            return false;
+        }
+
+        if let Some(version) = self.version {
+            if current_version.map(|current_version| current_version.version) != Some(version) {
+                return false;
+            }
+        }
+
+        if let Some(seq_no) = self.if_seq_no {
+            if current_version.map(|current_version| current_version.seq_no) != Some(seq_no) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+
+#[derive(Debug)]
+pub enum WriteError {
+    /// The current version of the document didn't meet the conditions of the write
+    VersionConflict(Option<DocumentVersion>),
+
+    /// The index is closed or the store failed
+    StoreError(String),
+}
+
+
+impl Index {
+    /// Inserts or replaces a document
+    ///
+    /// Returns the version that was given to it and whether the document was created
+    /// (rather than replacing a document that already existed).
+    pub fn insert_or_update_document(&self, doc: Document, conditions: &WriteConditions) -> Result<(DocumentVersion, bool), WriteError> {
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+
+        let current_version = self.get_document_version_buffered(&write_buffer, &doc.key);
+        if !conditions.check(current_version) {
+            return Err(WriteError::VersionConflict(current_version));
+        }
+
+        let last_version = self.get_last_document_version_buffered(&write_buffer, &doc.key);
+        let version = try!(self.next_document_version(last_version));
+        write_buffer.changes.insert(doc.key.clone(), BufferedChange {
+            doc: Some(doc),
+            version: version,
+        });
+
+        Ok((version, current_version.is_none()))
+    }
+
+    /// Deletes a document, returns `None` if the document doesn't exist
+    ///
+    /// Deleting a document counts as a new version of it. The version is remembered so a
+    /// document that is created again later carries on from it.
+    pub fn remove_document_by_key(&self, doc_key: &str, conditions: &WriteConditions) -> Result<Option<DocumentVersion>, WriteError> {
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+
+        let current_version = self.get_document_version_buffered(&write_buffer, doc_key);
+        if current_version.is_none() && conditions.version.is_none() && conditions.if_seq_no.is_none() {
+            return Ok(None);
+        }
+
+        if !conditions.check(current_version) {
+            return Err(WriteError::VersionConflict(current_version));
+        }
+
+        let last_version = self.get_last_document_version_buffered(&write_buffer, doc_key);
+        let version = try!(self.next_document_version(last_version));
+        write_buffer.changes.insert(doc_key.to_string(), BufferedChange {
+            doc: None,
+            version: version,
+        });
+
+        Ok(Some(version))
+    }
+
+    /// Checks if a document exists, including changes that haven't been refreshed yet
+    pub fn contains_document_key(&self, doc_key: &str) -> bool {
+        let write_buffer = self.write_buffer.lock().unwrap();
+        self.get_document_version_buffered(&write_buffer, doc_key).is_some()
+    }
+
+    /// Reads the source and version of the latest version of a document, including changes
+    /// that haven't been refreshed yet
+    ///
+    /// Returns `None` if the document doesn't exist and an error if it was indexed without
+    /// its source.
+    pub fn get_document(&self, doc_key: &str) -> Result<Option<(DocumentSource, DocumentVersion)>, String> {
+        let write_buffer = self.write_buffer.lock().unwrap();
+        self.get_document_buffered(&write_buffer, doc_key)
+    }
+
+    /// Opens a view of the index's documents that includes buffered changes, returns `None`
+    /// if the index is closed
+    pub fn document_snapshot(&self) -> Option<DocumentSnapshot> {
+        let store = match self.store() {
+            Some(store) => store,
+            None => return None,
+        };
+
+        // The buffer must be locked before the snapshot is taken, otherwise changes that
+        // are flushed in between would be missed by both
+        let write_buffer = self.write_buffer.lock().unwrap();
+
+        Some(DocumentSnapshot {
+            write_buffer: write_buffer,
+            reader: store.reader(),
+        })
+    }
+
+    /// Replaces a document with a new version that is built from its current source
+    ///
+    /// The write buffer is locked while `update` runs so no other change can be made to
+    /// the document in between reading it and replacing it. `update` is given the current
+    /// source of the document (if there is one) and returns the new version, or `None` to
+    /// leave the document unchanged.
+    ///
+    /// The conditions are checked against the current version of the document before
+    /// `update` is called. Also returns the version of the document after the update.
+    pub fn update_document<F, R>(&self, doc_key: &str, conditions: &WriteConditions, update: F) -> Result<(R, Option<DocumentVersion>), WriteError>
+        where F: FnOnce(Option<DocumentSource>) -> (Option<Document>, R)
+    {
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+        let (doc_source, current_version) = match try!(self.get_document_buffered(&write_buffer, doc_key).map_err(WriteError::StoreError)) {
+            Some((doc_source, version)) => (Some(doc_source), Some(version)),
+            None => (None, None),
+        };
+
+        if !conditions.check(current_version) {
+            return Err(WriteError::VersionConflict(current_version));
+        }
+
+        let (doc, result) = update(doc_source);
+        let doc = match doc {
+            Some(doc) => doc,
+            None => return Ok((result, current_version)),
+        };
+
+        let last_version = self.get_last_document_version_buffered(&write_buffer, doc_key);
+        let version = try!(self.next_document_version(last_version));
+        write_buffer.changes.insert(doc_key.to_string(), BufferedChange {
+            doc: Some(doc),
+            version: version,
+        });
+
+        Ok((result, Some(version)))
+    }
+
+    fn next_document_version(&self, last_version: Option<DocumentVersion>) -> Result<DocumentVersion, WriteError> {
+        let store = match self.store() {
+            Some(store) => store,
+            None => return Err(WriteError::StoreError("cannot write to a closed index".to_string())),
+        };
+
+        let seq_no = try!(store.allocate_seq_no().map_err(|e| WriteError::StoreError(format!("failed to allocate sequence number: {:?}", e))));
+
+        Ok(DocumentVersion {
+            version: last_version.map(|last_version| last_version.version + 1).unwrap_or(1),
+            seq_no: seq_no,
+        })
+    }
+
+    fn get_document_version_buffered(&self, write_buffer: &WriteBuffer, doc_key: &str) -> Option<DocumentVersion> {
+        match write_buffer.changes.get(doc_key) {
+            Some(&BufferedChange { doc: Some(_), version }) => Some(version),
+            Some(&BufferedChange { doc: None, .. }) => None,
+            None => {
+                match self.store() {
+                    Some(store) => store.reader().get_document_version(doc_key),
+                    None => None,
+                }
+            }
+        }
+    }
+
+    /// Finds the latest version of a key, including the version it was deleted at if the
+    /// document doesn't currently exist
+    fn get_last_document_version_buffered(&self, write_buffer: &WriteBuffer, doc_key: &str) -> Option<DocumentVersion> {
+        match write_buffer.changes.get(doc_key) {
+            Some(change) => Some(change.version),
+            None => {
+                match self.store() {
+                    Some(store) => store.reader().get_last_document_version(doc_key),
+                    None => None,
+                }
+            }
+        }
+    }
+
+    fn get_document_buffered(&self, write_buffer: &WriteBuffer, doc_key: &str) -> Result<Option<(DocumentSource, DocumentVersion)>, String> {
+        let meta_fields = match self.meta_fields() {
+            Some(meta_fields) => meta_fields,
+            None => return Ok(None),
+        };
+
+        let (doc_source, version) = match write_buffer.changes.get(doc_key) {
+            Some(&BufferedChange { doc: Some(ref doc), version }) => (try!(meta_fields.document_source(doc)), version),
+            Some(&BufferedChange { doc: None, .. }) => return Ok(None),
+            None => {
+                let store = match self.store() {
+                    Some(store) => store,
+                    None => return Ok(None),
+                };
+
+                let reader = store.reader();
+                let (doc_ref, version) = match try!(reader.find_document_in_snapshot(doc_key).map_err(|e| format!("failed to read primary key index: {:?}", e))) {
+                    Some(entry) => entry,
+                    None => return Ok(None),
+                };
+
+                (try!(meta_fields.read_source(&reader, doc_ref)), version)
+            }
+        };
+
+        match doc_source {
+            Some(doc_source) => Ok(Some((doc_source, version))),
+            None => Err("document was indexed without its source".to_string()),
+        }
+    }
+
+    /// Writes all buffered changes into the store, making them visible to searches
+    pub fn refresh(&self) -> Result<(), String> {
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+        self.flush_write_buffer(&mut write_buffer)
+    }
+
+    /// Refreshes the index if its refresh interval has passed since the last refresh
+    ///
+    /// This is called periodically by the maintenance thread.
+    pub fn refresh_if_due(&self) -> Result<(), String> {
+        let refresh_interval = match self.settings.read().unwrap().refresh_interval {
+            Some(refresh_interval) => refresh_interval,
+            None => return Ok(()),
+        };
+
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+        if write_buffer.last_refresh.elapsed() < refresh_interval {
+            return Ok(());
+        }
+
+        self.flush_write_buffer(&mut write_buffer)
+    }
+
+    /// Blocks until changes that were made before calling this are visible to searches
+    ///
+    /// Callers usually hold a read lock on the index registry while they wait. Waiting for
+    /// the maintenance thread to refresh could deadlock as it needs that lock too (and a
+    /// queued writer would stop it from getting it), so the buffer is flushed straight away
+    /// instead.
+    pub fn wait_for_refresh(&self) -> Result<(), String> {
+        let mut write_buffer = self.write_buffer.lock().unwrap();
+
+        if write_buffer.changes.is_empty() {
+            return Ok(());
+        }
+
+        self.flush_write_buffer(&mut write_buffer)
+    }
+
+    /// Makes a change visible according to the refresh policy that was requested
+    pub fn apply_refresh_policy(&self, refresh_policy: RefreshPolicy) -> Result<(), String> {
+        match refresh_policy {
+            RefreshPolicy::None => Ok(()),
+            RefreshPolicy::Immediate => self.refresh(),
+            RefreshPolicy::WaitFor => self.wait_for_refresh(),
+        }
+    }
+
+    fn flush_write_buffer(&self, write_buffer: &mut WriteBuffer) -> Result<(), String> {
+        // The changes are only removed from the buffer once they have been written, so
+        // they are retried by the next refresh if the write fails. Writing the same
+        // changes twice is harmless as they replace documents by key.
+        if !write_buffer.changes.is_empty() {
+            try!(self.write_changes(&write_buffer.changes));
+            write_buffer.changes.clear();
+        }
+
+        write_buffer.last_refresh = Instant::now();
+
+        Ok(())
+    }
+
+    fn write_changes(&self, changes: &BTreeMap<String, BufferedChange>) -> Result<(), String> {
+        let store = match self.store() {
+            Some(store) => store,
+            None => return Err("cannot write to a closed index".to_string()),
+        };
+
+        let mut docs = Vec::new();
+        let mut deleted_keys = Vec::new();
+        for (doc_key, change) in changes.iter() {
+            match change.doc {
+                Some(ref doc) => docs.push((doc, change.version)),
+                None => deleted_keys.push((doc_key.as_str(), change.version)),
+            }
+        }
+
+        // Delete documents in a single write
+        try!(store.remove_versioned_documents_by_key(&deleted_keys).map_err(|e| format!("failed to delete documents: {:?}", e)));
+
+        // Insert all documents into a single segment
+        try!(store.insert_or_update_versioned_documents(&docs).map_err(|e| format!("failed to insert documents: {:?}", e)));
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use kite_rocksdb::DocumentVersion;
+
+    use super::WriteConditions;
+
+    #[test]
+    fn test_write_conditions_default() {
+        let conditions = WriteConditions::default();
+
+        assert!(conditions.check(None));
+        assert!(conditions.check(Some(DocumentVersion { version: 3, seq_no: 10 })));
+    }
+
+    #[test]
+    fn test_write_conditions_create_only() {
+        let conditions = WriteConditions {
+            create_only: true,
+            .. WriteConditions::default()
+        };
+
+        assert!(conditions.check(None));
+        assert!(!conditions.check(Some(DocumentVersion { version: 1, seq_no: 0 })));
+    }
+
+    #[test]
+    fn test_write_conditions_version() {
+        let conditions = WriteConditions {
+            version: Some(3),
+            if_seq_no: Some(10),
+            .. WriteConditions::default()
+        };
+
+        assert!(conditions.check(Some(DocumentVersion { version: 3, seq_no: 10 })));
+        assert!(!conditions.check(Some(DocumentVersion { version: 4, seq_no: 10 })));
+        assert!(!conditions.check(Some(DocumentVersion { version: 3, seq_no: 11 })));
+        assert!(!conditions.check(None));
+    }
+}
@@ -22,18 +22,20 @@ pub mod query_parser;
 pub mod mapping;
 pub mod document;
 pub mod index;
+pub mod search;
 pub mod system;
 mod api;
 mod logger;
 
-use std::path::Path;
+use std::env;
+use std::process;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use slog::Logger;
 
-use system::System;
+use system::{System, SystemConfig};
 
 
 const VERSION: &'static str = "0.1a0";
@@ -47,24 +49,61 @@ fn main() {
 
     logger::init().unwrap();
 
-    let system = Arc::new(System::new(log, Path::new("data/").to_path_buf()));
+    let mut config = SystemConfig::default();
+
+    // Searches use a thread for each CPU unless this is set
+    if let Ok(search_threads) = env::var("RUSTICSEARCH_SEARCH_THREADS") {
+        match search_threads.parse::<usize>() {
+            Ok(search_threads) if search_threads > 0 => config.search_threads = Some(search_threads),
+            _ => {
+                log.error("[sys] RUSTICSEARCH_SEARCH_THREADS must be a positive number", b!("value" => search_threads));
+                process::exit(1);
+            }
+        }
+    }
+
+    let system = match System::new(log.clone(), config) {
+        Ok(system) => Arc::new(system),
+        Err(error) => {
+            log.error("[sys] failed to start", b!("error" => error));
+            process::exit(1);
+        }
+    };
 
     system.log.info("[sys] loading indices", b!());
     system.load_indices();
 
+    system.log.info("[sys] loading aliases", b!());
+    system.load_aliases();
+
     {
         let system = system.clone();
         thread::spawn(move || {
+            let mut last_maintenance = Instant::now();
+
             loop {
                 {
                     let indices = system.indices.read().unwrap();
+
+                    // Refresh indices that are due. This runs more often than the other tasks
+                    // so short refresh intervals are respected
                     for index in indices.values() {
-                        // TODO: Catch panic
-                        index.run_maintenance_task().unwrap();
+                        if let Err(error) = index.refresh_if_due() {
+                            system.log.error("[sys] failed to refresh index", b!("index" => index.canonical_name(), "error" => error));
+                        }
+                    }
+
+                    if last_maintenance.elapsed() >= Duration::new(1, 0) {
+                        for index in indices.values() {
+                            // TODO: Catch panic
+                            index.run_maintenance_task().unwrap();
+                        }
+
+                        last_maintenance = Instant::now();
                     }
                 }
 
-                thread::sleep(Duration::new(1, 0));
+                thread::sleep(Duration::from_millis(100));
             }
         });
     }
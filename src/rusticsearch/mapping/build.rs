@@ -1,9 +1,17 @@
 use std::collections::HashMap;
 
+use kite::schema::Norms;
+
 use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType, get_standard_analyzer};
 use index::metadata::IndexMetaData;
 
 
+#[derive(Debug, PartialEq)]
+pub enum MappingBuildError {
+    UnrecognisedSimilarity(String),
+}
+
+
 #[derive(Debug, PartialEq)]
 pub struct FieldMappingBuilder {
     pub field_type: FieldType,
@@ -14,7 +22,9 @@ pub struct FieldMappingBuilder {
     pub boost: f64,
     pub base_analyzer: Option<String>,
     pub index_analyzer: Option<String>,
-    pub search_analyzer: Option<String>
+    pub search_analyzer: Option<String>,
+    pub similarity: Option<String>,
+    pub norms: Option<Norms>,
 }
 
 
@@ -30,13 +40,15 @@ impl Default for FieldMappingBuilder {
             base_analyzer: None,
             index_analyzer: None,
             search_analyzer: None,
+            similarity: None,
+            norms: None,
         }
     }
 }
 
 
 impl FieldMappingBuilder {
-    pub fn build(&self, index_metadata: &IndexMetaData) -> FieldMapping {
+    pub fn build(&self, index_metadata: &IndexMetaData) -> Result<FieldMapping, MappingBuildError> {
         let base_analyzer = match self.base_analyzer {
             Some(ref base_analyzer) => {
                 match index_metadata.analyzers().get(base_analyzer) {
@@ -81,7 +93,17 @@ impl FieldMappingBuilder {
             None
         };
 
-        FieldMapping {
+        let similarity_model = match self.similarity {
+            Some(ref similarity) => {
+                match index_metadata.similarities().get(similarity) {
+                    Some(similarity_model) => similarity_model.clone(),
+                    None => return Err(MappingBuildError::UnrecognisedSimilarity(similarity.clone())),
+                }
+            }
+            None => index_metadata.get_default_similarity(),
+        };
+
+        Ok(FieldMapping {
             data_type: self.field_type,
             index_ref: None,
             is_indexed: self.is_indexed,
@@ -90,7 +112,10 @@ impl FieldMappingBuilder {
             boost: self.boost,
             index_analyzer: index_analyzer,
             search_analyzer: search_analyzer,
-        }
+            similarity: self.similarity.clone(),
+            similarity_model: similarity_model,
+            norms: self.norms,
+        })
     }
 }
 
@@ -111,23 +136,23 @@ impl Default for NestedMappingBuilder {
 
 
 impl NestedMappingBuilder {
-    pub fn build(&self, index_metadata: &IndexMetaData) -> NestedMapping {
+    pub fn build(&self, index_metadata: &IndexMetaData) -> Result<NestedMapping, MappingBuildError> {
         // Insert fields
         let mut properties = HashMap::new();
         for (field_name, builder) in self.properties.iter() {
             match *builder {
                 MappingPropertyBuilder::Field(ref field_builder) => {
-                     properties.insert(field_name.to_string(), MappingProperty::Field(field_builder.build(index_metadata)));
+                     properties.insert(field_name.to_string(), MappingProperty::Field(try!(field_builder.build(index_metadata))));
                 }
                 MappingPropertyBuilder::NestedMapping(ref nested_mapping_builder) => {
-                    properties.insert(field_name.to_string(), MappingProperty::NestedMapping(Box::new(nested_mapping_builder.build(index_metadata))));
+                    properties.insert(field_name.to_string(), MappingProperty::NestedMapping(Box::new(try!(nested_mapping_builder.build(index_metadata)))));
                 }
             }
         }
 
-        NestedMapping {
+        Ok(NestedMapping {
             properties: properties,
-        }
+        })
     }
 }
 
@@ -146,16 +171,16 @@ pub struct MappingBuilder {
 
 
 impl MappingBuilder {
-    pub fn build(&self, index_metadata: &IndexMetaData) -> Mapping {
+    pub fn build(&self, index_metadata: &IndexMetaData) -> Result<Mapping, MappingBuildError> {
         // Insert fields
         let mut properties = HashMap::new();
         for (field_name, builder) in self.properties.iter() {
             match *builder {
                 MappingPropertyBuilder::Field(ref field_builder) => {
-                     properties.insert(field_name.to_string(), MappingProperty::Field(field_builder.build(index_metadata)));
+                     properties.insert(field_name.to_string(), MappingProperty::Field(try!(field_builder.build(index_metadata))));
                 }
                 MappingPropertyBuilder::NestedMapping(ref nested_mapping_builder) => {
-                    properties.insert(field_name.to_string(), MappingProperty::NestedMapping(Box::new(nested_mapping_builder.build(index_metadata))));
+                    properties.insert(field_name.to_string(), MappingProperty::NestedMapping(Box::new(try!(nested_mapping_builder.build(index_metadata)))));
                 }
             }
         }
@@ -170,27 +195,30 @@ impl MappingBuilder {
                     is_in_all: false,
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
+                    similarity_model: index_metadata.get_default_similarity(),
                     .. FieldMapping::default()
                 }
             ));
         }
 
-        Mapping {
+        Ok(Mapping {
             properties: properties,
-        }
+        })
     }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use kite::similarity::SimilarityModel;
+
     use analysis::AnalyzerSpec;
     use analysis::tokenizers::TokenizerSpec;
     use analysis::filters::FilterSpec;
     use mapping::{Mapping, MappingProperty, FieldMapping, FieldType, get_standard_analyzer};
     use index::metadata::IndexMetaData;
 
-    use super::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder};
+    use super::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder, MappingBuildError};
 
     #[test]
     fn test_build() {
@@ -208,7 +236,7 @@ mod tests {
             },
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, Mapping {
             properties: hashmap! {
@@ -238,7 +266,7 @@ mod tests {
             properties: hashmap! {},
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, Mapping {
             properties: hashmap! {
@@ -268,7 +296,7 @@ mod tests {
             },
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, Mapping {
             properties: hashmap! {
@@ -291,7 +319,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -310,7 +338,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::Integer,
@@ -329,7 +357,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -349,7 +377,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -369,7 +397,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -388,16 +416,58 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
+
+        assert_eq!(mapping, FieldMapping {
+            data_type: FieldType::String,
+            index_analyzer: Some(get_standard_analyzer()),
+            search_analyzer: Some(get_standard_analyzer()),
+            ..FieldMapping::default()
+        });
+    }
+
+    #[test]
+    fn test_build_field_custom_similarity() {
+        let mut index_metadata = IndexMetaData::default();
+        index_metadata.insert_similarity("my-similarity".to_string(), SimilarityModel::Bm25 {
+            k1: 2.0,
+            b: 0.25,
+        });
+
+        let builder = FieldMappingBuilder {
+            field_type: FieldType::String,
+            similarity: Some("my-similarity".to_string()),
+            ..FieldMappingBuilder::default()
+        };
+
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
             index_analyzer: Some(get_standard_analyzer()),
             search_analyzer: Some(get_standard_analyzer()),
+            similarity: Some("my-similarity".to_string()),
+            similarity_model: SimilarityModel::Bm25 {
+                k1: 2.0,
+                b: 0.25,
+            },
             ..FieldMapping::default()
         });
     }
 
+    #[test]
+    fn test_build_field_unrecognised_similarity() {
+        let index_metadata = IndexMetaData::default();
+
+        let builder = FieldMappingBuilder {
+            field_type: FieldType::String,
+            similarity: Some("my-similarity".to_string()),
+            ..FieldMappingBuilder::default()
+        };
+
+        assert_eq!(builder.build(&index_metadata), Err(MappingBuildError::UnrecognisedSimilarity("my-similarity".to_string())));
+    }
+
     fn build_test_analyzer() -> AnalyzerSpec {
         AnalyzerSpec {
             tokenizer: TokenizerSpec::Standard,
@@ -418,7 +488,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -439,7 +509,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -460,7 +530,7 @@ mod tests {
             ..FieldMappingBuilder::default()
         };
 
-        let mapping = builder.build(&index_metadata);
+        let mapping = builder.build(&index_metadata).unwrap();
 
         assert_eq!(mapping, FieldMapping {
             data_type: FieldType::String,
@@ -6,10 +6,10 @@ use std::collections::{HashMap, BTreeMap};
 use serde_json;
 use serde_json::value::ToJson;
 use chrono::{DateTime, UTC};
-use kite::{Term, Token};
+use kite::{Term, Token, TermScorer};
 use kite::document::FieldValue;
 use kite::similarity::SimilarityModel;
-use kite::schema::FieldRef;
+use kite::schema::{Schema, FieldRef, Norms};
 
 use analysis::AnalyzerSpec;
 use analysis::tokenizers::TokenizerSpec;
@@ -28,6 +28,14 @@ fn get_standard_analyzer() -> AnalyzerSpec {
 }
 
 
+pub fn get_default_similarity_model() -> SimilarityModel {
+    SimilarityModel::Bm25 {
+        k1: 1.2,
+        b: 0.75,
+    }
+}
+
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FieldType {
     String,
@@ -67,16 +75,24 @@ impl Default for FieldSearchOptions {
     fn default() -> FieldSearchOptions {
         FieldSearchOptions {
             analyzer: Some(get_standard_analyzer()),
-            similarity_model: SimilarityModel::Bm25 {
-                k1: 1.2,
-                b: 0.75,
-            },
+            similarity_model: get_default_similarity_model(),
+        }
+    }
+}
+
+
+impl FieldSearchOptions {
+    /// Creates a scorer for term queries on this field
+    pub fn term_scorer(&self) -> TermScorer {
+        TermScorer {
+            similarity_model: self.similarity_model.clone(),
+            boost: 1.0f64,
         }
     }
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FieldMapping {
     pub data_type: FieldType,
     pub index_ref: Option<FieldRef>,
@@ -86,6 +102,11 @@ pub struct FieldMapping {
     boost: f64,
     index_analyzer: Option<AnalyzerSpec>,
     search_analyzer: Option<AnalyzerSpec>,
+    similarity: Option<String>,
+    similarity_model: SimilarityModel,
+
+    /// The norms of the field, None if the mapping doesn't set them
+    pub norms: Option<Norms>,
 }
 
 
@@ -100,6 +121,9 @@ impl Default for FieldMapping {
             boost: 1.0f64,
             index_analyzer: None,
             search_analyzer: None,
+            similarity: None,
+            similarity_model: get_default_similarity_model(),
+            norms: None,
         }
     }
 }
@@ -119,7 +143,7 @@ impl ToJson for FieldMapping {
             }
         };
 
-        Ok(json!({
+        let mut json = json!({
             "type": self.data_type.to_string(),
             "index": index,
             "store": self.is_stored,
@@ -128,7 +152,28 @@ impl ToJson for FieldMapping {
             // "search_analyzer"
             "boost": self.boost,
             "include_in_all": self.is_in_all
-        }))
+        });
+
+        if let Some(ref similarity) = self.similarity {
+            if let Some(object) = json.as_object_mut() {
+                object.insert("similarity".to_string(), serde_json::Value::String(similarity.clone()));
+            }
+        }
+
+        if let Some(norms) = self.norms {
+            if let Some(object) = json.as_object_mut() {
+                let norms = match norms {
+                    Norms::Disabled => json!({"enabled": false}),
+                    Norms::Byte => json!({"enabled": true, "precision": "byte"}),
+                    Norms::Short => json!({"enabled": true, "precision": "short"}),
+                    Norms::Int => json!({"enabled": true, "precision": "int"}),
+                };
+
+                object.insert("norms".to_string(), norms);
+            }
+        }
+
+        Ok(json)
     }
 }
 
@@ -150,10 +195,20 @@ impl FieldMapping {
         }
     }
 
+    /// Looks up the similarity model for this field again
+    ///
+    /// This must be called when the similarities defined on the index change.
+    pub fn resolve_similarity(&mut self, similarities: &HashMap<String, SimilarityModel>, default_similarity: &SimilarityModel) {
+        self.similarity_model = match self.similarity {
+            Some(ref similarity) => similarities.get(similarity).unwrap_or(default_similarity).clone(),
+            None => default_similarity.clone(),
+        };
+    }
+
     pub fn get_search_options(&self) -> FieldSearchOptions {
         FieldSearchOptions {
             analyzer: self.search_analyzer().cloned(),
-            .. FieldSearchOptions::default()
+            similarity_model: self.similarity_model.clone(),
         }
     }
 
@@ -324,12 +379,21 @@ impl FieldMapping {
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NestedMapping {
     pub properties: HashMap<String, MappingProperty>,
 }
 
 
+impl NestedMapping {
+    fn resolve_similarities(&mut self, similarities: &HashMap<String, SimilarityModel>, default_similarity: &SimilarityModel) {
+        for property in self.properties.values_mut() {
+            property.resolve_similarities(similarities, default_similarity);
+        }
+    }
+}
+
+
 impl ToJson for NestedMapping {
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
         let mut properties_json = BTreeMap::new();
@@ -347,13 +411,23 @@ impl ToJson for NestedMapping {
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MappingProperty {
     Field(FieldMapping),
     NestedMapping(Box<NestedMapping>),
 }
 
 
+impl MappingProperty {
+    fn resolve_similarities(&mut self, similarities: &HashMap<String, SimilarityModel>, default_similarity: &SimilarityModel) {
+        match *self {
+            MappingProperty::Field(ref mut field_mapping) => field_mapping.resolve_similarity(similarities, default_similarity),
+            MappingProperty::NestedMapping(ref mut nested_mapping) => nested_mapping.resolve_similarities(similarities, default_similarity),
+        }
+    }
+}
+
+
 impl ToJson for MappingProperty {
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
         match *self {
@@ -364,12 +438,31 @@ impl ToJson for MappingProperty {
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Mapping {
     pub properties: HashMap<String, MappingProperty>,
 }
 
 
+impl Mapping {
+    /// Links each field in the mapping to the field in the index schema with the same name
+    pub fn link_fields(&mut self, schema: &Schema) {
+        for (name, property) in self.properties.iter_mut() {
+            if let MappingProperty::Field(ref mut field_mapping) = *property {
+                field_mapping.index_ref = schema.get_field_by_name(&name)
+            }
+        }
+    }
+
+    /// Looks up the similarity model of every field in the mapping again
+    pub fn resolve_similarities(&mut self, similarities: &HashMap<String, SimilarityModel>, default_similarity: &SimilarityModel) {
+        for property in self.properties.values_mut() {
+            property.resolve_similarities(similarities, default_similarity);
+        }
+    }
+}
+
+
 impl ToJson for Mapping {
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
         let mut properties_json = BTreeMap::new();
@@ -1,6 +1,7 @@
 use std::collections::{HashMap, BTreeSet};
 
 use serde_json;
+use kite::schema::Norms;
 
 use mapping::FieldType;
 use mapping::build::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder, NestedMappingBuilder};
@@ -29,6 +30,9 @@ pub enum FieldMappingParseError {
     // "boost" setting
     BoostOnlyAllowedOnIndexedFields,
     BoostMustBePositive,
+
+    // "norms" setting
+    UnrecognisedNormsPrecision(String),
 }
 
 
@@ -84,6 +88,48 @@ fn parse_field_type(field_type_str: &str) -> Result<FieldType, FieldMappingParse
 }
 
 
+fn parse_norms(json: &serde_json::Value) -> Result<Norms, FieldMappingParseError> {
+    // Norms can be disabled with "norms": false
+    if let Some(norms_object) = json.as_object() {
+        // Check for unrecognised keys
+        let provided_keys = norms_object.keys().cloned().collect::<BTreeSet<String>>();
+        let allowed_keys = btreeset![
+            "enabled".to_string(),
+            "precision".to_string(),
+        ];
+        let unrecognised_keys = provided_keys.difference(&allowed_keys).cloned().collect::<Vec<String>>();
+
+        if !unrecognised_keys.is_empty() {
+            return Err(FieldMappingParseError::UnrecognisedKeys(unrecognised_keys));
+        }
+
+        if let Some(enabled_json) = norms_object.get("enabled") {
+            if !try!(parse_boolean(enabled_json)) {
+                return Ok(Norms::Disabled);
+            }
+        }
+
+        match norms_object.get("precision") {
+            Some(precision_json) => {
+                let precision_str = try!(precision_json.as_str().ok_or(FieldMappingParseError::ExpectedString));
+
+                match precision_str {
+                    "byte" => Ok(Norms::Byte),
+                    "short" => Ok(Norms::Short),
+                    "int" => Ok(Norms::Int),
+                    _ => Err(FieldMappingParseError::UnrecognisedNormsPrecision(precision_str.to_string())),
+                }
+            }
+            None => Ok(Norms::default()),
+        }
+    } else if try!(parse_boolean(json)) {
+        Ok(Norms::default())
+    } else {
+        Ok(Norms::Disabled)
+    }
+}
+
+
 fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMappingParseError> {
     let field_object = try!(json.as_object().ok_or(FieldMappingParseError::ExpectedObject));
     let mut mapping_builder = FieldMappingBuilder::default();
@@ -99,6 +145,8 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         "search_analyzer".to_string(),
         "boost".to_string(),
         "include_in_all".to_string(),
+        "similarity".to_string(),
+        "norms".to_string(),
     ];
     let unrecognised_keys = provided_keys.difference(&allowed_keys).cloned().collect::<Vec<String>>();
 
@@ -209,6 +257,17 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         mapping_builder.is_in_all = include_in_all;
     }
 
+    // "similarity" setting
+    if let Some(similarity_json) = field_object.get("similarity") {
+        let similarity_str = try!(similarity_json.as_str().ok_or(FieldMappingParseError::ExpectedString));
+        mapping_builder.similarity = Some(similarity_str.to_string());
+    }
+
+    // "norms" setting
+    if let Some(norms_json) = field_object.get("norms") {
+        mapping_builder.norms = Some(try!(parse_norms(norms_json)));
+    }
+
     Ok(mapping_builder)
 }
 
@@ -319,6 +378,7 @@ pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseErr
 #[cfg(test)]
 mod tests {
     use serde_json;
+    use kite::schema::Norms;
 
     use mapping::FieldType;
     use mapping::build::{FieldMappingBuilder, NestedMappingBuilder, MappingPropertyBuilder, MappingBuilder};
@@ -1014,4 +1074,86 @@ mod tests {
             ..FieldMappingBuilder::default()
         }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_similarity() {
+        let mapping = parse_field(&serde_json::from_str("
+        {
+            \"type\": \"string\",
+            \"similarity\": \"my_similarity\"
+        }
+        ").unwrap());
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            similarity: Some("my_similarity".to_string()),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_norms_disabled() {
+        let mapping = parse_field(&serde_json::from_str("
+        {
+            \"type\": \"string\",
+            \"norms\": false
+        }
+        ").unwrap());
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            norms: Some(Norms::Disabled),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_norms_precision() {
+        let mapping = parse_field(&serde_json::from_str("
+        {
+            \"type\": \"string\",
+            \"norms\": {
+                \"precision\": \"byte\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            norms: Some(Norms::Byte),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_norms_enabled_false() {
+        let mapping = parse_field(&serde_json::from_str("
+        {
+            \"type\": \"string\",
+            \"norms\": {
+                \"enabled\": false
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::String,
+            norms: Some(Norms::Disabled),
+            ..FieldMappingBuilder::default()
+        }));
+    }
+
+    #[test]
+    fn test_parse_norms_unrecognised_precision() {
+        let mapping = parse_field(&serde_json::from_str("
+        {
+            \"type\": \"string\",
+            \"norms\": {
+                \"precision\": \"huge\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(mapping, Err(FieldMappingParseError::UnrecognisedNormsPrecision("huge".to_string())));
+    }
+}
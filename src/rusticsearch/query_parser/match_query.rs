@@ -1,11 +1,9 @@
 //! Parses "match" queries
 
 use serde_json::Value as Json;
-use kite::{Term, Token, Query, TermScorer};
+use kite::{Term, Token, Query};
 use kite::schema::Schema;
 
-use mapping::FieldSearchOptions;
-
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
 use query_parser::utils::{parse_string, parse_float, Operator, parse_operator};
 
@@ -22,15 +20,7 @@ struct MatchQueryBuilder {
 impl QueryBuilder for MatchQueryBuilder {
     fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         // Get search options for field
-        let field_search_options = match context.index_metadata {
-            Some(index_metadata) => {
-                match index_metadata.get_field_mapping(&self.field) {
-                    Some(field_mapping) => field_mapping.get_search_options(),
-                    None => FieldSearchOptions::default(),  // TODO: error?
-                }
-            }
-            None => FieldSearchOptions::default(),  // TODO: error?
-        };
+        let field_search_options = context.get_field_search_options(&self.field);
 
         // Tokenise query string
         let tokens = match field_search_options.analyzer {
@@ -49,7 +39,7 @@ impl QueryBuilder for MatchQueryBuilder {
             sub_queries.push(Query::Term {
                 field: schema.get_field_by_name(&self.field).unwrap(),
                 term: token.term,
-                scorer: TermScorer::default(),
+                scorer: field_search_options.term_scorer(),
             });
         }
 
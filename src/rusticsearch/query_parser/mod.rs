@@ -20,6 +20,7 @@ use kite::Query;
 use kite::schema::Schema;
 
 use index::metadata::IndexMetaData;
+use mapping::FieldSearchOptions;
 
 
 #[derive(Debug, Clone)]
@@ -48,6 +49,19 @@ impl<'a> QueryBuildContext<'a> {
         self.score_required = false;
         self
     }
+
+    /// Gets the search options (analyzer and similarity model) for a field
+    pub fn get_field_search_options(&self, field_name: &str) -> FieldSearchOptions {
+        match self.index_metadata {
+            Some(index_metadata) => {
+                match index_metadata.get_field_mapping(field_name) {
+                    Some(field_mapping) => field_mapping.get_search_options(),
+                    None => FieldSearchOptions::default(),  // TODO: error?
+                }
+            }
+            None => FieldSearchOptions::default(),  // TODO: error?
+        }
+    }
 }
 
 
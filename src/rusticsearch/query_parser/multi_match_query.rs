@@ -1,11 +1,9 @@
 //! Parses "multi_match" queries
 
 use serde_json::Value as Json;
-use kite::{Term, Token, Query, TermScorer};
+use kite::{Term, Token, Query};
 use kite::schema::Schema;
 
-use mapping::FieldSearchOptions;
-
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
 use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, parse_field_and_boost};
 
@@ -25,15 +23,7 @@ impl QueryBuilder for MultiMatchQueryBuilder {
         let mut field_queries = Vec::new();
         for &(ref field_name, field_boost) in self.fields.iter() {
             // Get search options for field
-            let field_search_options = match context.index_metadata {
-                Some(index_metadata) => {
-                    match index_metadata.get_field_mapping(&field_name) {
-                        Some(field_mapping) => field_mapping.get_search_options(),
-                        None => FieldSearchOptions::default(),  // TODO: error?
-                    }
-                }
-                None => FieldSearchOptions::default(),  // TODO: error?
-            };
+            let field_search_options = context.get_field_search_options(field_name);
 
             // Tokenise query string
             let tokens = match field_search_options.analyzer {
@@ -51,7 +41,7 @@ impl QueryBuilder for MultiMatchQueryBuilder {
                 term_queries.push(Query::Term {
                     field: schema.get_field_by_name(field_name).unwrap(),
                     term: token.term,
-                    scorer: TermScorer::default(),
+                    scorer: field_search_options.term_scorer(),
                 });
             }
 
@@ -1,7 +1,7 @@
 //! Parses "prefix" queries
 
 use serde_json::Value as Json;
-use kite::{Query, TermSelector, TermScorer};
+use kite::{Query, TermSelector};
 use kite::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
@@ -17,11 +17,11 @@ struct PrefixQueryBuilder {
 
 
 impl QueryBuilder for PrefixQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         let mut query = Query::MultiTerm {
             field: schema.get_field_by_name(&self.field).unwrap(),
             term_selector: TermSelector::Prefix(self.prefix.clone()),
-            scorer: TermScorer::default(),
+            scorer: context.get_field_search_options(&self.field).term_scorer(),
         };
 
         // Add boost
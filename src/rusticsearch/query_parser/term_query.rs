@@ -1,7 +1,7 @@
 //! Parses "term" queries
 
 use serde_json::Value as Json;
-use kite::{Term, Query, TermScorer};
+use kite::{Term, Query};
 use kite::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
@@ -17,11 +17,11 @@ struct TermQueryBuilder {
 
 
 impl QueryBuilder for TermQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         let mut query = Query::Term {
             field: schema.get_field_by_name(&self.field).unwrap(),
             term: self.term.clone(),
-            scorer: TermScorer::default(),
+            scorer: context.get_field_search_options(&self.field).term_scorer(),
         };
 
         // Add boost
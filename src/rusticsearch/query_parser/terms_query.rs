@@ -1,7 +1,7 @@
 //! Parses "match" queries
 
 use serde_json::Value as Json;
-use kite::{Term, Query, TermScorer};
+use kite::{Term, Query};
 use kite::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
@@ -15,14 +15,16 @@ struct TermsQueryBuilder {
 
 
 impl QueryBuilder for TermsQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field_search_options = context.get_field_search_options(&self.field);
+
         // Create a term query for each token
         let mut queries = Vec::new();
         for term in self.terms.iter() {
             queries.push(Query::Term {
                 field: schema.get_field_by_name(&self.field).unwrap(),
                 term: term.clone(),
-                scorer: TermScorer::default(),
+                scorer: field_search_options.term_scorer(),
             });
         }
 
@@ -0,0 +1,94 @@
+//! Runs searches across one or more indices
+
+pub mod statistics;
+
+use std::cmp::Ordering;
+
+use kite::{Query, DocRef};
+use kite::collectors::DocumentMatch;
+use kite::collectors::top_score::TopScoreCollector;
+use kite::collectors::total_count::TotalCountCollector;
+use kite::collectors::doc_ids::DocIdCollector;
+use kite_rocksdb::{RocksDBIndexReader, SearchExecutor};
+
+use index::Index;
+use search::statistics::{QueryStatistics, GlobalStatisticsReader};
+
+
+/// A query that has been built for a particular index, along with a reader to run it with
+///
+/// The reader is borrowed so that many searches can share the same snapshot of an index.
+pub struct IndexSearcher<'a> {
+    pub index: &'a Index,
+    pub reader: &'a RocksDBIndexReader<'a>,
+    pub query: Query,
+}
+
+
+impl<'a> IndexSearcher<'a> {
+    pub fn new(index: &'a Index, reader: &'a RocksDBIndexReader<'a>, query: Query) -> IndexSearcher<'a> {
+        IndexSearcher {
+            index: index,
+            reader: reader,
+            query: query,
+        }
+    }
+}
+
+
+/// Finds the top "num_docs" documents across all of the searchers
+///
+/// Documents are scored using statistics that are combined from all of the indices being
+/// searched, so scores from different indices can be compared with each other.
+///
+/// Returns a list of (searcher position, document match) pairs, ordered by score.
+pub fn search_top_docs(executor: &SearchExecutor, searchers: &[IndexSearcher], num_docs: usize) -> Result<Vec<(usize, DocumentMatch)>, String> {
+    // Combine the statistics from each index
+    let mut global_stats = QueryStatistics::default();
+    for searcher in searchers.iter() {
+        try!(global_stats.add_index(searcher.reader, &searcher.query));
+    }
+
+    // Find the top documents in each index
+    let mut top_docs = Vec::new();
+    for (searcher_id, searcher) in searchers.iter().enumerate() {
+        let mut collector = TopScoreCollector::new(num_docs);
+        let mut stats = GlobalStatisticsReader::new(searcher.reader, &searcher.query, &global_stats);
+        try!(executor.search_with_statistics(searcher.reader, &mut collector, &searcher.query, &mut stats));
+
+        for doc_match in collector.into_sorted_vec() {
+            top_docs.push((searcher_id, doc_match));
+        }
+    }
+
+    // Merge them together
+    top_docs.sort_by(|a, b| b.1.score().partial_cmp(&a.1.score()).unwrap_or(Ordering::Equal));
+    top_docs.truncate(num_docs);
+
+    Ok(top_docs)
+}
+
+
+/// Counts the documents that match across all of the searchers
+pub fn count_docs(executor: &SearchExecutor, searchers: &[IndexSearcher]) -> Result<u64, String> {
+    let mut count = 0;
+
+    for searcher in searchers.iter() {
+        let mut collector = TotalCountCollector::new();
+        try!(executor.search(searcher.reader, &mut collector, &searcher.query));
+        count += collector.get_total_count();
+    }
+
+    Ok(count)
+}
+
+
+/// Finds every document that matches the searcher's query
+///
+/// The documents aren't scored so they are returned in the order they are stored in.
+pub fn find_all_docs(searcher: &IndexSearcher) -> Result<Vec<DocRef>, String> {
+    let mut collector = DocIdCollector::new();
+    try!(searcher.reader.search(&mut collector, &searcher.query));
+
+    Ok(collector.into_vec().into_iter().map(DocRef::from_u64).collect())
+}
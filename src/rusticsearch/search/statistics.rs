@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+
+use kite::{Term, TermRef, Query};
+use kite::schema::FieldRef;
+use kite_rocksdb::{RocksDBIndexReader, StatisticsReader, RocksDBStatisticsReader};
+
+
+/// Finds all the fields and terms that are used for scoring in a query
+fn collect_query_terms(query: &Query, fields: &mut Vec<FieldRef>, terms: &mut Vec<(FieldRef, Term)>) {
+    match *query {
+        Query::All{..} | Query::None => {}
+        Query::Term{field, ref term, ..} => {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+
+            terms.push((field, term.clone()));
+        }
+        Query::MultiTerm{field, ..} => {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        Query::Conjunction{ref queries} |
+        Query::Disjunction{ref queries} |
+        Query::DisjunctionMax{ref queries} => {
+            for query in queries.iter() {
+                collect_query_terms(query, fields, terms);
+            }
+        }
+        Query::Filter{ref query, ..} | Query::Exclude{ref query, ..} => {
+            // Filters are not scored
+            collect_query_terms(query, fields, terms);
+        }
+    }
+}
+
+
+/// Statistics for a query, keyed by field name so they can be combined across indices
+#[derive(Debug, Default)]
+pub struct QueryStatistics {
+    total_docs: HashMap<String, i64>,
+    total_tokens: HashMap<String, i64>,
+    term_document_frequencies: HashMap<(String, Term), i64>,
+}
+
+
+impl QueryStatistics {
+    /// Adds the statistics from an index to the totals
+    pub fn add_index(&mut self, index_reader: &RocksDBIndexReader, query: &Query) -> Result<(), String> {
+        let mut fields = Vec::new();
+        let mut terms = Vec::new();
+        collect_query_terms(query, &mut fields, &mut terms);
+
+        let schema = index_reader.schema();
+        let mut stats = RocksDBStatisticsReader::new(index_reader);
+
+        for field_ref in fields {
+            let field_name = match schema.get(&field_ref) {
+                Some(field_info) => field_info.name().to_string(),
+                None => continue,
+            };
+
+            *self.total_docs.entry(field_name.clone()).or_insert(0) += try!(stats.total_docs(field_ref));
+            *self.total_tokens.entry(field_name).or_insert(0) += try!(stats.total_tokens(field_ref));
+        }
+
+        // The query may have the same term in it more than once, each term must only be
+        // counted once for each index
+        let mut seen_terms = HashSet::new();
+
+        for (field_ref, term) in terms {
+            let field_name = match schema.get(&field_ref) {
+                Some(field_info) => field_info.name().to_string(),
+                None => continue,
+            };
+
+            let key = (field_name, term);
+            if !seen_terms.insert(key.clone()) {
+                continue;
+            }
+
+            let term_document_frequency = match try!(index_reader.term_ref(field_ref, &key.1)) {
+                Some(term_ref) => try!(stats.term_document_frequency(field_ref, term_ref)),
+                None => 0,
+            };
+
+            *self.term_document_frequencies.entry(key).or_insert(0) += term_document_frequency;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Combines totals from many indices with the statistics of the index being searched
+///
+/// Any statistic that wasn't collected beforehand (such as those for terms selected by a
+/// prefix query) falls back to the statistics of the local index.
+pub struct GlobalStatisticsReader<'a> {
+    local: RocksDBStatisticsReader<'a>,
+    total_docs: HashMap<FieldRef, i64>,
+    total_tokens: HashMap<FieldRef, i64>,
+    term_document_frequencies: HashMap<(FieldRef, TermRef), i64>,
+}
+
+
+impl<'a> GlobalStatisticsReader<'a> {
+    pub fn new(index_reader: &'a RocksDBIndexReader, query: &Query, global_stats: &QueryStatistics) -> GlobalStatisticsReader<'a> {
+        let mut fields = Vec::new();
+        let mut terms = Vec::new();
+        collect_query_terms(query, &mut fields, &mut terms);
+
+        let schema = index_reader.schema();
+        let mut total_docs = HashMap::new();
+        let mut total_tokens = HashMap::new();
+        let mut term_document_frequencies = HashMap::new();
+
+        for field_ref in fields {
+            if let Some(field_info) = schema.get(&field_ref) {
+                if let Some(val) = global_stats.total_docs.get(field_info.name()) {
+                    total_docs.insert(field_ref, *val);
+                }
+
+                if let Some(val) = global_stats.total_tokens.get(field_info.name()) {
+                    total_tokens.insert(field_ref, *val);
+                }
+            }
+        }
+
+        for (field_ref, term) in terms {
+            let term_ref = match index_reader.term_ref(field_ref, &term) {
+                Ok(Some(term_ref)) => term_ref,
+                Ok(None) => continue,  // Term will never match in this index
+                Err(_) => continue,  // Fall back to the local statistics
+            };
+
+            if let Some(field_info) = schema.get(&field_ref) {
+                if let Some(val) = global_stats.term_document_frequencies.get(&(field_info.name().to_string(), term)) {
+                    term_document_frequencies.insert((field_ref, term_ref), *val);
+                }
+            }
+        }
+
+        GlobalStatisticsReader {
+            local: RocksDBStatisticsReader::new(index_reader),
+            total_docs: total_docs,
+            total_tokens: total_tokens,
+            term_document_frequencies: term_document_frequencies,
+        }
+    }
+}
+
+
+impl<'a> StatisticsReader for GlobalStatisticsReader<'a> {
+    fn total_docs(&mut self, field_ref: FieldRef) -> Result<i64, String> {
+        match self.total_docs.get(&field_ref) {
+            Some(val) => Ok(*val),
+            None => self.local.total_docs(field_ref),
+        }
+    }
+
+    fn total_tokens(&mut self, field_ref: FieldRef) -> Result<i64, String> {
+        match self.total_tokens.get(&field_ref) {
+            Some(val) => Ok(*val),
+            None => self.local.total_tokens(field_ref),
+        }
+    }
+
+    fn term_document_frequency(&mut self, field_ref: FieldRef, term_ref: TermRef) -> Result<i64, String> {
+        match self.term_document_frequencies.get(&(field_ref, term_ref)) {
+            Some(val) => Ok(*val),
+            None => self.local.term_document_frequency(field_ref, term_ref),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use kite::{Term, Token, Document, Query, TermScorer};
+    use kite::schema::{FieldType, FIELD_INDEXED};
+    use kite_rocksdb::RocksDBIndexStore;
+
+    use super::QueryStatistics;
+
+    fn make_store(path: &str, docs: &[(&str, &[&str])]) -> RocksDBIndexStore {
+        remove_dir_all(path);
+
+        let mut store = RocksDBIndexStore::create(path).unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for &(key, words) in docs.iter() {
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: hashmap! {
+                    title_field => words.iter().enumerate().map(|(position, word)| {
+                        Token { term: Term::from_string(word), position: position as u32 + 1 }
+                    }).collect(),
+                },
+                stored_fields: hashmap! {},
+            }).unwrap();
+        }
+
+        store
+    }
+
+    #[test]
+    fn test_term_document_frequency_across_indices() {
+        let first = make_store("test_indices/test_statistics_first", &[("a", &["hello", "world"]), ("b", &["hello"])]);
+        let second = make_store("test_indices/test_statistics_second", &[("c", &["hello"]), ("d", &["goodbye"])]);
+
+        let mut stats = QueryStatistics::default();
+        for store in vec![&first, &second] {
+            let reader = store.reader();
+            let title_field = reader.schema().get_field_by_name("title").unwrap();
+            let hello = || {
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("hello"),
+                    scorer: TermScorer::default(),
+                }
+            };
+
+            // The term is in the query twice but must only be counted once for each index
+            let query = Query::Disjunction {
+                queries: vec![hello(), hello()],
+            };
+
+            stats.add_index(&reader, &query).unwrap();
+        }
+
+        assert_eq!(stats.term_document_frequencies[&("title".to_string(), Term::from_string("hello"))], 3);
+        assert_eq!(stats.total_docs["title"], 4);
+    }
+}
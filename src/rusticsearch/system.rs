@@ -1,30 +1,64 @@
 use std::sync::RwLock;
 use std::path::{Path, PathBuf};
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 
 use slog::Logger;
-use kite_rocksdb::RocksDBIndexStore;
-use uuid::Uuid;
+use serde_json;
+use kite_rocksdb::{RocksDBIndexStore, SearchExecutor};
+use atomicwrites::{AtomicFile, AllowOverwrite};
 
 use index::Index;
 use index::registry::IndexRegistry;
 use index::metadata::IndexMetaData;
+use index::settings::IndexSettings;
+use index::meta_fields::add_meta_fields;
+
+
+/// Settings that the server is started with
+#[derive(Debug, Clone)]
+pub struct SystemConfig {
+    /// The directory that indices are stored in
+    pub data_dir: PathBuf,
+
+    /// The number of threads that searches are run on, `None` for a thread for each CPU
+    pub search_threads: Option<usize>,
+}
+
+
+impl Default for SystemConfig {
+    fn default() -> SystemConfig {
+        SystemConfig {
+            data_dir: Path::new("data/").to_path_buf(),
+            search_threads: None,
+        }
+    }
+}
 
 
 pub struct System {
     pub log: Logger,
     data_dir: PathBuf,
     pub indices: RwLock<IndexRegistry>,
+
+    /// Thread pool that searches are run on, shared by all indices
+    pub search_executor: SearchExecutor,
 }
 
 
 impl System {
-    pub fn new(log: Logger, data_dir: PathBuf) -> System {
-        System {
+    pub fn new(log: Logger, config: SystemConfig) -> Result<System, String> {
+        let search_executor = match config.search_threads {
+            Some(search_threads) => try!(SearchExecutor::with_threads(search_threads)),
+            None => try!(SearchExecutor::new()),
+        };
+
+        Ok(System {
             log: log,
-            data_dir: data_dir,
+            data_dir: config.data_dir,
             indices: RwLock::new(IndexRegistry::new()),
-        }
+            search_executor: search_executor,
+        })
     }
 
     pub fn get_indices_dir(&self) -> PathBuf {
@@ -33,33 +67,68 @@ impl System {
         dir
     }
 
-    fn load_index(&self, id: Uuid, name: String, path: &Path) -> Result<Index, String> {
-        let store = try!(RocksDBIndexStore::open(path));
+    fn load_index(&self, name: String, path: &Path) -> Result<Index, String> {
+        // Load settings
+        // Indices created before settings were saved are given a new identity here which
+        // is then saved so it will stay the same from now on
+        let mut settings_path = path.to_path_buf();
+        settings_path.push("settings.json");
+        let settings = if settings_path.exists() {
+            try!(IndexSettings::load(&settings_path))
+        } else {
+            let settings = IndexSettings::new();
+            try!(settings.save(&settings_path));
+            settings
+        };
 
         // Load metadata
         let mut metadata_path = path.to_path_buf();
         metadata_path.push("metadata.json");
-        let metadata = try!(IndexMetaData::load(metadata_path));
+        let mut metadata = try!(IndexMetaData::load(metadata_path));
+
+        // Closed indices are left on disk until they are opened
+        let store = if settings.closed {
+            None
+        } else {
+            let mut store = try!(RocksDBIndexStore::open(path));
+            try!(add_meta_fields(&mut store));
+            metadata.link_fields(&store.reader().schema());
+            Some(store)
+        };
 
-        Ok(Index::new(id, name, metadata, store))
+        Ok(Index::new(name, path.to_path_buf(), settings, metadata, store))
     }
 
     pub fn load_indices(&self) {
         let indices_dir = self.get_indices_dir();
         match fs::read_dir(indices_dir.clone()) {
             Ok(files) => {
-                for file in files {
-                    let path = file.unwrap().path();
+                // Load indices in name order so duplicates are always resolved the same way
+                let mut paths = files.map(|file| file.unwrap().path()).collect::<Vec<PathBuf>>();
+                paths.sort();
+
+                for path in paths {
                     if path.is_dir() {
                         let index_name: String = path.file_name().unwrap().to_str().unwrap().to_owned();
 
-                        match self.load_index(Uuid::new_v4(), index_name.clone().to_owned(), path.as_path()) {
+                        match self.load_index(index_name.clone().to_owned(), path.as_path()) {
                             Ok(index) => {
+                                let index_id = *index.id();
                                 let mut indices_w = self.indices.write().unwrap();
-                                let index_ref = indices_w.insert(index);
+                                let index_ref = match indices_w.insert(index) {
+                                    Ok(index_ref) => index_ref,
+                                    Err(_) => {
+                                        // Most likely, the index directory has been copied
+                                        self.log.error("[sys] failed to open index", b!(
+                                            "index" => index_name,
+                                            "error" => format!("duplicate index uuid: {}", index_id)
+                                        ));
+                                        continue;
+                                    }
+                                };
                                 indices_w.names.insert_canonical(index_name.clone(), index_ref).unwrap();
 
-                                self.log.info("[sys] loaded index", b!("index" => index_name));
+                                self.log.info("[sys] loaded index", b!("index" => index_name, "uuid" => format!("{}", index_id)));
                             }
                             Err(e) => {
                                 self.log.error("[sys] failed to open index", b!(
@@ -79,4 +148,75 @@ impl System {
             }
         }
     }
+
+    fn get_aliases_path(&self) -> PathBuf {
+        let mut path = self.data_dir.clone();
+        path.push("aliases.json");
+        path
+    }
+
+    /// Writes all aliases in the registry to disk
+    pub fn save_aliases(&self, indices: &IndexRegistry) -> Result<(), String> {
+        let aliases_json = try!(indices.names.aliases_to_json().map_err(|e| format!("failed to save aliases: {}", e)));
+        let s = format!("{}", aliases_json);
+
+        try!(fs::create_dir_all(&self.data_dir).map_err(|e| format!("failed to save aliases: {}", e)));
+        let file = AtomicFile::new(self.get_aliases_path(), AllowOverwrite);
+        try!(file.write(|f| {
+            f.write_all(s.as_bytes())
+        }).map_err(|e| format!("failed to save aliases: {}", e)));
+
+        Ok(())
+    }
+
+    /// Reads aliases from disk and adds them into the registry
+    ///
+    /// This must be called after the indices have been loaded
+    pub fn load_aliases(&self) {
+        let aliases_path = self.get_aliases_path();
+        let mut s = String::new();
+        match File::open(&aliases_path).and_then(|mut f| f.read_to_string(&mut s)) {
+            Ok(_) => {}
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return,
+            Err(error) => {
+                self.log.error("[sys] cannot read aliases file", b!(
+                    "path" => aliases_path.to_str().unwrap(),
+                    "error" => format!("{}", error)
+                ));
+                return;
+            }
+        }
+
+        let aliases_json: serde_json::Value = match serde_json::from_str(&s) {
+            Ok(aliases_json) => aliases_json,
+            Err(error) => {
+                self.log.error("[sys] cannot parse aliases file", b!(
+                    "path" => aliases_path.to_str().unwrap(),
+                    "error" => format!("{}", error)
+                ));
+                return;
+            }
+        };
+
+        let mut indices_w = self.indices.write().unwrap();
+        match indices_w.names.load_aliases_json(&aliases_json) {
+            Ok(skipped) => {
+                for (alias_name, index_name, reason) in skipped {
+                    self.log.warn("[sys] skipped alias", b!(
+                        "alias" => alias_name,
+                        "index" => index_name,
+                        "reason" => reason
+                    ));
+                }
+
+                self.log.info("[sys] loaded aliases", b!());
+            }
+            Err(error) => {
+                self.log.error("[sys] cannot load aliases file", b!(
+                    "path" => aliases_path.to_str().unwrap(),
+                    "error" => error
+                ));
+            }
+        }
+    }
 }